@@ -0,0 +1,238 @@
+//! Fan-out benchmark: runs a command on many hosts at once and reports how
+//! long hosts took and why they failed, to find the fleet settings a large
+//! deployment can sustain before running it for real.
+//!
+//! Against the server of `docker-compose.yml`, with every "host" being the
+//! same server:
+//!
+//! ```sh
+//! cargo run --release --example fanout -- --count 500 --concurrency 100 --insecure
+//! ```
+//!
+//! Against real hosts, one per line in a file, authenticating with the agent:
+//!
+//! ```sh
+//! cargo run --release --example fanout -- --hosts hosts.txt --port 22 \
+//!     --user deploy --agent --concurrency 2000 --connects 200 --rate 500
+//! ```
+//!
+//! Options:
+//!
+//! - `--hosts FILE`: hosts to connect to, one per line. Defaults to `--count`
+//!   copies of `--host`.
+//! - `--host HOST`, `--count N`: defaults to `localhost` and 100.
+//! - `--port PORT`, `--user USER`: default to 2222 and `test_user`.
+//! - `--password PASSWORD`: defaults to `test_password`, unless `--agent`.
+//! - `--agent`: authenticates with the agent at `SSH_AUTH_SOCK`.
+//! - `--insecure`: accepts any host key instead of those in
+//!   `~/.ssh/known_hosts`.
+//! - `--concurrency N`: most hosts with a session open at once.
+//! - `--connects N`: most connections being established at once.
+//! - `--rate N`: most connections opened per second.
+//! - `--payload BYTES`: bytes sent to each host's `cat`. Defaults to 0.
+//! - `--window BYTES`: channel window size.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::bail;
+use futures::StreamExt;
+use secrecy::SecretString;
+use ssh_util::Auth;
+use ssh_util::ConnectedSession;
+use ssh_util::DriverKind;
+use ssh_util::Fleet;
+use ssh_util::HostKeyVerification;
+use ssh_util::HostOptions;
+use tokio::io::AsyncWriteExt;
+
+struct Args {
+    hosts: Vec<String>,
+    port: u16,
+    user: String,
+    password: String,
+    agent: bool,
+    insecure: bool,
+    concurrency: Option<usize>,
+    connects: Option<usize>,
+    rate: Option<u32>,
+    payload: usize,
+    window: Option<u32>,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Args> {
+        let mut hosts_file = None;
+        let mut host = "localhost".to_string();
+        let mut count = 100;
+        let mut args = Args {
+            hosts: Vec::new(),
+            port: 2222,
+            user: "test_user".to_string(),
+            password: "test_password".to_string(),
+            agent: false,
+            insecure: false,
+            concurrency: None,
+            connects: None,
+            rate: None,
+            payload: 0,
+            window: None,
+        };
+
+        let mut argv = std::env::args().skip(1);
+        while let Some(flag) = argv.next() {
+            match flag.as_str() {
+                "--agent" => {
+                    args.agent = true;
+                    continue;
+                }
+                "--insecure" => {
+                    args.insecure = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let value = argv
+                .next()
+                .with_context(|| format!("{flag} needs a value"))?;
+            match flag.as_str() {
+                "--hosts" => hosts_file = Some(value),
+                "--host" => host = value,
+                "--count" => count = value.parse()?,
+                "--port" => args.port = value.parse()?,
+                "--user" => args.user = value,
+                "--password" => args.password = value,
+                "--concurrency" => args.concurrency = Some(value.parse()?),
+                "--connects" => args.connects = Some(value.parse()?),
+                "--rate" => args.rate = Some(value.parse()?),
+                "--payload" => args.payload = value.parse()?,
+                "--window" => args.window = Some(value.parse()?),
+                _ => bail!("unknown option {flag}"),
+            }
+        }
+
+        args.hosts = match hosts_file {
+            Some(path) => std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {path}"))?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            None => vec![host; count],
+        };
+
+        Ok(args)
+    }
+
+    fn fleet(&self) -> anyhow::Result<Fleet> {
+        let auth = if self.agent {
+            Auth::from_agent_env()?
+        } else {
+            Auth::Password(SecretString::from(self.password.clone()))
+        };
+        let defaults = HostOptions::builder()
+            .user(self.user.clone())
+            .port(self.port)
+            .drivers([DriverKind::Russh])
+            .auth([auth])
+            .connect_timeout(Duration::from_secs(30))
+            .busy_retries(3)
+            .maybe_window_size(self.window)
+            .maybe_host_key_verification(self.insecure.then_some(HostKeyVerification::AcceptAny))
+            .build();
+
+        let mut builder = Fleet::builder()
+            .defaults(defaults)
+            .maybe_max_concurrency(self.concurrency)
+            .maybe_max_concurrent_connects(self.connects)
+            .maybe_connects_per_second(self.rate);
+        for host in &self.hosts {
+            builder = builder.host(host.clone());
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Sends `payload` through `cat` and waits for it to exit.
+async fn exercise(session: ConnectedSession, payload: usize) -> ssh_util::Result<()> {
+    let mut child = session
+        .command("sh")
+        .args(["-c", "cat > /dev/null"])
+        .spawn()
+        .await?;
+    let mut stdin = child.stdin.take().expect("stdin is set by spawn");
+    stdin.write_all(&vec![0; payload]).await?;
+    stdin.shutdown().await?;
+    child.wait().await?;
+
+    Ok(())
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[(sorted.len() - 1) * percent / 100]
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse()?;
+    let fleet = args.fleet()?;
+    let payload = args.payload;
+    println!(
+        "{} hosts, concurrency {:?}, connects {:?}, rate {:?}/s, payload {} bytes, window {:?}",
+        args.hosts.len(),
+        args.concurrency,
+        args.connects,
+        args.rate,
+        args.payload,
+        args.window,
+    );
+
+    let start = Instant::now();
+    let mut latencies = Vec::new();
+    // Count and first message of each kind of failure.
+    let mut failures = BTreeMap::<_, (usize, String)>::new();
+    let mut results = fleet.run(move |session| async move {
+        let started = Instant::now();
+        exercise(session, payload).await?;
+        Ok(started.elapsed())
+    });
+    while let Some((host, result)) = results.next().await {
+        match result {
+            Ok(latency) => latencies.push(latency),
+            Err(error) => {
+                failures
+                    .entry(error.code().as_str())
+                    .or_insert_with(|| (0, format!("{host}: {error}")))
+                    .0 += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort();
+    println!(
+        "{} succeeded, {} failed in {elapsed:.2?} ({:.1} hosts/s)",
+        latencies.len(),
+        failures.values().map(|(count, _)| count).sum::<usize>(),
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+    );
+    println!(
+        "operation p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(&latencies, 50),
+        percentile(&latencies, 90),
+        percentile(&latencies, 99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+    for (code, (count, first)) in failures {
+        println!("{code}: {count}, first {first}");
+    }
+
+    Ok(())
+}
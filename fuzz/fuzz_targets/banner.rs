@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssh_util::banner::Banner;
+
+fuzz_target!(|ident: &str| {
+    let _ = Banner::parse(ident);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ssh_util::KexInit;
+
+fuzz_target!(|payload: &[u8]| {
+    let _ = KexInit::parse(payload);
+});
@@ -0,0 +1,12 @@
+//! Decoding of SFTP packets as the file system client receives them from
+//! servers.
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use russh_sftp::protocol::Packet;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Packet::try_from(&mut Bytes::copy_from_slice(data));
+});
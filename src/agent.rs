@@ -0,0 +1,275 @@
+//! Client side of the SSH agent protocol, for handing keys to a running agent
+//! and listing the keys it holds.
+
+use std::io;
+use std::time::Duration;
+
+use bon::Builder;
+use camino::Utf8Path;
+use secrecy::zeroize::Zeroizing;
+#[cfg(feature = "russh")]
+use ssh_encoding::Decode;
+use ssh_encoding::Encode;
+use ssh_key::PrivateKey;
+#[cfg(feature = "russh")]
+use ssh_key::PublicKey;
+#[cfg(feature = "russh")]
+use ssh_key::public::KeyData;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+
+use crate::Error;
+use crate::Result;
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+#[cfg(feature = "russh")]
+const SSH2_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+#[cfg(feature = "russh")]
+const SSH2_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH2_AGENTC_ADD_IDENTITY: u8 = 17;
+const SSH2_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+const SSH_AGENT_CONSTRAIN_CONFIRM: u8 = 2;
+
+/// Largest reply accepted from an agent, matching OpenSSH's limit.
+const MAX_REPLY_LEN: u32 = 256 * 1024;
+
+/// Restrictions on how an agent may use a key added to it, like `ssh-add -t`
+/// and `ssh-add -c`.
+#[derive(Debug, Clone, Default, Builder)]
+pub struct AgentConstraints {
+    /// How long the agent keeps the key before forgetting it. Whole seconds
+    /// only; sub-second parts are dropped.
+    pub lifetime: Option<Duration>,
+    /// Whether the agent asks the user to confirm each use of the key.
+    #[builder(default)]
+    pub confirm: bool,
+}
+
+impl AgentConstraints {
+    fn is_empty(&self) -> bool {
+        self.lifetime.is_none() && !self.confirm
+    }
+
+    fn encode(&self, message: &mut Vec<u8>) -> ssh_encoding::Result<()> {
+        if let Some(lifetime) = self.lifetime {
+            let seconds = u32::try_from(lifetime.as_secs()).unwrap_or(u32::MAX);
+            SSH_AGENT_CONSTRAIN_LIFETIME.encode(message)?;
+            seconds.encode(message)?;
+        }
+        if self.confirm {
+            SSH_AGENT_CONSTRAIN_CONFIRM.encode(message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds `private_key` to the agent listening on `socket`. The encoded key is
+/// wiped from memory once sent.
+///
+/// # Errors
+///
+/// - If the agent cannot be reached.
+/// - If the agent refuses the key, such as for an algorithm or constraint it
+///   does not support.
+pub(crate) async fn add_identity(
+    socket: &Utf8Path,
+    private_key: &PrivateKey,
+    constraints: &AgentConstraints,
+) -> Result<()> {
+    let message = add_identity_message(private_key, constraints).map_err(ssh_key::Error::from)?;
+
+    match request(socket, &message).await?[0] {
+        SSH_AGENT_SUCCESS => Ok(()),
+        SSH_AGENT_FAILURE => Err(Error::AgentRefusedKey),
+        other => Err(unexpected_reply(other)),
+    }
+}
+
+/// Public keys held by the agent listening on `socket`, with their comments,
+/// in the agent's order. Certificates the agent holds are left out.
+///
+/// # Errors
+///
+/// - If the agent cannot be reached or its reply cannot be parsed.
+#[cfg(feature = "russh")]
+pub(crate) async fn request_identities(socket: &Utf8Path) -> Result<Vec<PublicKey>> {
+    let reply = request(socket, &[0, 0, 0, 1, SSH2_AGENTC_REQUEST_IDENTITIES]).await?;
+    let (&kind, mut body) = reply.split_first().unwrap_or((&0, &[]));
+    if kind != SSH2_AGENT_IDENTITIES_ANSWER {
+        return Err(unexpected_reply(kind));
+    }
+
+    let count = u32::decode(&mut body).map_err(ssh_key::Error::from)?;
+    let mut keys = Vec::new();
+    for _ in 0..count {
+        let blob = Vec::<u8>::decode(&mut body).map_err(ssh_key::Error::from)?;
+        let comment = String::decode(&mut body).map_err(ssh_key::Error::from)?;
+        match KeyData::decode(&mut blob.as_slice()) {
+            Ok(key_data) => keys.push(PublicKey::new(key_data, comment)),
+            Err(error) => tracing::debug!(%error, comment, "skipping agent identity"),
+        }
+    }
+
+    Ok(keys)
+}
+
+/// Sends the length-prefixed `message` to the agent listening on `socket`
+/// and returns its reply, without the length, which is never empty.
+async fn request(socket: &Utf8Path, message: &[u8]) -> Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket).await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+
+    let len = stream.read_u32().await?;
+    if len == 0 || len > MAX_REPLY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("agent sent a reply of {len} bytes"),
+        )
+        .into());
+    }
+    let mut reply = vec![0; len as usize];
+    stream.read_exact(&mut reply).await?;
+
+    Ok(reply)
+}
+
+fn unexpected_reply(kind: u8) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected agent reply type {kind}"),
+    )
+    .into()
+}
+
+/// Length-prefixed request adding `private_key` with `constraints`.
+fn add_identity_message(
+    private_key: &PrivateKey,
+    constraints: &AgentConstraints,
+) -> ssh_encoding::Result<Zeroizing<Vec<u8>>> {
+    let mut body = Zeroizing::new(Vec::new());
+    let kind = if constraints.is_empty() {
+        SSH2_AGENTC_ADD_IDENTITY
+    } else {
+        SSH2_AGENTC_ADD_ID_CONSTRAINED
+    };
+    kind.encode(&mut *body)?;
+    private_key.key_data().encode(&mut *body)?;
+    private_key.comment().encode(&mut *body)?;
+    constraints.encode(&mut body)?;
+
+    let mut message = Zeroizing::new(Vec::with_capacity(body.len() + 4));
+    body.encode(&mut *message)?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::net::UnixListener;
+
+    use super::*;
+
+    fn key() -> PrivateKey {
+        PrivateKey::read_openssh_file("test/creds/id_ed25519".as_ref()).unwrap()
+    }
+
+    #[rstest]
+    #[case(AgentConstraints::default(), SSH2_AGENTC_ADD_IDENTITY, &[])]
+    #[case(
+        AgentConstraints::builder().lifetime(Duration::from_mins(5)).confirm(true).build(),
+        SSH2_AGENTC_ADD_ID_CONSTRAINED,
+        &[1, 0, 0, 1, 44, 2]
+    )]
+    #[tokio::test]
+    async fn add_identity_works(
+        #[case] constraints: AgentConstraints,
+        #[case] kind_should: u8,
+        #[case] constraints_should: &[u8],
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = Utf8Path::from_path(dir.path()).unwrap().join("agent.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let agent = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let len = stream.read_u32().await.unwrap();
+            let mut request = vec![0; len as usize];
+            stream.read_exact(&mut request).await.unwrap();
+            stream
+                .write_all(&[0, 0, 0, 1, SSH_AGENT_SUCCESS])
+                .await
+                .unwrap();
+            request
+        });
+
+        add_identity(&socket, &key(), &constraints).await.unwrap();
+
+        let request = agent.await.unwrap();
+        assert_eq!(request[0], kind_should);
+        assert!(request.ends_with(constraints_should));
+        let key_type = b"ssh-ed25519";
+        assert_eq!(&request[5..5 + key_type.len()], key_type);
+    }
+
+    #[tokio::test]
+    async fn add_identity_reports_refusal() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = Utf8Path::from_path(dir.path()).unwrap().join("agent.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let len = stream.read_u32().await.unwrap();
+            let mut request = vec![0; len as usize];
+            stream.read_exact(&mut request).await.unwrap();
+            stream
+                .write_all(&[0, 0, 0, 1, SSH_AGENT_FAILURE])
+                .await
+                .unwrap();
+        });
+
+        let result = add_identity(&socket, &key(), &AgentConstraints::default()).await;
+
+        assert!(matches!(result, Err(Error::AgentRefusedKey)));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn request_identities_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = Utf8Path::from_path(dir.path()).unwrap().join("agent.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let public_key = key().public_key().clone();
+        let mut body = vec![SSH2_AGENT_IDENTITIES_ANSWER];
+        2u32.encode(&mut body).unwrap();
+        public_key
+            .key_data()
+            .encoded_len()
+            .unwrap()
+            .encode(&mut body)
+            .unwrap();
+        public_key.key_data().encode(&mut body).unwrap();
+        "deploy@ci".encode(&mut body).unwrap();
+        b"not a key".as_slice().encode(&mut body).unwrap();
+        "garbage".encode(&mut body).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 5];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [0, 0, 0, 1, SSH2_AGENTC_REQUEST_IDENTITIES]);
+            let mut reply = Vec::new();
+            body.encode(&mut reply).unwrap();
+            stream.write_all(&reply).await.unwrap();
+        });
+
+        let keys = request_identities(&socket).await.unwrap();
+
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key_data(), public_key.key_data());
+        assert_eq!(keys[0].comment(), "deploy@ci");
+    }
+}
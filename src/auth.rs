@@ -1,31 +1,71 @@
 use std::env;
+use std::fmt;
 use std::fs;
 use std::io;
+use std::sync::Arc;
+#[cfg(feature = "russh")]
+use std::time::SystemTime;
 
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
+use secrecy::ExposeSecret;
 use secrecy::SecretSlice;
 use secrecy::SecretString;
 use ssh_key::Certificate;
+use ssh_key::Fingerprint;
+use ssh_key::HashAlg;
 use ssh_key::PrivateKey;
 
+#[cfg(unix)]
+use crate::AgentConstraints;
 use crate::Error;
+use crate::OtpProvider;
 use crate::Result;
+use crate::SecretSource;
+
+/// Passphrases asked for per key before giving up, like OpenSSH's default
+/// `NumberOfPasswordPrompts`.
+const MAX_PASSPHRASE_ATTEMPTS: u32 = 3;
+
+/// Source of passphrases for encrypted private keys, asked only once a key
+/// turns out to be encrypted. Implemented for closures with the same
+/// signature as [`PassphraseProvider::passphrase`].
+///
+/// With the `keyring` feature, a passphrase stored in the OS keyring for the
+/// key is tried first, and the provider only asked if it is missing or wrong.
+pub trait PassphraseProvider {
+    /// Passphrase for the private key at `path`, or `None` to give up.
+    /// `attempt` starts at 1 and increases each time the previous
+    /// passphrase was wrong.
+    fn passphrase(&mut self, path: &Utf8Path, attempt: u32) -> Option<SecretString>;
+}
+
+impl<F: FnMut(&Utf8Path, u32) -> Option<SecretString>> PassphraseProvider for F {
+    fn passphrase(&mut self, path: &Utf8Path, attempt: u32) -> Option<SecretString> {
+        self(path, attempt)
+    }
+}
 
 /// SSH authentication payloads.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Auth {
     Password(SecretString),
     Key {
         private_key: PrivateKey,
     },
     Cert {
-        certificate: Certificate,
+        certificate: Box<Certificate>,
         private_key: PrivateKey,
     },
     Agent {
         path: Utf8PathBuf,
     },
+    /// Answers to keyboard-interactive prompts: `password` to prompts for a
+    /// password, and answers from `otp` to the others.
+    KeyboardInteractive {
+        password: Option<SecretString>,
+        otp: Arc<dyn OtpProvider>,
+    },
 }
 
 impl Auth {
@@ -40,7 +80,9 @@ impl Auth {
         Ok(Auth::Password(password))
     }
 
-    /// Sources SSH private key from file.
+    /// Sources SSH private key from file. With the `pem` feature, PKCS#1,
+    /// PKCS#8 and SEC1 PEM files are read as well as OpenSSH ones, told apart
+    /// by their header.
     ///
     /// # Errors
     ///
@@ -49,7 +91,119 @@ impl Auth {
         private_key_file: impl AsRef<Utf8Path>,
         passphrase: Option<impl AsRef<[u8]>>,
     ) -> Result<Auth> {
-        let private_key = read_openssh_private_key(private_key_file, passphrase)?;
+        let private_key = read_private_key(private_key_file, passphrase)?;
+
+        Ok(Auth::Key { private_key })
+    }
+
+    /// Sources password from the secret `name` in `source`.
+    ///
+    /// # Errors
+    ///
+    /// - If `source` does not have the secret or fails to fetch it.
+    /// - If the secret is not UTF-8.
+    pub async fn from_password_source(
+        source: &(impl SecretSource + ?Sized),
+        name: &str,
+    ) -> Result<Auth> {
+        let password = source.fetch_string(name).await?;
+
+        Ok(Auth::Password(password))
+    }
+
+    /// Sources SSH private key from the secret `name` in `source`, in any
+    /// format [`Auth::from_key_file`] reads. If the key is encrypted, its
+    /// passphrase is the secret `passphrase_name`.
+    ///
+    /// # Errors
+    ///
+    /// - If `source` does not have a secret or fails to fetch it.
+    /// - If the key cannot be parsed.
+    /// - If the key is encrypted and `passphrase_name` is `None` or names the
+    ///   wrong passphrase.
+    pub async fn from_key_source(
+        source: &(impl SecretSource + ?Sized),
+        name: &str,
+        passphrase_name: Option<&str>,
+    ) -> Result<Auth> {
+        let text = source.fetch_string(name).await?;
+        let key_file = KeyFile::parse(text.expose_secret())?;
+
+        let private_key = match passphrase_name {
+            Some(passphrase_name) if key_file.is_encrypted() => {
+                let passphrase = source.fetch(passphrase_name).await?;
+                key_file.decrypt(passphrase.expose_secret())?
+            }
+            _ => key_file.into_private_key()?,
+        };
+
+        Ok(Auth::Key { private_key })
+    }
+
+    /// Sources SSH private key from file, asking `passphrases` for its
+    /// passphrase only if it is encrypted. A wrong passphrase is asked again,
+    /// up to 3 times.
+    ///
+    /// # Errors
+    ///
+    /// - If `private_key_file` cannot be read.
+    /// - If the key is encrypted and `passphrases` gives up or never gives the
+    ///   right passphrase.
+    pub fn from_key_file_with(
+        private_key_file: impl AsRef<Utf8Path>,
+        passphrases: impl PassphraseProvider,
+    ) -> Result<Auth> {
+        let private_key_file = private_key_file.as_ref();
+        let key_file = KeyFile::read(private_key_file)?;
+        let private_key = decrypt_with(key_file, private_key_file, passphrases)?;
+
+        Ok(Auth::Key { private_key })
+    }
+
+    /// Adds the SSH private key from file to the agent named by
+    /// `SSH_AUTH_SOCK` under `constraints`, then authenticates through the
+    /// agent, like `ssh-add` followed by agent authentication. The decrypted
+    /// key is only held in memory until the agent has it, rather than for the
+    /// whole session. Passphrases are asked like
+    /// [`Auth::from_key_file_with`].
+    ///
+    /// # Errors
+    ///
+    /// - If `SSH_AUTH_SOCK` environment variable is nonexistent or unreadable.
+    /// - If `private_key_file` cannot be read or decrypted.
+    /// - If the agent cannot be reached or refuses the key.
+    #[cfg(unix)]
+    pub async fn from_key_file_via_agent(
+        private_key_file: impl AsRef<Utf8Path>,
+        passphrases: impl PassphraseProvider,
+        constraints: &AgentConstraints,
+    ) -> Result<Auth> {
+        let path = agent_path_from_env()?;
+
+        let private_key_file = private_key_file.as_ref();
+        let key_file = KeyFile::read(private_key_file)?;
+        let private_key = decrypt_with(key_file, private_key_file, passphrases)?;
+        crate::agent::add_identity(&path, &private_key, constraints).await?;
+
+        Ok(Self::Agent { path })
+    }
+
+    /// Sources SSH private key from a PuTTY `.ppk` file.
+    ///
+    /// # Errors
+    ///
+    /// - If `ppk_file` cannot be read.
+    /// - If the key is encrypted and `passphrase` is missing or wrong.
+    #[cfg(feature = "ppk")]
+    pub fn from_ppk_file(
+        ppk_file: impl AsRef<Utf8Path>,
+        passphrase: Option<impl AsRef<[u8]>>,
+    ) -> Result<Auth> {
+        let ppk = crate::PpkKey::read_file(ppk_file)?;
+        let private_key = match passphrase {
+            Some(passphrase) => ppk.decrypt(passphrase)?,
+            None => ppk.to_private_key()?,
+        };
 
         Ok(Auth::Key { private_key })
     }
@@ -68,10 +222,10 @@ impl Auth {
         let certificate_file = certificate_file.as_ref().as_std_path();
         let certificate = Certificate::read_file(certificate_file)?;
 
-        let private_key = read_openssh_private_key(private_key_file, passphrase)?;
+        let private_key = read_private_key(private_key_file, passphrase)?;
 
         Ok(Auth::Cert {
-            certificate,
+            certificate: Box::new(certificate),
             private_key,
         })
     }
@@ -84,13 +238,175 @@ impl Auth {
     /// - If `SSH_AUTH_SOCK` environment variable is nonexistent or unreadable.
     /// - If the path value sourced from `SSH_AUTH_SOCK` does not exist.
     pub fn from_agent_env() -> Result<Auth> {
-        let path = env::var("SSH_AUTH_SOCK").map(Utf8PathBuf::from)?;
+        let path = agent_path_from_env()?;
+
+        Ok(Self::Agent { path })
+    }
 
-        if !path.try_exists()? {
-            return Err(io::Error::from(io::ErrorKind::NotFound).into());
+    /// Answers keyboard-interactive prompts, as servers asking for a second
+    /// factor send them: prompts for a password with `password` if given,
+    /// and any other prompt, such as for a verification code, with `otp`.
+    pub fn keyboard_interactive(
+        password: Option<SecretString>,
+        otp: impl OtpProvider + 'static,
+    ) -> Auth {
+        Auth::KeyboardInteractive {
+            password,
+            otp: Arc::new(otp),
         }
+    }
 
-        Ok(Self::Agent { path })
+    /// Kind of this payload.
+    #[must_use]
+    pub fn kind(&self) -> AuthKind {
+        match self {
+            Auth::Password(_) => AuthKind::Password,
+            Auth::Key { .. } => AuthKind::Key,
+            Auth::Cert { .. } => AuthKind::Cert,
+            Auth::Agent { .. } => AuthKind::Agent,
+            Auth::KeyboardInteractive { .. } => AuthKind::KeyboardInteractive,
+        }
+    }
+
+    /// Why this payload cannot be accepted for `user` at `now`, or `None` if
+    /// it can. Certificates must be within their validity period and list
+    /// `user` as a principal, unless they list no principals at all. Other
+    /// payloads always apply.
+    #[cfg(feature = "russh")]
+    pub(crate) fn inapplicable_reason(&self, user: &str, now: SystemTime) -> Option<String> {
+        let Auth::Cert { certificate, .. } = self else {
+            return None;
+        };
+
+        let principals = certificate.valid_principals();
+        if !principals.is_empty() && !principals.iter().any(|p| p == user) {
+            return Some(format!(
+                "principals {} do not include {user}",
+                principals.join(",")
+            ));
+        }
+        if now < certificate.valid_after_time() {
+            return Some(format!(
+                "not valid before Unix time {}",
+                certificate.valid_after()
+            ));
+        }
+        if certificate.valid_before_time() <= now {
+            return Some(format!(
+                "expired at Unix time {}",
+                certificate.valid_before()
+            ));
+        }
+
+        None
+    }
+}
+
+/// Kind of an authentication payload, without any of its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum AuthKind {
+    Password,
+    Key,
+    Cert,
+    Agent,
+    KeyboardInteractive,
+}
+
+impl fmt::Display for AuthKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuthKind::Password => "password",
+            AuthKind::Key => "key",
+            AuthKind::Cert => "cert",
+            AuthKind::Agent => "agent",
+            AuthKind::KeyboardInteractive => "keyboard-interactive",
+        })
+    }
+}
+
+#[cfg(feature = "russh")]
+impl AuthKind {
+    /// SSH authentication method payloads of this kind are offered with.
+    pub(crate) fn method(self) -> &'static str {
+        match self {
+            AuthKind::Password => "password",
+            AuthKind::Key | AuthKind::Cert | AuthKind::Agent => "publickey",
+            AuthKind::KeyboardInteractive => "keyboard-interactive",
+        }
+    }
+}
+
+/// Payloads worth offering to a server that allows only the `allowed`
+/// methods, most likely to be accepted first: public keys, which fail
+/// without a guess being counted against a password, before the others.
+/// Payloads keep their order otherwise.
+#[cfg(feature = "russh")]
+pub(crate) fn prioritize<'a>(payloads: Vec<&'a Auth>, allowed: &[&str]) -> Vec<&'a Auth> {
+    let mut payloads: Vec<_> = payloads
+        .into_iter()
+        .filter(|payload| allowed.contains(&payload.kind().method()))
+        .collect();
+    payloads.sort_by_key(|payload| payload.kind().method() != "publickey");
+    payloads
+}
+
+/// Authentication payload the server accepted, identified without exposing any
+/// secrets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthOutcome {
+    Password,
+    Key {
+        fingerprint: Fingerprint,
+    },
+    Cert {
+        certificate: Box<Certificate>,
+    },
+    Agent {
+        fingerprint: Fingerprint,
+        comment: String,
+    },
+    KeyboardInteractive,
+}
+
+impl AuthOutcome {
+    pub(crate) fn cert(certificate: &Certificate) -> Self {
+        Self::Cert {
+            certificate: Box::new(certificate.clone()),
+        }
+    }
+
+    /// Kind of payload that was accepted.
+    #[must_use]
+    pub fn kind(&self) -> AuthKind {
+        match self {
+            AuthOutcome::Password => AuthKind::Password,
+            AuthOutcome::Key { .. } => AuthKind::Key,
+            AuthOutcome::Cert { .. } => AuthKind::Cert,
+            AuthOutcome::Agent { .. } => AuthKind::Agent,
+            AuthOutcome::KeyboardInteractive => AuthKind::KeyboardInteractive,
+        }
+    }
+}
+
+impl fmt::Display for AuthOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthOutcome::Password => write!(f, "password"),
+            AuthOutcome::Key { fingerprint } => write!(f, "publickey {fingerprint}"),
+            AuthOutcome::Cert { certificate } => write!(
+                f,
+                "certificate {} (key id \"{}\", serial {})",
+                certificate.public_key().fingerprint(HashAlg::Sha256),
+                certificate.key_id(),
+                certificate.serial()
+            ),
+            AuthOutcome::Agent {
+                fingerprint,
+                comment,
+            } => write!(f, "agent {fingerprint} ({comment})"),
+            AuthOutcome::KeyboardInteractive => write!(f, "keyboard-interactive"),
+        }
     }
 }
 
@@ -106,32 +422,168 @@ fn read_secret_string(path: impl AsRef<Utf8Path>) -> Result<SecretString> {
     Ok(secret)
 }
 
-fn read_openssh_private_key(
+fn agent_path_from_env() -> Result<Utf8PathBuf> {
+    let path = env::var("SSH_AUTH_SOCK").map(Utf8PathBuf::from)?;
+
+    if !path.try_exists()? {
+        return Err(io::Error::from(io::ErrorKind::NotFound).into());
+    }
+
+    Ok(path)
+}
+
+/// Private key file in any format key loading understands, possibly still
+/// encrypted.
+enum KeyFile {
+    OpenSsh(Box<PrivateKey>),
+    #[cfg(feature = "pem")]
+    Pem(crate::pem::PemKey),
+}
+
+impl KeyFile {
+    /// Reads `path`, telling formats apart by their header.
+    fn read(path: &Utf8Path) -> Result<KeyFile> {
+        let text = fs::read_to_string(path)?;
+
+        KeyFile::parse(&text)
+    }
+
+    /// Parses the contents of a key file, telling formats apart by their
+    /// header.
+    fn parse(text: &str) -> Result<KeyFile> {
+        #[cfg(feature = "pem")]
+        if crate::pem::PemKey::detect(text) {
+            return Ok(KeyFile::Pem(crate::pem::PemKey::from_pem(text)?));
+        }
+
+        Ok(KeyFile::OpenSsh(Box::new(PrivateKey::from_openssh(text)?)))
+    }
+
+    fn is_encrypted(&self) -> bool {
+        match self {
+            KeyFile::OpenSsh(private_key) => private_key.is_encrypted(),
+            #[cfg(feature = "pem")]
+            KeyFile::Pem(pem) => pem.is_encrypted(),
+        }
+    }
+
+    fn decrypt(&self, passphrase: &[u8]) -> Result<PrivateKey> {
+        match self {
+            KeyFile::OpenSsh(private_key) => Ok(private_key.decrypt(passphrase)?),
+            #[cfg(feature = "pem")]
+            KeyFile::Pem(pem) => pem.decrypt(passphrase),
+        }
+    }
+
+    /// Key of a file that is not encrypted.
+    fn into_private_key(self) -> Result<PrivateKey> {
+        match self {
+            KeyFile::OpenSsh(private_key) if private_key.is_encrypted() => {
+                Err(Error::EncryptedPrivateKeyNoPasshrase)
+            }
+            KeyFile::OpenSsh(private_key) => Ok(*private_key),
+            #[cfg(feature = "pem")]
+            KeyFile::Pem(pem) => pem.to_private_key(),
+        }
+    }
+}
+
+fn read_private_key(
     private_key_file: impl AsRef<Utf8Path>,
     passphrase: Option<impl AsRef<[u8]>>,
 ) -> Result<PrivateKey> {
-    let private_key_file = private_key_file.as_ref().as_std_path();
-    let private_key = PrivateKey::read_openssh_file(private_key_file)?;
+    let key_file = KeyFile::read(private_key_file.as_ref())?;
 
-    let private_key = match (private_key.is_encrypted(), passphrase) {
-        (true, Some(passphrase)) => private_key.decrypt(passphrase)?,
+    let private_key = match (key_file.is_encrypted(), passphrase) {
+        (true, Some(passphrase)) => key_file.decrypt(passphrase.as_ref())?,
         (true, None) => {
             return Err(Error::EncryptedPrivateKeyNoPasshrase);
         }
-        (false, _) => private_key,
+        (false, _) => key_file.into_private_key()?,
     };
 
     Ok(private_key)
 }
 
+/// Decrypts `key_file` with passphrases from `passphrases` if it is
+/// encrypted.
+fn decrypt_with(
+    key_file: KeyFile,
+    path: &Utf8Path,
+    mut passphrases: impl PassphraseProvider,
+) -> Result<PrivateKey> {
+    if !key_file.is_encrypted() {
+        return key_file.into_private_key();
+    }
+
+    #[cfg(feature = "keyring")]
+    if let Some(passphrase) = crate::keyring::stored_passphrase(path) {
+        match key_file.decrypt(passphrase.expose_secret().as_bytes()) {
+            Ok(private_key) => return Ok(private_key),
+            Err(error) => tracing::warn!(%path, %error, "wrong passphrase in keyring"),
+        }
+    }
+
+    let mut attempt = 1;
+    loop {
+        let passphrase = passphrases
+            .passphrase(path, attempt)
+            .ok_or(Error::EncryptedPrivateKeyNoPasshrase)?;
+        match key_file.decrypt(passphrase.expose_secret().as_bytes()) {
+            Ok(private_key) => return Ok(private_key),
+            Err(error) if attempt < MAX_PASSPHRASE_ATTEMPTS => {
+                tracing::warn!(%path, attempt, %error, "wrong passphrase for private key");
+                attempt += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rstest::rstest;
-    use secrecy::ExposeSecret;
-    use ssh_key::HashAlg;
 
     use super::*;
 
+    #[rstest]
+    #[case("id_ed25519", None, true)]
+    #[case("enc_ed25519", Some("password"), false)]
+    #[case("enc_ed25519", None, false)]
+    #[tokio::test]
+    async fn from_key_source_works(
+        #[case] name: &str,
+        #[case] passphrase_name: Option<&str>,
+        #[case] loads: bool,
+    ) {
+        let source = crate::FileSource::new("test/creds");
+
+        let auth = Auth::from_key_source(&source, name, passphrase_name).await;
+
+        assert_eq!(auth.is_ok(), loads);
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest]
+    #[case(&["publickey", "password"], &[AuthKind::Cert, AuthKind::Key, AuthKind::Password])]
+    #[case(&["password"], &[AuthKind::Password])]
+    #[case(&["keyboard-interactive"], &[])]
+    fn prioritize_works(#[case] allowed: &[&str], #[case] kinds_should: &[AuthKind]) {
+        let password = Auth::Password(SecretString::from("secret"));
+        let cert = Auth::from_cert_file(
+            "test/creds/id_ed25519-cert.pub",
+            "test/creds/id_ed25519",
+            None::<&str>,
+        )
+        .unwrap();
+        let key = Auth::from_key_file("test/creds/id_ed25519", None::<&str>).unwrap();
+
+        let payloads = prioritize(vec![&password, &cert, &key], allowed);
+
+        let kinds: Vec<_> = payloads.iter().map(|payload| payload.kind()).collect();
+        assert_eq!(kinds, kinds_should);
+    }
+
     #[rstest]
     #[case("test/creds/password", "test_password")]
     fn from_password_file_works(#[case] file: &str, #[case] password_should: &str) {
@@ -139,7 +591,7 @@ mod tests {
         match auth {
             Auth::Password(got) => assert_eq!(got.expose_secret(), password_should),
             other => panic!("Got wrong Auth type: {other:?}"),
-        };
+        }
     }
 
     #[rstest]
@@ -177,7 +629,33 @@ mod tests {
                 );
             }
             other => panic!("Got wrong Auth type: {other:?}"),
-        };
+        }
+    }
+
+    #[rstest]
+    #[case("test/creds/id_ed25519", &[], true, 0)]
+    #[case("test/creds/enc_ed25519", &["test_passphrase"], true, 1)]
+    #[case("test/creds/enc_ed25519", &["wrong", "test_passphrase"], true, 2)]
+    #[case("test/creds/enc_ed25519", &["wrong"], false, 2)]
+    #[case("test/creds/enc_ed25519", &["a", "b", "c", "test_passphrase"], false, 3)]
+    fn from_key_file_with_works(
+        #[case] private_key_file: &str,
+        #[case] passphrases: &[&str],
+        #[case] loads: bool,
+        #[case] asked_should: u32,
+    ) {
+        let mut asked = 0;
+
+        let result = Auth::from_key_file_with(private_key_file, |path: &Utf8Path, attempt| {
+            assert_eq!(path, private_key_file);
+            asked = attempt;
+            passphrases
+                .get(attempt as usize - 1)
+                .map(|passphrase| SecretString::from(*passphrase))
+        });
+
+        assert_eq!(result.is_ok(), loads);
+        assert_eq!(asked, asked_should);
     }
 
     #[rstest]
@@ -212,6 +690,75 @@ mod tests {
                 );
             }
             other => panic!("Got wrong Auth type: {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest]
+    #[case("test_user", 0x1000, true)]
+    #[case("other_user", 0x1000, false)]
+    #[case("test_user", 0x0, false)]
+    #[case("test_user", 0x20_0000_0000, false)]
+    fn cert_applies_to_works(#[case] user: &str, #[case] unix_time: u64, #[case] should: bool) {
+        let auth = Auth::from_cert_file(
+            "test/creds/id_ed25519-cert.pub",
+            "test/creds/id_ed25519",
+            None::<&str>,
+        )
+        .unwrap();
+        let now = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(unix_time);
+
+        assert_eq!(auth.inapplicable_reason(user, now).is_none(), should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest]
+    #[case("other_user", 0x1000, "principals test_user do not include other_user")]
+    #[case("test_user", 0x0, "not valid before Unix time")]
+    #[case("test_user", 0x20_0000_0000, "expired at Unix time")]
+    fn cert_inapplicable_reason_works(
+        #[case] user: &str,
+        #[case] unix_time: u64,
+        #[case] reason_should: &str,
+    ) {
+        let auth = Auth::from_cert_file(
+            "test/creds/id_ed25519-cert.pub",
+            "test/creds/id_ed25519",
+            None::<&str>,
+        )
+        .unwrap();
+        let now = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(unix_time);
+
+        let reason = auth.inapplicable_reason(user, now).unwrap();
+
+        assert!(reason.starts_with(reason_should), "{reason}");
+    }
+
+    #[test]
+    fn auth_outcome_display_works() {
+        let Auth::Cert { certificate, .. } = Auth::from_cert_file(
+            "test/creds/id_ed25519-cert.pub",
+            "test/creds/id_ed25519",
+            None::<&str>,
+        )
+        .unwrap() else {
+            panic!("Got wrong Auth type");
         };
+
+        assert_eq!(
+            AuthOutcome::cert(&certificate).to_string(),
+            "certificate SHA256:qqVUhwuqHFgBv4R85QmdFIsKWkacxZ/MeB9oSXDbC7k (key id \"test_identity\", serial 0)"
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[test]
+    fn password_applies_to_works() {
+        let auth = Auth::from_password_file("test/creds/password").unwrap();
+
+        assert!(
+            auth.inapplicable_reason("anyone", SystemTime::now())
+                .is_none()
+        );
     }
 }
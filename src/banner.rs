@@ -0,0 +1,259 @@
+//! Reading the identification strings servers send on connecting, to survey
+//! which SSH implementations and versions run across many endpoints.
+
+use std::io;
+use std::time::Duration;
+
+use bon::Builder;
+use futures::StreamExt;
+use tokio::io::AsyncBufRead;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::BufReader;
+use tokio::net::ToSocketAddrs;
+
+use crate::Error;
+use crate::Result;
+use crate::Session;
+
+/// Longest line accepted before the server's identification string,
+/// including it, as limited by RFC 4253.
+const MAX_LINE_LEN: u64 = 255;
+/// Most lines accepted before the server's identification string.
+const MAX_PRE_IDENT_LINES: usize = 1024;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_CONCURRENCY: usize = 256;
+
+/// Identification string of a server, such as
+/// `SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Banner {
+    /// Whole identification string, without the line ending.
+    pub ident: String,
+    /// Protocol version, `2.0`, or `1.99` for servers also speaking SSH 1.
+    pub protocol: String,
+    /// Software and its version, such as `OpenSSH_9.6p1`.
+    pub software: String,
+    /// Free-form comment following the software, often naming the
+    /// distribution's package.
+    pub comment: Option<String>,
+}
+
+impl Banner {
+    /// Splits an identification string into its parts, or returns `None` if
+    /// it is not one.
+    #[must_use]
+    pub fn parse(ident: &str) -> Option<Banner> {
+        let rest = ident.strip_prefix("SSH-")?;
+        let (protocol, rest) = rest.split_once('-')?;
+        let (software, comment) = match rest.split_once(' ') {
+            Some((software, comment)) => (software, Some(comment.to_string())),
+            None => (rest, None),
+        };
+        if protocol.is_empty() || software.is_empty() {
+            return None;
+        }
+
+        Some(Banner {
+            ident: ident.to_string(),
+            protocol: protocol.to_string(),
+            software: software.to_string(),
+            comment,
+        })
+    }
+}
+
+impl Session {
+    /// Connects to the server at `addr` and reads its identification string,
+    /// within `timeout`. Nothing is sent; the connection is closed as soon as
+    /// the identification string has been read.
+    ///
+    /// # Errors
+    ///
+    /// - If the TCP connection cannot be established.
+    /// - If no identification string arrives within `timeout`.
+    /// - If the server does not speak SSH.
+    pub async fn probe_banner(addr: impl ToSocketAddrs, timeout: Duration) -> Result<Banner> {
+        tokio::time::timeout(timeout, async {
+            let stream = tokio::net::TcpStream::connect(addr).await?;
+            let ident = read_ident(&mut BufReader::new(stream)).await?;
+            Banner::parse(&ident).ok_or_else(|| invalid_data("malformed identification string"))
+        })
+        .await
+        .unwrap_or(Err(Error::ConnectTimeout))
+    }
+}
+
+/// Survey of the identification strings of many hosts at once.
+#[derive(Debug, Builder)]
+pub struct BannerScan {
+    #[builder(field)]
+    hosts: Vec<(String, u16)>,
+    /// Time allowed per host, from connecting to reading its identification
+    /// string. Defaults to 5 seconds.
+    #[builder(default = DEFAULT_TIMEOUT)]
+    timeout: Duration,
+    /// Most hosts probed at once. Defaults to 256.
+    #[builder(default = DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+    /// Least time between starting connections, to keep the rate of new
+    /// connections below what firewalls and intrusion detection tolerate.
+    /// Unlimited by default.
+    pace: Option<Duration>,
+}
+
+impl<S: banner_scan_builder::State> BannerScanBuilder<S> {
+    /// Adds a host to probe on port 22.
+    pub fn host(self, host: impl Into<String>) -> Self {
+        self.host_with_port(host, 22)
+    }
+
+    /// Adds a host to probe on `port`.
+    pub fn host_with_port(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.hosts.push((host.into(), port));
+        self
+    }
+}
+
+/// Identification string found on a host.
+#[derive(Debug)]
+pub struct ProbedHost {
+    pub host: String,
+    pub port: u16,
+    /// Banner read, or why it could not be.
+    pub banner: Result<Banner>,
+}
+
+impl BannerScan {
+    /// Probes every host, at most `concurrency` at once and starting at most
+    /// one connection per `pace`. Results are in the order hosts were added.
+    pub async fn scan(&self) -> Vec<ProbedHost> {
+        let start = tokio::time::Instant::now();
+        futures::stream::iter(self.hosts.iter().enumerate())
+            .map(|(index, (host, port))| async move {
+                if let Some(pace) = self.pace {
+                    let slot = u32::try_from(index).unwrap_or(u32::MAX);
+                    tokio::time::sleep_until(start + pace.saturating_mul(slot)).await;
+                }
+                let banner = Session::probe_banner((host.as_str(), *port), self.timeout).await;
+                if let Err(error) = &banner {
+                    tracing::debug!(host, port, %error, "could not read banner");
+                }
+                ProbedHost {
+                    host: host.clone(),
+                    port: *port,
+                    banner,
+                }
+            })
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await
+    }
+}
+
+/// Reads lines from `reader` until the server's identification string, which
+/// servers may precede with other lines, and returns it without the line
+/// ending.
+///
+/// # Errors
+///
+/// - If reading fails or the connection closes first.
+/// - If a line is too long, or there are too many before the identification
+///   string.
+pub(crate) async fn read_ident(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<String> {
+    for _ in 0..MAX_PRE_IDENT_LINES {
+        let mut line = Vec::new();
+        reader
+            .take(MAX_LINE_LEN)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if !line.ends_with(b"\n") {
+            return Err(invalid_data("identification string is too long or missing"));
+        }
+        if line.starts_with(b"SSH-") {
+            let line = String::from_utf8(line)
+                .map_err(|_| invalid_data("identification string is not UTF-8"))?;
+            return Ok(line.trim_end().to_string());
+        }
+    }
+
+    Err(invalid_data("no identification string"))
+}
+
+fn invalid_data(message: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[rstest]
+    #[case(
+        "SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13",
+        Some(("2.0", "OpenSSH_9.6p1", Some("Ubuntu-3ubuntu13")))
+    )]
+    #[case("SSH-1.99-Cisco-1.25", Some(("1.99", "Cisco-1.25", None)))]
+    #[case("SSH-2.0-", None)]
+    #[case("HTTP/1.1 400 Bad Request", None)]
+    fn parse_works(#[case] ident: &str, #[case] parts_should: Option<(&str, &str, Option<&str>)>) {
+        let banner = Banner::parse(ident);
+
+        let parts = banner.as_ref().map(|banner| {
+            (
+                banner.protocol.as_str(),
+                banner.software.as_str(),
+                banner.comment.as_deref(),
+            )
+        });
+        assert_eq!(parts, parts_should);
+    }
+
+    #[rstest]
+    #[case(b"SSH-2.0-dropbear_2022.83\r\n", Some("SSH-2.0-dropbear_2022.83"))]
+    #[case(b"Welcome\r\nSSH-2.0-OpenSSH_9.6\n", Some("SSH-2.0-OpenSSH_9.6"))]
+    #[case(b"HTTP/1.1 400 Bad Request\r\n", None)]
+    #[case(b"SSH-2.0-OpenSSH_9.6", None)]
+    #[tokio::test]
+    async fn read_ident_works(#[case] input: &[u8], #[case] ident_should: Option<&str>) {
+        let ident = read_ident(&mut BufReader::new(input)).await;
+
+        assert_eq!(ident.ok().as_deref(), ident_should);
+    }
+
+    #[tokio::test]
+    async fn scan_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await.unwrap();
+            }
+        });
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let probed = BannerScan::builder()
+            .host_with_port("127.0.0.1", port)
+            .host_with_port("127.0.0.1", closed_port)
+            .host_with_port("127.0.0.1", port)
+            .pace(Duration::from_millis(10))
+            .build()
+            .scan()
+            .await;
+
+        let software: Vec<_> = probed
+            .iter()
+            .map(|probed| probed.banner.as_ref().ok().map(|b| b.software.as_str()))
+            .collect();
+        assert_eq!(software, [Some("OpenSSH_9.6"), None, Some("OpenSSH_9.6")]);
+        assert_eq!(probed[1].port, closed_port);
+    }
+}
@@ -0,0 +1,882 @@
+//! Loading of OpenSSH client configuration files, such as `~/.ssh/config`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+
+use crate::Algorithms;
+use crate::AuthKind;
+use crate::DriverKind;
+use crate::Error;
+use crate::Result;
+use crate::Tokens;
+
+/// Maximum nesting of `Include` directives, as in OpenSSH.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Keywords that may be given more than once, with every value used. For
+/// all others only the first value obtained is used.
+const MULTI_VALUED: &[&str] = &[
+    "certificatefile",
+    "dynamicforward",
+    "identityfile",
+    "localforward",
+    "remoteforward",
+    "sendenv",
+];
+
+/// Parsed OpenSSH client configuration.
+///
+/// `Include` directives are followed while parsing, while `Host` and `Match`
+/// conditions are evaluated by [`SshConfig::resolve`] for the host being
+/// connected to. `Match` supports the `all`, `canonical`, `final`, `host`,
+/// `originalhost`, `user`, `localuser` and `exec` criteria; others are
+/// rejected rather than ignored, since ignoring them would apply settings
+/// meant for other hosts. Hostnames are never canonicalized, so every
+/// evaluation is treated as the final one.
+#[derive(Debug, Clone, Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+/// Directives sharing the same conditions. Conditions of nested blocks, from
+/// `Host` and `Match` inside included files, include those of the block the
+/// `Include` was in.
+#[derive(Debug, Clone)]
+struct Block {
+    conditions: Vec<Condition>,
+    directives: Vec<Directive>,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    /// Patterns from a `Host` line, matched against the original host.
+    Host(Vec<String>),
+    /// Criteria from a `Match` line, all of which must hold.
+    Match(Vec<Criterion>),
+}
+
+#[derive(Debug, Clone)]
+struct Criterion {
+    negated: bool,
+    kind: CriterionKind,
+}
+
+#[derive(Debug, Clone)]
+enum CriterionKind {
+    All,
+    Canonical,
+    Final,
+    Host(String),
+    OriginalHost(String),
+    User(String),
+    LocalUser(String),
+    Exec(String),
+}
+
+#[derive(Debug, Clone)]
+struct Directive {
+    keyword: String,
+    args: Vec<String>,
+    origin: String,
+    line: usize,
+}
+
+/// Settings that apply to a host, as resolved by [`SshConfig::resolve`].
+/// Keywords are case-insensitive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostConfig {
+    options: Vec<(String, Vec<String>)>,
+}
+
+/// Settings a [`Session`](crate::Session) will connect with, after layering
+/// its explicitly set values over applied configuration, the environment and
+/// the defaults. Given by [`Session::resolved`](crate::Session::resolved).
+///
+/// Displays like `ssh -G`, one lowercase keyword and its value per line, and
+/// is serializable with the `serde` feature. Neither includes secrets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ResolvedConfig {
+    /// Host to connect to, after any `HostName` substitution.
+    pub host: String,
+    pub port: u16,
+    /// Remote user, with tokens expanded.
+    pub user: String,
+    /// Drivers to try, in order.
+    pub drivers: Vec<DriverKind>,
+    /// Kinds of the explicitly given authentication payloads, in the order
+    /// they are tried.
+    pub auth: Vec<AuthKind>,
+    /// Private key files from `IdentityFile`, with tokens expanded, tried
+    /// after the explicitly given authentication payloads.
+    pub identity_files: Vec<Utf8PathBuf>,
+    /// Algorithms allowed during key exchange, or `None` for the driver's
+    /// defaults.
+    pub algorithms: Option<Algorithms>,
+    pub require_strict_kex: bool,
+    pub connect_timeout: Duration,
+    pub rekey_bytes: Option<usize>,
+    pub rekey_interval: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl ResolvedConfig {
+    /// Values for `%` tokens describing this connection.
+    #[must_use]
+    pub fn tokens(&self) -> Tokens {
+        Tokens::local()
+            .with_host(&self.host, self.port)
+            .with_remote_user(&self.user)
+    }
+
+    /// One-line description of the connection for logs, such as
+    /// `deploy@web1:22 via russh (auth: password,key)`, naming the kinds of
+    /// authentication to try but never their secrets.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let mut auth = self.auth.clone();
+        if !self.identity_files.is_empty() {
+            auth.push(AuthKind::Key);
+        }
+
+        summary(&self.user, &self.host, self.port, &self.drivers, &auth)
+    }
+}
+
+/// Line like `user@host:port via driver (auth: kind)` describing a
+/// connection. Repeated kinds are listed once.
+pub(crate) fn summary(
+    user: &str,
+    host: &str,
+    port: u16,
+    drivers: &[DriverKind],
+    auth: &[AuthKind],
+) -> String {
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    let drivers: Vec<String> = drivers.iter().map(ToString::to_string).collect();
+    let mut kinds: Vec<String> = Vec::new();
+    for kind in auth.iter().map(ToString::to_string) {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    let kinds = if kinds.is_empty() {
+        "none".to_string()
+    } else {
+        kinds.join(",")
+    };
+
+    format!(
+        "{user}@{host}:{port} via {} (auth: {kinds})",
+        drivers.join(",")
+    )
+}
+
+impl fmt::Display for ResolvedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hostname {}", self.host)?;
+        writeln!(f, "port {}", self.port)?;
+        writeln!(f, "user {}", self.user)?;
+        for driver in &self.drivers {
+            writeln!(f, "driver {driver:?}")?;
+        }
+        for kind in &self.auth {
+            writeln!(f, "auth {kind}")?;
+        }
+        for path in &self.identity_files {
+            writeln!(f, "identityfile {path}")?;
+        }
+        if let Some(algorithms) = &self.algorithms {
+            writeln!(f, "kexalgorithms {}", algorithms.kex.join(","))?;
+            writeln!(f, "hostkeyalgorithms {}", algorithms.host_key.join(","))?;
+            writeln!(f, "ciphers {}", algorithms.cipher.join(","))?;
+            writeln!(f, "macs {}", algorithms.mac.join(","))?;
+        }
+        writeln!(f, "requirestrictkex {}", yes_no(self.require_strict_kex))?;
+        writeln!(f, "connecttimeout {}", self.connect_timeout.as_secs())?;
+        // Like OpenSSH, 0 stands for no limit.
+        writeln!(
+            f,
+            "rekeylimit {} {}",
+            self.rekey_bytes.unwrap_or_default(),
+            self.rekey_interval.unwrap_or_default().as_secs()
+        )?;
+        if let Some(max_bytes) = self.max_bytes {
+            writeln!(f, "maxbytes {max_bytes}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+impl SshConfig {
+    /// Parses configuration from `text`. Relative paths in `Include` are
+    /// resolved against `~/.ssh`, as for the user's configuration file.
+    ///
+    /// # Errors
+    ///
+    /// - If a line cannot be parsed, or a `Match` line uses an unsupported
+    ///   criterion.
+    /// - If an included file cannot be read.
+    pub fn parse(text: &str) -> Result<Self> {
+        let base = Tokens::local().expand_path("~/.ssh")?;
+        let mut parser = Parser {
+            base,
+            blocks: Vec::new(),
+        };
+        parser.parse(text, "<config>", &[], 0)?;
+
+        Ok(Self {
+            blocks: parser.blocks,
+        })
+    }
+
+    /// Parses configuration from a file. Relative paths in `Include` are
+    /// resolved against the file's directory, which matches OpenSSH for both
+    /// `~/.ssh/config` and `/etc/ssh/ssh_config`.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` cannot be read.
+    /// - For the same reasons as [`SshConfig::parse`].
+    pub fn load(path: impl AsRef<Utf8Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut parser = Parser {
+            base: path.parent().unwrap_or(Utf8Path::new(".")).to_path_buf(),
+            blocks: Vec::new(),
+        };
+        parser.parse(&text, path.as_str(), &[], 0)?;
+
+        Ok(Self {
+            blocks: parser.blocks,
+        })
+    }
+
+    /// Settings that apply when connecting to `host`, in order of
+    /// precedence. `user` is the remote user if already known, used to
+    /// evaluate `Match user`; otherwise the user from the configuration so
+    /// far, or the local user, is used like OpenSSH does.
+    ///
+    /// # Errors
+    ///
+    /// - If a `Match exec` command cannot be run.
+    /// - If `HostName` or a `Match exec` command has a token that cannot be
+    ///   expanded.
+    pub fn resolve(&self, host: &str, user: Option<&str>) -> Result<HostConfig> {
+        let mut config = HostConfig::default();
+        for block in &self.blocks {
+            if !block.matches(host, user, &config)? {
+                continue;
+            }
+
+            for directive in &block.directives {
+                let mut args = directive.args.clone();
+                if directive.keyword == "hostname"
+                    && let Some(hostname) = args.first_mut()
+                {
+                    *hostname = Tokens::builder()
+                        .host(host)
+                        .build()
+                        .expand(hostname)
+                        .map_err(|error| directive.error(error.to_string()))?;
+                }
+                config.add(&directive.keyword, args);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl Block {
+    fn matches(
+        &self,
+        original_host: &str,
+        user: Option<&str>,
+        config: &HostConfig,
+    ) -> Result<bool> {
+        for condition in &self.conditions {
+            let matches = match condition {
+                Condition::Host(patterns) => matches_pattern_list(patterns, original_host),
+                Condition::Match(criteria) => {
+                    let mut matches = true;
+                    for criterion in criteria {
+                        if !criterion.matches(original_host, user, config)? {
+                            matches = false;
+                            break;
+                        }
+                    }
+                    matches
+                }
+            };
+            if !matches {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Criterion {
+    fn matches(
+        &self,
+        original_host: &str,
+        user: Option<&str>,
+        config: &HostConfig,
+    ) -> Result<bool> {
+        let host = config.hostname().unwrap_or(original_host);
+        let user = user.or(config.user());
+
+        let matches = match &self.kind {
+            CriterionKind::All | CriterionKind::Canonical | CriterionKind::Final => true,
+            CriterionKind::Host(patterns) => matches_pattern_list(&split_list(patterns), host),
+            CriterionKind::OriginalHost(patterns) => {
+                matches_pattern_list(&split_list(patterns), original_host)
+            }
+            CriterionKind::User(patterns) => {
+                let user = user.map(str::to_string).or_else(crate::tokens::local_user);
+                user.is_some_and(|user| matches_pattern_list(&split_list(patterns), &user))
+            }
+            CriterionKind::LocalUser(patterns) => crate::tokens::local_user()
+                .is_some_and(|user| matches_pattern_list(&split_list(patterns), &user)),
+            CriterionKind::Exec(command) => {
+                let mut tokens = Tokens::local().with_host(host, config.port().unwrap_or(22));
+                if let Some(user) = user.map(str::to_string).or_else(crate::tokens::local_user) {
+                    tokens = tokens.with_remote_user(user);
+                }
+                run_exec(&tokens.expand(command)?)?
+            }
+        };
+
+        Ok(matches != self.negated)
+    }
+}
+
+impl Directive {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::InvalidConfig {
+            origin: self.origin.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+impl HostConfig {
+    /// Arguments of the first occurrence of `keyword`.
+    #[must_use]
+    pub fn get(&self, keyword: &str) -> Option<&[String]> {
+        self.get_all(keyword).next()
+    }
+
+    /// Arguments of every occurrence of `keyword`, for keywords such as
+    /// `IdentityFile` that may be given more than once.
+    pub fn get_all(&self, keyword: &str) -> impl Iterator<Item = &[String]> {
+        let keyword = keyword.to_ascii_lowercase();
+        self.options
+            .iter()
+            .filter(move |(k, _)| *k == keyword)
+            .map(|(_, args)| args.as_slice())
+    }
+
+    /// Real host to connect to, from `HostName`.
+    #[must_use]
+    pub fn hostname(&self) -> Option<&str> {
+        self.first_arg("hostname")
+    }
+
+    /// Remote user, from `User`.
+    #[must_use]
+    pub fn user(&self) -> Option<&str> {
+        self.first_arg("user")
+    }
+
+    /// Remote port, from `Port`. `None` if not set or not a valid port.
+    #[must_use]
+    pub fn port(&self) -> Option<u16> {
+        self.first_arg("port")?.parse().ok()
+    }
+
+    /// Maximum time to wait for the TCP connection, from `ConnectTimeout`.
+    /// `None` if not set or not a valid number of seconds.
+    #[must_use]
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.first_arg("connecttimeout")?
+            .parse()
+            .ok()
+            .map(Duration::from_secs)
+    }
+
+    /// Private key files, from `IdentityFile`, with tokens not yet expanded.
+    pub fn identity_files(&self) -> impl Iterator<Item = &str> {
+        self.get_all("identityfile")
+            .filter_map(|args| args.first().map(String::as_str))
+    }
+
+    fn first_arg(&self, keyword: &str) -> Option<&str> {
+        self.get(keyword)?.first().map(String::as_str)
+    }
+
+    fn add(&mut self, keyword: &str, args: Vec<String>) {
+        let is_set = self.options.iter().any(|(k, _)| k == keyword);
+        if !is_set || MULTI_VALUED.contains(&keyword) {
+            self.options.push((keyword.to_string(), args));
+        }
+    }
+}
+
+struct Parser {
+    /// Directory relative `Include` paths are resolved against.
+    base: Utf8PathBuf,
+    blocks: Vec<Block>,
+}
+
+impl Parser {
+    /// Parses `text`, whose directives apply under `conditions` until its
+    /// first `Host` or `Match` line.
+    fn parse(
+        &mut self,
+        text: &str,
+        origin: &str,
+        conditions: &[Condition],
+        depth: usize,
+    ) -> Result<()> {
+        self.start_block(conditions.to_vec());
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: &str| Error::InvalidConfig {
+                origin: origin.to_string(),
+                line: line_number,
+                message: message.to_string(),
+            };
+
+            let Some((keyword, args)) = split_line(line).map_err(error)? else {
+                continue;
+            };
+            match keyword.as_str() {
+                "host" => {
+                    if args.is_empty() {
+                        return Err(error("Host requires at least one pattern"));
+                    }
+                    let mut nested = conditions.to_vec();
+                    nested.push(Condition::Host(args));
+                    self.start_block(nested);
+                }
+                "match" => {
+                    let mut nested = conditions.to_vec();
+                    nested.push(Condition::Match(
+                        parse_criteria(args).map_err(|message| error(&message))?,
+                    ));
+                    self.start_block(nested);
+                }
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(error("Include nested too deeply"));
+                    }
+                    let current = self
+                        .blocks
+                        .last()
+                        .map(|block| block.conditions.clone())
+                        .unwrap_or_default();
+                    for pattern in args {
+                        for path in self.include_paths(&pattern)? {
+                            let text = match fs::read_to_string(&path) {
+                                Ok(text) => text,
+                                // Directories and files removed since listing
+                                // are skipped, as by OpenSSH.
+                                Err(error) if error.kind() != io::ErrorKind::PermissionDenied => {
+                                    continue;
+                                }
+                                Err(error) => return Err(error.into()),
+                            };
+                            self.parse(&text, path.as_str(), &current, depth + 1)?;
+                        }
+                    }
+                    self.start_block(current);
+                }
+                _ => {
+                    let block = self.blocks.last_mut().expect("a block is always started");
+                    block.directives.push(Directive {
+                        keyword,
+                        args,
+                        origin: origin.to_string(),
+                        line: line_number,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_block(&mut self, conditions: Vec<Condition>) {
+        self.blocks.push(Block {
+            conditions,
+            directives: Vec::new(),
+        });
+    }
+
+    /// Files matched by an `Include` pattern, in lexical order.
+    fn include_paths(&self, pattern: &str) -> Result<Vec<Utf8PathBuf>> {
+        let pattern = Tokens::local().expand_path(pattern)?;
+        let pattern = if pattern.is_absolute() {
+            pattern
+        } else {
+            self.base.join(pattern)
+        };
+
+        let mut paths = vec![Utf8PathBuf::new()];
+        for component in pattern.components() {
+            let component = component.as_str();
+            if !component.contains(['*', '?']) {
+                for path in &mut paths {
+                    path.push(component);
+                }
+                continue;
+            }
+
+            let mut matched = Vec::new();
+            for dir in &paths {
+                let Ok(entries) = dir.read_dir_utf8() else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let name = entry.file_name();
+                    if name.starts_with('.') && !component.starts_with('.') {
+                        continue;
+                    }
+                    if matches_wildcard(component, name) {
+                        matched.push(dir.join(name));
+                    }
+                }
+            }
+            paths = matched;
+        }
+        paths.sort();
+
+        Ok(paths)
+    }
+}
+
+/// Keyword, lowercased, and arguments of a line, or `None` for blank lines
+/// and comments. The keyword may be separated from its arguments by `=`, and
+/// arguments may be quoted.
+fn split_line(line: &str) -> std::result::Result<Option<(String, Vec<String>)>, &'static str> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    Ok(Some((keyword.to_ascii_lowercase(), split_args(rest)?)))
+}
+
+/// Splits arguments on whitespace, honouring double and single quotes and
+/// backslash escapes. An unquoted `#` starting an argument begins a comment.
+fn split_args(text: &str) -> std::result::Result<Vec<String>, &'static str> {
+    let mut args = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none_or(|c| *c == '#') {
+            break;
+        }
+
+        let mut arg = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                ('\\', _)
+                    if chars
+                        .peek()
+                        .is_some_and(|next| matches!(next, '\\' | '"' | '\'' | ' ')) =>
+                {
+                    arg.extend(chars.next());
+                }
+                ('"' | '\'', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                (c, None) if c.is_whitespace() => break,
+                (c, _) => arg.push(c),
+            }
+        }
+        if quote.is_some() {
+            return Err("unterminated quote");
+        }
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+fn parse_criteria(args: Vec<String>) -> std::result::Result<Vec<Criterion>, String> {
+    let mut criteria = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (negated, name) = match arg.strip_prefix('!') {
+            Some(name) => (true, name),
+            None => (false, arg.as_str()),
+        };
+        let name = name.to_ascii_lowercase();
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Match {name} requires an argument"))
+        };
+
+        let kind = match name.as_str() {
+            "all" => CriterionKind::All,
+            "canonical" => CriterionKind::Canonical,
+            "final" => CriterionKind::Final,
+            "host" => CriterionKind::Host(value()?),
+            "originalhost" => CriterionKind::OriginalHost(value()?),
+            "user" => CriterionKind::User(value()?),
+            "localuser" => CriterionKind::LocalUser(value()?),
+            "exec" => CriterionKind::Exec(value()?),
+            _ => return Err(format!("unsupported Match criterion {name}")),
+        };
+        criteria.push(Criterion { negated, kind });
+    }
+
+    if criteria.is_empty() {
+        return Err("Match requires at least one criterion".to_string());
+    }
+
+    Ok(criteria)
+}
+
+fn split_list(patterns: &str) -> Vec<String> {
+    patterns.split(',').map(str::to_string).collect()
+}
+
+/// Whether `value` matches any of `patterns` and none of those negated with
+/// `!`. Matching is case-insensitive, as for hostnames.
+pub(crate) fn matches_pattern_list(patterns: &[String], value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('!') {
+            Some(negated) if matches_wildcard(negated, &value) => return false,
+            Some(_) => {}
+            None => matched |= matches_wildcard(&pattern, &value),
+        }
+    }
+
+    matched
+}
+
+/// Whether `text` matches `pattern`, in which `*` stands for any number of
+/// characters and `?` for exactly one.
+fn matches_wildcard(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Runs a `Match exec` command with the local shell, returning whether it
+/// succeeded.
+fn run_exec(command: &str) -> Result<bool> {
+    #[cfg(windows)]
+    let status = std::process::Command::new("cmd")
+        .args(["/C", command])
+        .status()?;
+    #[cfg(not(windows))]
+    let status = std::process::Command::new("sh")
+        .args(["-c", command])
+        .status()?;
+
+    Ok(status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+# Comment
+Host web? !web9
+    HostName %h.example.com
+    User deploy
+    IdentityFile ~/.ssh/web
+
+Host db1
+    Port=2222
+
+Match originalhost db* user admin
+    IdentityFile "~/.ssh/db admin"
+
+Host *
+    User root
+    IdentityFile ~/.ssh/id_ed25519 # trailing comment
+"#;
+
+    #[rstest]
+    #[case("web1", None, Some("web1.example.com"), Some("deploy"), None, &["~/.ssh/web", "~/.ssh/id_ed25519"])]
+    #[case("web9", None, None, Some("root"), None, &["~/.ssh/id_ed25519"])]
+    #[case("db1", None, None, Some("root"), Some(2222), &["~/.ssh/id_ed25519"])]
+    #[case("db1", Some("admin"), None, Some("root"), Some(2222), &["~/.ssh/db admin", "~/.ssh/id_ed25519"])]
+    fn resolve_works(
+        #[case] host: &str,
+        #[case] user: Option<&str>,
+        #[case] hostname_should: Option<&str>,
+        #[case] user_should: Option<&str>,
+        #[case] port_should: Option<u16>,
+        #[case] identity_files_should: &[&str],
+    ) {
+        let config = SshConfig::parse(CONFIG).unwrap();
+
+        let resolved = config.resolve(host, user).unwrap();
+
+        assert_eq!(resolved.hostname(), hostname_should);
+        assert_eq!(resolved.user(), user_should);
+        assert_eq!(resolved.port(), port_should);
+        assert_eq!(
+            resolved.identity_files().collect::<Vec<_>>(),
+            identity_files_should
+        );
+    }
+
+    #[rstest]
+    #[case("Match host web.example.com\n  Port 1\n", Some(1))]
+    #[case("Match !host web.example.com\n  Port 1\n", None)]
+    #[case("Match exec \"test %h = web.example.com\"\n  Port 1\n", Some(1))]
+    #[case("Match exec false\n  Port 1\n", None)]
+    #[case("Match all\n  Port 1\n", Some(1))]
+    #[case("Match final host web*\n  Port 1\n", Some(1))]
+    fn match_uses_hostname(#[case] rest: &str, #[case] port_should: Option<u16>) {
+        let config =
+            SshConfig::parse(&format!("Host web\n  HostName web.example.com\n{rest}")).unwrap();
+
+        let resolved = config.resolve("web", None).unwrap();
+
+        assert_eq!(resolved.port(), port_should);
+    }
+
+    #[rstest]
+    #[case("Match tagged foo")]
+    #[case("Match host")]
+    #[case("Match")]
+    #[case("Host")]
+    #[case("User \"root")]
+    fn parse_rejects_invalid(#[case] text: &str) {
+        let result = SshConfig::parse(text);
+
+        assert!(matches!(result, Err(Error::InvalidConfig { line: 1, .. })));
+    }
+
+    #[test]
+    fn include_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        fs::create_dir(dir.join("config.d")).unwrap();
+        fs::write(dir.join("config.d/b.conf"), "Host db1\n  Port 2\n").unwrap();
+        fs::write(dir.join("config.d/a.conf"), "Port 1\nHost db1\n  User a\n").unwrap();
+        fs::write(dir.join("config.d/.hidden"), "User hidden\n").unwrap();
+        fs::write(dir.join("prod"), "User prod\n").unwrap();
+        fs::write(
+            dir.join("config"),
+            "Host web1\n  Include config.d/*.conf\n  HostName web1.internal\n\
+             Host db1\n  Include prod\n  Include missing\n",
+        )
+        .unwrap();
+        let config = SshConfig::load(dir.join("config")).unwrap();
+
+        let web = config.resolve("web1", None).unwrap();
+        let db = config.resolve("db1", None).unwrap();
+
+        assert_eq!(web.port(), Some(1));
+        assert_eq!(web.user(), None);
+        assert_eq!(web.hostname(), Some("web1.internal"));
+        assert_eq!(db.port(), None);
+        assert_eq!(db.user(), Some("prod"));
+    }
+
+    #[test]
+    fn include_rejects_recursion() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        fs::write(dir.join("config"), "Include config\n").unwrap();
+
+        let result = SshConfig::load(dir.join("config"));
+
+        assert!(matches!(result, Err(Error::InvalidConfig { .. })));
+    }
+
+    #[rstest]
+    #[case("*", "anything", true)]
+    #[case("web?", "web1", true)]
+    #[case("web?", "web10", false)]
+    #[case("*.example.com", "a.b.example.com", true)]
+    #[case("*.example.com", "example.com", false)]
+    #[case("a*b*c", "aXbYbZc", true)]
+    fn matches_wildcard_works(#[case] pattern: &str, #[case] text: &str, #[case] matches: bool) {
+        assert_eq!(matches_wildcard(pattern, text), matches);
+    }
+
+    #[rstest]
+    #[case("User root", Some(("user", vec!["root"])))]
+    #[case("  user=root", Some(("user", vec!["root"])))]
+    #[case("SendEnv = LANG LC_*", Some(("sendenv", vec!["LANG", "LC_*"])))]
+    #[case(r#"ProxyCommand "ssh -W %h:%p" jump"#, Some(("proxycommand", vec!["ssh -W %h:%p", "jump"])))]
+    #[case(r"IdentityFile my\ key", Some(("identityfile", vec!["my key"])))]
+    #[case("# comment", None)]
+    #[case("", None)]
+    fn split_line_works(#[case] line: &str, #[case] split_should: Option<(&str, Vec<&str>)>) {
+        let split = split_line(line).unwrap();
+
+        assert_eq!(
+            split,
+            split_should.map(|(keyword, args)| (
+                keyword.to_string(),
+                args.into_iter().map(str::to_string).collect()
+            ))
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Step-by-step explanation of why a session cannot connect, for showing
+//! users something actionable instead of a single opaque error.
+
+use std::fmt;
+use std::time::Instant;
+
+use crate::Error;
+use crate::ResolvedConfig;
+use crate::Result;
+use crate::Session;
+use crate::banner::Banner;
+use crate::kex::read_offer;
+
+/// Stage of connecting that a [`Step`] checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// Resolving the session's settings from the builder and applied
+    /// configuration.
+    Config,
+    /// Looking up the address of the host.
+    Dns,
+    /// Opening the TCP connection, after the pre-connect hook.
+    Tcp,
+    /// Reading the server's identification string.
+    Banner,
+    /// Reading the algorithms the server offers for key exchange.
+    Kex,
+    /// Completing the key exchange with the driver and verifying the
+    /// server's host key.
+    HostKey,
+    /// Authenticating with one payload.
+    Auth,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Check::Config => "config",
+            Check::Dns => "dns",
+            Check::Tcp => "tcp",
+            Check::Banner => "banner",
+            Check::Kex => "kex",
+            Check::HostKey => "host key",
+            Check::Auth => "auth",
+        })
+    }
+}
+
+/// Result of one check.
+#[derive(Debug)]
+pub struct Step {
+    pub check: Check,
+    /// What was found, such as the address the host resolved to, or why the
+    /// check failed.
+    pub detail: String,
+    /// Error that failed the check, or `None` if it passed.
+    pub error: Option<Error>,
+}
+
+impl Step {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed() { " ok " } else { "FAIL" };
+        write!(f, "[{status}] {}: {}", self.check, self.detail)
+    }
+}
+
+/// Report of [`diagnose`], one line per step when displayed.
+#[derive(Debug, Default)]
+pub struct Diagnosis {
+    /// Checks run, in order. Checking stops at the first failure, except
+    /// that every authentication payload is tried until one is accepted.
+    pub steps: Vec<Step>,
+}
+
+impl Diagnosis {
+    /// Whether the session would connect, which is when the last step
+    /// passed.
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.steps.last().is_some_and(Step::passed)
+    }
+
+    /// First step that failed.
+    #[must_use]
+    pub fn failure(&self) -> Option<&Step> {
+        self.steps.iter().find(|step| !step.passed())
+    }
+
+    /// Records the result of `check`, describing a success with `detail`, and
+    /// returns its value if it passed.
+    fn record<T>(
+        &mut self,
+        check: Check,
+        result: Result<T>,
+        detail: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.pass(check, detail(&value));
+                Some(value)
+            }
+            Err(error) => {
+                self.fail(check, error.to_string(), error);
+                None
+            }
+        }
+    }
+
+    fn pass(&mut self, check: Check, detail: String) {
+        self.steps.push(Step {
+            check,
+            detail,
+            error: None,
+        });
+    }
+
+    fn fail(&mut self, check: Check, detail: String, error: Error) {
+        self.steps.push(Step {
+            check,
+            detail,
+            error: Some(error),
+        });
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{step}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Connects like [`Session::connect`] one step at a time, checking the
+/// configuration, DNS, the TCP connection, the server's banner and key
+/// exchange offer, its host key, then each authentication payload in turn,
+/// and reports how far it got.
+///
+/// The host is always dialed, even if the session was built
+/// [`with_stream`], and nothing is run after authenticating: neither the
+/// initialization commands nor the hooks.
+///
+/// [`with_stream`]: crate::SessionBuilder::with_stream
+pub async fn diagnose(session: Session) -> Diagnosis {
+    let mut diagnosis = Diagnosis::default();
+    let Some(resolved) =
+        diagnosis.record(Check::Config, session.resolved(), ResolvedConfig::summary)
+    else {
+        return diagnosis;
+    };
+
+    let Some(addr) = diagnosis.record(Check::Dns, session.lookup(&resolved).await, |addr| {
+        format!("{} resolved to {}", resolved.host, addr.ip())
+    }) else {
+        return diagnosis;
+    };
+
+    let start = Instant::now();
+    let Some(transport) = diagnosis.record(Check::Tcp, session.dial(&resolved, addr).await, |_| {
+        format!("connected to {addr} in {} ms", start.elapsed().as_millis())
+    }) else {
+        return diagnosis;
+    };
+
+    let offer = match transport.into_stream() {
+        Some(stream) => tokio::time::timeout(resolved.connect_timeout, read_offer(stream))
+            .await
+            .unwrap_or(Err(Error::ConnectTimeout)),
+        None => Err(Error::NoDriver),
+    };
+    let Some(report) = diagnosis.record(Check::Banner, offer, |report| report.ident.clone()) else {
+        return diagnosis;
+    };
+    if Banner::parse(&report.ident).is_none() {
+        let error = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "malformed identification string",
+        );
+        diagnosis.fail(Check::Banner, error.to_string(), error.into());
+        return diagnosis;
+    }
+
+    if resolved.require_strict_kex && !report.offer.supports_strict_kex() {
+        let error = Error::StrictKexUnsupported;
+        diagnosis.fail(Check::Kex, error.to_string(), error);
+        return diagnosis;
+    }
+    diagnosis.pass(
+        Check::Kex,
+        format!(
+            "server offers key exchange {}; host keys {}",
+            report.offer.kex.join(","),
+            report.offer.host_key.join(",")
+        ),
+    );
+
+    #[cfg(feature = "russh")]
+    authenticate(&session, &resolved, &mut diagnosis).await;
+    #[cfg(not(feature = "russh"))]
+    skip_drivers(&session, &mut diagnosis);
+
+    diagnosis
+}
+
+/// Runs the [`Check::HostKey`] and [`Check::Auth`] steps with the first
+/// available driver.
+#[cfg(feature = "russh")]
+async fn authenticate(session: &Session, resolved: &ResolvedConfig, diagnosis: &mut Diagnosis) {
+    use std::time::SystemTime;
+
+    use ssh_key::HashAlg;
+
+    use crate::DriverKind;
+    use crate::HostKeyVerification;
+    use crate::driver::Driver as _;
+    use crate::driver::Session as _;
+    use crate::event::Events;
+
+    if !session.drivers.contains(&DriverKind::Russh) {
+        skip_drivers(session, diagnosis);
+        return;
+    }
+
+    let connected = match session.open(resolved).await {
+        Ok(transport) => match session.russh_driver(transport, resolved, Events::default()) {
+            Ok(driver) => driver.connect().await,
+            Err(error) => Err(error),
+        },
+        Err(error) => Err(error),
+    };
+    let verified = match session.host_key_verification {
+        HostKeyVerification::AcceptAny => "not verified, every host key is accepted",
+        _ => "verified",
+    };
+    let Some(mut connected) = diagnosis.record(Check::HostKey, connected, |connected| {
+        match connected.host_key() {
+            Some(key) => format!("{} ({verified})", key.fingerprint(HashAlg::Sha256)),
+            None => verified.to_string(),
+        }
+    }) else {
+        return;
+    };
+
+    let payloads = connected.payloads().to_vec();
+    let now = SystemTime::now();
+    for payload in &payloads {
+        let kind = payload.kind();
+        if let Some(reason) = payload.inapplicable_reason(&resolved.user, now) {
+            diagnosis.fail(
+                Check::Auth,
+                format!("{kind} skipped: {reason}"),
+                Error::AuthenticationFailed,
+            );
+            continue;
+        }
+        match connected.try_payload(payload).await {
+            Ok(Some(outcome)) => {
+                diagnosis.pass(Check::Auth, format!("{outcome} accepted"));
+                break;
+            }
+            Ok(None) => diagnosis.fail(
+                Check::Auth,
+                format!("{kind} rejected"),
+                Error::AuthenticationFailed,
+            ),
+            Err(error) => diagnosis.fail(Check::Auth, format!("{kind}: {error}"), error),
+        }
+    }
+    if diagnosis
+        .steps
+        .last()
+        .is_some_and(|step| step.check != Check::Auth)
+    {
+        diagnosis.fail(
+            Check::Auth,
+            format!("no payload applies to {}", resolved.user),
+            Error::AuthenticationFailed,
+        );
+    }
+
+    if let Err(error) = connected.disconnect().await {
+        tracing::debug!(%error, "could not disconnect diagnosis");
+    }
+}
+
+/// Fails the [`Check::HostKey`] step since no driver in the chain can be
+/// diagnosed.
+fn skip_drivers(session: &Session, diagnosis: &mut Diagnosis) {
+    let error = match session.drivers.first() {
+        Some(&driver) => Error::DriverUnavailable(driver),
+        None => Error::NoDriver,
+    };
+    diagnosis.fail(Check::HostKey, error.to_string(), error);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::DriverKind;
+    use crate::kex::tests::kex_init_payload;
+    use crate::kex::tests::serve_offer;
+
+    #[tokio::test]
+    async fn diagnose_stops_at_first_failure() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let diagnosis = diagnose(
+            Session::builder()
+                .user("deploy")
+                .host("127.0.0.1")
+                .port(port)
+                .driver(DriverKind::Mock)
+                .build(),
+        )
+        .await;
+
+        let checks: Vec<_> = diagnosis.steps.iter().map(|step| step.check).collect();
+        assert_eq!(checks, [Check::Config, Check::Dns, Check::Tcp]);
+        assert!(!diagnosis.passed());
+        assert_eq!(diagnosis.failure().unwrap().check, Check::Tcp);
+    }
+
+    #[tokio::test]
+    async fn diagnose_reads_offer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let payload = kex_init_payload(&["curve25519-sha256"]);
+            serve_offer(stream, b"SSH-2.0-OpenSSH_9.6\r\n", &payload).await;
+        });
+
+        let diagnosis = diagnose(
+            Session::builder()
+                .user("deploy")
+                .host("127.0.0.1")
+                .port(port)
+                .driver(DriverKind::Mock)
+                .build(),
+        )
+        .await;
+
+        let lines: Vec<_> = diagnosis.to_string().lines().map(str::to_string).collect();
+        assert_eq!(lines[3], "[ ok ] banner: SSH-2.0-OpenSSH_9.6");
+        assert_eq!(
+            lines[4],
+            "[ ok ] kex: server offers key exchange curve25519-sha256; host keys ssh-ed25519"
+        );
+        assert_eq!(lines[5], "[FAIL] host key: Driver is not available: Mock");
+    }
+}
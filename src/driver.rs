@@ -1,17 +1,25 @@
+use std::fmt;
+
+use tokio::sync::mpsc;
+
+use crate::AuthOutcome;
 use crate::Result;
-use crate::process::Command;
+use crate::process::Child;
+use crate::process::Pty;
+use crate::transport::AsyncStream;
 
 #[cfg(feature = "libssh2")]
 mod libssh2;
 #[cfg(test)]
 mod mock;
 #[cfg(feature = "openssh")]
-mod openssh;
+pub mod openssh;
 #[cfg(feature = "russh")]
-mod russh;
+pub mod russh;
 
 /// Underlying SSH implementation to use.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum DriverKind {
     /// Dummy driver used for testing.
     #[cfg(test)]
@@ -27,6 +35,48 @@ pub enum DriverKind {
     Russh,
 }
 
+impl fmt::Display for DriverKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            #[cfg(test)]
+            DriverKind::Mock => "mock",
+            #[cfg(feature = "libssh2")]
+            DriverKind::Libssh2 => "libssh2",
+            #[cfg(feature = "openssh")]
+            DriverKind::OpenSsh => "openssh",
+            #[cfg(feature = "russh")]
+            DriverKind::Russh => "russh",
+        })
+    }
+}
+
+impl DriverKind {
+    /// Whether this build can connect with the driver.
+    pub(crate) fn is_available(self) -> bool {
+        #[cfg(feature = "openssh")]
+        if self == DriverKind::OpenSsh {
+            return true;
+        }
+        #[cfg(feature = "russh")]
+        if self == DriverKind::Russh {
+            return true;
+        }
+
+        false
+    }
+
+    /// Whether the driver opens its own connection to the host, rather than
+    /// running over a transport.
+    pub(crate) fn dials_itself(self) -> bool {
+        #[cfg(feature = "openssh")]
+        if self == DriverKind::OpenSsh {
+            return true;
+        }
+
+        false
+    }
+}
+
 pub trait Driver {
     type Session: Session;
 
@@ -34,7 +84,119 @@ pub trait Driver {
 }
 
 pub trait Session {
-    async fn authenticate(&mut self) -> Result<()>;
+    async fn authenticate(&mut self) -> Result<AuthOutcome>;
+
+    /// Runs `command` through the remote user's shell, on a pseudo-terminal
+    /// if `pty` is set. `program` names it in events, such as
+    /// [`Event::SlowConsumer`](crate::Event::SlowConsumer).
+    async fn exec(&self, program: &str, command: &str, pty: Option<&Pty>) -> Result<Child>;
+
+    /// Starts the `sftp` subsystem, returning a stream to speak SFTP over.
+    async fn open_sftp(&self) -> Result<Box<dyn AsyncStream>>;
+
+    /// Asks the server to connect to `host` and `port`, returning a stream to
+    /// the connection.
+    async fn open_tunnel(&self, host: &str, port: u16) -> Result<Box<dyn AsyncStream>>;
+
+    /// Asks the server to listen on `address` and `port`, sending the
+    /// connections it accepts there to `connections`. Returns the port
+    /// listened on, which the server picks if `port` is 0.
+    async fn forward_remote(
+        &self,
+        address: &str,
+        port: u16,
+        connections: mpsc::UnboundedSender<Box<dyn AsyncStream>>,
+    ) -> Result<u16>;
+
+    /// Asks the server to stop listening on `address` and `port`.
+    async fn cancel_forward_remote(&self, address: &str, port: u16) -> Result<()>;
+
+    /// Number of key re-exchanges completed since the initial one.
+    fn rekey_count(&self) -> usize;
+
+    /// Tells the server the session is ending and closes it, along with
+    /// every channel open on it.
+    async fn disconnect(&self) -> Result<()>;
+}
+
+/// Session of any of the enabled drivers.
+pub enum Connected {
+    #[cfg(feature = "openssh")]
+    OpenSsh(openssh::OpenSshSession),
+    #[cfg(feature = "russh")]
+    Russh(russh::RusshSession),
+}
+
+impl Connected {
+    pub async fn exec(&self, program: &str, command: &str, pty: Option<&Pty>) -> Result<Child> {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => session.exec(program, command, pty).await,
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.exec(program, command, pty).await,
+        }
+    }
+
+    pub async fn open_sftp(&self) -> Result<Box<dyn AsyncStream>> {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => session.open_sftp().await,
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.open_sftp().await,
+        }
+    }
+
+    pub async fn open_tunnel(&self, host: &str, port: u16) -> Result<Box<dyn AsyncStream>> {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => session.open_tunnel(host, port).await,
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.open_tunnel(host, port).await,
+        }
+    }
+
+    pub async fn forward_remote(
+        &self,
+        address: &str,
+        port: u16,
+        connections: mpsc::UnboundedSender<Box<dyn AsyncStream>>,
+    ) -> Result<u16> {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => {
+                session.forward_remote(address, port, connections).await
+            }
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => {
+                session.forward_remote(address, port, connections).await
+            }
+        }
+    }
+
+    pub async fn cancel_forward_remote(&self, address: &str, port: u16) -> Result<()> {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => session.cancel_forward_remote(address, port).await,
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.cancel_forward_remote(address, port).await,
+        }
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => session.disconnect().await,
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.disconnect().await,
+        }
+    }
 
-    fn command(&self) -> Command;
+    pub fn kind(&self) -> DriverKind {
+        match *self {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(_) => DriverKind::OpenSsh,
+            #[cfg(feature = "russh")]
+            Connected::Russh(_) => DriverKind::Russh,
+        }
+    }
 }
@@ -1 +1,857 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
+use bon::Builder;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use ssh_key::Fingerprint;
+use ssh_key::HashAlg;
+use ssh_key::LineEnding;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+
+use crate::Auth;
+use crate::AuthOutcome;
+use crate::Error;
+use crate::Result;
+use crate::driver::Driver;
+use crate::driver::Session;
+use crate::process::Child;
+use crate::process::ExitStatus;
+use crate::process::Flow;
+use crate::process::Pty;
+use crate::transport::AsyncStream;
+use crate::transport::stdio_stream;
+
+/// Time between checks that the master connection is ready.
+const MASTER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Sessions started by this process, to name their control directories.
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Driver that runs the `ssh` binary: a master process authenticates once,
+/// and every command, SFTP session and tunnel is multiplexed over its
+/// connection through a control socket.
+///
+/// `ssh` dials the host itself, reads the user's `ssh_config` and checks the
+/// host key itself, against the session's known hosts file only. Only key,
+/// certificate and agent payloads can be given to it; it runs in batch mode, so
+/// it never prompts for passwords or one-time codes.
+#[derive(Builder)]
+pub struct OpenSshDriver {
+    #[builder(field)]
+    auth: Vec<Auth>,
+
+    #[builder(into)]
+    user: String,
+    #[builder(into)]
+    host: String,
+    #[builder(default = 22)]
+    port: u16,
+    /// Give up on connecting after this long, rounded up to whole seconds.
+    connect_timeout: Option<Duration>,
+    /// `known_hosts` file the host key must be recorded in. Every key is
+    /// accepted if not set.
+    #[builder(into)]
+    known_hosts: Option<Utf8PathBuf>,
+    /// Records unknown hosts in `known_hosts` and accepts them, like
+    /// `StrictHostKeyChecking=accept-new`.
+    #[builder(default)]
+    accept_new: bool,
+    /// Records hosts hashed, like `HashKnownHosts=yes`.
+    #[builder(default)]
+    hash_known_hosts: bool,
+    /// Command to reach the host through, given to `ssh` as its
+    /// `ProxyCommand`, which expands its tokens.
+    #[builder(into)]
+    proxy_command: Option<String>,
+    /// `ssh` binary to run, looked up in `PATH` unless it is a path.
+    #[builder(into, default = "ssh")]
+    program: String,
+}
+
+impl<S: open_ssh_driver_builder::State> OpenSshDriverBuilder<S> {
+    pub fn auth(mut self, value: Auth) -> Self {
+        self.auth.push(value);
+        self
+    }
+}
+
+impl Driver for OpenSshDriver {
+    type Session = OpenSshSession;
+
+    async fn connect(self) -> Result<Self::Session> {
+        let dir = control_dir()?;
+        let options = match options(&self, &dir) {
+            Ok(options) => options,
+            Err(error) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(error);
+            }
+        };
+
+        Ok(OpenSshSession {
+            program: self.program,
+            options,
+            host: self.host,
+            control_path: dir.join("control"),
+            dir,
+            auth: self.auth,
+            master: Mutex::new(None),
+            forwards: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+/// Creates a directory only the current user can enter, to hold the control
+/// socket and the key files given to `ssh`.
+fn control_dir() -> Result<Utf8PathBuf> {
+    let dir = std::env::temp_dir().join(format!(
+        "ssh-util-{}-{}",
+        std::process::id(),
+        SESSIONS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let dir = Utf8PathBuf::try_from(dir)
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    Ok(dir)
+}
+
+/// Command-line options of every `ssh` run by a session, writing the keys of
+/// the payloads to `dir` for `-i` to read.
+fn options(driver: &OpenSshDriver, dir: &Utf8Path) -> Result<Vec<String>> {
+    let mut options = vec![
+        "-o".to_string(),
+        "BatchMode=yes".to_string(),
+        "-o".to_string(),
+        "PreferredAuthentications=publickey".to_string(),
+        "-p".to_string(),
+        driver.port.to_string(),
+        "-l".to_string(),
+        driver.user.clone(),
+    ];
+    if let Some(timeout) = driver.connect_timeout {
+        let seconds = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+        options.extend(["-o".to_string(), format!("ConnectTimeout={seconds}")]);
+    }
+    // The system-wide file is left out, as other drivers do not read it.
+    let (known_hosts, strict) = match &driver.known_hosts {
+        Some(path) if driver.accept_new => (format!("\"{path}\""), "accept-new"),
+        Some(path) => (format!("\"{path}\""), "yes"),
+        None => ("/dev/null".to_string(), "no"),
+    };
+    options.extend([
+        "-o".to_string(),
+        format!("UserKnownHostsFile={known_hosts}"),
+        "-o".to_string(),
+        "GlobalKnownHostsFile=/dev/null".to_string(),
+        "-o".to_string(),
+        format!("StrictHostKeyChecking={strict}"),
+    ]);
+    if driver.hash_known_hosts {
+        options.extend(["-o".to_string(), "HashKnownHosts=yes".to_string()]);
+    }
+    if let Some(command) = &driver.proxy_command {
+        options.extend(["-o".to_string(), format!("ProxyCommand={command}")]);
+    }
+
+    let mut agent = None;
+    for (i, payload) in driver.auth.iter().enumerate() {
+        match payload {
+            Auth::Key { private_key } => {
+                let path = dir.join(format!("id_{i}"));
+                private_key.write_openssh_file(path.as_std_path(), LineEnding::LF)?;
+                options.extend(["-i".to_string(), path.into_string()]);
+            }
+            Auth::Cert {
+                certificate,
+                private_key,
+            } => {
+                // Found by `ssh` next to the key, like `ssh-keygen -s` leaves
+                // it.
+                let path = dir.join(format!("id_{i}"));
+                private_key.write_openssh_file(path.as_std_path(), LineEnding::LF)?;
+                std::fs::write(format!("{path}-cert.pub"), certificate.to_openssh()?)?;
+                options.extend(["-i".to_string(), path.into_string()]);
+            }
+            // `ssh` talks to a single agent.
+            Auth::Agent { path } if agent.is_none() => agent = Some(path),
+            Auth::Agent { path } => {
+                tracing::warn!(%path, "ssh uses only the first agent, skipping");
+            }
+            Auth::Password(_) | Auth::KeyboardInteractive { .. } => {
+                tracing::warn!(kind = ?payload.kind(), "ssh cannot be given this payload, skipping");
+            }
+        }
+    }
+
+    // Without an agent, keys from the environment's agent would be offered
+    // as well.
+    match agent {
+        Some(path) => options.extend(["-o".to_string(), format!("IdentityAgent={path}")]),
+        None => options.extend([
+            "-o".to_string(),
+            "IdentityAgent=none".to_string(),
+            "-o".to_string(),
+            "IdentitiesOnly=yes".to_string(),
+        ]),
+    }
+
+    Ok(options)
+}
+
+pub struct OpenSshSession {
+    program: String,
+    /// Options common to every `ssh` run, before the control socket's.
+    options: Vec<String>,
+    host: String,
+    control_path: Utf8PathBuf,
+    /// Directory holding the control socket and key files, removed with the
+    /// session.
+    dir: Utf8PathBuf,
+    auth: Vec<Auth>,
+    /// Process holding the connection, killed with the session.
+    master: Mutex<Option<tokio::process::Child>>,
+    /// Remotely forwarded ports, by port, with the `-R` specification they
+    /// were forwarded with and the task handing on connections to them.
+    forwards: Mutex<HashMap<u16, (String, AbortHandle)>>,
+}
+
+impl OpenSshSession {
+    /// `ssh` with the session's options, going through the master connection.
+    fn ssh(&self) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.program);
+        command
+            .args(&self.options)
+            .arg("-S")
+            .arg(self.control_path.as_str());
+        command
+    }
+
+    /// `ssh` with `args` before the host and `remote_args` after it, with its
+    /// stdio piped.
+    fn remote(&self, args: &[&str], remote_args: &[&str]) -> tokio::process::Command {
+        let mut command = self.ssh();
+        command
+            .args(args)
+            .arg("--")
+            .arg(&self.host)
+            .args(remote_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+
+    /// Path of the control socket of the master connection, for running
+    /// `ssh -S` over the same connection.
+    #[cfg(feature = "unstable-raw")]
+    pub fn control_path(&self) -> &Utf8Path {
+        &self.control_path
+    }
+
+    /// Process ID of the master connection, for signalling or watching it.
+    /// `None` once it has exited and been waited for.
+    #[cfg(feature = "unstable-raw")]
+    pub fn master_pid(&self) -> Option<u32> {
+        self.master
+            .lock()
+            .expect("master lock is not poisoned")
+            .as_ref()
+            .and_then(tokio::process::Child::id)
+    }
+}
+
+impl Session for OpenSshSession {
+    async fn authenticate(&mut self) -> Result<AuthOutcome> {
+        let log = self.dir.join("master.log");
+        let mut master = self
+            .ssh()
+            .args([
+                "-M",
+                "-N",
+                "-o",
+                "ControlPersist=no",
+                "-o",
+                "LogLevel=DEBUG1",
+            ])
+            .arg("-E")
+            .arg(log.as_str())
+            .arg("--")
+            .arg(&self.host)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // The control socket is only listened on once authenticated.
+        loop {
+            if master.try_wait()?.is_some() {
+                let log = tokio::fs::read_to_string(&log).await?;
+                return Err(master_error(&log));
+            }
+            let check = self
+                .ssh()
+                .args(["-O", "check", "--", &self.host])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await?;
+            if check.success() {
+                break;
+            }
+            tokio::time::sleep(MASTER_POLL_INTERVAL).await;
+        }
+
+        let log = tokio::fs::read_to_string(&log).await?;
+        *self.master.lock().expect("master lock is not poisoned") = Some(master);
+
+        accepted(&log, &self.auth).ok_or_else(|| {
+            Error::OpenSsh("could not tell which key ssh authenticated with".to_string())
+        })
+    }
+
+    async fn exec(&self, _program: &str, command: &str, pty: Option<&Pty>) -> Result<Child> {
+        // The terminal's size is that of `ssh`'s own, which it has none of.
+        let mut child = match pty {
+            Some(pty) => self
+                .remote(&["-tt"], &[command])
+                .env("TERM", &pty.term)
+                .spawn()?,
+            None => self.remote(&["-T"], &[command]).spawn()?,
+        };
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let (status_sender, status) = oneshot::channel();
+        tokio::spawn(async move {
+            let status = child.wait().await.map(exit_status).map_err(Into::into);
+            let _ = status_sender.send(status);
+        });
+
+        Ok(Child::new(
+            stdin,
+            stdout,
+            stderr,
+            status,
+            Arc::new(Flow::default()),
+        ))
+    }
+
+    async fn open_sftp(&self) -> Result<Box<dyn AsyncStream>> {
+        let child = self
+            .remote(&["-s"], &["sftp"])
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        Ok(stdio_stream(child))
+    }
+
+    async fn open_tunnel(&self, host: &str, port: u16) -> Result<Box<dyn AsyncStream>> {
+        let target = if host.contains(':') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+        let child = self
+            .remote(&["-W", &target], &[])
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        Ok(stdio_stream(child))
+    }
+
+    /// `ssh` forwards connections to a local listener, which hands them on.
+    async fn forward_remote(
+        &self,
+        address: &str,
+        port: u16,
+        connections: mpsc::UnboundedSender<Box<dyn AsyncStream>>,
+    ) -> Result<u16> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let local_port = listener.local_addr()?.port();
+        let output = self
+            .ssh()
+            .args(["-O", "forward", "-R"])
+            .arg(remote_forward_spec(address, port, local_port))
+            .arg("--")
+            .arg(&self.host)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::OpenSsh(format!(
+                "ssh -O forward failed: {}",
+                stderr.trim()
+            )));
+        }
+        // `ssh` prints the port only when the server picked it.
+        let port = match port {
+            0 => String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse()
+                .map_err(|_| Error::OpenSsh("ssh -O forward did not print the port".to_string()))?,
+            port => port,
+        };
+
+        let accept = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if connections.send(Box::new(stream)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.forwards
+            .lock()
+            .expect("forwards lock is not poisoned")
+            .insert(
+                port,
+                (
+                    remote_forward_spec(address, port, local_port),
+                    accept.abort_handle(),
+                ),
+            );
+
+        Ok(port)
+    }
+
+    async fn cancel_forward_remote(&self, _address: &str, port: u16) -> Result<()> {
+        let forward = self
+            .forwards
+            .lock()
+            .expect("forwards lock is not poisoned")
+            .remove(&port);
+        let Some((spec, accept)) = forward else {
+            return Ok(());
+        };
+        accept.abort();
+
+        let status = self
+            .ssh()
+            .args(["-O", "cancel", "-R", &spec, "--", &self.host])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(Error::OpenSsh(format!(
+                "ssh -O cancel failed with {status}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// `ssh` does not report key re-exchanges.
+    fn rekey_count(&self) -> usize {
+        0
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        let status = self
+            .ssh()
+            .args(["-O", "exit", "--", &self.host])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(Error::OpenSsh(format!("ssh -O exit failed with {status}")));
+        }
+
+        let master = self
+            .master
+            .lock()
+            .expect("master lock is not poisoned")
+            .take();
+        if let Some(mut master) = master {
+            master.wait().await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for OpenSshSession {
+    fn drop(&mut self) {
+        let forwards = self
+            .forwards
+            .get_mut()
+            .expect("forwards lock is not poisoned");
+        for (_, accept) in forwards.values() {
+            accept.abort();
+        }
+        if let Err(error) = std::fs::remove_dir_all(&self.dir) {
+            tracing::debug!(dir = %self.dir, %error, "could not remove control directory");
+        }
+    }
+}
+
+/// Argument to `ssh -R` forwarding `address` and `port` on the server to
+/// `local_port` on the loopback address.
+fn remote_forward_spec(address: &str, port: u16, local_port: u16) -> String {
+    let local = format!("{}:{local_port}", Ipv4Addr::LOCALHOST);
+    match address {
+        "" => format!("{port}:{local}"),
+        address if address.contains(':') => format!("[{address}]:{port}:{local}"),
+        address => format!("{address}:{port}:{local}"),
+    }
+}
+
+fn exit_status(status: std::process::ExitStatus) -> ExitStatus {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => ExitStatus::from_code(code.cast_unsigned()),
+        (None, signal) => ExitStatus::from_signal(signal.unwrap_or_default().to_string()),
+    }
+}
+
+/// Payload `ssh` authenticated with, from its debug log, which names the
+/// accepted key as `Server accepts key: <path or comment> <type> <fingerprint>
+/// <origin>`. Keys that are not among `auth` came from the agent.
+fn accepted(log: &str, auth: &[Auth]) -> Option<AuthOutcome> {
+    let accepted = log
+        .lines()
+        .find_map(|line| line.split_once("Server accepts key: "))?
+        .1;
+    let mut fields = accepted.split_whitespace();
+    let ident = fields.next()?;
+    let fingerprint = fields.find_map(|field| Fingerprint::from_str(field).ok())?;
+
+    let outcome = auth.iter().find_map(|payload| match payload {
+        Auth::Key { private_key }
+            if private_key.public_key().fingerprint(HashAlg::Sha256) == fingerprint =>
+        {
+            Some(AuthOutcome::Key { fingerprint })
+        }
+        Auth::Cert { certificate, .. }
+            if certificate.public_key().fingerprint(HashAlg::Sha256) == fingerprint =>
+        {
+            Some(AuthOutcome::cert(certificate))
+        }
+        _ => None,
+    });
+
+    Some(outcome.unwrap_or_else(|| AuthOutcome::Agent {
+        fingerprint,
+        comment: ident.to_string(),
+    }))
+}
+
+/// Error for a master connection that exited, from its log.
+fn master_error(log: &str) -> Error {
+    if log.contains("Permission denied") {
+        return Error::AuthenticationFailed;
+    }
+    if log.contains("Host key verification failed") {
+        let fingerprint = log
+            .lines()
+            .find_map(|line| line.split_once("Server host key: "))
+            .and_then(|(_, key)| {
+                key.split_whitespace()
+                    .find_map(|field| Fingerprint::from_str(field).ok())
+            });
+        if let Some(fingerprint) = fingerprint {
+            return Error::HostKeyRejected(Box::new(fingerprint));
+        }
+    }
+    if log.contains("Connection timed out") {
+        return Error::ConnectTimeout;
+    }
+
+    let message = log
+        .lines()
+        .rfind(|line| !line.starts_with("debug"))
+        .unwrap_or("ssh exited without an error message");
+    Error::OpenSsh(message.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "unstable-raw")]
+    use std::os::unix::fs::PermissionsExt;
+
+    use rstest::rstest;
+    use ssh_key::PrivateKey;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::ErrorCode;
+
+    fn key(name: &str) -> PrivateKey {
+        PrivateKey::read_openssh_file(format!("test/creds/{name}").as_ref()).unwrap()
+    }
+
+    #[test]
+    fn options_work() {
+        let dir = control_dir().unwrap();
+        let driver = OpenSshDriver::builder()
+            .user("deploy")
+            .host("web1")
+            .port(2222)
+            .connect_timeout(Duration::from_millis(1500))
+            .known_hosts("/home/deploy/.ssh/known_hosts")
+            .auth(Auth::Key {
+                private_key: key("id_ed25519"),
+            })
+            .auth(Auth::Password("hunter2".into()))
+            .build();
+
+        let options = options(&driver, &dir).unwrap();
+
+        let key_path = dir.join("id_0");
+        assert_eq!(
+            options,
+            [
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "PreferredAuthentications=publickey",
+                "-p",
+                "2222",
+                "-l",
+                "deploy",
+                "-o",
+                "ConnectTimeout=2",
+                "-o",
+                "UserKnownHostsFile=\"/home/deploy/.ssh/known_hosts\"",
+                "-o",
+                "GlobalKnownHostsFile=/dev/null",
+                "-o",
+                "StrictHostKeyChecking=yes",
+                "-i",
+                key_path.as_str(),
+                "-o",
+                "IdentityAgent=none",
+                "-o",
+                "IdentitiesOnly=yes",
+            ]
+        );
+        assert_eq!(
+            PrivateKey::read_openssh_file(key_path.as_std_path()).unwrap(),
+            key("id_ed25519")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn options_accept_new_hosts() {
+        let dir = control_dir().unwrap();
+        let driver = OpenSshDriver::builder()
+            .user("deploy")
+            .host("web1")
+            .known_hosts("/home/deploy/.ssh/known_hosts")
+            .accept_new(true)
+            .hash_known_hosts(true)
+            .build();
+
+        let options = options(&driver, &dir).unwrap();
+
+        assert!(options.contains(&"StrictHostKeyChecking=accept-new".to_string()));
+        assert!(options.contains(&"HashKnownHosts=yes".to_string()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn options_use_proxy_command() {
+        let dir = control_dir().unwrap();
+        let driver = OpenSshDriver::builder()
+            .user("deploy")
+            .host("web1")
+            .proxy_command("cloudflared access ssh --hostname %h")
+            .build();
+
+        let options = options(&driver, &dir).unwrap();
+
+        assert!(options.contains(&"ProxyCommand=cloudflared access ssh --hostname %h".to_string()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn accepted_works() {
+        let private_key = key("id_ed25519");
+        let fingerprint = private_key.public_key().fingerprint(HashAlg::Sha256);
+        let auth = [Auth::Key { private_key }];
+
+        let explicit = format!(
+            "debug1: Offering public key: /tmp/id_0 ED25519 {fingerprint} explicit\n\
+             debug1: Server accepts key: /tmp/id_0 ED25519 {fingerprint} explicit\n"
+        );
+        let from_agent =
+            format!("debug1: Server accepts key: deploy@laptop ED25519 {fingerprint} agent\n");
+
+        assert_eq!(
+            accepted(&explicit, &auth),
+            Some(AuthOutcome::Key { fingerprint })
+        );
+        assert_eq!(
+            accepted(&from_agent, &[]),
+            Some(AuthOutcome::Agent {
+                fingerprint,
+                comment: "deploy@laptop".to_string(),
+            })
+        );
+        assert_eq!(
+            accepted(
+                "debug1: Authentications that can continue: publickey\n",
+                &auth
+            ),
+            None
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "debug1: Next authentication method: publickey\ndeploy@web1: Permission denied (publickey).\n",
+        ErrorCode::AuthDenied
+    )]
+    #[case(
+        "ssh: connect to host web1 port 22: Connection timed out\n",
+        ErrorCode::ConnectTimeout
+    )]
+    #[case(
+        "ssh: Could not resolve hostname web1: Name or service not known\n",
+        ErrorCode::Protocol
+    )]
+    fn master_error_works(#[case] log: &str, #[case] code_should: ErrorCode) {
+        assert_eq!(master_error(log).code(), code_should);
+    }
+
+    #[test]
+    fn master_error_reports_last_message() {
+        let error = master_error(
+            "debug1: Reading configuration data /etc/ssh/ssh_config\n\
+             ssh: Could not resolve hostname web1: Name or service not known\n",
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "ssh failed: ssh: Could not resolve hostname web1: Name or service not known"
+        );
+    }
+
+    #[rstest]
+    #[case("", 8080, "8080:127.0.0.1:40000")]
+    #[case("0.0.0.0", 0, "0.0.0.0:0:127.0.0.1:40000")]
+    #[case("::1", 8080, "[::1]:8080:127.0.0.1:40000")]
+    fn remote_forward_spec_works(
+        #[case] address: &str,
+        #[case] port: u16,
+        #[case] spec_should: &str,
+    ) {
+        assert_eq!(remote_forward_spec(address, port, 40000), spec_should);
+    }
+
+    #[tokio::test]
+    async fn authenticate_reports_refused_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let mut session = OpenSshDriver::builder()
+            .user("deploy")
+            .host("127.0.0.1")
+            .port(port)
+            .build()
+            .connect()
+            .await
+            .unwrap();
+        let dir = session.dir.clone();
+
+        let error = session.authenticate().await.unwrap_err();
+        drop(session);
+
+        assert!(error.to_string().contains("Connection refused"), "{error}");
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn open_sftp_requests_subsystem() {
+        // `echo` prints the arguments `ssh` would be run with.
+        let session = OpenSshDriver::builder()
+            .user("deploy")
+            .host("web1")
+            .program("echo")
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let mut stream = session.open_sftp().await.unwrap();
+        let mut args = String::new();
+        stream.read_to_string(&mut args).await.unwrap();
+
+        assert!(
+            args.contains(&format!("-S {}", session.control_path)),
+            "{args}"
+        );
+        assert!(args.ends_with(" -s -- web1 sftp\n"), "{args}");
+    }
+
+    #[cfg(feature = "unstable-raw")]
+    #[tokio::test]
+    async fn control_path_works() {
+        let session = OpenSshDriver::builder()
+            .user("deploy")
+            .host("web1")
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        assert_eq!(session.control_path(), session.dir.join("control"));
+    }
+
+    #[cfg(feature = "unstable-raw")]
+    #[tokio::test]
+    async fn master_pid_works() {
+        // Stands in for `ssh`: the master logs that a key was accepted and
+        // stays up, and checks on it succeed.
+        let fingerprint = key("id_ed25519").public_key().fingerprint(HashAlg::Sha256);
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("ssh");
+        std::fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\n\
+                 case \"$*\" in *'-O check'*) exit 0;; esac\n\
+                 while [ \"$1\" != -E ]; do shift; done\n\
+                 echo 'debug1: Server accepts key: agent ED25519 {fingerprint}' > \"$2\"\n\
+                 exec sleep 60\n"
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut session = OpenSshDriver::builder()
+            .user("deploy")
+            .host("web1")
+            .program(program.to_str().unwrap())
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let before = session.master_pid();
+        session.authenticate().await.unwrap();
+        let pid = session.master_pid().unwrap();
+
+        assert_eq!(before, None);
+        assert!(std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
+}
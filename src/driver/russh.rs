@@ -1,94 +1,1261 @@
-use std::net::SocketAddr;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::pin::pin;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use bon::Builder;
+use camino::Utf8Path;
+use futures::FutureExt;
+use russh::ChannelMsg;
 use russh::client::Handle;
+use russh::client::KeyboardInteractiveAuthResponse;
+use russh::keys::PrivateKeyWithHashAlg;
 use secrecy::ExposeSecret;
+use secrecy::SecretString;
+use ssh_key::Certificate;
+use ssh_key::HashAlg;
+use ssh_key::LineEnding;
+use ssh_key::PrivateKey;
+use ssh_key::PublicKey;
+use tokio::io::AsyncWriteExt;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 
+use crate::Algorithms;
 use crate::Auth;
+use crate::AuthOutcome;
+use crate::ChannelKind;
+use crate::Error;
+use crate::OtpProvider;
+use crate::Policy;
 use crate::Result;
+use crate::auth::prioritize;
+use crate::driver::Connected;
 use crate::driver::Driver;
 use crate::driver::Session;
+use crate::event::Event;
+use crate::event::Events;
+use crate::host_key::Verification;
+use crate::kex::KexInit;
+use crate::process::Child;
+use crate::process::ExitStatus;
+use crate::process::Flow;
+use crate::process::Pty;
+use crate::transport::AsyncStream;
 use crate::transport::Transport;
-use crate::transport::TransportFactory;
+use crate::transport::inspect::Handshake;
+use crate::transport::inspect::Inspect;
+
+/// Largest rekey data limit russh accepts without risking nonce reuse.
+const MAX_REKEY_BYTES: usize = 1 << 30;
+/// Output buffered per stream of a command before the channel is stalled.
+const OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+/// Extended data type code of stderr.
+const EXTENDED_DATA_STDERR: u32 = 1;
 
 #[derive(Builder)]
-pub struct RusshDriver<T: TransportFactory> {
+pub struct RusshDriver {
     #[builder(field)]
     auth: Vec<Auth>,
 
     #[builder(into)]
     user: String,
-    addr: SocketAddr,
-    transport_factory: T,
+    /// Connected transport to run the session over.
+    transport: Transport,
+    /// Renegotiate keys after this many bytes have been sent or received.
+    rekey_bytes: Option<usize>,
+    /// Renegotiate keys after this much time has elapsed.
+    rekey_interval: Option<Duration>,
+    /// Bytes the server may send on a channel before waiting for more room.
+    window_size: Option<u32>,
+    /// Largest data packet the server may send on a channel.
+    max_packet_size: Option<u32>,
+    /// Fail unless the server supports strict key exchange.
+    #[builder(default)]
+    require_strict_kex: bool,
+    /// Algorithms allowed during key exchange.
+    policy: Option<Policy>,
+    /// Where to report session lifecycle events.
+    #[builder(default)]
+    events: Events,
+    /// Report commands whose output has not been read for this long.
+    slow_consumer_after: Option<Duration>,
+    /// Verifier of the host key presented during the initial key exchange.
+    /// Every key is accepted without one.
+    verification: Option<Verification>,
+    /// Most payloads offered, after asking the server which methods it
+    /// allows.
+    max_auth_attempts: Option<u32>,
+}
+
+impl<S: russh_driver_builder::State> RusshDriverBuilder<S> {
+    pub fn auth(mut self, value: Auth) -> Self {
+        self.auth.push(value);
+        self
+    }
 }
 
-impl<T: TransportFactory> Driver for RusshDriver<T> {
+impl RusshDriver {
+    fn config(&self) -> Result<russh::client::Config> {
+        let mut config = russh::client::Config::default();
+
+        if let Some(policy) = &self.policy {
+            config.preferred = preferred(&policy.algorithms())?;
+        }
+
+        // Pseudo-algorithms that signal extension support rather than select
+        // an algorithm, so policies never list them.
+        for extension in [
+            russh::kex::EXTENSION_SUPPORT_AS_CLIENT,
+            russh::kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+        ] {
+            if !config.preferred.kex.contains(&extension) {
+                config.preferred.kex.to_mut().push(extension);
+            }
+        }
+
+        if let Some(bytes) = self.rekey_bytes {
+            let bytes = bytes.min(MAX_REKEY_BYTES);
+            config.limits.rekey_read_limit = bytes;
+            config.limits.rekey_write_limit = bytes;
+        }
+        if let Some(interval) = self.rekey_interval {
+            config.limits.rekey_time_limit = interval;
+        }
+        if let Some(size) = self.window_size {
+            config.window_size = size;
+        }
+        if let Some(size) = self.max_packet_size {
+            config.maximum_packet_size = size;
+        }
+
+        Ok(config)
+    }
+}
+
+impl Driver for RusshDriver {
     type Session = RusshSession;
 
     async fn connect(self) -> Result<Self::Session> {
-        let transport = self.transport_factory.connect(self.addr).await.unwrap();
+        let config = Arc::new(self.config()?);
 
-        let config = Arc::new(russh::client::Config::default());
+        let state = Arc::new(HandlerState::default());
+        let handler = ClientHandler {
+            state: Arc::clone(&state),
+            events: self.events.clone(),
+            verification: self.verification.clone(),
+        };
 
-        let handle = match transport {
+        let handshake = Arc::new(Handshake::default());
+        let connected = match self.transport {
             Transport::None => panic!(),
             Transport::TokioTcp(tcp_stream) => {
-                russh::client::connect_stream(config, tcp_stream, ClientHandler)
-                    .await
-                    .unwrap()
+                let stream = Inspect::new(tcp_stream, Arc::clone(&handshake));
+                russh::client::connect_stream(config, stream, handler).await
+            }
+            Transport::Stream(stream) => {
+                let stream = Inspect::new(stream, Arc::clone(&handshake));
+                russh::client::connect_stream(config, stream, handler).await
+            }
+            #[cfg(test)]
+            Transport::Memory(stream) => {
+                let stream = Inspect::new(stream, Arc::clone(&handshake));
+                russh::client::connect_stream(config, stream, handler).await
             }
         };
+        let handle = connected.map_err(|error| match handshake.closed_early.get() {
+            Some(&banner_received) => Error::ServerBusy { banner_received },
+            None => error,
+        })?;
+
+        // The handshake is complete but nothing has been authenticated yet, so
+        // dropping the handle here fails closed.
+        let kex_init = handshake.kex_init.get();
+        if self.require_strict_kex && !kex_init.is_some_and(KexInit::supports_strict_kex) {
+            return Err(Error::StrictKexUnsupported);
+        }
 
         Ok(RusshSession {
             handle,
+            state,
             user: self.user,
             auth: self.auth,
+            max_auth_attempts: self.max_auth_attempts,
+            events: self.events,
+            slow_consumer_after: self.slow_consumer_after,
         })
     }
 }
 
 pub struct RusshSession {
     handle: Handle<ClientHandler>,
+    state: Arc<HandlerState>,
     user: String,
     auth: Vec<Auth>,
+    max_auth_attempts: Option<u32>,
+    events: Events,
+    slow_consumer_after: Option<Duration>,
 }
 
-impl Session for RusshSession {
-    async fn authenticate(&mut self) -> Result<()> {
-        for payload in &self.auth {
-            let auth_result = match payload {
-                Auth::Password(password) => self
-                    .handle
-                    .authenticate_password(&self.user, password.expose_secret())
-                    .await
-                    .unwrap(),
-                _ => todo!(),
+impl RusshSession {
+    /// Host key the server presented during the initial key exchange.
+    pub(crate) fn host_key(&self) -> Option<PublicKey> {
+        self.state.host_key.lock().unwrap().clone()
+    }
+
+    /// Payloads this session authenticates with.
+    pub(crate) fn payloads(&self) -> &[Auth] {
+        &self.auth
+    }
+
+    /// Tries to authenticate with `payload` alone, returning what the server
+    /// accepted, or `None` if it rejected the payload.
+    pub(crate) async fn try_payload(&mut self, payload: &Auth) -> Result<Option<AuthOutcome>> {
+        try_payload(&mut self.handle, &self.user, payload).await
+    }
+
+    /// Underlying russh handle.
+    #[cfg(feature = "unstable-raw")]
+    pub fn as_raw(&self) -> &Handle<ClientHandler> {
+        &self.handle
+    }
+}
+
+/// Methods the server allows `user` to authenticate with, learned from its
+/// reply to the `none` method, which sshd does not count as an attempt. `None`
+/// if the server let the user in without authenticating.
+async fn allowed_methods(
+    handle: &mut Handle<ClientHandler>,
+    user: &str,
+) -> Result<Option<Vec<&'static str>>> {
+    let russh::client::AuthResult::Failure {
+        remaining_methods, ..
+    } = handle.authenticate_none(user).await?
+    else {
+        return Ok(None);
+    };
+
+    let allowed = [
+        (russh::MethodKind::PublicKey, "publickey"),
+        (russh::MethodKind::Password, "password"),
+        (
+            russh::MethodKind::KeyboardInteractive,
+            "keyboard-interactive",
+        ),
+    ]
+    .into_iter()
+    .filter(|(kind, _)| remaining_methods.contains(kind))
+    .map(|(_, method)| method)
+    .collect();
+
+    Ok(Some(allowed))
+}
+
+/// Error reporting why `payload` was rejected, if it is a certificate.
+fn cert_rejection_error(payload: &Auth, reason: String) -> Option<Error> {
+    let Auth::Cert { certificate, .. } = payload else {
+        return None;
+    };
+
+    Some(Error::CertificateRejected {
+        key_id: certificate.key_id().to_string(),
+        reason,
+    })
+}
+
+/// Tries to authenticate as `user` with `payload`, returning what the server
+/// accepted, or `None` if it rejected the payload.
+async fn try_payload(
+    handle: &mut Handle<ClientHandler>,
+    user: &str,
+    payload: &Auth,
+) -> Result<Option<AuthOutcome>> {
+    let (accepted, outcome) = match payload {
+        Auth::Password(password) => {
+            let auth_result = handle
+                .authenticate_password(user, password.expose_secret())
+                .await?;
+            (auth_result.success(), AuthOutcome::Password)
+        }
+        Auth::Key { private_key } => {
+            let key = to_russh_private_key(private_key)?;
+            // RSA keys are signed with the strongest hash the server
+            // supports, falling back to SHA-1 for servers that do not say.
+            let hash_alg = if key.algorithm().is_rsa() {
+                handle.best_supported_rsa_hash().await?.flatten()
+            } else {
+                None
+            };
+            let auth_result = handle
+                .authenticate_publickey(user, PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+                .await?;
+            let outcome = AuthOutcome::Key {
+                fingerprint: private_key.public_key().fingerprint(HashAlg::Sha256),
+            };
+            (auth_result.success(), outcome)
+        }
+        Auth::Cert {
+            certificate,
+            private_key,
+        } => {
+            let auth_result = handle
+                .authenticate_openssh_cert(
+                    user,
+                    Arc::new(to_russh_private_key(private_key)?),
+                    to_russh_certificate(certificate)?,
+                )
+                .await?;
+            (auth_result.success(), AuthOutcome::cert(certificate))
+        }
+        Auth::Agent { path } => return authenticate_agent(handle, user, path).await,
+        Auth::KeyboardInteractive { password, otp } => {
+            let accepted = keyboard_interactive(handle, user, password.as_ref(), &**otp).await?;
+            (accepted, AuthOutcome::KeyboardInteractive)
+        }
+    };
+
+    Ok(accepted.then_some(outcome))
+}
+
+/// Tries each key held by the agent listening on `socket` in turn, having
+/// the agent sign for it, until the server accepts one.
+async fn authenticate_agent(
+    handle: &mut Handle<ClientHandler>,
+    user: &str,
+    socket: &Utf8Path,
+) -> Result<Option<AuthOutcome>> {
+    let keys = crate::agent::request_identities(socket).await?;
+    if keys.is_empty() {
+        tracing::debug!(%socket, "agent holds no keys");
+        return Ok(None);
+    }
+
+    let mut agent = russh::keys::agent::client::AgentClient::connect_uds(socket)
+        .await
+        .map_err(russh::Error::from)?;
+    for key in keys {
+        let russh_key = russh::keys::PublicKey::from_openssh(&key.to_openssh()?)
+            .map_err(russh::keys::Error::from)
+            .map_err(russh::Error::from)?;
+        let hash_alg = if russh_key.algorithm().is_rsa() {
+            handle.best_supported_rsa_hash().await?.flatten()
+        } else {
+            None
+        };
+        let auth_result = handle
+            .authenticate_publickey_with(user, russh_key, hash_alg, &mut agent)
+            .await
+            .map_err(|error| Error::Io(std::io::Error::other(error)))?;
+        if auth_result.success() {
+            return Ok(Some(AuthOutcome::Agent {
+                fingerprint: key.fingerprint(HashAlg::Sha256),
+                comment: key.comment().to_string(),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Runs keyboard-interactive authentication as `user`, answering prompts
+/// that ask for a password with `password` and the others with `otp`, until
+/// the server decides or a prompt goes unanswered.
+async fn keyboard_interactive(
+    handle: &mut Handle<ClientHandler>,
+    user: &str,
+    password: Option<&SecretString>,
+    otp: &dyn OtpProvider,
+) -> Result<bool> {
+    let mut response = handle
+        .authenticate_keyboard_interactive_start(user, None::<String>)
+        .await?;
+    loop {
+        let prompts = match response {
+            KeyboardInteractiveAuthResponse::Success => return Ok(true),
+            KeyboardInteractiveAuthResponse::Failure { .. } => return Ok(false),
+            KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } => prompts,
+        };
+
+        let mut answers = Vec::with_capacity(prompts.len());
+        for prompt in prompts {
+            let answer = match password {
+                Some(password) if prompt.prompt.to_lowercase().contains("password") => {
+                    Some(password.clone())
+                }
+                _ => otp.answer(&prompt.prompt).await?,
+            };
+            let Some(answer) = answer else {
+                tracing::debug!(prompt = prompt.prompt, "no answer to prompt");
+                return Ok(false);
             };
+            answers.push(answer.expose_secret().to_string());
+        }
 
-            if auth_result.success() {
-                return Ok(());
+        response = handle
+            .authenticate_keyboard_interactive_respond(answers)
+            .await?;
+    }
+}
+
+impl Session for RusshSession {
+    async fn authenticate(&mut self) -> Result<AuthOutcome> {
+        let now = SystemTime::now();
+
+        // Certificates are the payloads most often rejected for reasons that
+        // can be told, so the last such reason is reported if all fail.
+        let mut cert_rejection = None;
+        let mut payloads = Vec::with_capacity(self.auth.len());
+        for (index, payload) in self.auth.iter().enumerate() {
+            if let Some(reason) = payload.inapplicable_reason(&self.user, now) {
+                tracing::debug!(index, reason, "skipping payload not applicable to user");
+                cert_rejection = cert_rejection_error(payload, reason);
+                continue;
+            }
+            payloads.push(payload);
+        }
+
+        let mut attempts = usize::MAX;
+        if let Some(max_auth_attempts) = self.max_auth_attempts {
+            attempts = usize::try_from(max_auth_attempts).unwrap_or(usize::MAX);
+            if let Some(allowed) = allowed_methods(&mut self.handle, &self.user).await? {
+                tracing::debug!(?allowed, "server allows authentication methods");
+                payloads = prioritize(payloads, &allowed);
             }
         }
 
-        todo!()
+        for payload in payloads.into_iter().take(attempts) {
+            if let Some(outcome) = try_payload(&mut self.handle, &self.user, payload).await? {
+                tracing::info!(%outcome, "authenticated");
+                return Ok(outcome);
+            }
+            // The protocol gives no reason, but the certificate was checked to
+            // be valid for the user, which leaves the server's trust in it.
+            cert_rejection = cert_rejection_error(
+                payload,
+                "rejected by the server, which may not trust its certificate authority or \
+                 have revoked it"
+                    .to_string(),
+            )
+            .or(cert_rejection);
+        }
+
+        Err(cert_rejection.unwrap_or(Error::AuthenticationFailed))
+    }
+
+    async fn exec(&self, program: &str, command: &str, pty: Option<&Pty>) -> Result<Child> {
+        let channel = self.handle.channel_open_session().await?;
+        // Without a reply, a server that refuses the terminal still runs the
+        // command, which is more useful than failing it.
+        if let Some(pty) = pty {
+            channel
+                .request_pty(false, &pty.term, pty.cols, pty.rows, 0, 0, &[])
+                .await?;
+        }
+        channel.exec(true, command).await?;
+        self.events.emit(Event::ChannelOpened {
+            kind: ChannelKind::Exec,
+        });
+
+        let stdin = channel.make_writer();
+        let (stdout, stdout_writer) = tokio::io::duplex(OUTPUT_BUFFER_SIZE);
+        let (stderr, stderr_writer) = tokio::io::duplex(OUTPUT_BUFFER_SIZE);
+        let (status_sender, status) = oneshot::channel();
+        let watch = OutputWatch {
+            flow: Arc::new(Flow::default()),
+            events: self.events.clone(),
+            program: program.to_string(),
+            command: command.to_string(),
+            slow_consumer_after: self.slow_consumer_after,
+        };
+        let flow = Arc::clone(&watch.flow);
+        tokio::spawn(async move {
+            let status = forward_output(channel, stdout_writer, stderr_writer, watch).await;
+            let _ = status_sender.send(status);
+        });
+
+        Ok(Child::new(stdin, stdout, stderr, status, flow))
+    }
+
+    async fn open_sftp(&self) -> Result<Box<dyn AsyncStream>> {
+        let channel = self.handle.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        self.events.emit(Event::ChannelOpened {
+            kind: ChannelKind::Sftp,
+        });
+
+        Ok(Box::new(channel.into_stream()))
+    }
+
+    async fn open_tunnel(&self, host: &str, port: u16) -> Result<Box<dyn AsyncStream>> {
+        // The originator is informational only; OpenSSH sends the local end
+        // of the forwarded connection, which a tunnel does not have.
+        let channel = self
+            .handle
+            .channel_open_direct_tcpip(host, u32::from(port), "127.0.0.1", 0)
+            .await?;
+        self.events.emit(Event::ChannelOpened {
+            kind: ChannelKind::Forward,
+        });
+
+        Ok(Box::new(channel.into_stream()))
+    }
+
+    async fn forward_remote(
+        &self,
+        address: &str,
+        port: u16,
+        connections: mpsc::UnboundedSender<Box<dyn AsyncStream>>,
+    ) -> Result<u16> {
+        // Servers only reply with the port when they picked it.
+        let port = match self.handle.tcpip_forward(address, u32::from(port)).await? {
+            0 => port,
+            picked => u16::try_from(picked).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("server picked invalid port {picked} to forward"),
+                )
+            })?,
+        };
+        self.state
+            .forwards
+            .lock()
+            .unwrap()
+            .insert(port, connections);
+
+        Ok(port)
+    }
+
+    async fn cancel_forward_remote(&self, address: &str, port: u16) -> Result<()> {
+        self.state.forwards.lock().unwrap().remove(&port);
+        self.handle
+            .cancel_tcpip_forward(address, u32::from(port))
+            .await?;
+
+        Ok(())
+    }
+
+    fn rekey_count(&self) -> usize {
+        self.state
+            .key_exchanges
+            .load(Ordering::Relaxed)
+            .saturating_sub(1)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.handle
+            .disconnect(russh::Disconnect::ByApplication, "", "en")
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Tracks how a command's output is consumed.
+struct OutputWatch {
+    flow: Arc<Flow>,
+    events: Events,
+    program: String,
+    command: String,
+    slow_consumer_after: Option<Duration>,
+}
+
+impl OutputWatch {
+    /// Writes `data` to one of the command's output streams. While the caller
+    /// does not read it, nothing else is received on the channel, so the
+    /// server runs out of window and the command stalls.
+    async fn deliver(&self, stream: &mut DuplexStream, data: &[u8]) -> std::io::Result<()> {
+        self.flow.add_received(data.len());
+
+        let mut write = pin!(stream.write_all(data));
+        if let Some(result) = write.as_mut().now_or_never() {
+            return result;
+        }
+
+        self.flow.stall();
+        let result = match self.slow_consumer_after {
+            Some(after) => match tokio::time::timeout(after, write.as_mut()).await {
+                Ok(result) => result,
+                Err(_) => {
+                    tracing::warn!(command = %self.command, "command output is not being read");
+                    self.events.emit(Event::SlowConsumer {
+                        program: self.program.clone(),
+                        command: self.command.clone(),
+                        stalled_for: after,
+                    });
+                    write.await
+                }
+            },
+            None => write.await,
+        };
+        self.flow.resume();
+
+        result
     }
+}
+
+/// Copies a command's output from `channel` until it closes, returning the
+/// command's exit status.
+async fn forward_output(
+    mut channel: russh::Channel<russh::client::Msg>,
+    mut stdout: DuplexStream,
+    mut stderr: DuplexStream,
+    watch: OutputWatch,
+) -> Result<ExitStatus> {
+    let mut status = None;
 
-    fn command(&self) -> crate::process::Command {
-        todo!()
+    // Write errors only mean the caller dropped that stream, which must not
+    // stop the exit status from being received.
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => {
+                let _ = watch.deliver(&mut stdout, &data).await;
+            }
+            ChannelMsg::ExtendedData { data, ext } if ext == EXTENDED_DATA_STDERR => {
+                let _ = watch.deliver(&mut stderr, &data).await;
+            }
+            ChannelMsg::Eof => {
+                let _ = stdout.shutdown().await;
+                let _ = stderr.shutdown().await;
+            }
+            ChannelMsg::ExitStatus { exit_status } => {
+                status = Some(ExitStatus::from_code(exit_status));
+            }
+            ChannelMsg::ExitSignal { signal_name, .. } => {
+                let signal = match signal_name {
+                    russh::Sig::Custom(name) => name,
+                    other => format!("{other:?}"),
+                };
+                status = Some(ExitStatus::from_signal(signal));
+            }
+            ChannelMsg::Failure => return Err(Error::ExecRejected),
+            _ => {}
+        }
     }
+
+    status.ok_or(Error::MissingExitStatus)
+}
+
+/// State shared between the connected session and its handler.
+#[derive(Default)]
+struct HandlerState {
+    /// Host key accepted during the initial key exchange.
+    host_key: Mutex<Option<PublicKey>>,
+    /// Number of key exchanges seen, including the initial one.
+    key_exchanges: AtomicUsize,
+    /// Where connections to remotely forwarded ports go, by port. Servers
+    /// report the address they were asked to listen on in their own way, so
+    /// it is not matched.
+    forwards: Mutex<HashMap<u16, mpsc::UnboundedSender<Box<dyn AsyncStream>>>>,
 }
 
-struct ClientHandler;
+/// Handler russh calls back into for the client side of a session.
+pub struct ClientHandler {
+    state: Arc<HandlerState>,
+    events: Events,
+    verification: Option<Verification>,
+}
+
+impl ClientHandler {
+    /// Runs the verifier on `key`, presented on a rekey in place of
+    /// `expected`. Fails with [`Error::HostKeyChanged`] if it is rejected,
+    /// and always if every key was accepted at first, since there is then
+    /// nothing to vouch for the new one.
+    async fn verify_changed(&self, expected: &PublicKey, key: &PublicKey) -> Result<()> {
+        let changed = || Error::HostKeyChanged {
+            expected: Box::new(expected.fingerprint(HashAlg::Sha256)),
+            got: Box::new(key.fingerprint(HashAlg::Sha256)),
+        };
+        let Some(verification) = &self.verification else {
+            return Err(changed());
+        };
+
+        match verification.verify(key).await {
+            Err(Error::HostKeyRejected(_)) => Err(changed()),
+            result => result,
+        }
+    }
+}
 
 impl russh::client::Handler for ClientHandler {
-    type Error = russh::Error;
+    type Error = Error;
 
-    // FIXME: Verify server key
+    // russh calls this for every key exchange, so it doubles as the hook for
+    // rekeys. A server presenting another host key than before must have it
+    // accepted by the verifier again.
     async fn check_server_key(
         &mut self,
-        _server_public_key: &russh::keys::PublicKey,
-    ) -> std::result::Result<bool, Self::Error> {
+        server_public_key: &russh::keys::PublicKey,
+    ) -> Result<bool> {
+        let key = to_public_key(server_public_key)?;
+        let exchanges = self.state.key_exchanges.fetch_add(1, Ordering::Relaxed);
+
+        let accepted = self.state.host_key.lock().unwrap().clone();
+        match accepted {
+            Some(expected) if expected.key_data() != key.key_data() => {
+                self.verify_changed(&expected, &key).await?;
+                tracing::warn!(
+                    expected = %expected.fingerprint(HashAlg::Sha256),
+                    got = %key.fingerprint(HashAlg::Sha256),
+                    "host key changed on rekey and was accepted"
+                );
+                *self.state.host_key.lock().unwrap() = Some(key);
+            }
+            Some(_) => {}
+            None => {
+                // Without a verification, the session was set to accept any
+                // key.
+                if let Some(verification) = &self.verification {
+                    verification.verify(&key).await?;
+                }
+                *self.state.host_key.lock().unwrap() = Some(key);
+                return Ok(true);
+            }
+        }
+
+        tracing::info!(rekeys = exchanges, "key re-exchange completed");
+        self.events.emit(Event::Rekey { count: exchanges });
         Ok(true)
     }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: russh::Channel<russh::client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut russh::client::Session,
+    ) -> Result<()> {
+        let forwards = self.state.forwards.lock().unwrap();
+        let sent = u16::try_from(connected_port)
+            .ok()
+            .and_then(|port| forwards.get(&port))
+            .is_some_and(|connections| connections.send(Box::new(channel.into_stream())).is_ok());
+        // Dropping the channel closes it.
+        if sent {
+            self.events.emit(Event::ChannelOpened {
+                kind: ChannelKind::Forward,
+            });
+        } else {
+            tracing::warn!(
+                connected_address,
+                connected_port,
+                originator_address,
+                originator_port,
+                "refusing connection to port not forwarded"
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn disconnected(
+        &mut self,
+        reason: russh::client::DisconnectReason<Self::Error>,
+    ) -> Result<()> {
+        match reason {
+            russh::client::DisconnectReason::ReceivedDisconnect(_) => {
+                self.events.emit(Event::Disconnected { error: None });
+                Ok(())
+            }
+            russh::client::DisconnectReason::Error(error) => {
+                self.events.emit(Event::Disconnected {
+                    error: Some(error.to_string()),
+                });
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Converts a private key into russh's fork of `ssh-key`.
+pub(crate) fn to_russh_private_key(key: &PrivateKey) -> Result<russh::keys::PrivateKey> {
+    let encoded = key.to_openssh(LineEnding::LF)?;
+    let key = russh::keys::PrivateKey::from_openssh(encoded.as_bytes())
+        .map_err(russh::keys::Error::from)
+        .map_err(russh::Error::from)?;
+
+    Ok(key)
+}
+
+/// Converts a certificate into russh's fork of `ssh-key`.
+fn to_russh_certificate(certificate: &Certificate) -> Result<russh::keys::Certificate> {
+    let encoded = certificate.to_openssh()?;
+    let certificate = russh::keys::Certificate::from_openssh(&encoded)
+        .map_err(russh::keys::Error::from)
+        .map_err(russh::Error::from)?;
+
+    Ok(certificate)
+}
+
+/// Converts a certificate from russh's fork of `ssh-key` into the upstream
+/// type used throughout this crate.
+pub(crate) fn from_russh_certificate(
+    certificate: &russh::keys::Certificate,
+) -> Result<Certificate> {
+    let encoded = certificate
+        .to_openssh()
+        .map_err(russh::keys::Error::from)
+        .map_err(russh::Error::from)?;
+
+    Ok(Certificate::from_openssh(&encoded)?)
+}
+
+/// Maps algorithm names onto the ones russh implements.
+pub(crate) fn preferred(algorithms: &Algorithms) -> Result<russh::Preferred> {
+    Ok(russh::Preferred {
+        kex: supported("kex", &algorithms.kex, |name| {
+            russh::kex::Name::try_from(name).ok()
+        })?,
+        key: supported("host key", &algorithms.host_key, |name| {
+            russh::keys::Algorithm::new(name).ok()
+        })?,
+        cipher: supported("cipher", &algorithms.cipher, |name| {
+            russh::cipher::Name::try_from(name).ok()
+        })?,
+        mac: supported("MAC", &algorithms.mac, |name| {
+            russh::mac::Name::try_from(name).ok()
+        })?,
+        ..russh::Preferred::default()
+    })
+}
+
+fn supported<T: Clone>(
+    kind: &'static str,
+    names: &[String],
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Cow<'static, [T]>> {
+    let supported: Vec<T> = names
+        .iter()
+        .filter_map(|name| {
+            let parsed = parse(name);
+            if parsed.is_none() {
+                tracing::debug!(kind, name, "skipping algorithm not supported by russh");
+            }
+            parsed
+        })
+        .collect();
+
+    if supported.is_empty() {
+        return Err(Error::NoSupportedAlgorithms(kind));
+    }
+
+    Ok(supported.into())
+}
+
+/// Pings the server of `session` every `interval` until the session is gone,
+/// like OpenSSH's `ServerAliveInterval`. Each ping the server does not reply
+/// to within the interval is reported, and the session is disconnected after
+/// `max` in a row.
+pub(crate) async fn keep_alive(
+    session: Weak<Connected>,
+    events: Events,
+    interval: Duration,
+    max: u32,
+) {
+    let mut missed = 0;
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(session) = session.upgrade() else {
+            return;
+        };
+        let ping = match *session {
+            Connected::Russh(ref russh) => russh.handle.send_ping(),
+            #[allow(unreachable_patterns)]
+            _ => return,
+        };
+
+        match tokio::time::timeout(interval, ping).await {
+            Ok(Ok(())) => missed = 0,
+            // The session has ended.
+            Ok(Err(_)) => return,
+            Err(_) => {
+                missed += 1;
+                tracing::warn!(missed, "server did not reply to keepalive");
+                events.emit(Event::KeepaliveMissed { missed });
+                if missed >= max {
+                    if let Err(error) = session.disconnect().await {
+                        tracing::debug!(%error, "could not disconnect unresponsive session");
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Converts a public key from russh's fork of `ssh-key` into the upstream type
+/// used throughout this crate.
+pub(crate) fn to_public_key(key: &russh::keys::PublicKey) -> Result<PublicKey> {
+    let encoded = key
+        .to_openssh()
+        .map_err(russh::keys::Error::from)
+        .map_err(russh::Error::from)?;
+
+    Ok(PublicKey::from_openssh(&encoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use rstest::rstest;
+    use secrecy::SecretString;
+
+    use super::*;
+    use crate::AuthKind;
+    use crate::Decision;
+    use crate::HostKeyVerifier;
+    use crate::test_server;
+
+    #[rstest]
+    #[case(1 << 20, 1 << 20)]
+    #[case(usize::MAX, MAX_REKEY_BYTES)]
+    fn config_applies_rekey_limits(#[case] rekey_bytes: usize, #[case] limit_should: usize) {
+        let driver = RusshDriver::builder()
+            .user("test_user")
+            .transport(Transport::None)
+            .rekey_bytes(rekey_bytes)
+            .rekey_interval(Duration::from_secs(60))
+            .build();
+
+        let config = driver.config().unwrap();
+
+        assert_eq!(config.limits.rekey_read_limit, limit_should);
+        assert_eq!(config.limits.rekey_write_limit, limit_should);
+        assert_eq!(config.limits.rekey_time_limit, Duration::from_secs(60));
+        assert!(
+            config
+                .preferred
+                .kex
+                .contains(&russh::kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT)
+        );
+    }
+
+    #[test]
+    fn config_applies_window_sizes() {
+        let driver = RusshDriver::builder()
+            .user("test_user")
+            .transport(Transport::None)
+            .window_size(16 << 20)
+            .max_packet_size(64 << 10)
+            .build();
+
+        let config = driver.config().unwrap();
+
+        assert_eq!(config.window_size, 16 << 20);
+        assert_eq!(config.maximum_packet_size, 64 << 10);
+    }
+
+    #[test]
+    fn config_applies_policy() {
+        let driver = RusshDriver::builder()
+            .user("test_user")
+            .transport(Transport::None)
+            .policy(Policy::Fips)
+            .build();
+
+        let config = driver.config().unwrap();
+
+        assert_eq!(config.preferred.kex[0], russh::kex::ECDH_SHA2_NISTP256);
+        assert!(!config.preferred.kex.contains(&russh::kex::CURVE25519));
+        assert!(
+            !config
+                .preferred
+                .cipher
+                .contains(&russh::cipher::CHACHA20_POLY1305)
+        );
+        assert!(
+            config
+                .preferred
+                .kex
+                .contains(&russh::kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT)
+        );
+    }
+
+    #[test]
+    fn config_rejects_unsupported_policy() {
+        let algorithms = Algorithms::builder()
+            .kex(["not-a-real-kex"])
+            .host_key(["ssh-ed25519"])
+            .cipher(["aes256-ctr"])
+            .mac(["hmac-sha2-256"])
+            .build();
+        let driver = RusshDriver::builder()
+            .user("test_user")
+            .transport(Transport::None)
+            .policy(Policy::Custom(algorithms))
+            .build();
+
+        assert!(matches!(
+            driver.config(),
+            Err(Error::NoSupportedAlgorithms("kex"))
+        ));
+    }
+
+    fn wrong_password() -> Auth {
+        Auth::Password(SecretString::from("wrong_password"))
+    }
+
+    fn password() -> Auth {
+        Auth::from_password_file("test/creds/password").unwrap()
+    }
+
+    fn cert() -> Auth {
+        Auth::from_cert_file(
+            "test/creds/id_ed25519-cert.pub",
+            "test/creds/id_ed25519",
+            None::<&str>,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(Auth::from_key_file("test/creds/id_ed25519", None::<&str>).unwrap())]
+    #[case(Auth::from_key_file("test/creds/id_ecdsa", None::<&str>).unwrap())]
+    #[case(Auth::from_key_file("test/creds/id_rsa", None::<&str>).unwrap())]
+    #[case(Auth::from_key_file("test/creds/enc_ed25519", Some("test_passphrase")).unwrap())]
+    #[tokio::test]
+    async fn authenticate_accepts_keys(#[case] key: Auth) {
+        let Auth::Key { private_key } = &key else {
+            unreachable!();
+        };
+        let fingerprint = private_key.public_key().fingerprint(HashAlg::Sha256);
+        let mut session = RusshDriver::builder()
+            .user(test_server::USER)
+            .transport(test_server::spawn())
+            .auth(wrong_password())
+            .auth(key)
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let outcome = session.authenticate().await.unwrap();
+
+        assert_eq!(outcome, AuthOutcome::Key { fingerprint });
+    }
+
+    #[tokio::test]
+    async fn authenticate_signs_with_agent() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = Utf8Path::from_path(dir.path()).unwrap().join("agent.sock");
+        let mut agent = tokio::process::Command::new("ssh-agent")
+            .args(["-D", "-a", socket.as_str()])
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        while !socket.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let private_key = PrivateKey::read_openssh_file("test/creds/id_ed25519".as_ref()).unwrap();
+        crate::agent::add_identity(&socket, &private_key, &Default::default())
+            .await
+            .unwrap();
+        let mut session = RusshDriver::builder()
+            .user(test_server::USER)
+            .transport(test_server::spawn())
+            .auth(Auth::Agent {
+                path: socket.clone(),
+            })
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let outcome = session.authenticate().await.unwrap();
+
+        assert!(matches!(
+            outcome,
+            AuthOutcome::Agent { fingerprint, .. }
+                if fingerprint == private_key.public_key().fingerprint(HashAlg::Sha256)
+        ));
+        agent.kill().await.unwrap();
+    }
+
+    #[rstest]
+    #[case(vec![wrong_password(), cert()], Some(AuthKind::Cert))]
+    #[case(vec![wrong_password(), password()], None)]
+    #[tokio::test]
+    async fn authenticate_limits_attempts(
+        #[case] payloads: Vec<Auth>,
+        #[case] kind_should: Option<AuthKind>,
+    ) {
+        let mut builder = RusshDriver::builder()
+            .user(test_server::USER)
+            .transport(test_server::spawn())
+            .max_auth_attempts(1);
+        for payload in payloads {
+            builder = builder.auth(payload);
+        }
+        let mut session = builder.build().connect().await.unwrap();
+
+        let outcome = session.authenticate().await;
+
+        assert_eq!(outcome.ok().map(|outcome| outcome.kind()), kind_should);
+    }
+
+    #[tokio::test]
+    async fn authenticate_tries_payloads_in_order() {
+        let mut session = RusshDriver::builder()
+            .user(test_server::USER)
+            .transport(test_server::spawn())
+            .auth(wrong_password())
+            .auth(cert())
+            .auth(password())
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let outcome = session.authenticate().await.unwrap();
+
+        assert!(matches!(outcome, AuthOutcome::Cert { .. }));
+    }
+
+    #[tokio::test]
+    async fn authenticate_skips_inapplicable_certificates() {
+        let mut session = RusshDriver::builder()
+            .user("other_user")
+            .transport(test_server::spawn())
+            .auth(cert())
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let result = session.authenticate().await;
+
+        assert!(matches!(
+            result,
+            Err(Error::CertificateRejected { reason, .. }) if reason.starts_with("principals")
+        ));
+    }
+
+    #[tokio::test]
+    async fn authenticate_fails_when_all_rejected() {
+        let mut session = RusshDriver::builder()
+            .user(test_server::USER)
+            .transport(test_server::spawn())
+            .auth(wrong_password())
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        let result = session.authenticate().await;
+
+        assert!(matches!(result, Err(Error::AuthenticationFailed)));
+    }
+
+    #[tokio::test]
+    async fn events_report_channels() {
+        let session = test_server::connect().await;
+        let mut events = session.events();
+
+        session.command("true").status().await.unwrap();
+        session.fs().try_exists("~").await.unwrap();
+
+        assert_eq!(
+            events.next().await,
+            Some(Event::ChannelOpened {
+                kind: ChannelKind::Exec
+            })
+        );
+        assert_eq!(
+            events.next().await,
+            Some(Event::ChannelOpened {
+                kind: ChannelKind::Sftp
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn keep_alive_disconnects_unresponsive_server() {
+        let mut session = test_server::session("localhost");
+        session.stream = Some(test_server::spawn().into_stream().unwrap());
+        // Replies take longer than the interval.
+        session.chaos = Some(
+            crate::Chaos::builder()
+                .latency(Duration::from_millis(30))
+                .build(),
+        );
+        session.keepalive_interval = Some(Duration::from_millis(20));
+        session.keepalive_max = Some(2);
+        let session = session.connect().await.unwrap();
+
+        let events: Vec<_> = session.events().take(2).collect().await;
+
+        assert_eq!(
+            events,
+            [
+                Event::KeepaliveMissed { missed: 1 },
+                Event::KeepaliveMissed { missed: 2 }
+            ]
+        );
+        assert!(session.command("true").status().await.is_err());
+    }
+
+    fn server_key(name: &str) -> russh::keys::PublicKey {
+        let encoded = std::fs::read_to_string(format!("test/creds/{name}.pub")).unwrap();
+        russh::keys::PublicKey::from_openssh(&encoded).unwrap()
+    }
+
+    fn handler(verifier: Option<Arc<dyn HostKeyVerifier>>) -> ClientHandler {
+        ClientHandler {
+            state: Arc::default(),
+            events: Events::default(),
+            verification: verifier.map(|verifier| Verification {
+                verifier,
+                host: "web1".to_string(),
+                port: 22,
+                timeout: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_server_key_verifies_changed_key() {
+        let verifier: Arc<dyn HostKeyVerifier> =
+            Arc::new(|_, _, _: PublicKey| async { Ok(Decision::Accept) });
+        let mut handler = handler(Some(verifier));
+
+        handler
+            .check_server_key(&server_key("id_ed25519"))
+            .await
+            .unwrap();
+        let accepted = handler
+            .check_server_key(&server_key("id_rsa"))
+            .await
+            .unwrap();
+
+        assert!(accepted);
+        let host_key = handler.state.host_key.lock().unwrap().clone().unwrap();
+        let rotated = to_public_key(&server_key("id_rsa")).unwrap();
+        assert_eq!(host_key.key_data(), rotated.key_data());
+    }
+
+    #[rstest]
+    #[case::rejected(true)]
+    #[case::accept_any(false)]
+    #[tokio::test]
+    async fn check_server_key_fails_on_changed_key(#[case] verify: bool) {
+        let trusted = to_public_key(&server_key("id_ed25519")).unwrap();
+        let verifier: Arc<dyn HostKeyVerifier> = Arc::new(trusted.fingerprint(HashAlg::Sha256));
+        let mut handler = handler(verify.then_some(verifier));
+
+        handler
+            .check_server_key(&server_key("id_ed25519"))
+            .await
+            .unwrap();
+        let result = handler.check_server_key(&server_key("id_rsa")).await;
+
+        assert!(matches!(result, Err(Error::HostKeyChanged { .. })));
+    }
 }
@@ -1,6 +1,16 @@
+use std::fmt;
+
 use thiserror::Error;
 
+/// Errors of this crate.
+///
+/// Variants may be added in any release, and their messages may change. To
+/// tell failures apart programmatically, match on [`Error::code`] instead.
+/// The data in a message is available on its own from [`Error::args`] and
+/// getters such as [`Error::host`], so that messages can be translated or
+/// logged as structured fields without parsing them.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -14,10 +24,582 @@ pub enum Error {
     #[error("Encrypted private key requires passphrase to be used")]
     EncryptedPrivateKeyNoPasshrase,
 
+    #[error("Invalid PEM private key: {0}")]
+    InvalidPem(String),
+
+    #[error("Invalid PuTTY private key: {0}")]
+    InvalidPpk(&'static str),
+
+    #[error("SSH agent refused the key")]
+    AgentRefusedKey,
+
+    #[error("ssh failed: {0}")]
+    OpenSsh(String),
+
     #[cfg(feature = "russh")]
     #[error("Russh library error: {0}")]
     Russh(#[from] ::russh::Error),
 
     #[error("Connect timed out")]
     ConnectTimeout,
+
+    #[error("Pre-connect hook failed: {0}")]
+    PreConnectFailed(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("No driver configured")]
+    NoDriver,
+
+    #[error("Host {host} has no {option} configured")]
+    MissingHostOption { host: String, option: &'static str },
+
+    #[error("Driver is not available: {0:?}")]
+    DriverUnavailable(crate::DriverKind),
+
+    #[error("All authentication payloads were rejected")]
+    AuthenticationFailed,
+
+    #[error("All authentication payloads were rejected; certificate {key_id}: {reason}")]
+    CertificateRejected { key_id: String, reason: String },
+
+    #[error("Policy allows no {0} algorithms supported by the driver")]
+    NoSupportedAlgorithms(&'static str),
+
+    #[error("Server does not support strict key exchange")]
+    StrictKexUnsupported,
+
+    #[error("Server rejected the command")]
+    ExecRejected,
+
+    #[error("Session ended before the command's exit status was received")]
+    MissingExitStatus,
+
+    #[error("Unexpected output from remote command `{command}`: {output}")]
+    UnexpectedOutput { command: String, output: String },
+
+    #[error("Remote command `{command}` failed with {status}")]
+    CommandFailed {
+        command: String,
+        status: crate::process::ExitStatus,
+    },
+
+    #[error("SFTP error: {0}")]
+    Sftp(#[from] russh_sftp::client::error::Error),
+
+    #[error("SCP failed: {0}")]
+    Scp(String),
+
+    #[error("Local command `{command}` failed with {status}")]
+    LocalCommandFailed {
+        command: String,
+        status: std::process::ExitStatus,
+    },
+
+    #[error("Checksum mismatch for {path}: expected {expected}, found {actual}")]
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Could not open tunnel to {host}:{port}: {source}")]
+    TunnelFailed {
+        host: String,
+        port: u16,
+        source: Box<Error>,
+    },
+
+    #[error("Could not connect to jump host {host}: {source}")]
+    JumpHostFailed { host: String, source: Box<Error> },
+
+    #[error("Could not forward remote port {address}:{port}: {source}")]
+    RemoteForwardFailed {
+        address: String,
+        port: u16,
+        source: Box<Error>,
+    },
+
+    #[error("Host key store failed: {0}")]
+    Store(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Invalid SSH config at {origin}:{line}: {message}")]
+    InvalidConfig {
+        origin: String,
+        line: usize,
+        message: String,
+    },
+
+    #[error("Cannot expand token %{token} in {value}")]
+    UnexpandableToken { value: String, token: char },
+
+    #[error("Invalid permissions: {0}")]
+    InvalidPermissions(String),
+
+    #[error("User does not exist on remote host: {0}")]
+    UnknownRemoteUser(String),
+
+    #[error("Variable is not set in the remote environment: {0}")]
+    UnknownRemoteVariable(String),
+
+    #[error("Program not found on remote host: {0}")]
+    ProgramNotFound(String),
+
+    #[error("Could not become {user} on remote host: {reason}")]
+    BecomeFailed { user: String, reason: String },
+
+    #[error("Could not determine version of remote program: {0}")]
+    ProgramVersionUnknown(String),
+
+    #[error("Remote {program} version {found} does not satisfy {required}")]
+    ProgramVersionMismatch {
+        program: String,
+        found: semver::Version,
+        required: semver::VersionReq,
+    },
+
+    #[error("Host key was rejected: {0}")]
+    HostKeyRejected(Box<ssh_key::Fingerprint>),
+
+    #[error("Host key verification timed out")]
+    HostKeyVerificationTimeout,
+
+    #[error("Host key changed: expected {expected}, got {got}")]
+    HostKeyChanged {
+        expected: Box<ssh_key::Fingerprint>,
+        got: Box<ssh_key::Fingerprint>,
+    },
+
+    #[error(
+        "Host key does not match the one recorded for the host: expected {expected}, got {got}"
+    )]
+    HostKeyMismatch {
+        expected: Box<ssh_key::Fingerprint>,
+        got: Box<ssh_key::Fingerprint>,
+    },
+
+    #[error("Dry run passed; not authenticating")]
+    DryRun,
+
+    #[error(
+        "Server closed the connection before key exchange (banner received: {banner_received}), as sshd does past MaxStartups"
+    )]
+    ServerBusy { banner_received: bool },
+
+    #[error("Secret not found: {0}")]
+    SecretNotFound(String),
+
+    #[error("Secret source failed: {0}")]
+    SecretSource(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("One-time password secret is not base32")]
+    InvalidOtpSecret,
+
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(#[from] regex::Error),
+
+    #[error("Timed out waiting for {condition}")]
+    WaitTimeout { condition: String },
+
+    #[error("Session reached its limit of {max} {limit}")]
+    LimitReached { limit: &'static str, max: u64 },
+}
+
+/// Stable code identifying the kind of an [`Error`], for tools that map
+/// failures to remediation advice without matching on messages.
+///
+/// Codes are never renamed or removed, and an error never moves to another
+/// code, except in a major release. New codes may be added in any release,
+/// for new kinds of errors only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// `E_IO`: a local or network I/O operation failed.
+    Io,
+    /// `E_CONNECT_TIMEOUT`: the TCP connection was not established in time.
+    ConnectTimeout,
+    /// `E_PRE_CONNECT_FAILED`: a pre-connect hook failed.
+    PreConnectFailed,
+    /// `E_CONFIG_INVALID`: the session's configuration is incomplete or
+    /// cannot be parsed.
+    ConfigInvalid,
+    /// `E_DRIVER_UNAVAILABLE`: the requested driver is not built in.
+    DriverUnavailable,
+    /// `E_PROTOCOL`: the SSH connection failed at the protocol level.
+    Protocol,
+    /// `E_ALGORITHM_NEGOTIATION`: client and server share no acceptable
+    /// algorithms or protocol extensions.
+    AlgorithmNegotiation,
+    /// `E_HOSTKEY_REJECTED`: the server's host key was not accepted.
+    HostKeyRejected,
+    /// `E_HOSTKEY_MISMATCH`: the server presented a different host key than
+    /// the one accepted earlier in the session or recorded for the host.
+    HostKeyMismatch,
+    /// `E_HOSTKEY_TIMEOUT`: host key verification did not finish in time.
+    HostKeyTimeout,
+    /// `E_HOSTKEY_STORE`: the known hosts store failed.
+    HostKeyStore,
+    /// `E_AUTH_DENIED`: the server rejected every authentication payload.
+    AuthDenied,
+    /// `E_KEY_INVALID`: a private or public key could not be read or used.
+    KeyInvalid,
+    /// `E_KEY_PASSPHRASE_REQUIRED`: a private key is encrypted and no
+    /// passphrase was given.
+    KeyPassphraseRequired,
+    /// `E_AGENT_UNAVAILABLE`: no SSH agent is configured.
+    AgentUnavailable,
+    /// `E_AGENT_REFUSED`: the SSH agent refused a key.
+    AgentRefused,
+    /// `E_COMMAND_REJECTED`: the server refused to run a command.
+    CommandRejected,
+    /// `E_COMMAND_FAILED`: a command ran but failed.
+    CommandFailed,
+    /// `E_EXIT_STATUS_MISSING`: the session ended before a command's exit
+    /// status arrived.
+    ExitStatusMissing,
+    /// `E_UNEXPECTED_OUTPUT`: a command's output could not be understood.
+    UnexpectedOutput,
+    /// `E_SFTP`: an SFTP operation failed.
+    Sftp,
+    /// `E_SCP`: an SCP copy failed.
+    Scp,
+    /// `E_CHECKSUM_MISMATCH`: a transferred file's checksum did not match.
+    ChecksumMismatch,
+    /// `E_TUNNEL_FAILED`: a tunnel through a session could not be opened, a
+    /// jump host could not be connected to, or a remote port could not be
+    /// forwarded.
+    TunnelFailed,
+    /// `E_INVALID_ARGUMENT`: a value given to this crate is malformed.
+    InvalidArgument,
+    /// `E_REMOTE_USER_UNKNOWN`: a user does not exist on the remote host.
+    RemoteUserUnknown,
+    /// `E_REMOTE_VARIABLE_UNKNOWN`: a variable is not set in the remote
+    /// environment.
+    RemoteVariableUnknown,
+    /// `E_PROGRAM_NOT_FOUND`: a program is not installed on the remote host.
+    ProgramNotFound,
+    /// `E_BECOME_FAILED`: a command could not switch to another remote user.
+    BecomeFailed,
+    /// `E_PROGRAM_VERSION`: a remote program's version is unknown or
+    /// unsupported.
+    ProgramVersion,
+    /// `E_SERVER_BUSY`: the server closed the connection before key
+    /// exchange, as sshd does to connections beyond its `MaxStartups`.
+    ServerBusy,
+    /// `E_DRY_RUN`: a dry run passed every check and stopped before
+    /// authenticating.
+    DryRun,
+    /// `E_SECRET_NOT_FOUND`: a secret source does not have a secret.
+    SecretNotFound,
+    /// `E_SECRET_SOURCE`: a secret source failed.
+    SecretSource,
+    /// `E_WAIT_TIMEOUT`: a condition on the remote host did not hold in time.
+    WaitTimeout,
+    /// `E_LIMIT_REACHED`: a session ran as many commands or opened as many
+    /// channels as it was allowed to.
+    LimitReached,
+}
+
+impl Error {
+    /// Stable code for the kind of this error.
+    #[must_use]
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Io(_) => ErrorCode::Io,
+            Error::EnvVar(_) => ErrorCode::AgentUnavailable,
+            Error::Key(_) | Error::InvalidPem(_) | Error::InvalidPpk(_) => ErrorCode::KeyInvalid,
+            Error::EncryptedPrivateKeyNoPasshrase => ErrorCode::KeyPassphraseRequired,
+            Error::AgentRefusedKey => ErrorCode::AgentRefused,
+            #[cfg(feature = "russh")]
+            Error::Russh(_) => ErrorCode::Protocol,
+            Error::OpenSsh(_) => ErrorCode::Protocol,
+            Error::ConnectTimeout => ErrorCode::ConnectTimeout,
+            Error::PreConnectFailed(_) => ErrorCode::PreConnectFailed,
+            Error::NoDriver
+            | Error::MissingHostOption { .. }
+            | Error::InvalidConfig { .. }
+            | Error::UnexpandableToken { .. } => ErrorCode::ConfigInvalid,
+            Error::DriverUnavailable(_) => ErrorCode::DriverUnavailable,
+            Error::AuthenticationFailed | Error::CertificateRejected { .. } => {
+                ErrorCode::AuthDenied
+            }
+            Error::NoSupportedAlgorithms(_) | Error::StrictKexUnsupported => {
+                ErrorCode::AlgorithmNegotiation
+            }
+            Error::ExecRejected => ErrorCode::CommandRejected,
+            Error::MissingExitStatus => ErrorCode::ExitStatusMissing,
+            Error::UnexpectedOutput { .. } => ErrorCode::UnexpectedOutput,
+            Error::CommandFailed { .. } | Error::LocalCommandFailed { .. } => {
+                ErrorCode::CommandFailed
+            }
+            Error::Sftp(_) => ErrorCode::Sftp,
+            Error::Scp(_) => ErrorCode::Scp,
+            Error::ChecksumMismatch { .. } => ErrorCode::ChecksumMismatch,
+            Error::TunnelFailed { .. }
+            | Error::JumpHostFailed { .. }
+            | Error::RemoteForwardFailed { .. } => ErrorCode::TunnelFailed,
+            Error::Store(_) => ErrorCode::HostKeyStore,
+            Error::InvalidPermissions(_) | Error::InvalidOtpSecret | Error::InvalidRegex(_) => {
+                ErrorCode::InvalidArgument
+            }
+            Error::UnknownRemoteUser(_) => ErrorCode::RemoteUserUnknown,
+            Error::UnknownRemoteVariable(_) => ErrorCode::RemoteVariableUnknown,
+            Error::ProgramNotFound(_) => ErrorCode::ProgramNotFound,
+            Error::BecomeFailed { .. } => ErrorCode::BecomeFailed,
+            Error::ProgramVersionUnknown(_) | Error::ProgramVersionMismatch { .. } => {
+                ErrorCode::ProgramVersion
+            }
+            Error::HostKeyRejected(_) => ErrorCode::HostKeyRejected,
+            Error::HostKeyVerificationTimeout => ErrorCode::HostKeyTimeout,
+            Error::HostKeyChanged { .. } | Error::HostKeyMismatch { .. } => {
+                ErrorCode::HostKeyMismatch
+            }
+            Error::DryRun => ErrorCode::DryRun,
+            Error::ServerBusy { .. } => ErrorCode::ServerBusy,
+            Error::SecretNotFound(_) => ErrorCode::SecretNotFound,
+            Error::SecretSource(_) => ErrorCode::SecretSource,
+            Error::WaitTimeout { .. } => ErrorCode::WaitTimeout,
+            Error::LimitReached { .. } => ErrorCode::LimitReached,
+        }
+    }
+
+    /// Data of this error apart from its message, as named values, to fill
+    /// in a translated message chosen by [`Error::code`] or to log as
+    /// structured fields. Names are those of the variant's fields, and
+    /// `reason` for the message of an underlying error. Names are stable
+    /// like codes.
+    #[must_use]
+    #[allow(clippy::too_many_lines)]
+    pub fn args(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Error::Io(error) => vec![("reason", error.to_string())],
+            Error::EnvVar(error) => vec![("reason", error.to_string())],
+            Error::Key(error) => vec![("reason", error.to_string())],
+            #[cfg(feature = "russh")]
+            Error::Russh(error) => vec![("reason", error.to_string())],
+            Error::Sftp(error) => vec![("reason", error.to_string())],
+            Error::Scp(reason) => vec![("reason", reason.clone())],
+            Error::InvalidRegex(error) => vec![("reason", error.to_string())],
+            Error::PreConnectFailed(error) | Error::Store(error) | Error::SecretSource(error) => {
+                vec![("reason", error.to_string())]
+            }
+            Error::InvalidPem(reason) | Error::OpenSsh(reason) => {
+                vec![("reason", reason.clone())]
+            }
+            Error::InvalidPpk(reason) => vec![("reason", (*reason).to_string())],
+            Error::MissingHostOption { host, option } => {
+                vec![("host", host.clone()), ("option", (*option).to_string())]
+            }
+            Error::DriverUnavailable(driver) => vec![("driver", format!("{driver:?}"))],
+            Error::NoSupportedAlgorithms(kind) => vec![("kind", (*kind).to_string())],
+            Error::UnexpectedOutput { command, output } => {
+                vec![("command", command.clone()), ("output", output.clone())]
+            }
+            Error::CommandFailed { command, status } => {
+                vec![("command", command.clone()), ("status", status.to_string())]
+            }
+            Error::LocalCommandFailed { command, status } => {
+                vec![("command", command.clone()), ("status", status.to_string())]
+            }
+            Error::ChecksumMismatch {
+                path,
+                expected,
+                actual,
+            } => vec![
+                ("path", path.clone()),
+                ("expected", expected.clone()),
+                ("actual", actual.clone()),
+            ],
+            Error::TunnelFailed { host, port, .. } => {
+                vec![("host", host.clone()), ("port", port.to_string())]
+            }
+            Error::JumpHostFailed { host, .. } => vec![("host", host.clone())],
+            Error::RemoteForwardFailed { address, port, .. } => {
+                vec![("address", address.clone()), ("port", port.to_string())]
+            }
+            Error::InvalidConfig {
+                origin,
+                line,
+                message,
+            } => vec![
+                ("origin", origin.clone()),
+                ("line", line.to_string()),
+                ("message", message.clone()),
+            ],
+            Error::CertificateRejected { key_id, reason } => {
+                vec![("key_id", key_id.clone()), ("reason", reason.clone())]
+            }
+            Error::UnexpandableToken { value, token } => {
+                vec![("value", value.clone()), ("token", token.to_string())]
+            }
+            Error::InvalidPermissions(value) => vec![("value", value.clone())],
+            Error::SecretNotFound(name) => vec![("name", name.clone())],
+            Error::WaitTimeout { condition } => vec![("condition", condition.clone())],
+            Error::LimitReached { limit, max } => {
+                vec![("limit", (*limit).to_string()), ("max", max.to_string())]
+            }
+            Error::UnknownRemoteUser(user) => vec![("user", user.clone())],
+            Error::UnknownRemoteVariable(variable) => vec![("variable", variable.clone())],
+            Error::ProgramNotFound(program) | Error::ProgramVersionUnknown(program) => {
+                vec![("program", program.clone())]
+            }
+            Error::BecomeFailed { user, reason } => {
+                vec![("user", user.clone()), ("reason", reason.clone())]
+            }
+            Error::ProgramVersionMismatch {
+                program,
+                found,
+                required,
+            } => vec![
+                ("program", program.clone()),
+                ("found", found.to_string()),
+                ("required", required.to_string()),
+            ],
+            Error::HostKeyRejected(fingerprint) => vec![("fingerprint", fingerprint.to_string())],
+            Error::HostKeyChanged { expected, got } | Error::HostKeyMismatch { expected, got } => {
+                vec![("expected", expected.to_string()), ("got", got.to_string())]
+            }
+            Error::ServerBusy { banner_received } => {
+                vec![("banner_received", banner_received.to_string())]
+            }
+            Error::EncryptedPrivateKeyNoPasshrase
+            | Error::AgentRefusedKey
+            | Error::ConnectTimeout
+            | Error::NoDriver
+            | Error::AuthenticationFailed
+            | Error::StrictKexUnsupported
+            | Error::ExecRejected
+            | Error::MissingExitStatus
+            | Error::HostKeyVerificationTimeout
+            | Error::DryRun
+            | Error::InvalidOtpSecret => Vec::new(),
+        }
+    }
+
+    /// Host the error concerns, if any.
+    #[must_use]
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            Error::MissingHostOption { host, .. }
+            | Error::TunnelFailed { host, .. }
+            | Error::JumpHostFailed { host, .. } => Some(host),
+            _ => None,
+        }
+    }
+
+    /// Path the error concerns, if any.
+    #[must_use]
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Error::ChecksumMismatch { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Fingerprint of the host key the error concerns, if any. For a key that
+    /// changed, this is the new key's.
+    #[must_use]
+    pub fn fingerprint(&self) -> Option<&ssh_key::Fingerprint> {
+        match self {
+            Error::HostKeyRejected(fingerprint)
+            | Error::HostKeyChanged {
+                got: fingerprint, ..
+            }
+            | Error::HostKeyMismatch {
+                got: fingerprint, ..
+            } => Some(fingerprint),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorCode {
+    /// Code as written in logs and documentation, such as `E_AUTH_DENIED`.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Io => "E_IO",
+            ErrorCode::ConnectTimeout => "E_CONNECT_TIMEOUT",
+            ErrorCode::PreConnectFailed => "E_PRE_CONNECT_FAILED",
+            ErrorCode::ConfigInvalid => "E_CONFIG_INVALID",
+            ErrorCode::DriverUnavailable => "E_DRIVER_UNAVAILABLE",
+            ErrorCode::Protocol => "E_PROTOCOL",
+            ErrorCode::AlgorithmNegotiation => "E_ALGORITHM_NEGOTIATION",
+            ErrorCode::HostKeyRejected => "E_HOSTKEY_REJECTED",
+            ErrorCode::HostKeyMismatch => "E_HOSTKEY_MISMATCH",
+            ErrorCode::HostKeyTimeout => "E_HOSTKEY_TIMEOUT",
+            ErrorCode::HostKeyStore => "E_HOSTKEY_STORE",
+            ErrorCode::AuthDenied => "E_AUTH_DENIED",
+            ErrorCode::KeyInvalid => "E_KEY_INVALID",
+            ErrorCode::KeyPassphraseRequired => "E_KEY_PASSPHRASE_REQUIRED",
+            ErrorCode::AgentUnavailable => "E_AGENT_UNAVAILABLE",
+            ErrorCode::AgentRefused => "E_AGENT_REFUSED",
+            ErrorCode::CommandRejected => "E_COMMAND_REJECTED",
+            ErrorCode::CommandFailed => "E_COMMAND_FAILED",
+            ErrorCode::ExitStatusMissing => "E_EXIT_STATUS_MISSING",
+            ErrorCode::UnexpectedOutput => "E_UNEXPECTED_OUTPUT",
+            ErrorCode::Sftp => "E_SFTP",
+            ErrorCode::Scp => "E_SCP",
+            ErrorCode::ChecksumMismatch => "E_CHECKSUM_MISMATCH",
+            ErrorCode::TunnelFailed => "E_TUNNEL_FAILED",
+            ErrorCode::InvalidArgument => "E_INVALID_ARGUMENT",
+            ErrorCode::RemoteUserUnknown => "E_REMOTE_USER_UNKNOWN",
+            ErrorCode::RemoteVariableUnknown => "E_REMOTE_VARIABLE_UNKNOWN",
+            ErrorCode::ProgramNotFound => "E_PROGRAM_NOT_FOUND",
+            ErrorCode::BecomeFailed => "E_BECOME_FAILED",
+            ErrorCode::ProgramVersion => "E_PROGRAM_VERSION",
+            ErrorCode::ServerBusy => "E_SERVER_BUSY",
+            ErrorCode::DryRun => "E_DRY_RUN",
+            ErrorCode::SecretNotFound => "E_SECRET_NOT_FOUND",
+            ErrorCode::SecretSource => "E_SECRET_SOURCE",
+            ErrorCode::WaitTimeout => "E_WAIT_TIMEOUT",
+            ErrorCode::LimitReached => "E_LIMIT_REACHED",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Error::AuthenticationFailed, "E_AUTH_DENIED")]
+    #[case(Error::InvalidPpk("invalid hex"), "E_KEY_INVALID")]
+    #[case(
+        Error::TunnelFailed {
+            host: "db".to_string(),
+            port: 5432,
+            source: Box::new(Error::ConnectTimeout),
+        },
+        "E_TUNNEL_FAILED"
+    )]
+    #[case(Error::HostKeyVerificationTimeout, "E_HOSTKEY_TIMEOUT")]
+    #[case(Error::Scp("scp: missing: No such file or directory".to_string()), "E_SCP")]
+    #[case(Error::LimitReached { limit: "commands", max: 5 }, "E_LIMIT_REACHED")]
+    fn code_works(#[case] error: Error, #[case] code_should: &str) {
+        assert_eq!(error.code().to_string(), code_should);
+    }
+
+    #[test]
+    fn args_works() {
+        let error = Error::TunnelFailed {
+            host: "db".to_string(),
+            port: 5432,
+            source: Box::new(Error::ConnectTimeout),
+        };
+
+        assert_eq!(
+            error.args(),
+            [("host", "db".to_string()), ("port", "5432".to_string())]
+        );
+        assert_eq!(error.host(), Some("db"));
+        assert_eq!(error.path(), None);
+        assert!(Error::ConnectTimeout.args().is_empty());
+    }
 }
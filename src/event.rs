@@ -0,0 +1,129 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::ChannelKind;
+
+/// Events buffered per subscriber before the oldest are dropped.
+const CAPACITY: usize = 64;
+
+/// Lifecycle event of a connected session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A channel was opened, by the client for a command, the SFTP subsystem
+    /// or a tunnel, or by the server for a connection to a remote forward.
+    ChannelOpened { kind: ChannelKind },
+    /// Session keys were renegotiated and the server presented the same host
+    /// key as during the initial key exchange.
+    Rekey {
+        /// Number of key re-exchanges completed so far, including this one.
+        count: usize,
+    },
+    /// A command's stdout or stderr has not been read for a while, so its
+    /// channel is stalled and the command is likely blocked writing output.
+    SlowConsumer {
+        /// Program the stalled command runs, as given to
+        /// [`ConnectedSession::command`](crate::ConnectedSession::command).
+        program: String,
+        /// Command line of the stalled command.
+        command: String,
+        /// How long the output has gone unread so far.
+        stalled_for: Duration,
+    },
+    /// The server did not reply to a keepalive within the keepalive
+    /// interval. The session is disconnected once the keepalive count max is
+    /// reached.
+    KeepaliveMissed {
+        /// Keepalives the server has not replied to in a row, including this
+        /// one.
+        missed: u32,
+    },
+    /// A local or SOCKS forward listens again after accepting connections on
+    /// it failed.
+    ForwardRestored {
+        /// Address the forward listens on again.
+        local_addr: SocketAddr,
+    },
+    /// A remote forward was requested again after connections to it stopped
+    /// being handed on.
+    RemoteForwardRestored {
+        /// Address the server listens on, as given to
+        /// [`ConnectedSession::forward_remote`](crate::ConnectedSession::forward_remote).
+        address: String,
+        /// Port the server listens on.
+        port: u16,
+    },
+    /// The connection to the server was closed.
+    Disconnected {
+        /// Error that ended the session, or `None` if the server closed it
+        /// cleanly.
+        error: Option<String>,
+    },
+}
+
+/// Sending half of a session's event stream.
+#[derive(Debug, Clone)]
+pub struct Events(broadcast::Sender<Event>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Events {
+    pub fn emit(&self, event: Event) {
+        tracing::debug!(?event, "session event");
+        // Fails only when nobody is subscribed, which is fine.
+        let _ = self.0.send(event);
+    }
+
+    /// Stream of events emitted from now on. Subscribers that fall behind
+    /// skip the events they missed.
+    pub fn subscribe(&self) -> BoxStream<'static, Event> {
+        futures::stream::unfold(self.0.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "slow subscriber missed session events");
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn subscribe_works() {
+        let events = Events::default();
+        let mut before = events.subscribe();
+
+        events.emit(Event::Rekey { count: 1 });
+        let mut after = events.subscribe();
+        events.emit(Event::Disconnected { error: None });
+        drop(events);
+
+        assert_eq!(before.next().await, Some(Event::Rekey { count: 1 }));
+        assert_eq!(
+            before.next().await,
+            Some(Event::Disconnected { error: None })
+        );
+        assert_eq!(before.next().await, None);
+        assert_eq!(
+            after.next().await,
+            Some(Event::Disconnected { error: None })
+        );
+    }
+}
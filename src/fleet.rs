@@ -0,0 +1,600 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bon::Builder;
+use futures::Stream;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use regex::Regex;
+use tokio::sync::Semaphore;
+
+use crate::Auth;
+use crate::ConnectedSession;
+use crate::DriverKind;
+use crate::Error;
+use crate::HostKeyVerification;
+use crate::Policy;
+use crate::Result;
+use crate::Session;
+use crate::config::matches_pattern_list;
+use crate::process::ExitStatus;
+
+mod output;
+
+pub use output::HostOutput;
+pub use output::OutputDir;
+pub use output::OutputSink;
+
+/// Connection settings for a host. Settings left unset on a host fall back to
+/// the fleet's defaults.
+#[derive(Debug, Clone, Default, Builder)]
+pub struct HostOptions {
+    /// Remote user to login as. Defaults to the local user.
+    #[builder(into)]
+    pub user: Option<String>,
+    /// Port to connect to. Defaults to 22.
+    pub port: Option<u16>,
+    /// Underlying SSH implementations to try, in order.
+    #[builder(with = |drivers: impl IntoIterator<Item = DriverKind>| drivers.into_iter().collect())]
+    pub drivers: Option<Vec<DriverKind>>,
+    /// Authentication payloads, replacing the defaults' payloads entirely
+    /// rather than adding to them.
+    #[builder(with = |auth: impl IntoIterator<Item = Auth>| auth.into_iter().collect())]
+    pub auth: Option<Vec<Auth>>,
+    /// Maximum time to wait for the TCP connection to be established.
+    pub connect_timeout: Option<Duration>,
+    /// Algorithms allowed during key exchange.
+    pub policy: Option<Policy>,
+    /// Refuse to authenticate unless the server supports strict key exchange.
+    pub require_strict_kex: Option<bool>,
+    /// Total bytes that may be transferred over the session.
+    pub max_bytes: Option<u64>,
+    /// Times to reconnect when the server is too busy to take the connection.
+    pub busy_retries: Option<u32>,
+    /// Most authentication payloads offered per connection.
+    pub max_auth_attempts: Option<u32>,
+    /// Bytes the server may send on a channel before waiting for more room.
+    pub window_size: Option<u32>,
+    /// Largest data packet the server may send on a channel.
+    pub max_packet_size: Option<u32>,
+    /// How to decide whether to trust the host's key.
+    pub host_key_verification: Option<HostKeyVerification>,
+    /// Locale commands without a pty run in.
+    #[builder(into)]
+    pub locale: Option<String>,
+    /// `TERM` of commands without a pty.
+    #[builder(into)]
+    pub term: Option<String>,
+    /// Labels to select the host by with [`Target::Tag`], such as its role
+    /// or environment, replacing the defaults' tags entirely rather than
+    /// adding to them.
+    #[builder(with = |tags: impl IntoIterator<Item = impl Into<String>>| tags.into_iter().map(Into::into).collect())]
+    pub tags: Option<Vec<String>>,
+}
+
+impl HostOptions {
+    /// Settings of `self`, with unset ones taken from `defaults`.
+    #[must_use]
+    pub fn merged_over(&self, defaults: &HostOptions) -> HostOptions {
+        HostOptions {
+            user: self.user.clone().or_else(|| defaults.user.clone()),
+            port: self.port.or(defaults.port),
+            drivers: self.drivers.clone().or_else(|| defaults.drivers.clone()),
+            auth: self.auth.clone().or_else(|| defaults.auth.clone()),
+            connect_timeout: self.connect_timeout.or(defaults.connect_timeout),
+            policy: self.policy.clone().or_else(|| defaults.policy.clone()),
+            require_strict_kex: self.require_strict_kex.or(defaults.require_strict_kex),
+            max_bytes: self.max_bytes.or(defaults.max_bytes),
+            busy_retries: self.busy_retries.or(defaults.busy_retries),
+            max_auth_attempts: self.max_auth_attempts.or(defaults.max_auth_attempts),
+            window_size: self.window_size.or(defaults.window_size),
+            max_packet_size: self.max_packet_size.or(defaults.max_packet_size),
+            host_key_verification: self
+                .host_key_verification
+                .clone()
+                .or_else(|| defaults.host_key_verification.clone()),
+            locale: self.locale.clone().or_else(|| defaults.locale.clone()),
+            term: self.term.clone().or_else(|| defaults.term.clone()),
+            tags: self.tags.clone().or_else(|| defaults.tags.clone()),
+        }
+    }
+
+    fn is_targeted(&self, host: &str, target: &Target) -> bool {
+        target.matches(host, self.tags.as_deref().unwrap_or_default())
+    }
+
+    fn session(self, host: &str) -> Result<Session> {
+        let missing = |option| Error::MissingHostOption {
+            host: host.to_string(),
+            option,
+        };
+
+        let mut builder = Session::builder()
+            .maybe_user(self.user)
+            .host(host)
+            .driver_chain(self.drivers.ok_or_else(|| missing("drivers"))?)
+            .maybe_port(self.port)
+            .maybe_connect_timeout(self.connect_timeout)
+            .maybe_policy(self.policy)
+            .maybe_require_strict_kex(self.require_strict_kex)
+            .maybe_max_bytes(self.max_bytes)
+            .maybe_busy_retries(self.busy_retries)
+            .maybe_max_auth_attempts(self.max_auth_attempts)
+            .maybe_window_size(self.window_size)
+            .maybe_max_packet_size(self.max_packet_size)
+            .maybe_locale(self.locale)
+            .maybe_term(self.term);
+        for payload in self.auth.unwrap_or_default() {
+            builder = builder.auth(payload);
+        }
+        if let Some(verification) = self.host_key_verification {
+            builder = builder.host_key_verification(verification);
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Hosts of a fleet to run an operation on, selected by name or by tag.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Every host.
+    All,
+    /// Hosts whose name matches a comma-separated list of patterns, as on a
+    /// `Host` line of `ssh_config`: `*` matches any characters, `?` one, and
+    /// a pattern starting with `!` excludes the hosts it matches, such as
+    /// `web-*.prod,!web-canary.prod`. Matching is case-insensitive.
+    Glob(String),
+    /// Hosts whose name the regular expression matches, anywhere unless
+    /// anchored.
+    Regex(Regex),
+    /// Hosts having the tag.
+    Tag(String),
+    /// Hosts matched by every target.
+    And(Vec<Target>),
+    /// Hosts matched by any target.
+    Or(Vec<Target>),
+    /// Hosts not matched by the target.
+    Not(Box<Target>),
+}
+
+impl Target {
+    /// Hosts whose name matches the comma-separated glob patterns, as
+    /// [`Target::Glob`].
+    pub fn glob(patterns: impl Into<String>) -> Target {
+        Target::Glob(patterns.into())
+    }
+
+    /// Hosts whose name matches the regular expression `pattern`, as
+    /// [`Target::Regex`].
+    ///
+    /// # Errors
+    ///
+    /// - If `pattern` is not a valid regular expression.
+    pub fn regex(pattern: &str) -> Result<Target> {
+        Ok(Target::Regex(Regex::new(pattern)?))
+    }
+
+    /// Hosts having `tag`, as [`Target::Tag`].
+    pub fn tag(tag: impl Into<String>) -> Target {
+        Target::Tag(tag.into())
+    }
+
+    /// Whether the host named `host` with `tags` is targeted.
+    #[must_use]
+    pub fn matches(&self, host: &str, tags: &[String]) -> bool {
+        match self {
+            Target::All => true,
+            Target::Glob(patterns) => {
+                let patterns: Vec<_> = patterns
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                matches_pattern_list(&patterns, host)
+            }
+            Target::Regex(regex) => regex.is_match(host),
+            Target::Tag(tag) => tags.contains(tag),
+            Target::And(targets) => targets.iter().all(|target| target.matches(host, tags)),
+            Target::Or(targets) => targets.iter().any(|target| target.matches(host, tags)),
+            Target::Not(target) => !target.matches(host, tags),
+        }
+    }
+}
+
+/// Group of hosts connected to together, sharing default settings that each
+/// host may override.
+#[derive(Debug, Builder)]
+pub struct Fleet {
+    #[builder(field)]
+    hosts: Vec<(String, HostOptions)>,
+    /// Settings used for every host unless it overrides them.
+    #[builder(default)]
+    defaults: HostOptions,
+    /// Most connections opened per second across the fleet, spread evenly,
+    /// to stay under the thresholds of intrusion detection systems.
+    /// Unlimited by default.
+    connects_per_second: Option<u32>,
+    /// Most connections opened to the same host and port per minute, spread
+    /// evenly, to stay under the thresholds of tools such as fail2ban.
+    /// Unlimited by default.
+    host_connects_per_minute: Option<u32>,
+    /// Most connections being established at once, from opening the TCP
+    /// connection to authenticating, to stay under sshd's `MaxStartups` on
+    /// shared bastions and to spread the CPU cost of key exchanges.
+    /// Unlimited by default.
+    max_concurrent_connects: Option<usize>,
+    /// Most hosts [`Fleet::run`] and [`Fleet::run_command`] have a session
+    /// open to at once, which bounds the file descriptors and memory used by
+    /// large fleets. Further hosts are connected to as operations finish.
+    /// Unlimited by default.
+    max_concurrency: Option<usize>,
+}
+
+impl<S: fleet_builder::State> FleetBuilder<S> {
+    /// Adds a host using only the fleet's default settings.
+    pub fn host(self, host: impl Into<String>) -> Self {
+        self.host_with(host, HostOptions::default())
+    }
+
+    /// Adds a host with settings that take precedence over the fleet's
+    /// defaults.
+    pub fn host_with(mut self, host: impl Into<String>, options: HostOptions) -> Self {
+        self.hosts.push((host.into(), options));
+        self
+    }
+}
+
+impl Fleet {
+    /// Effective settings for each host, after merging over the defaults.
+    pub fn hosts(&self) -> impl Iterator<Item = (&str, HostOptions)> {
+        self.hosts
+            .iter()
+            .map(|(host, options)| (host.as_str(), options.merged_over(&self.defaults)))
+    }
+
+    /// Names of the hosts `target` selects, in the order hosts were added,
+    /// judged by their tags after merging over the defaults.
+    pub fn matching<'a>(&'a self, target: &'a Target) -> impl Iterator<Item = &'a str> {
+        self.hosts()
+            .filter(|(host, options)| options.is_targeted(host, target))
+            .map(|(host, _)| host)
+    }
+
+    /// Fleet of the hosts `target` selects, with the same defaults and rate
+    /// limits, to run an operation on only those hosts.
+    #[must_use]
+    pub fn select(&self, target: &Target) -> Fleet {
+        let hosts = self
+            .hosts
+            .iter()
+            .zip(self.hosts())
+            .filter(|(_, (host, options))| options.is_targeted(host, target))
+            .map(|(entry, _)| entry.clone())
+            .collect();
+
+        Fleet {
+            hosts,
+            defaults: self.defaults.clone(),
+            connects_per_second: self.connects_per_second,
+            host_connects_per_minute: self.host_connects_per_minute,
+            max_concurrent_connects: self.max_concurrent_connects,
+            max_concurrency: self.max_concurrency,
+        }
+    }
+
+    /// Connects to every host concurrently, starting connections as often as
+    /// the rate limits allow, in the order hosts were added. Results are in
+    /// the same order.
+    ///
+    /// # Errors
+    ///
+    /// Each host fails independently, for the same reasons as
+    /// [`Session::connect`], or if it has no `drivers` configured either
+    /// directly or through the defaults.
+    pub async fn connect_all(&self) -> Vec<(String, Result<ConnectedSession>)> {
+        futures::future::join_all(self.connects()).await
+    }
+
+    /// Connects to every host concurrently like [`Fleet::connect_all`], but
+    /// yields each host's result as soon as it is known, to report progress
+    /// while the slowest hosts are still connecting.
+    ///
+    /// # Errors
+    ///
+    /// Each host fails independently, as for [`Fleet::connect_all`].
+    pub fn connect_each(&self) -> impl Stream<Item = (String, Result<ConnectedSession>)> + '_ {
+        self.connects().collect::<FuturesUnordered<_>>()
+    }
+
+    /// Connects to every host and runs `operation` with each session,
+    /// concurrently up to the fleet's `max_concurrency`, yielding each host's
+    /// result as soon as its operation finishes. The session is dropped, and
+    /// so disconnected, once the operation's future completes.
+    ///
+    /// # Errors
+    ///
+    /// Each host fails independently, either to connect, as for
+    /// [`Fleet::connect_all`], or with the error of `operation`.
+    pub fn run<F, Fut, T>(&self, operation: F) -> impl Stream<Item = (String, Result<T>)> + '_
+    where
+        F: Fn(ConnectedSession) -> Fut + 'static,
+        Fut: Future<Output = Result<T>> + 'static,
+        T: 'static,
+    {
+        let concurrency = self.max_concurrency.unwrap_or(self.hosts.len()).max(1);
+        let operation = Arc::new(operation);
+
+        futures::stream::iter(self.connects())
+            .map(move |connect| {
+                let operation = operation.clone();
+                async move {
+                    let (host, result) = connect.await;
+                    let result = match result {
+                        Ok(session) => (*operation)(session).await,
+                        Err(error) => Err(error),
+                    };
+                    (host, result)
+                }
+            })
+            .buffer_unordered(concurrency)
+    }
+
+    /// Runs `program` with `args` on every host like [`Fleet::run`], copying
+    /// each host's stdout and stderr to the writers `output` opens for it as
+    /// the output arrives, and yields each host's exit status as it finishes.
+    ///
+    /// # Errors
+    ///
+    /// Each host fails independently, either to connect, as for
+    /// [`Fleet::connect_all`], or if its writers cannot be opened or the
+    /// command cannot be run, as for [`Child::wait_with_sinks`].
+    ///
+    /// [`Child::wait_with_sinks`]: crate::process::Child::wait_with_sinks
+    pub fn run_command(
+        &self,
+        program: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+        output: impl OutputSink + 'static,
+    ) -> impl Stream<Item = (String, Result<ExitStatus>)> + '_ {
+        let program = program.into();
+        let args: Vec<String> = args.into_iter().map(Into::into).collect();
+        let output = Arc::new(output);
+
+        self.run(move |session| {
+            let (program, args, output) = (program.clone(), args.clone(), output.clone());
+            async move {
+                let sinks = output.open(session.host()).await?;
+                session
+                    .command(program)
+                    .args(args)
+                    .spawn()
+                    .await?
+                    .wait_with_sinks(sinks.stdout, sinks.stderr)
+                    .await
+            }
+        })
+    }
+
+    /// Connection to each host, started after its delay in the schedule.
+    fn connects(
+        &self,
+    ) -> impl Iterator<Item = impl Future<Output = (String, Result<ConnectedSession>)> + '_> {
+        let start = tokio::time::Instant::now();
+        let dialer = Arc::new(Semaphore::new(
+            self.max_concurrent_connects
+                .unwrap_or(Semaphore::MAX_PERMITS)
+                .clamp(1, Semaphore::MAX_PERMITS),
+        ));
+        self.hosts()
+            .zip(self.schedule())
+            .map(move |((host, options), delay)| {
+                let dialer = dialer.clone();
+                async move {
+                    tokio::time::sleep_until(start + delay).await;
+                    let result = match options.session(host) {
+                        Ok(session) => {
+                            let _permit = dialer.acquire().await.expect("dialer is never closed");
+                            session.connect().await
+                        }
+                        Err(error) => Err(error),
+                    };
+                    (host.to_string(), result)
+                }
+            })
+    }
+
+    /// Delay after which to start connecting to each host, in the order hosts
+    /// were added, so that no rate limit is exceeded.
+    fn schedule(&self) -> Vec<Duration> {
+        let interval = |period: Duration, limit: Option<u32>| {
+            limit.map_or(Duration::ZERO, |limit| period / limit.max(1))
+        };
+        let fleet_interval = interval(Duration::from_secs(1), self.connects_per_second);
+        let host_interval = interval(Duration::from_mins(1), self.host_connects_per_minute);
+
+        let mut fleet_next = Duration::ZERO;
+        let mut host_next = HashMap::new();
+        self.hosts()
+            .map(|(host, options)| {
+                let host_next = host_next
+                    .entry((host, options.port.unwrap_or(22)))
+                    .or_insert(Duration::ZERO);
+                let delay = fleet_next.max(*host_next);
+                fleet_next = delay + fleet_interval;
+                *host_next = delay + host_interval;
+                delay
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use secrecy::ExposeSecret;
+    use secrecy::SecretString;
+
+    use super::*;
+
+    fn fleet() -> Fleet {
+        Fleet::builder()
+            .defaults(
+                HostOptions::builder()
+                    .user("root")
+                    .port(22)
+                    .auth([Auth::Password(SecretString::from("default"))])
+                    .build(),
+            )
+            .host("web1")
+            .host_with(
+                "db1",
+                HostOptions::builder()
+                    .port(2222)
+                    .auth([Auth::Password(SecretString::from("override"))])
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn hosts_merge_over_defaults() {
+        let fleet = fleet();
+        let hosts: Vec<_> = fleet.hosts().collect();
+
+        assert_eq!(hosts[0].0, "web1");
+        assert_eq!(hosts[0].1.port, Some(22));
+        assert_eq!(hosts[1].0, "db1");
+        assert_eq!(hosts[1].1.user.as_deref(), Some("root"));
+        assert_eq!(hosts[1].1.port, Some(2222));
+        assert!(matches!(
+            hosts[1].1.auth.as_deref(),
+            Some([Auth::Password(password)]) if password.expose_secret() == "override"
+        ));
+    }
+
+    #[test]
+    fn schedule_spaces_connections() {
+        let fleet = Fleet::builder()
+            .host("web1")
+            .host("web1")
+            .host("db1")
+            .host_with("web1", HostOptions::builder().port(2222).build())
+            .connects_per_second(10)
+            .host_connects_per_minute(60)
+            .build();
+
+        let schedule = fleet.schedule();
+
+        let millis: Vec<_> = schedule.iter().map(Duration::as_millis).collect();
+        assert_eq!(millis, [0, 1000, 1100, 1200]);
+    }
+
+    fn inventory() -> Fleet {
+        let tagged = |tags: &[&str]| HostOptions::builder().tags(tags.iter().copied()).build();
+
+        Fleet::builder()
+            .defaults(HostOptions::builder().tags(["prod"]).build())
+            .host("web-1.prod")
+            .host_with("web-2.prod", tagged(&["prod", "canary"]))
+            .host_with("db-1.prod", tagged(&["prod", "db"]))
+            .host_with("web-1.staging", tagged(&["staging"]))
+            .build()
+    }
+
+    #[rstest]
+    #[case(Target::All, &["web-1.prod", "web-2.prod", "db-1.prod", "web-1.staging"])]
+    #[case(Target::glob("web-*.prod"), &["web-1.prod", "web-2.prod"])]
+    #[case(Target::glob("WEB-*, !*.staging"), &["web-1.prod", "web-2.prod"])]
+    #[case(Target::regex(r"^\w+-1\.").unwrap(), &["web-1.prod", "db-1.prod", "web-1.staging"])]
+    #[case(Target::tag("prod"), &["web-1.prod", "web-2.prod", "db-1.prod"])]
+    #[case(
+        Target::And(vec![Target::tag("prod"), Target::Not(Box::new(Target::tag("canary")))]),
+        &["web-1.prod", "db-1.prod"]
+    )]
+    #[case(Target::Or(vec![Target::tag("db"), Target::tag("staging")]), &["db-1.prod", "web-1.staging"])]
+    fn matching_works(#[case] target: Target, #[case] hosts_should: &[&str]) {
+        let fleet = inventory();
+
+        let hosts: Vec<_> = fleet.matching(&target).collect();
+
+        assert_eq!(hosts, hosts_should);
+    }
+
+    #[test]
+    fn select_keeps_settings() {
+        let fleet = Fleet::builder()
+            .host("web1")
+            .host_with(
+                "web1",
+                HostOptions::builder().port(2222).tags(["canary"]).build(),
+            )
+            .connects_per_second(10)
+            .build();
+
+        let selected = fleet.select(&Target::tag("canary"));
+
+        let hosts: Vec<_> = selected.hosts().collect();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].1.port, Some(2222));
+        assert_eq!(selected.connects_per_second, Some(10));
+    }
+
+    #[test]
+    fn regex_rejects_invalid_pattern() {
+        let target = Target::regex("web-(");
+
+        assert!(matches!(target, Err(Error::InvalidRegex(_))));
+    }
+
+    #[tokio::test]
+    async fn run_yields_each_host() {
+        let results: Vec<_> = fleet()
+            .run(|session| async move { Ok(session.host().to_string()) })
+            .collect()
+            .await;
+
+        let mut hosts: Vec<_> = results.iter().map(|(host, _)| host.as_str()).collect();
+        hosts.sort_unstable();
+        assert_eq!(hosts, ["db1", "web1"]);
+        assert!(results.iter().all(|(_, result)| matches!(
+            result,
+            Err(Error::MissingHostOption {
+                option: "drivers",
+                ..
+            })
+        )));
+    }
+
+    #[tokio::test]
+    async fn run_limits_concurrency() {
+        let fleet = Fleet::builder()
+            .host("web1")
+            .host("web2")
+            .host("web3")
+            .max_concurrent_connects(1)
+            .max_concurrency(1)
+            .build();
+
+        let results: Vec<_> = fleet.run(|_| async { Ok(()) }).collect().await;
+
+        let hosts: Vec<_> = results.iter().map(|(host, _)| host.as_str()).collect();
+        assert_eq!(hosts, ["web1", "web2", "web3"]);
+    }
+
+    #[tokio::test]
+    async fn connect_all_reports_missing_options() {
+        let results = fleet().connect_all().await;
+
+        assert_eq!(results.len(), 2);
+        for (host, result) in results {
+            assert!(matches!(
+                result,
+                Err(Error::MissingHostOption { host: ref missing, option: "drivers" }) if *missing == host
+            ));
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use camino::Utf8PathBuf;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use tokio::io::AsyncWrite;
+
+use crate::Result;
+
+/// Writers that one host's output is copied to as it arrives.
+pub struct HostOutput {
+    pub stdout: Pin<Box<dyn AsyncWrite + Send>>,
+    pub stderr: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+impl HostOutput {
+    pub fn new(
+        stdout: impl AsyncWrite + Send + 'static,
+        stderr: impl AsyncWrite + Send + 'static,
+    ) -> Self {
+        Self {
+            stdout: Box::pin(stdout),
+            stderr: Box::pin(stderr),
+        }
+    }
+}
+
+impl fmt::Debug for HostOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostOutput").finish_non_exhaustive()
+    }
+}
+
+/// Opens the writers each host's output goes to in
+/// [`Fleet::run_command`](super::Fleet::run_command), so that the output of
+/// large runs never has to be held in memory. Implemented by [`OutputDir`],
+/// and for closures taking the host by value and returning a future, such as
+/// `|host| async move { log_store.writers(&host).await }`.
+pub trait OutputSink: Send + Sync {
+    /// Writers for the output of `host`.
+    fn open<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<HostOutput>>;
+}
+
+impl<F, Fut> OutputSink for F
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<HostOutput>> + Send + 'static,
+{
+    fn open<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<HostOutput>> {
+        self(host.to_string()).boxed()
+    }
+}
+
+impl fmt::Debug for dyn OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutputSink")
+    }
+}
+
+/// Output of each host written to `{host}.stdout` and `{host}.stderr` in a
+/// local directory, which is created if needed. Files left by an earlier run
+/// are truncated. A host added to the fleet more than once, such as on
+/// several ports, shares its files between the runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputDir {
+    dir: Utf8PathBuf,
+}
+
+impl OutputDir {
+    pub fn new(dir: impl Into<Utf8PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl OutputSink for OutputDir {
+    fn open<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<HostOutput>> {
+        async move {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let stdout = tokio::fs::File::create(self.dir.join(format!("{host}.stdout"))).await?;
+            let stderr = tokio::fs::File::create(self.dir.join(format!("{host}.stderr"))).await?;
+
+            Ok(HostOutput::new(stdout, stderr))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn output_dir_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(dir.path().join("out")).unwrap();
+
+        let mut output = OutputDir::new(&dir).open("web1").await.unwrap();
+        output.stdout.write_all(b"up 3 days\n").await.unwrap();
+        output.stdout.flush().await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.join("web1.stdout")).unwrap(),
+            "up 3 days\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("web1.stderr")).unwrap(),
+            ""
+        );
+    }
+}
@@ -0,0 +1,570 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+
+use futures::Stream;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::net::ToSocketAddrs;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::task::JoinSet;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Event;
+use crate::Result;
+use crate::driver::Connected;
+use crate::event::Events;
+use crate::transport::AsyncStream;
+use crate::transport::meter::ChannelKind;
+use crate::transport::meter::Counters;
+
+mod socks;
+
+/// Local port forwarded through a session, like OpenSSH's `-L`, or `-D` for
+/// a SOCKS proxy. Created by [`ConnectedSession::forward_local`] and
+/// [`ConnectedSession::socks5_proxy`].
+///
+/// Dropping the handle stops forwarding and closes the connections it
+/// carries. Awaiting it waits for forwarding to stop on its own, which it
+/// does once the session is dropped, or with an error if accepting
+/// connections fails. With [`SessionBuilder::restore_forwards_after`], the
+/// forward listens again instead.
+///
+/// [`SessionBuilder::restore_forwards_after`]: crate::SessionBuilder::restore_forwards_after
+pub struct LocalForward {
+    local_addr: SocketAddr,
+    task: JoinHandle<Result<()>>,
+}
+
+impl LocalForward {
+    /// Address the forward listens on, with the port the system picked if
+    /// port 0 was asked for.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Future for LocalForward {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let result = ready!(Pin::new(&mut self.task).poll(cx));
+
+        Poll::Ready(result.unwrap_or_else(|error| Err(io::Error::other(error).into())))
+    }
+}
+
+impl Drop for LocalForward {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Remote port forwarded through a session, like OpenSSH's `-R`. Created by
+/// [`ConnectedSession::forward_remote`].
+///
+/// A stream of the connections the server accepts on the port, each a stream
+/// of its own. It ends once the session is dropped, or once connections stop
+/// being handed on, unless [`SessionBuilder::restore_forwards_after`] has the
+/// forward requested again. Dropping the handle asks the server to stop
+/// listening; connections already accepted stay open.
+///
+/// [`SessionBuilder::restore_forwards_after`]: crate::SessionBuilder::restore_forwards_after
+pub struct RemoteForward {
+    session: Weak<Connected>,
+    address: String,
+    port: u16,
+    connections: Connections,
+    traffic: Arc<Counters>,
+    restore: Option<(Duration, Events)>,
+    /// Request for the forward again, with the connections it hands on once
+    /// it is granted, or `None` if the session is gone.
+    restoring: Option<BoxFuture<'static, Option<Connections>>>,
+}
+
+/// Connections a server accepts on a remotely forwarded port.
+type Connections = mpsc::UnboundedReceiver<Box<dyn AsyncStream>>;
+
+impl RemoteForward {
+    /// Port the server listens on, which it picked if port 0 was asked for.
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Runs `handler` on every connection accepted, each in a task of its
+    /// own, until the session is dropped and the connections handled so far
+    /// are done.
+    pub async fn serve<F, Fut>(mut self, handler: F)
+    where
+        F: Fn(Box<dyn AsyncStream>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut handlers = JoinSet::new();
+        while let Some(stream) = self.next().await {
+            handlers.spawn(handler(stream));
+            while handlers.try_join_next().is_some() {}
+        }
+        handlers.join_all().await;
+    }
+
+    /// Forwards every connection accepted to `host` and `port` as this host
+    /// sees them, a local web server under development for example. A
+    /// connection that cannot be made is closed and logged, and forwarding
+    /// goes on until the session is dropped.
+    pub async fn forward_to(self, host: impl Into<String>, port: u16) {
+        let host: Arc<str> = host.into().into();
+        self.serve(|mut stream| {
+            let host = Arc::clone(&host);
+            async move {
+                let result = async {
+                    let mut local = TcpStream::connect((&*host, port)).await?;
+                    tokio::io::copy_bidirectional(&mut stream, &mut local).await
+                }
+                .await;
+                if let Err(error) = result {
+                    tracing::warn!(%host, port, %error, "forwarded connection failed");
+                }
+            }
+        })
+        .await;
+    }
+}
+
+impl Stream for RemoteForward {
+    type Item = Box<dyn AsyncStream>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Box<dyn AsyncStream>>> {
+        loop {
+            if let Some(restoring) = self.restoring.as_mut() {
+                let connections = ready!(restoring.as_mut().poll(cx));
+                self.restoring = None;
+                let Some(connections) = connections else {
+                    return Poll::Ready(None);
+                };
+                self.connections = connections;
+            }
+
+            if let Some(stream) = ready!(self.connections.poll_recv(cx)) {
+                return Poll::Ready(Some(self.traffic.meter(stream, ChannelKind::Forward)));
+            }
+            let Some((after, events)) = self.restore.clone() else {
+                return Poll::Ready(None);
+            };
+            tracing::warn!(address = %self.address, port = self.port, "remote forward stopped");
+            self.restoring = Some(Box::pin(reforward(
+                Weak::clone(&self.session),
+                self.address.clone(),
+                self.port,
+                after,
+                events,
+            )));
+        }
+    }
+}
+
+impl Drop for RemoteForward {
+    fn drop(&mut self) {
+        let Some(session) = self.session.upgrade() else {
+            return;
+        };
+        let address = std::mem::take(&mut self.address);
+        let port = self.port;
+
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                let _ = session.cancel_forward_remote(&address, port).await;
+            });
+        }
+    }
+}
+
+impl ConnectedSession {
+    /// Asks the server to listen on `bind_address` and `bind_port`, like
+    /// OpenSSH's `-R`, returning the connections it accepts there as a
+    /// [`Stream`], to expose a local service to the remote network for
+    /// example. Use [`RemoteForward::forward_to`] to forward them to a local
+    /// address, or [`RemoteForward::serve`] to handle them otherwise.
+    ///
+    /// An empty `bind_address` listens on the server's loopback addresses
+    /// only, and `*` on all of them if its `GatewayPorts` setting allows it.
+    /// With port 0, the server picks a free port, found with
+    /// [`RemoteForward::port`].
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses to listen, because the port is taken or its
+    ///   policy forbids forwarding for example, reported as
+    ///   [`Error::RemoteForwardFailed`].
+    pub async fn forward_remote(
+        &self,
+        bind_address: &str,
+        bind_port: u16,
+    ) -> Result<RemoteForward> {
+        let (sender, connections) = mpsc::unbounded_channel();
+        let port = self
+            .inner
+            .forward_remote(bind_address, bind_port, sender)
+            .await
+            .map_err(|source| Error::RemoteForwardFailed {
+                address: bind_address.to_string(),
+                port: bind_port,
+                source: Box::new(source),
+            })?;
+
+        Ok(RemoteForward {
+            session: Arc::downgrade(&self.inner),
+            address: bind_address.to_string(),
+            port,
+            connections,
+            traffic: Arc::clone(&self.traffic),
+            restore: self
+                .restore_forwards_after
+                .map(|after| (after, self.events.clone())),
+            restoring: None,
+        })
+    }
+
+    /// Listens on `bind_addr` and forwards every connection accepted there to
+    /// `target_host` and `target_port` as the remote host sees them, like
+    /// OpenSSH's `-L`, to reach a database behind a bastion for example. Each
+    /// connection gets a tunnel of its own; one that the server refuses is
+    /// closed and logged, and forwarding goes on.
+    ///
+    /// Bind to a loopback address such as `127.0.0.1:0` unless other hosts
+    /// are to use the forward. With port 0, the system picks a free port,
+    /// found with [`LocalForward::local_addr`].
+    ///
+    /// # Errors
+    ///
+    /// - If `bind_addr` cannot be listened on.
+    pub async fn forward_local(
+        &self,
+        bind_addr: impl ToSocketAddrs,
+        target_host: impl Into<String>,
+        target_port: u16,
+    ) -> Result<LocalForward> {
+        let host: Arc<str> = target_host.into().into();
+        let port = target_port;
+        self.listen(bind_addr, move |tunnels, stream, peer| {
+            let host = Arc::clone(&host);
+            async move {
+                if let Err(error) = forward(&tunnels, stream, &host, port).await {
+                    tracing::warn!(%peer, %host, port, %error, "forwarded connection failed");
+                }
+            }
+        })
+        .await
+    }
+
+    /// Listens on `bind_addr` and serves every connection accepted there
+    /// with `serve`, in a task of its own, until the session is dropped.
+    async fn listen<F, Fut>(&self, bind_addr: impl ToSocketAddrs, serve: F) -> Result<LocalForward>
+    where
+        F: Fn(Tunnels, TcpStream, SocketAddr) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind(bind_addr).await?;
+        let local_addr = listener.local_addr()?;
+        let task = tokio::spawn(accept(
+            listener,
+            Arc::downgrade(&self.inner),
+            Arc::clone(&self.traffic),
+            self.restore_forwards_after
+                .map(|after| (after, self.events.clone())),
+            serve,
+        ));
+
+        Ok(LocalForward { local_addr, task })
+    }
+}
+
+/// Session a local forward opens its tunnels over.
+struct Tunnels {
+    session: Arc<Connected>,
+    traffic: Arc<Counters>,
+}
+
+impl Tunnels {
+    /// Opens a tunnel to `host` and `port`, counting its data in the
+    /// session's traffic.
+    async fn open(&self, host: &str, port: u16) -> Result<Box<dyn AsyncStream>> {
+        let slot = self.traffic.open_channel(ChannelKind::Forward)?;
+        let stream = self.session.open_tunnel(host, port).await?;
+        slot.commit();
+
+        Ok(self.traffic.meter(stream, ChannelKind::Forward))
+    }
+}
+
+/// Accepts connections until `session` is gone, serving each with `serve`.
+/// If accepting fails, the error is returned, unless `restore` is set, in
+/// which case the address is bound again and the event emitted once it is.
+async fn accept<F, Fut>(
+    mut listener: TcpListener,
+    session: Weak<Connected>,
+    traffic: Arc<Counters>,
+    restore: Option<(Duration, Events)>,
+    serve: F,
+) -> Result<()>
+where
+    F: Fn(Tunnels, TcpStream, SocketAddr) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let local_addr = listener.local_addr()?;
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = connections.join_next() => continue,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                let Some((after, events)) = &restore else {
+                    return Err(error.into());
+                };
+                tracing::warn!(%local_addr, %error, "accepting forwarded connections failed");
+                drop(listener);
+                let Some(rebound) = rebind(local_addr, *after, &session).await else {
+                    return Ok(());
+                };
+                listener = rebound;
+                events.emit(Event::ForwardRestored { local_addr });
+                continue;
+            }
+        };
+        let Some(session) = session.upgrade() else {
+            return Ok(());
+        };
+        let tunnels = Tunnels {
+            session,
+            traffic: Arc::clone(&traffic),
+        };
+        connections.spawn(serve(tunnels, stream, peer));
+    }
+}
+
+/// Binds `local_addr` again, trying every `after`, until it is bound or
+/// `session` is gone.
+async fn rebind(
+    local_addr: SocketAddr,
+    after: Duration,
+    session: &Weak<Connected>,
+) -> Option<TcpListener> {
+    loop {
+        tokio::time::sleep(after).await;
+        if session.strong_count() == 0 {
+            return None;
+        }
+        match TcpListener::bind(local_addr).await {
+            Ok(listener) => return Some(listener),
+            Err(error) => tracing::warn!(%local_addr, %error, "binding forward again failed"),
+        }
+    }
+}
+
+/// Requests the remote forward on `address` and `port` again, trying every
+/// `after`, until it is granted or `session` is gone. The forward is
+/// cancelled first, so that the port is free even if only this side of it
+/// failed.
+async fn reforward(
+    session: Weak<Connected>,
+    address: String,
+    port: u16,
+    after: Duration,
+    events: Events,
+) -> Option<Connections> {
+    loop {
+        tokio::time::sleep(after).await;
+        let session = session.upgrade()?;
+        let _ = session.cancel_forward_remote(&address, port).await;
+        let (sender, connections) = mpsc::unbounded_channel();
+        match session.forward_remote(&address, port, sender).await {
+            Ok(_) => {
+                events.emit(Event::RemoteForwardRestored { address, port });
+                return Some(connections);
+            }
+            Err(error) => {
+                tracing::warn!(%address, port, %error, "requesting remote forward again failed");
+            }
+        }
+    }
+}
+
+async fn forward(tunnels: &Tunnels, mut stream: TcpStream, host: &str, port: u16) -> Result<()> {
+    let mut tunnel = tunnels.open(host, port).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut tunnel).await?;
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::BufReader;
+
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn forward_local_works() {
+        let session = test_server::connect().await;
+        let forward = session
+            .forward_local("127.0.0.1:0", "target", 22)
+            .await
+            .unwrap();
+
+        let stream = TcpStream::connect(forward.local_addr()).await.unwrap();
+        let mut banner = String::new();
+        BufReader::new(stream).read_line(&mut banner).await.unwrap();
+
+        assert!(banner.starts_with("SSH-2.0-"));
+    }
+
+    #[tokio::test]
+    async fn forward_local_closes_refused_connections() {
+        let session = test_server::connect().await;
+        let forward = session
+            .forward_local("127.0.0.1:0", test_server::UNREACHABLE_HOST, 22)
+            .await
+            .unwrap();
+
+        let mut stream = TcpStream::connect(forward.local_addr()).await.unwrap();
+        let read = stream.read(&mut [0; 16]).await.unwrap();
+
+        assert_eq!(read, 0);
+    }
+
+    #[tokio::test]
+    async fn forward_local_ends_with_session() {
+        let session = test_server::connect().await;
+        let forward = session
+            .forward_local("127.0.0.1:0", "target", 22)
+            .await
+            .unwrap();
+        let local_addr = forward.local_addr();
+
+        drop(session);
+        let _stream = TcpStream::connect(local_addr).await.unwrap();
+
+        forward.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebind_waits_for_address() {
+        let session = test_server::connect().await;
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = taken.local_addr().unwrap();
+
+        let rebound = tokio::spawn({
+            let session = Arc::downgrade(&session.inner);
+            async move { rebind(local_addr, Duration::from_millis(10), &session).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(taken);
+        let listener = rebound.await.unwrap().unwrap();
+
+        assert_eq!(listener.local_addr().unwrap(), local_addr);
+    }
+
+    #[tokio::test]
+    async fn rebind_ends_with_session() {
+        let session = test_server::connect().await;
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let weak = Arc::downgrade(&session.inner);
+
+        drop(session);
+        let rebound = rebind(
+            taken.local_addr().unwrap(),
+            Duration::from_millis(10),
+            &weak,
+        )
+        .await;
+
+        assert!(rebound.is_none());
+    }
+
+    #[tokio::test]
+    async fn forward_remote_works() {
+        let session = test_server::connect().await;
+        let mut forward = session.forward_remote("127.0.0.1", 0).await.unwrap();
+
+        let (client, accepted) = tokio::join!(
+            TcpStream::connect(("127.0.0.1", forward.port())),
+            forward.next()
+        );
+        client.unwrap().write_all(b"ping").await.unwrap();
+        let mut read = [0; 4];
+        accepted.unwrap().read_exact(&mut read).await.unwrap();
+
+        assert_eq!(&read, b"ping");
+    }
+
+    #[tokio::test]
+    async fn forward_remote_is_restored() {
+        let mut session = test_server::session("localhost");
+        session.restore_forwards_after = Some(Duration::from_millis(10));
+        session.stream = Some(test_server::spawn().into_stream().unwrap());
+        let session = session.connect().await.unwrap();
+        let mut events = session.events();
+        let mut forward = session.forward_remote("127.0.0.1", 0).await.unwrap();
+        let port = forward.port();
+
+        // Stands in for the driver no longer handing on connections.
+        forward.connections = mpsc::unbounded_channel().1;
+        let (client, accepted) = tokio::join!(
+            async {
+                while let Some(event) = events.next().await {
+                    if let Event::RemoteForwardRestored { port: restored, .. } = event
+                        && restored == port
+                    {
+                        break;
+                    }
+                }
+                TcpStream::connect(("127.0.0.1", port)).await
+            },
+            forward.next()
+        );
+        client.unwrap().write_all(b"ping").await.unwrap();
+        let mut read = [0; 4];
+        accepted.unwrap().read_exact(&mut read).await.unwrap();
+
+        assert_eq!(&read, b"ping");
+    }
+
+    #[tokio::test]
+    async fn forward_remote_forwards_to_local_address() {
+        let session = test_server::connect().await;
+        let local = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_port = local.local_addr().unwrap().port();
+        let forward = session.forward_remote("127.0.0.1", 0).await.unwrap();
+        let port = forward.port();
+        tokio::spawn(forward.forward_to("127.0.0.1", local_port));
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let (mut accepted, _) = local.accept().await.unwrap();
+        let mut read = [0; 4];
+        accepted.read_exact(&mut read).await.unwrap();
+
+        assert_eq!(&read, b"ping");
+    }
+}
@@ -0,0 +1,243 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::ToSocketAddrs;
+
+use super::LocalForward;
+use super::Tunnels;
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+const VERSION: u8 = 5;
+
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+
+const CONNECT: u8 = 1;
+
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+const SUCCEEDED: u8 = 0;
+const GENERAL_FAILURE: u8 = 1;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+impl ConnectedSession {
+    /// Runs a SOCKS5 proxy on `bind_addr`, like OpenSSH's `-D`, connecting
+    /// from the remote host to wherever clients ask, through a tunnel for
+    /// each connection. Tools with proxy settings, such as browsers and
+    /// `curl --socks5-hostname`, can then reach what the remote host can.
+    /// Host names are resolved by the remote host.
+    ///
+    /// Only the `CONNECT` command is supported, without authentication, so
+    /// bind to a loopback address such as `127.0.0.1:1080` unless other hosts
+    /// are to use the proxy. With port 0, the system picks a free port, found
+    /// with [`LocalForward::local_addr`].
+    ///
+    /// # Errors
+    ///
+    /// - If `bind_addr` cannot be listened on.
+    pub async fn socks5_proxy(&self, bind_addr: impl ToSocketAddrs) -> Result<LocalForward> {
+        self.listen(bind_addr, |tunnels, stream, peer| async move {
+            if let Err(error) = proxy(&tunnels, stream).await {
+                tracing::warn!(%peer, %error, "proxied connection failed");
+            }
+        })
+        .await
+    }
+}
+
+/// Serves a SOCKS5 client on `stream`, through a tunnel to where it asks.
+async fn proxy(tunnels: &Tunnels, mut stream: TcpStream) -> Result<()> {
+    let (host, port) = request(&mut stream).await?;
+    let mut tunnel = match tunnels.open(&host, port).await {
+        Ok(tunnel) => tunnel,
+        Err(source) => {
+            reply(&mut stream, GENERAL_FAILURE).await?;
+            return Err(Error::TunnelFailed {
+                host,
+                port,
+                source: Box::new(source),
+            });
+        }
+    };
+    reply(&mut stream, SUCCEEDED).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut tunnel).await?;
+
+    Ok(())
+}
+
+/// Negotiates no authentication with the client on `stream` and reads its
+/// request, returning the host and port to connect to. Requests that cannot
+/// be served are replied to before failing.
+async fn request(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> io::Result<(String, u16)> {
+    let version = stream.read_u8().await?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported SOCKS version {version}")));
+    }
+    let mut methods = vec![0; stream.read_u8().await?.into()];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTHENTICATION) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        return Err(invalid("client requires authentication".to_string()));
+    }
+    stream.write_all(&[VERSION, NO_AUTHENTICATION]).await?;
+
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _, address_type] = header;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported SOCKS version {version}")));
+    }
+    if command != CONNECT {
+        reply(stream, COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid(format!("unsupported SOCKS command {command}")));
+    }
+    let host = match address_type {
+        IPV4 => {
+            let mut octets = [0; 4];
+            stream.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        DOMAIN_NAME => {
+            let mut name = vec![0; stream.read_u8().await?.into()];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("host name is not UTF-8".to_string()))?
+        }
+        IPV6 => {
+            let mut octets = [0; 16];
+            stream.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        _ => {
+            reply(stream, ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(invalid(format!("unsupported address type {address_type}")));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    Ok((host, port))
+}
+
+/// Replies to a request with `code`. The address the tunnel is bound to is
+/// unknown, and reported as unspecified.
+async fn reply(stream: &mut (impl AsyncWrite + Unpin), code: u8) -> io::Result<()> {
+    stream
+        .write_all(&[VERSION, code, 0, IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    #[cfg(feature = "russh")]
+    use tokio::io::AsyncBufReadExt;
+    #[cfg(feature = "russh")]
+    use tokio::io::BufReader;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case(&[5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 1, 0, 22], "10.0.0.1", 22)]
+    #[case(&[5, 2, 2, 0, 5, 1, 0, 3, 2, b'd', b'b', 0x15, 0x38], "db", 5432)]
+    #[case(&[5, 1, 0, 5, 1, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 80], "::1", 80)]
+    #[tokio::test]
+    async fn request_works(
+        #[case] sent: &[u8],
+        #[case] host_should: &str,
+        #[case] port_should: u16,
+    ) {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(sent).await.unwrap();
+
+        let (host, port) = request(&mut server).await.unwrap();
+        let mut replied = [0; 2];
+        client.read_exact(&mut replied).await.unwrap();
+
+        assert_eq!((host.as_str(), port), (host_should, port_should));
+        assert_eq!(replied, [VERSION, NO_AUTHENTICATION]);
+    }
+
+    #[rstest]
+    #[case(&[4, 1, 0, 22], &[])]
+    #[case(&[5, 1, 2], &[VERSION, NO_ACCEPTABLE_METHODS])]
+    #[case(
+        &[5, 1, 0, 5, 2, 0, 1, 10, 0, 0, 1, 0, 22],
+        &[VERSION, NO_AUTHENTICATION, VERSION, COMMAND_NOT_SUPPORTED, 0, IPV4, 0, 0, 0, 0, 0, 0]
+    )]
+    #[tokio::test]
+    async fn request_rejects_unsupported(#[case] sent: &[u8], #[case] replied_should: &[u8]) {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(sent).await.unwrap();
+
+        let result = request(&mut server).await;
+        drop(server);
+        let mut replied = Vec::new();
+        client.read_to_end(&mut replied).await.unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(replied, replied_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn socks5_proxy_works() {
+        let session = test_server::connect().await;
+        let proxy = session.socks5_proxy("127.0.0.1:0").await.unwrap();
+
+        let mut stream = TcpStream::connect(proxy.local_addr()).await.unwrap();
+        stream
+            .write_all(&[
+                5, 1, 0, 5, 1, 0, 3, 6, b't', b'a', b'r', b'g', b'e', b't', 0, 22,
+            ])
+            .await
+            .unwrap();
+        let mut replied = [0; 12];
+        stream.read_exact(&mut replied).await.unwrap();
+        let mut banner = String::new();
+        BufReader::new(stream).read_line(&mut banner).await.unwrap();
+
+        assert_eq!(
+            replied[..4],
+            [VERSION, NO_AUTHENTICATION, VERSION, SUCCEEDED]
+        );
+        assert!(banner.starts_with("SSH-2.0-"));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn socks5_proxy_reports_refused_connections() {
+        let session = test_server::connect().await;
+        let proxy = session.socks5_proxy("127.0.0.1:0").await.unwrap();
+        let host = test_server::UNREACHABLE_HOST.as_bytes();
+
+        let mut stream = TcpStream::connect(proxy.local_addr()).await.unwrap();
+        let header = [5, 1, 0, 5, 1, 0, 3, u8::try_from(host.len()).unwrap()];
+        stream
+            .write_all(&[&header[..], host, &[0, 22]].concat())
+            .await
+            .unwrap();
+        let mut replied = Vec::new();
+        stream.read_to_end(&mut replied).await.unwrap();
+
+        assert_eq!(
+            replied[..4],
+            [VERSION, NO_AUTHENTICATION, VERSION, GENERAL_FAILURE]
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::StatusCode;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncSeek;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::io::ReadBuf;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+mod checksum;
+mod digest;
+mod dir;
+mod ensure;
+mod metadata;
+mod options;
+mod permissions;
+mod selinux;
+mod tar;
+mod transfer;
+mod watch;
+
+pub use checksum::Checksum;
+pub use digest::Drift;
+pub use digest::TreeDigest;
+pub use digest::TreeEntry;
+pub use dir::DirBuilder;
+pub use dir::DirEntry;
+pub use dir::ReadDir;
+pub use ensure::DirChange;
+pub use ensure::Owner;
+pub use metadata::Metadata;
+pub use options::OpenOptions;
+pub use permissions::Permissions;
+pub use selinux::SecurityContext;
+pub use transfer::Compression;
+pub use transfer::TransferOptions;
+pub use transfer::UploadPermissions;
+pub use transfer::remote_to_remote;
+pub use watch::Watch;
+pub use watch::WatchEvent;
+
+/// File system of the remote host, accessed over SFTP. Created by
+/// [`ConnectedSession::fs`].
+///
+/// SFTP has no notion of `~`, so a leading `~` or `~user` in paths is expanded
+/// to the matching home directory before it is sent to the server, unless
+/// disabled with [`Fs::expand_tilde`].
+///
+/// OpenSSH on Windows names files like `/C:/Users/deploy`. Paths written the
+/// Windows way, such as `C:\Users\deploy` or `~\app`, are converted to that
+/// form when the server is found to run Windows.
+#[derive(Clone, Copy)]
+pub struct Fs<'s> {
+    session: &'s ConnectedSession,
+    expand_tilde: bool,
+}
+
+impl<'s> Fs<'s> {
+    pub(crate) fn new(session: &'s ConnectedSession) -> Self {
+        Self {
+            session,
+            expand_tilde: true,
+        }
+    }
+
+    /// Whether to expand a leading `~` or `~user` in paths. Enabled by
+    /// default; disable it to send paths verbatim, for example to reach a file
+    /// actually named `~`.
+    #[must_use]
+    pub fn expand_tilde(mut self, enabled: bool) -> Self {
+        self.expand_tilde = enabled;
+        self
+    }
+
+    /// Opens a file in read-only mode.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist or cannot be read.
+    pub async fn open(&self, path: impl AsRef<Utf8Path>) -> Result<File> {
+        self.open_options().read(true).open(path).await
+    }
+
+    /// Opens a file in write-only mode, creating it if it does not exist and
+    /// truncating it if it does.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` cannot be created or written.
+    pub async fn create(&self, path: impl AsRef<Utf8Path>) -> Result<File> {
+        self.open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await
+    }
+
+    /// Options to open a file with, for access [`Fs::open`] and
+    /// [`Fs::create`] do not give, such as appending or reading and writing.
+    #[must_use]
+    pub fn open_options(&self) -> OpenOptions<'s> {
+        OpenOptions::new(*self)
+    }
+
+    /// Builder to create directories with, recursively or with given
+    /// permissions.
+    #[must_use]
+    pub fn dir_builder(&self) -> DirBuilder<'s> {
+        DirBuilder::new(*self)
+    }
+
+    /// Reads the entire contents of a file.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist or cannot be read.
+    pub async fn read(&self, path: impl AsRef<Utf8Path>) -> Result<Vec<u8>> {
+        let path = self.resolve(path.as_ref()).await?;
+
+        Ok(self.sftp().await?.read(path.as_str()).await?)
+    }
+
+    /// Writes `contents` to a file, replacing it if it exists.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` cannot be created or written.
+    pub async fn write(
+        &self,
+        path: impl AsRef<Utf8Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> Result<()> {
+        let mut file = self.create(path).await?;
+        file.write_all(contents.as_ref()).await?;
+        file.shutdown().await?;
+
+        Ok(())
+    }
+
+    /// Creates a directory. Its parent must already exist.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` already exists or its parent does not.
+    pub async fn create_dir(&self, path: impl AsRef<Utf8Path>) -> Result<()> {
+        let path = self.resolve(path.as_ref()).await?;
+
+        Ok(self.sftp().await?.create_dir(path.as_str()).await?)
+    }
+
+    /// Removes a file.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist or is a directory.
+    pub async fn remove_file(&self, path: impl AsRef<Utf8Path>) -> Result<()> {
+        let path = self.resolve(path.as_ref()).await?;
+
+        Ok(self.sftp().await?.remove_file(path.as_str()).await?)
+    }
+
+    /// Whether `path` exists.
+    ///
+    /// # Errors
+    ///
+    /// - If the server fails for a reason other than `path` not existing.
+    pub async fn try_exists(&self, path: impl AsRef<Utf8Path>) -> Result<bool> {
+        let path = self.resolve(path.as_ref()).await?;
+
+        Ok(self.sftp().await?.try_exists(path.as_str()).await?)
+    }
+
+    /// Metadata of `path`, following symbolic links.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist.
+    pub async fn metadata(&self, path: impl AsRef<Utf8Path>) -> Result<Metadata> {
+        let path = self.resolve(path.as_ref()).await?;
+        let metadata = self.sftp().await?.metadata(path.as_str()).await?;
+
+        Ok(Metadata::new(metadata))
+    }
+
+    /// Metadata of `path` itself, without following a symbolic link.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist.
+    pub async fn symlink_metadata(&self, path: impl AsRef<Utf8Path>) -> Result<Metadata> {
+        let path = self.resolve(path.as_ref()).await?;
+        let metadata = self.sftp().await?.symlink_metadata(path.as_str()).await?;
+
+        Ok(Metadata::new(metadata))
+    }
+
+    /// Absolute form of `path`, with symbolic links resolved, as reported by
+    /// the server.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist.
+    pub async fn canonicalize(&self, path: impl AsRef<Utf8Path>) -> Result<Utf8PathBuf> {
+        let path = self.resolve(path.as_ref()).await?;
+
+        Ok(self.sftp().await?.canonicalize(path.as_str()).await?.into())
+    }
+
+    /// Path as it is sent to the server, after conversion from the Windows
+    /// form and tilde expansion.
+    ///
+    /// # Errors
+    ///
+    /// - If the home directory cannot be determined.
+    /// - If `path` starts with `~user` and `user` does not exist.
+    pub async fn resolve(&self, path: &Utf8Path) -> Result<Utf8PathBuf> {
+        // Whether the server runs Windows is only looked up for paths that
+        // would need converting.
+        let path = match windows_path(path.as_str()) {
+            Some(converted) if is_windows_home(self.session.home_dir().await?) => {
+                Utf8PathBuf::from(converted)
+            }
+            _ => path.to_path_buf(),
+        };
+        let Some((user, rest)) = split_tilde(path.as_str()).filter(|_| self.expand_tilde) else {
+            return Ok(path);
+        };
+
+        let home = if user.is_empty() {
+            self.session.home_dir().await?.clone()
+        } else {
+            self.user_home_dir(user).await?
+        };
+
+        Ok(under_home(home, rest))
+    }
+
+    async fn user_home_dir(&self, user: &str) -> Result<Utf8PathBuf> {
+        let output = self
+            .session
+            .command("sh")
+            .args(["-c", &format!("echo ~{user}")])
+            .output()
+            .await?;
+        let home = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        // Shells leave the prefix as is when the user does not exist.
+        if !output.status.success() || !home.starts_with('/') {
+            return Err(Error::UnknownRemoteUser(user.to_string()));
+        }
+
+        Ok(home.into())
+    }
+
+    async fn sftp(&self) -> Result<&'s SftpSession> {
+        self.session.sftp().await
+    }
+}
+
+/// User named by a leading `~` or `~user` in `path`, empty for the session's
+/// own user, and the rest of the path after the following `/`. `None` if
+/// `path` has no tilde prefix a shell would expand.
+fn split_tilde(path: &str) -> Option<(&str, &str)> {
+    let tilde = path.strip_prefix('~')?;
+    let (user, rest) = tilde.split_once('/').unwrap_or((tilde, ""));
+
+    is_user_name(user).then_some((user, rest))
+}
+
+/// `rest` of a tilde-prefixed path, under `home`. Extra leading slashes are
+/// dropped, since a shell reads `~//etc` as `$HOME//etc` and not `/etc`.
+fn under_home(home: Utf8PathBuf, rest: &str) -> Utf8PathBuf {
+    let rest = rest.trim_start_matches('/');
+    if rest.is_empty() {
+        home
+    } else {
+        home.join(rest)
+    }
+}
+
+/// `path` in the form OpenSSH on Windows expects, with forward slashes and a
+/// drive letter after a leading slash, if written with backslashes or a drive
+/// letter. `None` if it is not.
+fn windows_path(path: &str) -> Option<String> {
+    let drive = has_drive(path);
+    if !drive && !path.contains('\\') {
+        return None;
+    }
+
+    let path = path.replace('\\', "/");
+    Some(if drive { format!("/{path}") } else { path })
+}
+
+/// Whether `path` starts with a drive letter, as in `C:` or `C:\`.
+fn has_drive(path: &str) -> bool {
+    match path.as_bytes() {
+        [letter, b':', rest @ ..] => {
+            letter.is_ascii_alphabetic() && matches!(rest.first(), None | Some(b'/' | b'\\'))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `home` is on a drive, as OpenSSH on Windows reports it.
+fn is_windows_home(home: &Utf8Path) -> bool {
+    home.as_str().strip_prefix('/').is_some_and(has_drive)
+}
+
+/// Whether the server failed because a path does not exist.
+fn is_not_found(error: &russh_sftp::client::error::Error) -> bool {
+    matches!(
+        error,
+        russh_sftp::client::error::Error::Status(status)
+            if status.status_code == StatusCode::NoSuchFile
+    )
+}
+
+/// Whether `name` is safe to pass to the remote shell unquoted after `~`.
+fn is_user_name(name: &str) -> bool {
+    name.bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || b"._-".contains(&byte))
+}
+
+/// Open file on the remote host.
+///
+/// - A completed write has been acknowledged by the server, so flushing has
+///   nothing left to do.
+/// - Shutting down closes the file on the server. Writing afterward fails.
+/// - [`File::sync_all`] asks the server to write the file to disk.
+/// - Dropping without shutting down closes the file in the background.
+/// - Seeking relative to the end asks the server for the file's size.
+pub struct File(russh_sftp::client::fs::File);
+
+impl File {
+    /// Metadata of the open file.
+    ///
+    /// # Errors
+    ///
+    /// - If the server fails to stat the file.
+    pub async fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata::new(self.0.metadata().await?))
+    }
+
+    /// Asks the server to write the file's contents to disk, with the
+    /// `fsync@openssh.com` extension. Servers without the extension make no
+    /// promises about durability, and for them this does nothing.
+    ///
+    /// # Errors
+    ///
+    /// - If the server fails to sync the file.
+    pub async fn sync_all(&self) -> Result<()> {
+        Ok(self.0.sync_all().await?)
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for File {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.0).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.0).poll_complete(cx)
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    // The inner file syncs to disk on flush, which callers like
+    // `tokio::io::copy` do routinely; that is left to `sync_all` instead.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rstest::rstest;
+    #[cfg(feature = "russh")]
+    use tokio::io::AsyncReadExt;
+    #[cfg(feature = "russh")]
+    use tokio::io::AsyncSeekExt;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case("alice", true)]
+    #[case("svc.deploy-01_x", true)]
+    #[case("bob;reboot", false)]
+    #[case("$(id)", false)]
+    fn is_user_name_works(#[case] name: &str, #[case] valid_should: bool) {
+        assert_eq!(is_user_name(name), valid_should);
+    }
+
+    #[rstest]
+    #[case("C:\\Users\\deploy", Some("/C:/Users/deploy"))]
+    #[case("d:/data", Some("/d:/data"))]
+    #[case("C:", Some("/C:"))]
+    #[case("~\\app\\config.json", Some("~/app/config.json"))]
+    #[case("/home/deploy", None)]
+    #[case("C:data", None)]
+    fn windows_path_works(#[case] path: &str, #[case] converted_should: Option<&str>) {
+        assert_eq!(windows_path(path).as_deref(), converted_should);
+    }
+
+    #[rstest]
+    #[case("/C:/Users/deploy", true)]
+    #[case("/home/deploy", false)]
+    #[case("/C", false)]
+    fn is_windows_home_works(#[case] home: &str, #[case] windows_should: bool) {
+        assert_eq!(is_windows_home(Utf8Path::new(home)), windows_should);
+    }
+
+    proptest! {
+        #[test]
+        fn split_tilde_round_trips(path in "~?[a-z._$;~/-]{0,12}") {
+            let Some((user, rest)) = split_tilde(&path) else {
+                // Only tildes followed by something other than a user name are
+                // left alone, as by a shell.
+                let user = path
+                    .strip_prefix('~')
+                    .map(|tilde| tilde.split('/').next().unwrap_or_default());
+                prop_assert!(user.is_none_or(|user| !is_user_name(user)));
+                return Ok(());
+            };
+
+            prop_assert!(is_user_name(user));
+            let rejoined = path == format!("~{user}") || path == format!("~{user}/{rest}");
+            prop_assert!(rejoined, "{path:?} does not split into {user:?} and {rest:?}");
+        }
+
+        #[test]
+        fn under_home_stays_under_home(
+            home in "/[a-z]{1,8}(/[a-z.]{1,8}){0,2}",
+            rest in "[a-z./]{0,16}",
+        ) {
+            let path = under_home(Utf8PathBuf::from(&home), &rest);
+
+            prop_assert!(path.starts_with(&home));
+            let rest = rest.trim_start_matches('/');
+            if rest.is_empty() {
+                prop_assert_eq!(path.as_str(), home);
+            } else {
+                prop_assert_eq!(path.as_str(), format!("{home}/{rest}"));
+            }
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn resolve_expands_tilde() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+        let home = fs.canonicalize(".").await.unwrap();
+
+        assert_eq!(fs.resolve("~".into()).await.unwrap(), home);
+        assert_eq!(fs.resolve("~/a/b".into()).await.unwrap(), home.join("a/b"));
+        assert_eq!(
+            fs.expand_tilde(false).resolve("~/a".into()).await.unwrap(),
+            "~/a"
+        );
+        assert_eq!(fs.resolve("/tmp/~".into()).await.unwrap(), "/tmp/~");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn resolve_rejects_unknown_users() {
+        let session = test_server::connect().await;
+
+        let result = session
+            .fs()
+            .resolve("~ssh-util-no-such-user/x".into())
+            .await;
+
+        assert!(matches!(result, Err(Error::UnknownRemoteUser(_))));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn write_then_read_works() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+
+        fs.write("~/hello.txt", "hello").await.unwrap();
+
+        assert_eq!(fs.read("hello.txt").await.unwrap(), b"hello");
+        assert!(fs.try_exists("~/hello.txt").await.unwrap());
+        fs.remove_file("~/hello.txt").await.unwrap();
+        assert!(!fs.try_exists("~/hello.txt").await.unwrap());
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn file_sync_then_shutdown_works() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+
+        let mut file = fs.create("~/synced.txt").await.unwrap();
+        file.write_all(b"durable").await.unwrap();
+        file.flush().await.unwrap();
+        file.sync_all().await.unwrap();
+        file.shutdown().await.unwrap();
+
+        assert!(file.write_all(b"late").await.is_err());
+        assert_eq!(fs.read("~/synced.txt").await.unwrap(), b"durable");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn file_seek_works() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+        fs.write("~/seek.txt", "hello world").await.unwrap();
+
+        let mut file = fs.open("~/seek.txt").await.unwrap();
+        let position = file.seek(io::SeekFrom::End(-5)).await.unwrap();
+        let mut end = String::new();
+        file.read_to_string(&mut end).await.unwrap();
+
+        assert_eq!(position, 6);
+        assert_eq!(end, "world");
+        assert_eq!(file.metadata().await.unwrap().len(), 11);
+        assert!(fs.symlink_metadata("~/seek.txt").await.unwrap().is_file());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use sha2::Digest;
+
+use super::Compression;
+use super::Fs;
+use crate::Error;
+use crate::Result;
+use crate::shell;
+
+/// Hash algorithm used to verify file contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Sha256,
+}
+
+impl Checksum {
+    /// Commands that print the hash of a file, tried in order, as not every
+    /// system has the GNU coreutils one.
+    fn commands(self) -> &'static [&'static [&'static str]] {
+        match self {
+            Checksum::Sha256 => &[&["sha256sum"], &["shasum", "-a", "256"]],
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            Checksum::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+}
+
+/// Incremental hash of data as it is transferred.
+pub(crate) enum Hasher {
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Lowercase hex digest, as printed by `sha256sum` and friends.
+    pub(crate) fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => hex(&hasher.finalize()),
+        }
+    }
+}
+
+impl Fs<'_> {
+    /// Hash of a file's contents as a lowercase hex string, computed on the
+    /// remote host so the file does not have to be downloaded.
+    ///
+    /// # Errors
+    ///
+    /// - If no command to compute `checksum` is available remotely.
+    /// - If `path` does not exist or cannot be read.
+    pub async fn checksum(&self, path: impl AsRef<Utf8Path>, checksum: Checksum) -> Result<String> {
+        let path = self.resolve(path.as_ref()).await?;
+
+        self.checksum_of(&path, checksum, None).await
+    }
+
+    /// Hash of a file's contents, after decompressing them if `compression`
+    /// is set. A file that fails to decompress yields the hash of whatever
+    /// was decompressed, which is then simply a mismatch.
+    pub(crate) async fn checksum_of(
+        &self,
+        path: &Utf8Path,
+        checksum: Checksum,
+        compression: Option<Compression>,
+    ) -> Result<String> {
+        let commands = checksum.commands();
+        let mut result = Err(Error::ProgramNotFound(commands[0][0].to_string()));
+        for &words in commands {
+            let mut command = match compression {
+                Some(_) => self.session.command("sh"),
+                None => self.session.command(words[0]),
+            };
+            if let Some(compression) = compression {
+                let program = compression.program();
+                command.args([
+                    "-c",
+                    &format!(
+                        "{program} -dc -- {path} | {hash}",
+                        path = shell::quote(path.as_str()),
+                        hash = shell::join(words.iter().copied()),
+                    ),
+                ]);
+            } else {
+                command
+                    .args(words[1..].iter().copied())
+                    .arg("--")
+                    .arg(path.as_str());
+            }
+            let output = command.output().await?;
+
+            if output.status.success()
+                && let Some(digest) = parse_digest(&output.stdout)
+            {
+                return Ok(digest);
+            }
+            result = Err(Error::CommandFailed {
+                command: command.command_line(),
+                status: output.status,
+            });
+        }
+
+        result
+    }
+
+    /// Hashes of the files at `paths`, in the same order, from one command
+    /// for all of them.
+    pub(crate) async fn checksum_batch(
+        &self,
+        paths: &[Utf8PathBuf],
+        checksum: Checksum,
+    ) -> Result<Vec<String>> {
+        let commands = checksum.commands();
+        let mut result = Err(Error::ProgramNotFound(commands[0][0].to_string()));
+        for &words in commands {
+            let mut command = self.session.command(words[0]);
+            command
+                .args(words[1..].iter().copied())
+                .arg("--")
+                .args(paths.iter().map(|path| path.as_str()));
+            let output = command.output().await?;
+
+            if !output.status.success() {
+                result = Err(Error::CommandFailed {
+                    command: command.command_line(),
+                    status: output.status,
+                });
+                continue;
+            }
+            let mut digests = parse_digests(&output.stdout);
+            return paths
+                .iter()
+                .map(|path| {
+                    digests
+                        .remove(path.as_str())
+                        .ok_or_else(|| Error::UnexpectedOutput {
+                            command: command.command_line(),
+                            output: format!("no digest for {path}"),
+                        })
+                })
+                .collect();
+        }
+
+        result
+    }
+}
+
+/// Digest from the first line of `sha256sum`-style output, `<digest>  <path>`.
+fn parse_digest(output: &[u8]) -> Option<String> {
+    let output = std::str::from_utf8(output).ok()?;
+    let digest = output.split_whitespace().next()?;
+
+    // GNU tools prefix the line with `\` when the path needs escaping.
+    let digest = digest.strip_prefix('\\').unwrap_or(digest);
+    digest
+        .bytes()
+        .all(|byte| byte.is_ascii_hexdigit())
+        .then(|| digest.to_ascii_lowercase())
+}
+
+/// Digests by path from `sha256sum`-style output of several files, one
+/// `<digest>  <path>` line each, or `<digest> *<path>` in binary mode.
+fn parse_digests(output: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter_map(|line| {
+            // GNU tools prefix the line with `\` when the path needs escaping.
+            let (escaped, line) = match line.strip_prefix('\\') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (digest, path) = line.split_once(' ')?;
+            let path = path.strip_prefix([' ', '*'])?;
+            let path = if escaped {
+                unescape(path)
+            } else {
+                path.to_string()
+            };
+            digest
+                .bytes()
+                .all(|byte| byte.is_ascii_hexdigit())
+                .then(|| (path, digest.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Path escaped by GNU tools, with `\\` for a backslash, `\n` for a newline
+/// and `\r` for a carriage return.
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn hasher_works() {
+        let mut hasher = Checksum::Sha256.hasher();
+        hasher.update(b"hel");
+        hasher.update(b"lo");
+
+        assert_eq!(hasher.finalize(), HELLO_SHA256);
+    }
+
+    #[rstest]
+    #[case(b"2CF24DBA  hello.txt\n", Some("2cf24dba"))]
+    #[case(b"\\2cf24dba  hello\\nworld\n", Some("2cf24dba"))]
+    #[case(b"sha256sum: hello.txt: No such file\n", None)]
+    #[case(b"", None)]
+    fn parse_digest_works(#[case] output: &[u8], #[case] digest_should: Option<&str>) {
+        assert_eq!(parse_digest(output).as_deref(), digest_should);
+    }
+
+    #[test]
+    fn parse_digests_works() {
+        let digests = parse_digests(
+            b"2CF24DBA  /srv/hello.txt\n\
+              \\486ea462  /srv/two\\nlines\\\\.txt\n\
+              fcde2b2e */srv/binary.bin\n\
+              sha256sum: /srv/gone: No such file or directory\n",
+        );
+
+        assert_eq!(
+            digests,
+            HashMap::from([
+                ("/srv/hello.txt".to_string(), "2cf24dba".to_string()),
+                ("/srv/two\nlines\\.txt".to_string(), "486ea462".to_string()),
+                ("/srv/binary.bin".to_string(), "fcde2b2e".to_string()),
+            ])
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn checksum_works() {
+        let session = crate::test_server::connect().await;
+        let fs = session.fs();
+        fs.write("~/hello.txt", "hello").await.unwrap();
+
+        let digest = fs.checksum("~/hello.txt", Checksum::Sha256).await.unwrap();
+
+        assert_eq!(digest, HELLO_SHA256);
+    }
+}
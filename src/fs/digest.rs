@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use tokio::io::AsyncReadExt;
+
+use super::Checksum;
+use super::Fs;
+use super::Permissions;
+use crate::Result;
+
+/// Files hashed by one remote command.
+const HASH_BATCH_SIZE: usize = 256;
+/// Hashing commands run at once, each on its own channel, well under the 10
+/// sessions per connection sshd allows by default.
+const HASH_BATCHES_IN_FLIGHT: usize = 4;
+/// Bytes read at a time when hashing local files.
+const LOCAL_READ_SIZE: usize = 64 * 1024;
+
+/// What a [`TreeDigest`] records about one entry of a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeEntry {
+    File {
+        size: u64,
+        permissions: Permissions,
+        checksum: String,
+    },
+    Dir {
+        permissions: Permissions,
+    },
+    /// Symbolic link, which is recorded rather than followed.
+    Symlink {
+        target: Utf8PathBuf,
+    },
+}
+
+/// Difference between a tree and the one it is expected to match, as found by
+/// [`TreeDigest::drift`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    /// Expected but absent.
+    Missing(Utf8PathBuf),
+    /// Present but not expected.
+    Extra(Utf8PathBuf),
+    /// Present with another size, permissions, contents, link target or kind
+    /// than expected.
+    Changed {
+        path: Utf8PathBuf,
+        expected: TreeEntry,
+        found: TreeEntry,
+    },
+}
+
+/// Manifest of a directory tree: every entry under its root, keyed by its
+/// path relative to the root, so that trees on different hosts, or a remote
+/// one and a local golden copy, can be compared with [`TreeDigest::drift`].
+/// The root itself is not an entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeDigest {
+    entries: BTreeMap<Utf8PathBuf, TreeEntry>,
+}
+
+impl TreeDigest {
+    /// Digest of a local directory tree, for comparison with remote ones.
+    /// Special files such as sockets are skipped.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` or an entry under it cannot be read.
+    /// - If an entry name is not UTF-8.
+    pub async fn local(path: impl AsRef<Utf8Path>, checksum: Checksum) -> Result<Self> {
+        let root = path.as_ref();
+        let mut entries = BTreeMap::new();
+
+        let mut dirs = vec![Utf8PathBuf::new()];
+        while let Some(relative) = dirs.pop() {
+            let mut dir = tokio::fs::read_dir(root.join(&relative)).await?;
+            while let Some(entry) = dir.next_entry().await? {
+                let name = entry.file_name().into_string().map_err(|name| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("file name is not UTF-8: {}", name.display()),
+                    )
+                })?;
+                let path = relative.join(name);
+                let metadata = tokio::fs::symlink_metadata(root.join(&path)).await?;
+                let permissions = Permissions::from_mode(metadata.permissions().mode());
+
+                let entry = if metadata.is_dir() {
+                    dirs.push(path.clone());
+                    TreeEntry::Dir { permissions }
+                } else if metadata.is_symlink() {
+                    let target = tokio::fs::read_link(root.join(&path)).await?;
+                    let target = Utf8PathBuf::try_from(target)
+                        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                    TreeEntry::Symlink { target }
+                } else if metadata.is_file() {
+                    TreeEntry::File {
+                        size: metadata.len(),
+                        permissions,
+                        checksum: local_checksum(&root.join(&path), checksum).await?,
+                    }
+                } else {
+                    tracing::warn!(path = %root.join(path), "skipping special file");
+                    continue;
+                };
+                entries.insert(path, entry);
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Entries by path relative to the root, in path order.
+    #[must_use]
+    pub fn entries(&self) -> &BTreeMap<Utf8PathBuf, TreeEntry> {
+        &self.entries
+    }
+
+    /// How this tree differs from `expected`, in path order. Empty if the
+    /// trees match.
+    #[must_use]
+    pub fn drift(&self, expected: &TreeDigest) -> Vec<Drift> {
+        let mut drift = Vec::new();
+        for (path, expected_entry) in &expected.entries {
+            match self.entries.get(path) {
+                None => drift.push(Drift::Missing(path.clone())),
+                Some(found) if found != expected_entry => drift.push(Drift::Changed {
+                    path: path.clone(),
+                    expected: expected_entry.clone(),
+                    found: found.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for path in self.entries.keys() {
+            if !expected.entries.contains_key(path) {
+                drift.push(Drift::Extra(path.clone()));
+            }
+        }
+        drift.sort_by(|a, b| a.path().cmp(b.path()));
+
+        drift
+    }
+}
+
+impl Drift {
+    /// Path of the entry that differs, relative to the root.
+    #[must_use]
+    pub fn path(&self) -> &Utf8Path {
+        match self {
+            Drift::Missing(path) | Drift::Extra(path) | Drift::Changed { path, .. } => path,
+        }
+    }
+}
+
+impl Fs<'_> {
+    /// Digest of the tree under `path`, listed over SFTP. Files are hashed on
+    /// the remote host, many per command and several commands at once, so
+    /// that large trees take few round trips and are never downloaded.
+    /// Special files such as sockets are skipped.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` or a directory under it cannot be listed.
+    /// - If no command to compute `checksum` is available remotely.
+    /// - If a file cannot be hashed, for example because it was removed while
+    ///   the tree was listed.
+    pub async fn tree_digest(
+        &self,
+        path: impl AsRef<Utf8Path>,
+        checksum: Checksum,
+    ) -> Result<TreeDigest> {
+        let root = self.resolve(path.as_ref()).await?;
+        let sftp = self.sftp().await?;
+        let mut entries = BTreeMap::new();
+        let mut files = Vec::new();
+
+        let mut dirs = vec![Utf8PathBuf::new()];
+        while let Some(relative) = dirs.pop() {
+            for entry in sftp.read_dir(root.join(&relative).as_str()).await? {
+                let name = entry.file_name();
+                // Listed by some servers, such as OpenSSH's.
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let path = relative.join(name);
+                let metadata = entry.metadata();
+                let permissions = Permissions::from_mode(metadata.permissions.unwrap_or_default());
+
+                let file_type = entry.file_type();
+                if file_type.is_dir() {
+                    dirs.push(path.clone());
+                    entries.insert(path, TreeEntry::Dir { permissions });
+                } else if file_type.is_symlink() {
+                    let target = sftp.read_link(root.join(&path).as_str()).await?;
+                    entries.insert(
+                        path,
+                        TreeEntry::Symlink {
+                            target: target.into(),
+                        },
+                    );
+                } else if file_type.is_file() {
+                    files.push((path, metadata.size.unwrap_or_default(), permissions));
+                } else {
+                    tracing::warn!(path = %root.join(path), "skipping special file");
+                }
+            }
+        }
+
+        let paths: Vec<_> = files.iter().map(|(path, ..)| root.join(path)).collect();
+        let checksums: Vec<Vec<String>> = futures::stream::iter(paths.chunks(HASH_BATCH_SIZE))
+            .map(|batch| self.checksum_batch(batch, checksum))
+            .buffered(HASH_BATCHES_IN_FLIGHT)
+            .try_collect()
+            .await?;
+
+        for ((path, size, permissions), checksum) in
+            files.into_iter().zip(checksums.into_iter().flatten())
+        {
+            entries.insert(
+                path,
+                TreeEntry::File {
+                    size,
+                    permissions,
+                    checksum,
+                },
+            );
+        }
+
+        Ok(TreeDigest { entries })
+    }
+}
+
+async fn local_checksum(path: &Utf8Path, checksum: Checksum) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = checksum.hasher();
+    let mut buf = vec![0; LOCAL_READ_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(checksum: &str) -> TreeEntry {
+        TreeEntry::File {
+            size: 5,
+            permissions: Permissions::from_mode(0o644),
+            checksum: checksum.to_string(),
+        }
+    }
+
+    fn digest(entries: &[(&str, TreeEntry)]) -> TreeDigest {
+        TreeDigest {
+            entries: entries
+                .iter()
+                .map(|(path, entry)| (Utf8PathBuf::from(path), entry.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn drift_works() {
+        let expected = digest(&[
+            ("a.txt", file("aaaa")),
+            ("b.txt", file("bbbb")),
+            ("c.txt", file("cccc")),
+        ]);
+        let found = digest(&[
+            ("a.txt", file("aaaa")),
+            ("b.txt", file("ffff")),
+            ("d.txt", file("dddd")),
+        ]);
+
+        assert_eq!(
+            found.drift(&expected),
+            [
+                Drift::Changed {
+                    path: "b.txt".into(),
+                    expected: file("bbbb"),
+                    found: file("ffff"),
+                },
+                Drift::Missing("c.txt".into()),
+                Drift::Extra("d.txt".into()),
+            ]
+        );
+        assert!(expected.drift(&expected).is_empty());
+    }
+
+    #[tokio::test]
+    async fn local_works() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("etc")).unwrap();
+        std::fs::write(dir.path().join("etc/app.conf"), "hello").unwrap();
+        std::os::unix::fs::symlink("etc/app.conf", dir.path().join("app.conf")).unwrap();
+        let root = Utf8Path::from_path(dir.path()).unwrap();
+
+        let digest = TreeDigest::local(root, Checksum::Sha256).await.unwrap();
+
+        let paths: Vec<_> = digest.entries().keys().map(|path| path.as_str()).collect();
+        assert_eq!(paths, ["app.conf", "etc", "etc/app.conf"]);
+        assert_eq!(
+            digest.entries()[Utf8Path::new("app.conf")],
+            TreeEntry::Symlink {
+                target: "etc/app.conf".into()
+            }
+        );
+        assert!(matches!(
+            &digest.entries()[Utf8Path::new("etc/app.conf")],
+            TreeEntry::File { size: 5, checksum, .. }
+                if checksum == "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        ));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn tree_digest_matches_local() {
+        let session = crate::test_server::connect().await;
+        let fs = session.fs();
+        let dir = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(dir.path()).unwrap();
+        std::fs::create_dir(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/app.conf"), "hello").unwrap();
+        std::fs::write(root.join("top.txt"), "top").unwrap();
+        for path in ["etc", "etc/app.conf", "top.txt"] {
+            std::fs::set_permissions(root.join(path), std::fs::Permissions::from_mode(0o750))
+                .unwrap();
+        }
+        let golden = TreeDigest::local(root, Checksum::Sha256).await.unwrap();
+        fs.create_dir("~/tree").await.unwrap();
+        fs.create_dir("~/tree/etc").await.unwrap();
+        fs.write("~/tree/etc/app.conf", "hello").await.unwrap();
+        fs.write("~/tree/top.txt", "drifted").await.unwrap();
+        for path in ["~/tree/etc", "~/tree/etc/app.conf", "~/tree/top.txt"] {
+            fs.set_permissions(path, Permissions::from_mode(0o750))
+                .await
+                .unwrap();
+        }
+
+        let digest = fs.tree_digest("~/tree", Checksum::Sha256).await.unwrap();
+
+        let drift = digest.drift(&golden);
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].path(), "top.txt");
+    }
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use futures::Stream;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileAttributes;
+
+use super::Fs;
+use super::Metadata;
+use super::Permissions;
+use super::is_not_found;
+use crate::Result;
+
+/// Builder for remote directories, like [`std::fs::DirBuilder`]. Created by
+/// [`Fs::dir_builder`].
+#[derive(Clone)]
+pub struct DirBuilder<'s> {
+    fs: Fs<'s>,
+    recursive: bool,
+    mode: Option<Permissions>,
+}
+
+impl<'s> DirBuilder<'s> {
+    pub(crate) fn new(fs: Fs<'s>) -> Self {
+        Self {
+            fs,
+            recursive: false,
+            mode: None,
+        }
+    }
+
+    /// Creates missing parents too, and succeeds if the directory already
+    /// exists, like `mkdir -p`.
+    pub fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Permissions created directories get, exactly, rather than those the
+    /// server's umask leaves.
+    pub fn mode(&mut self, permissions: Permissions) -> &mut Self {
+        self.mode = Some(permissions);
+        self
+    }
+
+    /// Creates the directory at `path`.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` already exists, unless recursive and it is a directory.
+    /// - If its parent does not exist, unless recursive.
+    /// - If a component of `path` exists but is not a directory.
+    /// - If a directory cannot be created or its permissions set.
+    pub async fn create(&self, path: impl AsRef<Utf8Path>) -> Result<()> {
+        let path = self.fs.resolve(path.as_ref()).await?;
+        let sftp = self.fs.sftp().await?;
+        if !self.recursive {
+            return self.create_one(sftp, &path).await;
+        }
+
+        let mut missing = Vec::new();
+        for ancestor in path.ancestors().filter(|dir| !dir.as_str().is_empty()) {
+            match sftp.metadata(ancestor.as_str()).await {
+                Ok(metadata) if metadata.is_dir() => break,
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotADirectory,
+                        format!("{ancestor} is not a directory"),
+                    )
+                    .into());
+                }
+                Err(error) if is_not_found(&error) => missing.push(ancestor),
+                Err(error) => return Err(error.into()),
+            }
+        }
+        for dir in missing.into_iter().rev() {
+            self.create_one(sftp, dir).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn create_one(&self, sftp: &SftpSession, path: &Utf8Path) -> Result<()> {
+        sftp.create_dir(path.as_str()).await?;
+        if let Some(mode) = self.mode {
+            let attributes = FileAttributes {
+                permissions: Some(mode.mode()),
+                ..FileAttributes::empty()
+            };
+            sftp.set_metadata(path.as_str(), attributes).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Entries of a remote directory, from [`Fs::read_dir`], in the order the
+/// server lists them. `.` and `..` are left out. The whole listing is fetched
+/// before the stream is returned, so it never fails.
+pub struct ReadDir {
+    entries: std::vec::IntoIter<DirEntry>,
+}
+
+impl Stream for ReadDir {
+    type Item = DirEntry;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<DirEntry>> {
+        Poll::Ready(self.entries.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+/// Entry of a remote directory, with the metadata listed along with it.
+/// Symbolic links are not followed.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path: Utf8PathBuf,
+    metadata: Metadata,
+}
+
+impl DirEntry {
+    /// Path of the entry: the listed directory, after tilde expansion, joined
+    /// with the entry's name.
+    #[must_use]
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    #[must_use]
+    pub fn file_name(&self) -> &str {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+impl Fs<'_> {
+    /// Lists the entries of the directory at `path`.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist or is not a directory.
+    /// - If the directory cannot be listed.
+    pub async fn read_dir(&self, path: impl AsRef<Utf8Path>) -> Result<ReadDir> {
+        let path = self.resolve(path.as_ref()).await?;
+        let entries: Vec<_> = self
+            .sftp()
+            .await?
+            .read_dir(path.as_str())
+            .await?
+            .map(|entry| DirEntry {
+                path: path.join(entry.file_name()),
+                metadata: Metadata::new(entry.metadata()),
+            })
+            .collect();
+
+        Ok(ReadDir {
+            entries: entries.into_iter(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn dir_builder_works() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+
+        fs.dir_builder()
+            .recursive(true)
+            .mode(Permissions::from_mode(0o750))
+            .create("~/a/b/c")
+            .await
+            .unwrap();
+        fs.dir_builder()
+            .recursive(true)
+            .create("~/a/b")
+            .await
+            .unwrap();
+        let exists = fs.dir_builder().create("~/a/b").await;
+        let no_parent = fs.dir_builder().create("~/x/y").await;
+
+        let metadata = fs.metadata("~/a/b/c").await.unwrap();
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.permissions(), Permissions::from_mode(0o750));
+        assert!(exists.is_err());
+        assert!(no_parent.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_dir_works() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+        fs.create_dir("~/listed").await.unwrap();
+        fs.create_dir("~/listed/sub").await.unwrap();
+        fs.write("~/listed/file.txt", "hello").await.unwrap();
+        let home = fs.canonicalize("~").await.unwrap();
+
+        let mut entries: Vec<_> = fs.read_dir("~/listed").await.unwrap().collect().await;
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+        let names: Vec<_> = entries.iter().map(DirEntry::file_name).collect();
+        assert_eq!(names, ["file.txt", "sub"]);
+        assert_eq!(entries[0].path(), home.join("listed/file.txt"));
+        assert!(entries[0].metadata().is_file());
+        assert_eq!(entries[0].metadata().len(), 5);
+        assert!(entries[1].metadata().is_dir());
+    }
+}
@@ -0,0 +1,241 @@
+use std::io;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use russh_sftp::protocol::FileAttributes;
+
+use super::Fs;
+use super::Permissions;
+use super::is_not_found;
+use crate::Result;
+
+/// Numeric owner and group of a remote file. SFTP identifies them by number
+/// only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    #[must_use]
+    pub const fn new(uid: u32, gid: u32) -> Self {
+        Self { uid, gid }
+    }
+}
+
+/// What [`Fs::ensure_dir_all`] did to one component of the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirChange {
+    /// Already existed and was left as is.
+    Unchanged,
+    /// Did not exist and was created with the requested permissions and
+    /// owner.
+    Created,
+    /// Existed with other permissions or another owner, which were replaced.
+    /// Holds the values found, for those that differed.
+    Fixed {
+        permissions: Option<Permissions>,
+        owner: Option<Owner>,
+    },
+}
+
+impl Fs<'_> {
+    /// Makes sure `path` is a directory with `permissions` and, if given,
+    /// `owner`: creates it and any missing parents with them, and fixes them
+    /// on `path` itself if it already exists. Parents that already exist are
+    /// left as they are. Returns what was done to each component of the path,
+    /// from the outermost.
+    ///
+    /// Running it again with the same arguments changes nothing, so it is
+    /// safe to repeat in provisioning flows.
+    ///
+    /// # Errors
+    ///
+    /// - If a component of `path` exists but is not a directory.
+    /// - If a directory cannot be created, or its permissions or owner set, for
+    ///   example because only root may change owners.
+    pub async fn ensure_dir_all(
+        &self,
+        path: impl AsRef<Utf8Path>,
+        permissions: Permissions,
+        owner: Option<Owner>,
+    ) -> Result<Vec<(Utf8PathBuf, DirChange)>> {
+        let path = self.resolve(path.as_ref()).await?;
+        let sftp = self.sftp().await?;
+
+        let mut components: Vec<_> = path
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_str().is_empty())
+            .collect();
+        components.reverse();
+
+        let mut changes = Vec::with_capacity(components.len());
+        let mut missing = false;
+        for component in components {
+            // Everything under a missing directory is missing too.
+            let metadata = if missing {
+                None
+            } else {
+                match sftp.metadata(component.as_str()).await {
+                    Ok(metadata) => Some(metadata),
+                    Err(error) if is_not_found(&error) => None,
+                    Err(error) => return Err(error.into()),
+                }
+            };
+
+            let change = match metadata {
+                Some(metadata) if !metadata.is_dir() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotADirectory,
+                        format!("{component} is not a directory"),
+                    )
+                    .into());
+                }
+                Some(_) if component != path => DirChange::Unchanged,
+                Some(metadata) => {
+                    let (found_permissions, found_owner) =
+                        differences(&metadata, permissions, owner);
+                    if found_permissions.is_none() && found_owner.is_none() {
+                        DirChange::Unchanged
+                    } else {
+                        sftp.set_metadata(component.as_str(), attributes(permissions, owner))
+                            .await?;
+                        DirChange::Fixed {
+                            permissions: found_permissions,
+                            owner: found_owner,
+                        }
+                    }
+                }
+                None => {
+                    missing = true;
+                    sftp.create_dir(component.as_str()).await?;
+                    // The server applies its umask when creating.
+                    sftp.set_metadata(component.as_str(), attributes(permissions, owner))
+                        .await?;
+                    DirChange::Created
+                }
+            };
+            changes.push((component.to_path_buf(), change));
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Permissions and owner in `found` that differ from those wanted, as found.
+fn differences(
+    found: &FileAttributes,
+    permissions: Permissions,
+    owner: Option<Owner>,
+) -> (Option<Permissions>, Option<Owner>) {
+    let found_permissions = found
+        .permissions
+        .map(Permissions::from_mode)
+        .filter(|&found| found != permissions);
+    let found_owner = match (owner, found.uid, found.gid) {
+        (Some(owner), Some(uid), Some(gid)) => {
+            Some(Owner::new(uid, gid)).filter(|&found| found != owner)
+        }
+        _ => None,
+    };
+
+    (found_permissions, found_owner)
+}
+
+fn attributes(permissions: Permissions, owner: Option<Owner>) -> FileAttributes {
+    FileAttributes {
+        permissions: Some(permissions.mode()),
+        uid: owner.map(|owner| owner.uid),
+        gid: owner.map(|owner| owner.gid),
+        ..FileAttributes::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case(0o40755, None, None, (None, None))]
+    #[case(0o40700, None, None, (Some(0o700), None))]
+    #[case(0o40755, Some((0, 0)), Some((0, 0)), (None, None))]
+    #[case(0o40750, Some((1000, 1000)), Some((0, 0)), (Some(0o750), Some((1000, 1000))))]
+    fn differences_works(
+        #[case] mode: u32,
+        #[case] found_owner: Option<(u32, u32)>,
+        #[case] owner: Option<(u32, u32)>,
+        #[case] differences_should: (Option<u32>, Option<(u32, u32)>),
+    ) {
+        let found = FileAttributes {
+            permissions: Some(mode),
+            uid: found_owner.map(|(uid, _)| uid),
+            gid: found_owner.map(|(_, gid)| gid),
+            ..FileAttributes::empty()
+        };
+        let owner = owner.map(|(uid, gid)| Owner::new(uid, gid));
+
+        let (permissions, owner) = differences(&found, Permissions::from_mode(0o755), owner);
+
+        assert_eq!(permissions.map(Permissions::mode), differences_should.0);
+        assert_eq!(
+            owner,
+            differences_should.1.map(|(uid, gid)| Owner::new(uid, gid))
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn ensure_dir_all_is_idempotent() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+        let permissions = Permissions::from_mode(0o750);
+
+        let created = fs
+            .ensure_dir_all("~/ensure/a/b", permissions, None)
+            .await
+            .unwrap();
+        fs.set_permissions("~/ensure/a/b", Permissions::from_mode(0o700))
+            .await
+            .unwrap();
+        let fixed = fs
+            .ensure_dir_all("~/ensure/a/b", permissions, None)
+            .await
+            .unwrap();
+        let unchanged = fs
+            .ensure_dir_all("~/ensure/a/b", permissions, None)
+            .await
+            .unwrap();
+
+        let changes = |changes: Vec<(Utf8PathBuf, DirChange)>| {
+            changes[changes.len() - 3..]
+                .iter()
+                .map(|(_, change)| *change)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            changes(created),
+            [DirChange::Created, DirChange::Created, DirChange::Created]
+        );
+        assert_eq!(
+            changes(fixed),
+            [
+                DirChange::Unchanged,
+                DirChange::Unchanged,
+                DirChange::Fixed {
+                    permissions: Some(Permissions::from_mode(0o700)),
+                    owner: None,
+                },
+            ]
+        );
+        assert!(
+            changes(unchanged)
+                .iter()
+                .all(|change| *change == DirChange::Unchanged)
+        );
+    }
+}
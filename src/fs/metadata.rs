@@ -0,0 +1,117 @@
+use std::time::Duration;
+use std::time::SystemTime;
+
+use russh_sftp::protocol::FileAttributes;
+
+use super::Permissions;
+
+/// Metadata of a remote file, as reported by the server. Servers may leave
+/// out any attribute; times and owners are then `None`, and the size and
+/// permissions zero.
+#[derive(Debug, Clone)]
+pub struct Metadata(FileAttributes);
+
+impl Metadata {
+    pub(crate) fn new(attributes: FileAttributes) -> Self {
+        Self(attributes)
+    }
+
+    #[must_use]
+    pub fn is_dir(&self) -> bool {
+        self.0.file_type().is_dir()
+    }
+
+    #[must_use]
+    pub fn is_file(&self) -> bool {
+        self.0.file_type().is_file()
+    }
+
+    /// Whether this is a symbolic link, which is only ever the case for
+    /// metadata that does not follow links, like
+    /// [`Fs::symlink_metadata`](super::Fs::symlink_metadata).
+    #[must_use]
+    pub fn is_symlink(&self) -> bool {
+        self.0.file_type().is_symlink()
+    }
+
+    /// Size in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.0.size.unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[must_use]
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from_mode(self.0.permissions.unwrap_or_default())
+    }
+
+    /// Last modification time, to the second.
+    #[must_use]
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.0.mtime.map(from_unix)
+    }
+
+    /// Last access time, to the second.
+    #[must_use]
+    pub fn accessed(&self) -> Option<SystemTime> {
+        self.0.atime.map(from_unix)
+    }
+
+    /// Numeric ID of the owning user.
+    #[must_use]
+    pub fn uid(&self) -> Option<u32> {
+        self.0.uid
+    }
+
+    /// Numeric ID of the owning group.
+    #[must_use]
+    pub fn gid(&self) -> Option<u32> {
+        self.0.gid
+    }
+}
+
+fn from_unix(seconds: u32) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(0o100_644, false, true, false)]
+    #[case(0o040_755, true, false, false)]
+    #[case(0o120_777, false, false, true)]
+    fn file_type_works(
+        #[case] mode: u32,
+        #[case] dir_should: bool,
+        #[case] file_should: bool,
+        #[case] symlink_should: bool,
+    ) {
+        let metadata = Metadata::new(FileAttributes {
+            permissions: Some(mode),
+            ..FileAttributes::empty()
+        });
+
+        assert_eq!(metadata.is_dir(), dir_should);
+        assert_eq!(metadata.is_file(), file_should);
+        assert_eq!(metadata.is_symlink(), symlink_should);
+        assert_eq!(metadata.permissions(), Permissions::from_mode(mode));
+    }
+
+    #[test]
+    fn missing_attributes_are_empty() {
+        let metadata = Metadata::new(FileAttributes::empty());
+
+        assert_eq!(metadata.len(), 0);
+        assert_eq!(metadata.modified(), None);
+        assert_eq!(metadata.uid(), None);
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+
+use camino::Utf8Path;
+use russh_sftp::protocol::FileAttributes;
+use russh_sftp::protocol::OpenFlags;
+
+use super::File;
+use super::Fs;
+use super::Permissions;
+use crate::Result;
+
+/// Options to open a remote file with, like [`std::fs::OpenOptions`].
+/// Created by [`Fs::open_options`], with every option off.
+#[derive(Clone)]
+pub struct OpenOptions<'s> {
+    fs: Fs<'s>,
+    flags: OpenFlags,
+    mode: Option<Permissions>,
+}
+
+impl<'s> OpenOptions<'s> {
+    pub(crate) fn new(fs: Fs<'s>) -> Self {
+        Self {
+            fs,
+            flags: OpenFlags::empty(),
+            mode: None,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.flags.set(OpenFlags::READ, read);
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.flags.set(OpenFlags::WRITE, write);
+        self
+    }
+
+    /// Writes at the end of the file, whatever the position. Implies write
+    /// access.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.flags.set(OpenFlags::APPEND, append);
+        self
+    }
+
+    /// Empties the file if it exists. Requires write access.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.flags.set(OpenFlags::TRUNCATE, truncate);
+        self
+    }
+
+    /// Creates the file if it does not exist. Requires write access.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.flags.set(OpenFlags::CREATE, create);
+        self
+    }
+
+    /// Creates the file, failing if it exists, which also fails on a symbolic
+    /// link to it. Requires write access.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.flags.set(OpenFlags::EXCLUDE, create_new);
+        self
+    }
+
+    /// Permissions a created file gets. Servers may apply their umask to
+    /// them, as OpenSSH's does.
+    pub fn mode(&mut self, permissions: Permissions) -> &mut Self {
+        self.mode = Some(permissions);
+        self
+    }
+
+    /// Opens `path` with these options.
+    ///
+    /// # Errors
+    ///
+    /// - If the options neither read nor write, or create or truncate without
+    ///   writing, as [`io::ErrorKind::InvalidInput`].
+    /// - If `path` does not exist and is not to be created, or exists and is to
+    ///   be created new.
+    /// - If `path` cannot be opened with the requested access.
+    pub async fn open(&self, path: impl AsRef<Utf8Path>) -> Result<File> {
+        let flags = checked(self.flags)?;
+        let path = self.fs.resolve(path.as_ref()).await?;
+        let attributes = FileAttributes {
+            permissions: self.mode.map(Permissions::mode),
+            ..FileAttributes::empty()
+        };
+        let file = self
+            .fs
+            .sftp()
+            .await?
+            .open_with_flags_and_attributes(path.as_str(), flags, attributes)
+            .await?;
+
+        Ok(File(file))
+    }
+}
+
+/// Flags to send for those set, checked the way [`std::fs::OpenOptions`]
+/// checks them.
+fn checked(mut flags: OpenFlags) -> io::Result<OpenFlags> {
+    if flags.contains(OpenFlags::APPEND) {
+        flags |= OpenFlags::WRITE;
+    }
+    if flags.contains(OpenFlags::EXCLUDE) {
+        flags |= OpenFlags::CREATE;
+    }
+
+    let invalid = |message| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    if !flags.intersects(OpenFlags::READ | OpenFlags::WRITE) {
+        return invalid("file must be opened for reading, writing or appending");
+    }
+    if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNCATE)
+        && !flags.contains(OpenFlags::WRITE)
+    {
+        return invalid("file must be opened for writing to be created or truncated");
+    }
+
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    #[cfg(feature = "russh")]
+    use tokio::io::AsyncReadExt;
+    #[cfg(feature = "russh")]
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::Error;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case(OpenFlags::READ, Some(OpenFlags::READ))]
+    #[case(OpenFlags::APPEND, Some(OpenFlags::APPEND | OpenFlags::WRITE))]
+    #[case(
+        OpenFlags::WRITE | OpenFlags::EXCLUDE,
+        Some(OpenFlags::WRITE | OpenFlags::EXCLUDE | OpenFlags::CREATE)
+    )]
+    #[case(OpenFlags::empty(), None)]
+    #[case(OpenFlags::READ | OpenFlags::TRUNCATE, None)]
+    fn checked_works(#[case] flags: OpenFlags, #[case] checked_should: Option<OpenFlags>) {
+        // The flags do not implement `PartialEq`.
+        assert_eq!(
+            checked(flags).ok().map(|flags| flags.bits()),
+            checked_should.map(|flags| flags.bits())
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn open_options_work() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+        fs.write("~/log.txt", "one\n").await.unwrap();
+
+        let mut file = fs
+            .open_options()
+            .append(true)
+            .open("~/log.txt")
+            .await
+            .unwrap();
+        file.write_all(b"two\n").await.unwrap();
+        file.shutdown().await.unwrap();
+        let exists = fs
+            .open_options()
+            .write(true)
+            .create_new(true)
+            .open("~/log.txt")
+            .await;
+        let invalid = fs.open_options().create(true).open("~/new.txt").await;
+        let mut contents = String::new();
+        fs.open_options()
+            .read(true)
+            .open("~/log.txt")
+            .await
+            .unwrap()
+            .read_to_string(&mut contents)
+            .await
+            .unwrap();
+
+        assert_eq!(contents, "one\ntwo\n");
+        assert!(exists.is_err());
+        assert!(matches!(
+            invalid,
+            Err(Error::Io(error)) if error.kind() == io::ErrorKind::InvalidInput
+        ));
+    }
+}
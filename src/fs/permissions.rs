@@ -0,0 +1,157 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Error;
+use crate::Result;
+
+/// Classes in the order they appear in symbolic notation, with their read,
+/// write and execute bits, and the special bit shown in place of execute.
+const CLASSES: [(u32, u32, u32, u32, char); 3] = [
+    (0o400, 0o200, 0o100, 0o4000, 's'),
+    (0o040, 0o020, 0o010, 0o2000, 's'),
+    (0o004, 0o002, 0o001, 0o1000, 't'),
+];
+
+/// Unix file permission bits, including setuid, setgid and sticky.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    /// Permissions from an octal mode like `0o755`. Bits other than the
+    /// permission bits, like the file type, are ignored.
+    #[must_use]
+    pub const fn from_mode(mode: u32) -> Self {
+        Self(mode & 0o7777)
+    }
+
+    /// Permissions from symbolic notation like `rwxr-x---`, as shown by
+    /// `ls -l`.
+    ///
+    /// # Errors
+    ///
+    /// - If `symbolic` is not nine valid permission characters.
+    pub fn from_symbolic(symbolic: &str) -> Result<Self> {
+        let invalid = || Error::InvalidPermissions(symbolic.to_string());
+        let chars: Vec<char> = symbolic.chars().collect();
+        if chars.len() != 9 {
+            return Err(invalid());
+        }
+
+        let mut mode = 0;
+        for (class, &(read, write, exec, special, special_char)) in chars.chunks(3).zip(&CLASSES) {
+            mode |= match class[0] {
+                'r' => read,
+                '-' => 0,
+                _ => return Err(invalid()),
+            };
+            mode |= match class[1] {
+                'w' => write,
+                '-' => 0,
+                _ => return Err(invalid()),
+            };
+            mode |= match class[2] {
+                'x' => exec,
+                '-' => 0,
+                c if c == special_char => exec | special,
+                c if c == special_char.to_ascii_uppercase() => special,
+                _ => return Err(invalid()),
+            };
+        }
+
+        Ok(Self(mode))
+    }
+
+    /// Octal mode, like `0o755`.
+    #[must_use]
+    pub const fn mode(self) -> u32 {
+        self.0
+    }
+
+    /// These permissions with the bits set in `umask` cleared, like the mode
+    /// a process with that umask creates files with.
+    #[must_use]
+    pub const fn with_umask(self, umask: Permissions) -> Self {
+        Self(self.0 & !umask.0)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (read, write, exec, special, special_char) in CLASSES {
+            let flag = |bit, c| if self.0 & bit == 0 { '-' } else { c };
+            let exec = match (self.0 & exec != 0, self.0 & special != 0) {
+                (true, true) => special_char,
+                (false, true) => special_char.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            };
+            write!(f, "{}{}{exec}", flag(read, 'r'), flag(write, 'w'))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Permissions {
+    type Err = Error;
+
+    /// Parses either an octal mode like `0755` or symbolic notation like
+    /// `rwxr-xr-x`.
+    fn from_str(s: &str) -> Result<Self> {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            return u32::from_str_radix(s, 8)
+                .ok()
+                .filter(|&mode| mode <= 0o7777)
+                .map(Self)
+                .ok_or_else(|| Error::InvalidPermissions(s.to_string()));
+        }
+
+        Self::from_symbolic(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("rwxr-x---", 0o750)]
+    #[case("rw-r--r--", 0o644)]
+    #[case("rwsr-xr-x", 0o4755)]
+    #[case("rwxr-Sr--", 0o2744)]
+    #[case("rwxrwxrwt", 0o1777)]
+    #[case("---------", 0o000)]
+    fn symbolic_round_trips(#[case] symbolic: &str, #[case] mode: u32) {
+        let permissions = Permissions::from_symbolic(symbolic).unwrap();
+
+        assert_eq!(permissions.mode(), mode);
+        assert_eq!(permissions.to_string(), symbolic);
+    }
+
+    #[rstest]
+    #[case("rwx")]
+    #[case("rwxr-x--z")]
+    #[case("rwtr-xr-x")]
+    #[case("0799")]
+    #[case("17777")]
+    fn from_str_rejects_invalid(#[case] s: &str) {
+        assert!(matches!(
+            s.parse::<Permissions>(),
+            Err(Error::InvalidPermissions(_))
+        ));
+    }
+
+    #[test]
+    fn from_str_accepts_octal() {
+        assert_eq!("0755".parse::<Permissions>().unwrap().mode(), 0o755);
+    }
+
+    #[test]
+    fn with_umask_works() {
+        let permissions = Permissions::from_mode(0o777).with_umask(Permissions::from_mode(0o027));
+
+        assert_eq!(permissions.mode(), 0o750);
+    }
+}
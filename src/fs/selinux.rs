@@ -0,0 +1,92 @@
+use camino::Utf8Path;
+
+use super::Fs;
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+/// SELinux security context to give a remote file, so that confined services
+/// may still read it once it is replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecurityContext {
+    /// The context the policy assigns to the path, with `restorecon`. Right
+    /// for files put where the service expects them.
+    Restore,
+    /// The given context, such as `system_u:object_r:httpd_config_t:s0`,
+    /// with `chcon`. Lost on the next relabel unless the policy agrees.
+    Explicit(String),
+}
+
+impl ConnectedSession {
+    /// Whether SELinux is enabled on the remote host, as `selinuxenabled`
+    /// reports. Hosts without it, such as those confining services with
+    /// AppArmor, whose profiles go by path rather than file labels, have it
+    /// disabled. Checked once and cached for the lifetime of the session.
+    ///
+    /// # Errors
+    ///
+    /// - If the check itself cannot be run.
+    pub async fn selinux_enabled(&self) -> Result<bool> {
+        self.selinux
+            .get_or_try_init(|| async {
+                // Exits with 1 if disabled, and 127 if not installed.
+                let status = self.command("selinuxenabled").status().await?;
+                Ok(status.success())
+            })
+            .await
+            .copied()
+    }
+}
+
+impl Fs<'_> {
+    /// Gives the file or directory at `path` the security context `context`.
+    /// Requires SELinux on the remote host; see
+    /// [`ConnectedSession::selinux_enabled`].
+    ///
+    /// # Errors
+    ///
+    /// - If `restorecon` or `chcon` fails, for example because `path` does not
+    ///   exist or the context is unknown.
+    pub async fn set_security_context(
+        &self,
+        path: impl AsRef<Utf8Path>,
+        context: &SecurityContext,
+    ) -> Result<()> {
+        let path = self.resolve(path.as_ref()).await?;
+        let (program, args) = match context {
+            SecurityContext::Restore => ("restorecon", vec!["--", path.as_str()]),
+            SecurityContext::Explicit(context) => ("chcon", vec!["--", context, path.as_str()]),
+        };
+        let mut command = self.session.command(program);
+        command.args(args);
+
+        let status = command.status().await?;
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn selinux_enabled_works() {
+        let session = test_server::connect().await;
+
+        let enabled = session.selinux_enabled().await.unwrap();
+
+        let local = std::process::Command::new("sh")
+            .args(["-c", "selinuxenabled"])
+            .status()
+            .unwrap();
+        assert_eq!(enabled, local.success());
+    }
+}
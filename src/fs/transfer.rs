@@ -0,0 +1,548 @@
+use async_compression::tokio::write::GzipEncoder;
+use async_compression::tokio::write::ZstdEncoder;
+use bon::Builder;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use russh_sftp::protocol::FileAttributes;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+
+use super::Checksum;
+use super::Fs;
+use super::Owner;
+use super::Permissions;
+use super::SecurityContext;
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::shell;
+
+/// Size of the chunks files are copied in.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Permissions to give an uploaded file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadPermissions {
+    /// Same permissions as the local file.
+    Preserve,
+    /// The given permissions.
+    Explicit(Permissions),
+}
+
+/// Compression applied to data in transit, independent of any compression
+/// negotiated by SSH itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Program that decompresses this format, with `-dc`, on the remote host.
+    pub(crate) fn program(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Flag selecting this compression in GNU and BSD `tar`.
+    pub(crate) fn tar_flag(self) -> &'static str {
+        match self {
+            Compression::Gzip => "--gzip",
+            Compression::Zstd => "--zstd",
+        }
+    }
+}
+
+/// Options for copying files to or from the remote host.
+#[derive(Debug, Clone, Default, Builder)]
+pub struct TransferOptions {
+    /// Permissions to give uploaded files. If not set, the server decides,
+    /// usually applying its umask to `0o666`.
+    permissions: Option<UploadPermissions>,
+    /// Bits to clear from `permissions`, like a shell's umask. Permissions are
+    /// applied exactly as given if not set.
+    umask: Option<Permissions>,
+    /// Owner to give uploaded files, which usually requires root. Left to the
+    /// server if not set.
+    owner: Option<Owner>,
+    /// SELinux security context to give uploaded files, on hosts with SELinux
+    /// enabled, so that confined services can still read replaced configs.
+    /// Ignored on other hosts, including those using AppArmor, which goes by
+    /// path. Left to the server if not set, which gives new files the context
+    /// of their directory and keeps that of files replaced in place.
+    security_context: Option<SecurityContext>,
+    /// Hash computed while copying and compared against the hash of the copy
+    /// on the remote host afterward. Not verified if not set.
+    verify: Option<Checksum>,
+    /// Compression applied to file contents on the way, independent of SSH
+    /// compression, which servers may disable. Uploaded files are
+    /// decompressed on the remote host with `gzip` or `zstd`, unless
+    /// `keep_compressed` is set. Not compressed if not set.
+    pub(crate) compression: Option<Compression>,
+    /// Whether uploaded files are stored compressed as sent, instead of being
+    /// decompressed on the remote host. Has no effect on tar transfers.
+    #[builder(default)]
+    keep_compressed: bool,
+}
+
+impl TransferOptions {
+    /// Permissions an uploaded file should end up with, given the local
+    /// file's permissions.
+    fn permissions(&self, local: Permissions) -> Option<Permissions> {
+        let permissions = match self.permissions? {
+            UploadPermissions::Preserve => local,
+            UploadPermissions::Explicit(permissions) => permissions,
+        };
+
+        Some(match self.umask {
+            Some(umask) => permissions.with_umask(umask),
+            None => permissions,
+        })
+    }
+}
+
+impl Fs<'_> {
+    /// Copies a local file to the remote host, replacing `remote_path` if it
+    /// exists. Returns the number of bytes read from `local_path`, before any
+    /// compression.
+    ///
+    /// # Errors
+    ///
+    /// - If `local_path` cannot be read.
+    /// - If `remote_path` cannot be written, or its permissions, owner or
+    ///   security context set.
+    /// - If compression is enabled and the remote host cannot decompress.
+    /// - If verification is enabled and the remote copy does not match what was
+    ///   sent.
+    pub async fn upload(
+        &self,
+        local_path: impl AsRef<Utf8Path>,
+        remote_path: impl AsRef<Utf8Path>,
+        options: &TransferOptions,
+    ) -> Result<u64> {
+        let remote_path = self.resolve(remote_path.as_ref()).await?;
+        let mut local = tokio::fs::File::open(local_path.as_ref()).await?;
+        let local_permissions = local_permissions(&local.metadata().await?);
+
+        // Compressed data is decompressed from a sibling into place afterward.
+        let decompress = options.compression.filter(|_| !options.keep_compressed);
+        let upload_path = match decompress {
+            Some(compression) => {
+                Utf8PathBuf::from(format!("{remote_path}.part.{}", compression.extension()))
+            }
+            None => remote_path.clone(),
+        };
+
+        let mut hasher = options.verify.map(Checksum::hasher);
+        let remote = self.create(&upload_path).await?;
+        let mut remote: Box<dyn AsyncWrite + Send + Unpin> = match options.compression {
+            Some(Compression::Gzip) => Box::new(GzipEncoder::new(remote)),
+            Some(Compression::Zstd) => Box::new(ZstdEncoder::new(remote)),
+            None => Box::new(remote),
+        };
+        let mut buffer = vec![0; COPY_BUFFER_SIZE];
+        let mut copied = 0;
+        loop {
+            let len = local.read(&mut buffer).await?;
+            if len == 0 {
+                break;
+            }
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&buffer[..len]);
+            }
+            remote.write_all(&buffer[..len]).await?;
+            copied += len as u64;
+        }
+        remote.shutdown().await?;
+
+        if let Some(compression) = decompress {
+            self.decompress(compression, &upload_path, &remote_path)
+                .await?;
+        }
+
+        self.apply_attributes(
+            &remote_path,
+            options.permissions(local_permissions),
+            options,
+        )
+        .await?;
+
+        if let (Some(checksum), Some(hasher)) = (options.verify, hasher) {
+            let expected = hasher.finalize();
+            let stored_compression = options.compression.filter(|_| options.keep_compressed);
+            let actual = self
+                .checksum_of(&remote_path, checksum, stored_compression)
+                .await?;
+            if actual != expected {
+                return Err(Error::ChecksumMismatch {
+                    path: remote_path.into_string(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(copied)
+    }
+
+    /// Decompresses `source` into `target` on the remote host, then removes
+    /// `source`.
+    async fn decompress(
+        &self,
+        compression: Compression,
+        source: &Utf8Path,
+        target: &Utf8Path,
+    ) -> Result<()> {
+        let source = shell::quote(source.as_str());
+        let target = shell::quote(target.as_str());
+        let program = compression.program();
+
+        let mut command = self.session.command("sh");
+        command.args([
+            "-c",
+            &format!("{program} -dc -- {source} > {target} && rm -f -- {source}"),
+        ]);
+        let output = command.output().await?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status: output.status,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Gives a copied file `permissions` and the owner and security context
+    /// in `options`. Set after writing, since the server applies its own
+    /// umask to the mode a file is created with.
+    async fn apply_attributes(
+        &self,
+        path: &Utf8Path,
+        permissions: Option<Permissions>,
+        options: &TransferOptions,
+    ) -> Result<()> {
+        // Changing the owner clears the setuid and setgid bits, so it goes
+        // first.
+        if let Some(owner) = options.owner {
+            let attributes = FileAttributes {
+                uid: Some(owner.uid),
+                gid: Some(owner.gid),
+                ..FileAttributes::empty()
+            };
+            self.sftp()
+                .await?
+                .set_metadata(path.as_str(), attributes)
+                .await?;
+        }
+        if let Some(permissions) = permissions {
+            self.set_permissions(path, permissions).await?;
+        }
+        if let Some(context) = &options.security_context
+            && self.session.selinux_enabled().await?
+        {
+            self.set_security_context(path, context).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the permissions of a file or directory.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` does not exist or its permissions cannot be changed.
+    pub async fn set_permissions(
+        &self,
+        path: impl AsRef<Utf8Path>,
+        permissions: Permissions,
+    ) -> Result<()> {
+        let path = self.resolve(path.as_ref()).await?;
+        let attributes = FileAttributes {
+            permissions: Some(permissions.mode()),
+            ..FileAttributes::empty()
+        };
+        self.sftp()
+            .await?
+            .set_metadata(path.as_str(), attributes)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Copies a file from one remote host to another, streaming it through this
+/// process chunk by chunk so that it never has to be stored locally. Replaces
+/// `target_path` if it exists. Calls `progress` with the number of bytes
+/// copied so far after each chunk, and returns the total.
+///
+/// With [`UploadPermissions::Preserve`], the copy gets the source file's
+/// permissions. Compression does not apply, as the data only ever travels
+/// over the two sessions.
+///
+/// # Errors
+///
+/// - If `source_path` cannot be read.
+/// - If `target_path` cannot be written, or its permissions, owner or security
+///   context set.
+/// - If verification is enabled and the copy does not match what was read.
+pub async fn remote_to_remote(
+    source: &ConnectedSession,
+    source_path: impl AsRef<Utf8Path>,
+    target: &ConnectedSession,
+    target_path: impl AsRef<Utf8Path>,
+    options: &TransferOptions,
+    mut progress: impl FnMut(u64),
+) -> Result<u64> {
+    let (source, target) = (source.fs(), target.fs());
+    let source_path = source.resolve(source_path.as_ref()).await?;
+    let target_path = target.resolve(target_path.as_ref()).await?;
+    let source_permissions = source
+        .sftp()
+        .await?
+        .metadata(source_path.as_str())
+        .await?
+        .permissions
+        .map(Permissions::from_mode);
+
+    let mut hasher = options.verify.map(Checksum::hasher);
+    let mut reader = source.open(&source_path).await?;
+    let mut writer = target.create(&target_path).await?;
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        let len = reader.read(&mut buffer).await?;
+        if len == 0 {
+            break;
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buffer[..len]);
+        }
+        writer.write_all(&buffer[..len]).await?;
+        copied += len as u64;
+        progress(copied);
+    }
+    writer.shutdown().await?;
+
+    target
+        .apply_attributes(
+            &target_path,
+            source_permissions.and_then(|source| options.permissions(source)),
+            options,
+        )
+        .await?;
+
+    if let (Some(checksum), Some(hasher)) = (options.verify, hasher) {
+        let expected = hasher.finalize();
+        let actual = target.checksum_of(&target_path, checksum, None).await?;
+        if actual != expected {
+            return Err(Error::ChecksumMismatch {
+                path: target_path.into_string(),
+                expected,
+                actual,
+            });
+        }
+    }
+
+    Ok(copied)
+}
+
+#[cfg(unix)]
+fn local_permissions(metadata: &std::fs::Metadata) -> Permissions {
+    use std::os::unix::fs::PermissionsExt;
+
+    Permissions::from_mode(metadata.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn local_permissions(metadata: &std::fs::Metadata) -> Permissions {
+    if metadata.permissions().readonly() {
+        Permissions::from_mode(0o444)
+    } else {
+        Permissions::from_mode(0o644)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(None, None, None)]
+    #[case(Some(UploadPermissions::Preserve), None, Some(0o755))]
+    #[case(Some(UploadPermissions::Preserve), Some(0o022), Some(0o755))]
+    #[case(
+        Some(UploadPermissions::Explicit(Permissions::from_mode(0o777))),
+        Some(0o027),
+        Some(0o750)
+    )]
+    #[case(
+        Some(UploadPermissions::Explicit(Permissions::from_mode(0o600))),
+        None,
+        Some(0o600)
+    )]
+    fn permissions_works(
+        #[case] permissions: Option<UploadPermissions>,
+        #[case] umask: Option<u32>,
+        #[case] mode_should: Option<u32>,
+    ) {
+        let options = TransferOptions::builder()
+            .maybe_permissions(permissions)
+            .maybe_umask(umask.map(Permissions::from_mode))
+            .build();
+
+        let mode = options
+            .permissions(Permissions::from_mode(0o755))
+            .map(Permissions::mode);
+
+        assert_eq!(mode, mode_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn upload_applies_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let session = crate::test_server::connect().await;
+        let fs = session.fs();
+        let local = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(local.path(), "#!/bin/sh\n").unwrap();
+        let options = TransferOptions::builder()
+            .permissions(UploadPermissions::Explicit("rwxr-x---".parse().unwrap()))
+            .build();
+
+        let copied = fs
+            .upload(local.path().to_str().unwrap(), "~/run.sh", &options)
+            .await
+            .unwrap();
+
+        let remote = fs.canonicalize("~/run.sh").await.unwrap();
+        let mode = std::fs::metadata(remote).unwrap().permissions().mode();
+        assert_eq!(copied, 10);
+        assert_eq!(mode & 0o777, 0o750);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn upload_applies_owner_and_context() {
+        use std::os::unix::fs::MetadataExt;
+
+        let session = crate::test_server::connect().await;
+        let fs = session.fs();
+        let local = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(local.path(), "listen 80;\n").unwrap();
+        let metadata = std::fs::metadata(local.path()).unwrap();
+        let owner = Owner::new(metadata.uid(), metadata.gid());
+        // Ignored, as the test server runs without SELinux.
+        let options = TransferOptions::builder()
+            .owner(owner)
+            .security_context(SecurityContext::Explicit("bogus".to_string()))
+            .build();
+
+        fs.upload(local.path().to_str().unwrap(), "~/site.conf", &options)
+            .await
+            .unwrap();
+
+        let remote = fs.canonicalize("~/site.conf").await.unwrap();
+        let metadata = std::fs::metadata(remote).unwrap();
+        assert_eq!(Owner::new(metadata.uid(), metadata.gid()), owner);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn upload_verifies_checksum() {
+        let session = crate::test_server::connect().await;
+        let local = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(local.path(), vec![7; 3 * COPY_BUFFER_SIZE + 1]).unwrap();
+        let options = TransferOptions::builder().verify(Checksum::Sha256).build();
+
+        let copied = session
+            .fs()
+            .upload(local.path().to_str().unwrap(), "~/blob", &options)
+            .await
+            .unwrap();
+
+        assert_eq!(copied, 3 * COPY_BUFFER_SIZE as u64 + 1);
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest]
+    #[case(Compression::Gzip, false)]
+    #[case(Compression::Gzip, true)]
+    #[case(Compression::Zstd, false)]
+    #[tokio::test]
+    async fn upload_compresses(#[case] compression: Compression, #[case] keep_compressed: bool) {
+        let session = crate::test_server::connect().await;
+        let fs = session.fs();
+        let local = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(local.path(), "hello ".repeat(1000)).unwrap();
+        let options = TransferOptions::builder()
+            .compression(compression)
+            .keep_compressed(keep_compressed)
+            .verify(Checksum::Sha256)
+            .build();
+
+        fs.upload(local.path().to_str().unwrap(), "~/hello", &options)
+            .await
+            .unwrap();
+
+        let stored = fs.read("~/hello").await.unwrap();
+        if keep_compressed {
+            assert!(stored.len() < 6000);
+        } else {
+            assert_eq!(stored, "hello ".repeat(1000).as_bytes());
+        }
+        let part = format!("~/hello.part.{}", compression.extension());
+        assert!(!fs.try_exists(part).await.unwrap());
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn remote_to_remote_works() {
+        let source = crate::test_server::connect().await;
+        let target = crate::test_server::connect().await;
+        let contents = "artifact ".repeat(20_000);
+        source.fs().write("~/artifact", &contents).await.unwrap();
+        source
+            .fs()
+            .set_permissions("~/artifact", Permissions::from_mode(0o750))
+            .await
+            .unwrap();
+        let options = TransferOptions::builder()
+            .permissions(UploadPermissions::Preserve)
+            .verify(Checksum::Sha256)
+            .build();
+        let mut reported = Vec::new();
+
+        let copied = remote_to_remote(
+            &source,
+            "~/artifact",
+            &target,
+            "~/copy",
+            &options,
+            |copied| reported.push(copied),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(copied, contents.len() as u64);
+        assert_eq!(reported.last(), Some(&copied));
+        assert!(reported.len() > 1);
+        assert_eq!(
+            target.fs().read("~/copy").await.unwrap(),
+            contents.as_bytes()
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use futures::Stream;
+use futures::stream::BoxStream;
+
+use super::Fs;
+use super::Metadata;
+use super::is_not_found;
+use crate::Result;
+use crate::process::ExitStatus;
+
+/// Exit code of `inotifywait` when it times out without an event.
+const INOTIFYWAIT_TIMED_OUT: u32 = 2;
+/// Exit code of the shell when a program is not found.
+const NOT_FOUND: u32 = 127;
+
+/// Change to a watched file or directory entry, found by [`Fs::watch`].
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    Created {
+        path: Utf8PathBuf,
+        metadata: Metadata,
+    },
+    /// Changed size, modification time or kind.
+    Modified {
+        path: Utf8PathBuf,
+        metadata: Metadata,
+    },
+    Deleted {
+        path: Utf8PathBuf,
+    },
+}
+
+impl WatchEvent {
+    #[must_use]
+    pub fn path(&self) -> &Utf8Path {
+        match self {
+            WatchEvent::Created { path, .. }
+            | WatchEvent::Modified { path, .. }
+            | WatchEvent::Deleted { path } => path,
+        }
+    }
+
+    /// Metadata of the entry after the change. `None` if it was deleted.
+    #[must_use]
+    pub fn metadata(&self) -> Option<&Metadata> {
+        match self {
+            WatchEvent::Created { metadata, .. } | WatchEvent::Modified { metadata, .. } => {
+                Some(metadata)
+            }
+            WatchEvent::Deleted { .. } => None,
+        }
+    }
+}
+
+/// Changes to a remote file or directory, from [`Fs::watch`]. The stream
+/// never ends; drop it to stop watching. A failed check yields an error, and
+/// watching goes on with the next one.
+pub struct Watch<'s> {
+    events: BoxStream<'s, Result<WatchEvent>>,
+}
+
+impl Stream for Watch<'_> {
+    type Item = Result<WatchEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.as_mut().poll_next(cx)
+    }
+}
+
+/// State of a [`Watch`] between checks.
+struct Watcher<'s> {
+    fs: Fs<'s>,
+    path: Utf8PathBuf,
+    interval: Duration,
+    entries: BTreeMap<Utf8PathBuf, Metadata>,
+    pending: VecDeque<WatchEvent>,
+    /// Whether `inotifywait` may be installed, until it is found not to be.
+    inotifywait: bool,
+}
+
+impl<'s> Fs<'s> {
+    /// Watches `path` for changes, checking it every `interval` over SFTP, as
+    /// a stream of what changed since the check before. A directory is
+    /// watched for changes to its entries, but not to what is under them; a
+    /// file, or a path that does not exist yet, is watched itself.
+    ///
+    /// If the remote host has `inotifywait`, it is run to check as soon as
+    /// something changes rather than at the end of the interval. Without it,
+    /// changes within the interval are seen together, and a file rewritten
+    /// to the same size within the second of its last change, which is all
+    /// SFTP tells modification times to, is not seen at all.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` cannot be checked the first time, before the stream is
+    ///   returned.
+    pub async fn watch(&self, path: impl AsRef<Utf8Path>, interval: Duration) -> Result<Watch<'s>> {
+        let path = self.resolve(path.as_ref()).await?;
+        let mut watcher = Watcher {
+            fs: *self,
+            path,
+            interval,
+            entries: BTreeMap::new(),
+            pending: VecDeque::new(),
+            inotifywait: true,
+        };
+        watcher.entries = watcher.entries().await?;
+
+        let events = futures::stream::unfold(watcher, |mut watcher| async move {
+            let event = watcher.next().await;
+            Some((event, watcher))
+        });
+
+        Ok(Watch {
+            events: Box::pin(events),
+        })
+    }
+}
+
+impl Watcher<'_> {
+    async fn next(&mut self) -> Result<WatchEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            self.wait().await;
+            let entries = self.entries().await?;
+            self.pending.extend(changes(&self.entries, &entries));
+            self.entries = entries;
+        }
+    }
+
+    /// Waits for the path to change, or for the interval to pass.
+    async fn wait(&mut self) {
+        if self.inotifywait {
+            // Waits at least a second, the shortest timeout it takes.
+            let timeout = self.interval.as_secs().max(1);
+            let status = self
+                .fs
+                .session
+                .command("inotifywait")
+                .args(["-qq", "-t", &timeout.to_string()])
+                .args([
+                    "-e",
+                    "modify,attrib,create,delete,move,delete_self,move_self",
+                ])
+                .arg(self.path.as_str())
+                .status()
+                .await;
+            match status.as_ref().map(ExitStatus::code) {
+                Ok(Some(0 | INOTIFYWAIT_TIMED_OUT)) => return,
+                Ok(Some(NOT_FOUND)) => self.inotifywait = false,
+                // The path may not exist yet, or the session may have
+                // failed, which the check after reports.
+                _ => {}
+            }
+        }
+
+        tokio::time::sleep(self.interval).await;
+    }
+
+    /// Metadata of the path if it is a file, or else of its entries, by path.
+    async fn entries(&self) -> Result<BTreeMap<Utf8PathBuf, Metadata>> {
+        let sftp = self.fs.sftp().await?;
+        let metadata = match sftp.metadata(self.path.as_str()).await {
+            Ok(metadata) => metadata,
+            Err(error) if is_not_found(&error) => return Ok(BTreeMap::new()),
+            Err(error) => return Err(error.into()),
+        };
+        if !metadata.is_dir() {
+            return Ok(BTreeMap::from([(
+                self.path.clone(),
+                Metadata::new(metadata),
+            )]));
+        }
+
+        let mut entries = BTreeMap::new();
+        for entry in sftp.read_dir(self.path.as_str()).await? {
+            let name = entry.file_name();
+            // Listed by some servers, such as OpenSSH's.
+            if name == "." || name == ".." {
+                continue;
+            }
+            entries.insert(self.path.join(name), Metadata::new(entry.metadata()));
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Changes from `before` to `after`, in path order.
+fn changes(
+    before: &BTreeMap<Utf8PathBuf, Metadata>,
+    after: &BTreeMap<Utf8PathBuf, Metadata>,
+) -> Vec<WatchEvent> {
+    let mut changes = Vec::new();
+    for (path, metadata) in after {
+        match before.get(path) {
+            None => changes.push(WatchEvent::Created {
+                path: path.clone(),
+                metadata: metadata.clone(),
+            }),
+            Some(old) if is_modified(old, metadata) => changes.push(WatchEvent::Modified {
+                path: path.clone(),
+                metadata: metadata.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for path in before.keys() {
+        if !after.contains_key(path) {
+            changes.push(WatchEvent::Deleted { path: path.clone() });
+        }
+    }
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+
+    changes
+}
+
+fn is_modified(before: &Metadata, after: &Metadata) -> bool {
+    before.len() != after.len()
+        || before.modified() != after.modified()
+        || before.is_dir() != after.is_dir()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "russh")]
+    use futures::StreamExt;
+    use russh_sftp::protocol::FileAttributes;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    fn file(size: u64, mtime: u32) -> Metadata {
+        Metadata::new(FileAttributes {
+            size: Some(size),
+            mtime: Some(mtime),
+            permissions: Some(0o100_644),
+            ..FileAttributes::empty()
+        })
+    }
+
+    fn entries(files: &[(&str, Metadata)]) -> BTreeMap<Utf8PathBuf, Metadata> {
+        files
+            .iter()
+            .map(|(path, metadata)| (Utf8PathBuf::from(path), metadata.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn changes_works() {
+        let before = entries(&[
+            ("/srv/a", file(5, 100)),
+            ("/srv/b", file(5, 100)),
+            ("/srv/c", file(5, 100)),
+            ("/srv/d", file(5, 100)),
+        ]);
+        let after = entries(&[
+            ("/srv/a", file(5, 100)),
+            ("/srv/b", file(8, 100)),
+            ("/srv/bb", file(1, 160)),
+            ("/srv/c", file(5, 160)),
+        ]);
+
+        let changes = changes(&before, &after);
+
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|change| match change {
+                WatchEvent::Created { path, .. } => format!("created {path}"),
+                WatchEvent::Modified { path, metadata } => {
+                    format!("modified {path} {}", metadata.len())
+                }
+                WatchEvent::Deleted { path } => format!("deleted {path}"),
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "modified /srv/b 8",
+                "created /srv/bb",
+                "modified /srv/c 5",
+                "deleted /srv/d",
+            ]
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn watch_works() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+        fs.create_dir("~/logs").await.unwrap();
+        fs.write("~/logs/old.log", "old").await.unwrap();
+
+        let mut watch = fs.watch("~/logs", Duration::from_millis(50)).await.unwrap();
+        fs.write("~/logs/new.log", "new").await.unwrap();
+        let created = watch.next().await.unwrap().unwrap();
+        fs.remove_file("~/logs/old.log").await.unwrap();
+        let deleted = watch.next().await.unwrap().unwrap();
+
+        let home = session.home_dir().await.unwrap();
+        assert!(matches!(
+            created,
+            WatchEvent::Created { path, .. } if path == home.join("logs/new.log")
+        ));
+        assert!(matches!(
+            deleted,
+            WatchEvent::Deleted { path } if path == home.join("logs/old.log")
+        ));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn watch_works_for_missing_file() {
+        let session = test_server::connect().await;
+        let fs = session.fs();
+
+        let mut watch = fs
+            .watch("~/later.txt", Duration::from_millis(50))
+            .await
+            .unwrap();
+        fs.write("~/later.txt", "here").await.unwrap();
+        let created = watch.next().await.unwrap().unwrap();
+
+        let home = session.home_dir().await.unwrap();
+        assert!(matches!(created, WatchEvent::Created { .. }));
+        assert_eq!(created.path(), home.join("later.txt"));
+    }
+}
@@ -0,0 +1,34 @@
+//! Entry points for the fuzz targets in `fuzz/`, reaching parsers that are
+//! not public. Only built by `cargo fuzz`, which sets `cfg(fuzzing)`.
+
+use ssh_key::PublicKey;
+
+use crate::SshConfig;
+use crate::known_hosts::check_text;
+
+/// Key looked up in fuzzed `known_hosts` files.
+const KEY: &str =
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHNh29bMgw9cld6US18vWXAPm2Lw6UGSKm8Aw2LrVG13";
+
+/// Looks up a key in `text` as the contents of a `known_hosts` file, for a
+/// host on the default port and on another.
+pub fn known_hosts(text: &str) {
+    let key = PublicKey::from_openssh(KEY).expect("key is valid");
+    for port in [22, 2222] {
+        let _ = check_text(text, "web1.example.com", port, &key);
+    }
+}
+
+/// Parses `text` as an `ssh_config` file and resolves a host with it.
+/// Configurations with `Match exec` are only parsed, so that fuzzing never
+/// runs commands.
+pub fn ssh_config(text: &str) {
+    let Ok(config) = SshConfig::parse(text) else {
+        return;
+    };
+    if text.to_ascii_lowercase().contains("exec") {
+        return;
+    }
+
+    let _ = config.resolve("web1.example.com", Some("deploy"));
+}
@@ -0,0 +1,289 @@
+//! Verification of the host keys servers present.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+#[cfg(feature = "russh")]
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use ssh_key::Fingerprint;
+#[cfg(feature = "russh")]
+use ssh_key::HashAlg;
+use ssh_key::PublicKey;
+
+#[cfg(feature = "russh")]
+use crate::Error;
+#[cfg(feature = "russh")]
+use crate::KnownHostsFile;
+use crate::Result;
+#[cfg(feature = "russh")]
+use crate::Tokens;
+#[cfg(feature = "russh")]
+use crate::TrustOnFirstUse;
+
+/// `known_hosts` file sessions verify host keys against by default, like
+/// OpenSSH's `UserKnownHostsFile`.
+const DEFAULT_KNOWN_HOSTS: &str = "~/.ssh/known_hosts";
+
+/// Whether to trust a host key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Accept,
+    Reject,
+}
+
+/// Decides whether to trust the host key a server presents during the
+/// initial key exchange. Decisions may take as long as they need, such as to
+/// ask an attestation service or the user, within the session's
+/// `host_key_timeout`. Later key exchanges presenting the same key are not
+/// verified again, but a changed key is, and the session fails unless it is
+/// accepted.
+///
+/// Implemented for closures taking the host, port and key by value and
+/// returning a future, such as
+/// `|host, port, key| async move { Ok(Decision::Accept) }`.
+pub trait HostKeyVerifier: Send + Sync {
+    /// Decides whether `key` may belong to `host` and `port`. The connection
+    /// fails with the error if one is returned.
+    fn verify<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<Decision>>;
+}
+
+impl<F, Fut> HostKeyVerifier for F
+where
+    F: Fn(String, u16, PublicKey) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Decision>> + Send + 'static,
+{
+    fn verify<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<Decision>> {
+        self(host.to_string(), port, key.clone()).boxed()
+    }
+}
+
+/// Accepts only keys with this fingerprint, for hosts whose key is known
+/// out of band, such as from a cloud provider's console.
+impl HostKeyVerifier for Fingerprint {
+    fn verify<'a>(
+        &'a self,
+        _host: &'a str,
+        _port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<Decision>> {
+        let decision = if key.fingerprint(self.algorithm()) == *self {
+            Decision::Accept
+        } else {
+            Decision::Reject
+        };
+
+        futures::future::ready(Ok(decision)).boxed()
+    }
+}
+
+/// How a session decides whether to trust the host key a server presents.
+#[derive(Clone)]
+pub enum HostKeyVerification {
+    /// Accepts keys recorded for the host in the `known_hosts` file at the
+    /// path, and rejects others like OpenSSH's `StrictHostKeyChecking=yes`.
+    /// `~` and tokens such as `%d` are expanded. The default, with
+    /// `~/.ssh/known_hosts`.
+    KnownHostsFile(Utf8PathBuf),
+    /// Like [`HostKeyVerification::KnownHostsFile`], but records the key of a
+    /// host with none of its type in the file and accepts it, like OpenSSH's
+    /// `StrictHostKeyChecking=accept-new`. Hosts are recorded hashed if
+    /// `hash` is set. See [`TrustOnFirstUse`].
+    TrustOnFirstUse { path: Utf8PathBuf, hash: bool },
+    /// Accepts only keys with the fingerprint.
+    Fingerprint(Fingerprint),
+    /// Accepts every key, leaving the connection open to impostors. Only for
+    /// tests and hosts reached over a trusted network.
+    AcceptAny,
+    /// Leaves the decision to a verifier, such as a
+    /// [`KnownHostsStore`](crate::KnownHostsStore) backed one.
+    Callback(Arc<dyn HostKeyVerifier>),
+}
+
+impl HostKeyVerification {
+    /// Verifier deciding for this setting, or `None` if every key is
+    /// accepted.
+    #[cfg(feature = "russh")]
+    pub(crate) fn verifier(&self, tokens: &Tokens) -> Result<Option<Arc<dyn HostKeyVerifier>>> {
+        let verifier: Arc<dyn HostKeyVerifier> = match self {
+            HostKeyVerification::KnownHostsFile(path) => {
+                Arc::new(KnownHostsFile::new(tokens.expand_path(path.as_str())?))
+            }
+            HostKeyVerification::TrustOnFirstUse { path, hash } => Arc::new(TrustOnFirstUse(
+                KnownHostsFile::new(tokens.expand_path(path.as_str())?).hash_hosts(*hash),
+            )),
+            HostKeyVerification::Fingerprint(fingerprint) => Arc::new(*fingerprint),
+            HostKeyVerification::AcceptAny => return Ok(None),
+            HostKeyVerification::Callback(verifier) => Arc::clone(verifier),
+        };
+
+        Ok(Some(verifier))
+    }
+}
+
+impl Default for HostKeyVerification {
+    fn default() -> Self {
+        HostKeyVerification::KnownHostsFile(DEFAULT_KNOWN_HOSTS.into())
+    }
+}
+
+impl fmt::Debug for HostKeyVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyVerification::KnownHostsFile(path) => {
+                f.debug_tuple("KnownHostsFile").field(path).finish()
+            }
+            HostKeyVerification::TrustOnFirstUse { path, hash } => f
+                .debug_struct("TrustOnFirstUse")
+                .field("path", path)
+                .field("hash", hash)
+                .finish(),
+            HostKeyVerification::Fingerprint(fingerprint) => {
+                f.debug_tuple("Fingerprint").field(fingerprint).finish()
+            }
+            HostKeyVerification::AcceptAny => f.write_str("AcceptAny"),
+            HostKeyVerification::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// Verifier of a session, with the host it is verifying keys for.
+#[cfg(feature = "russh")]
+#[derive(Clone)]
+pub(crate) struct Verification {
+    pub(crate) verifier: Arc<dyn HostKeyVerifier>,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) timeout: Option<Duration>,
+}
+
+#[cfg(feature = "russh")]
+impl Verification {
+    /// Runs the verifier, failing unless it accepts `key` in time.
+    pub(crate) async fn verify(&self, key: &PublicKey) -> Result<()> {
+        let decision = self.verifier.verify(&self.host, self.port, key);
+        let decision = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, decision)
+                .await
+                .map_err(|_| Error::HostKeyVerificationTimeout)??,
+            None => decision.await?,
+        };
+
+        match decision {
+            Decision::Accept => Ok(()),
+            Decision::Reject => Err(Error::HostKeyRejected(Box::new(
+                key.fingerprint(HashAlg::Sha256),
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "russh")]
+impl fmt::Debug for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Verification")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn key() -> PublicKey {
+        PublicKey::read_openssh_file("test/creds/id_ed25519.pub".as_ref()).unwrap()
+    }
+
+    fn verification(verifier: impl HostKeyVerifier + 'static) -> Verification {
+        Verification {
+            verifier: Arc::new(verifier),
+            host: "web1".to_string(),
+            port: 22,
+            timeout: Some(Duration::from_millis(50)),
+        }
+    }
+
+    #[rstest]
+    #[case(Decision::Accept, true)]
+    #[case(Decision::Reject, false)]
+    #[tokio::test]
+    async fn verify_follows_decision(#[case] decision: Decision, #[case] accepted: bool) {
+        let verification = verification(move |host: String, port, _| async move {
+            assert_eq!((host.as_str(), port), ("web1", 22));
+            Ok(decision)
+        });
+
+        let result = verification.verify(&key()).await;
+
+        assert_eq!(result.is_ok(), accepted);
+        if !accepted {
+            assert!(matches!(result, Err(Error::HostKeyRejected(_))));
+        }
+    }
+
+    #[rstest]
+    #[case(key().fingerprint(HashAlg::Sha256), true)]
+    #[case(key().fingerprint(HashAlg::Sha512), true)]
+    #[case(
+        PublicKey::read_openssh_file("test/creds/id_rsa.pub".as_ref())
+            .unwrap()
+            .fingerprint(HashAlg::Sha256),
+        false
+    )]
+    #[tokio::test]
+    async fn fingerprint_verifier_works(#[case] fingerprint: Fingerprint, #[case] accepted: bool) {
+        let result = verification(fingerprint).verify(&key()).await;
+
+        assert_eq!(result.is_ok(), accepted);
+    }
+
+    #[rstest]
+    #[case(HostKeyVerification::default(), true)]
+    #[case(
+        HostKeyVerification::TrustOnFirstUse {
+            path: "~/.ssh/known_hosts".into(),
+            hash: true
+        },
+        true
+    )]
+    #[case(HostKeyVerification::Fingerprint(key().fingerprint(HashAlg::Sha256)), true)]
+    #[case(HostKeyVerification::AcceptAny, false)]
+    fn verification_has_verifier(
+        #[case] verification: HostKeyVerification,
+        #[case] verifies: bool,
+    ) {
+        let verifier = verification.verifier(&Tokens::local()).unwrap();
+
+        assert_eq!(verifier.is_some(), verifies);
+    }
+
+    #[tokio::test]
+    async fn verify_times_out() {
+        let verification = verification(|_, _, _| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(Decision::Accept)
+        });
+
+        let result = verification.verify(&key()).await;
+
+        assert!(matches!(result, Err(Error::HostKeyVerificationTimeout)));
+    }
+}
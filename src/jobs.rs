@@ -0,0 +1,307 @@
+//! Tracking of detached jobs in a state file on the remote host, so that they
+//! can be found again from any later session.
+
+use std::time::Duration;
+use std::time::SystemTime;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::process::Command;
+use crate::process::Job;
+use crate::process::JobStatus;
+use crate::shell;
+
+/// State file used unless another is given, relative to the home directory.
+const DEFAULT_STATE_FILE: &str = ".ssh-util/jobs";
+
+/// Detached jobs recorded on the remote host. Created by
+/// [`ConnectedSession::jobs`].
+///
+/// The state file has one line per job, with its PID, start time in seconds
+/// since the epoch and output path separated by tabs. Appending is safe from
+/// concurrent sessions, but [`Jobs::reap`] rewrites the file and may lose jobs
+/// recorded while it runs.
+#[derive(Clone)]
+pub struct Jobs<'s> {
+    session: &'s ConnectedSession,
+    state_file: Utf8PathBuf,
+}
+
+/// Job found in the state file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedJob {
+    job: Job,
+    started: SystemTime,
+}
+
+impl TrackedJob {
+    #[must_use]
+    pub fn job(&self) -> &Job {
+        &self.job
+    }
+
+    /// When the job was recorded, by the remote host's clock.
+    #[must_use]
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    fn to_line(&self) -> String {
+        let started = self
+            .started
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!("{}\t{started}\t{}", self.job.pid(), self.job.output())
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, '\t');
+        let pid = fields.next()?.parse().ok()?;
+        let started = fields.next()?.parse().ok()?;
+        let output = fields.next()?;
+
+        Some(Self {
+            job: Job::new(pid, output),
+            started: SystemTime::UNIX_EPOCH + Duration::from_secs(started),
+        })
+    }
+}
+
+impl<'s> Jobs<'s> {
+    pub(crate) fn new(session: &'s ConnectedSession) -> Self {
+        Self {
+            session,
+            state_file: Utf8PathBuf::from(DEFAULT_STATE_FILE),
+        }
+    }
+
+    /// Remote file jobs are recorded in, relative to the home directory
+    /// unless absolute. Defaults to `.ssh-util/jobs`.
+    #[must_use]
+    pub fn state_file(mut self, path: impl Into<Utf8PathBuf>) -> Self {
+        self.state_file = path.into();
+        self
+    }
+
+    /// Starts `command` detached, like [`Command::detach`], and records it.
+    ///
+    /// # Errors
+    ///
+    /// - If `output` contains a newline, which the state file cannot hold.
+    /// - If the command cannot be started.
+    /// - If the state file cannot be written.
+    pub async fn spawn(
+        &self,
+        command: &mut Command<'_>,
+        output: impl AsRef<Utf8Path>,
+    ) -> Result<TrackedJob> {
+        let output = output.as_ref();
+        if output.as_str().contains('\n') {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "job output path contains a newline",
+            )));
+        }
+
+        let job = command.detach(output).await?;
+        self.record(job).await
+    }
+
+    /// Appends `job` to the state file, stamped with the current time.
+    async fn record(&self, job: Job) -> Result<TrackedJob> {
+        let state_file = shell::quote(self.state_file.as_str());
+        let script = format!(
+            "mkdir -p -- \"$(dirname -- {state_file})\" && \
+             started=$(date +%s) && \
+             printf '%s\\t%s\\t%s\\n' {pid} \"$started\" {output} >> {state_file} && \
+             echo \"$started\"",
+            pid = job.pid(),
+            output = shell::quote(job.output().as_str()),
+        );
+        let stdout = self.run(&script).await?;
+        let started = stdout.trim().parse().map_err(|_| Error::UnexpectedOutput {
+            command: script,
+            output: stdout.clone(),
+        })?;
+
+        Ok(TrackedJob {
+            job,
+            started: SystemTime::UNIX_EPOCH + Duration::from_secs(started),
+        })
+    }
+
+    /// Jobs recorded in the state file, oldest first. Lines that cannot be
+    /// parsed are skipped.
+    ///
+    /// # Errors
+    ///
+    /// - If the state file exists but cannot be read.
+    pub async fn list(&self) -> Result<Vec<TrackedJob>> {
+        let state_file = shell::quote(self.state_file.as_str());
+        let stdout = self
+            .run(&format!(
+                "if [ -e {state_file} ]; then cat -- {state_file}; fi"
+            ))
+            .await?;
+
+        Ok(stdout
+            .lines()
+            .filter_map(|line| {
+                let job = TrackedJob::from_line(line);
+                if job.is_none() {
+                    tracing::warn!(line, "skipping malformed job record");
+                }
+                job
+            })
+            .collect())
+    }
+
+    /// Recorded jobs with their current status.
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`Jobs::list`].
+    /// - If the status of a job cannot be queried.
+    pub async fn poll(&self) -> Result<Vec<(TrackedJob, JobStatus)>> {
+        let mut statuses = Vec::new();
+        for job in self.list().await? {
+            let status = job.job.status(self.session).await?;
+            statuses.push((job, status));
+        }
+
+        Ok(statuses)
+    }
+
+    /// Last `lines` lines of a job's output.
+    ///
+    /// # Errors
+    ///
+    /// - If the job's output cannot be read.
+    pub async fn tail(&self, job: &Job, lines: usize) -> Result<String> {
+        let mut command = self.session.command("tail");
+        command
+            .args(["-n", &lines.to_string(), "--"])
+            .arg(job.output().as_str());
+        let output = command.output().await?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status: output.status,
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Removes jobs that are no longer running from the state file, along
+    /// with their exit code files, and returns them. Their output is kept.
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`Jobs::poll`].
+    /// - If the state file cannot be rewritten.
+    pub async fn reap(&self) -> Result<Vec<(TrackedJob, JobStatus)>> {
+        let (running, finished): (Vec<_>, Vec<_>) = self
+            .poll()
+            .await?
+            .into_iter()
+            .partition(|(_, status)| *status == JobStatus::Running);
+        if finished.is_empty() {
+            return Ok(finished);
+        }
+
+        let mut state = String::new();
+        for (job, _) in &running {
+            state.push_str(&job.to_line());
+            state.push('\n');
+        }
+        let status_files = finished
+            .iter()
+            .map(|(job, _)| shell::quote(job.job.status_file().as_str()).into_owned())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let state_file = shell::quote(self.state_file.as_str());
+        self.run(&format!(
+            "printf %s {state} > {state_file}.new && mv -f -- {state_file}.new {state_file} && \
+             rm -f -- {status_files}",
+            state = shell::quote(&state),
+        ))
+        .await?;
+
+        Ok(finished)
+    }
+
+    /// Runs `script` with `sh`, returning its stdout.
+    async fn run(&self, script: &str) -> Result<String> {
+        let mut command = self.session.command("sh");
+        command.args(["-c", script]);
+        let output = command.output().await?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status: output.status,
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("42\t1700000000\tjob.log", Some((42, 1_700_000_000, "job.log")))]
+    #[case("42\t1700000000\tmy\tjob.log", Some((42, 1_700_000_000, "my\tjob.log")))]
+    #[case("42\tyesterday\tjob.log", None)]
+    #[case("", None)]
+    fn from_line_works(#[case] line: &str, #[case] parsed_should: Option<(u32, u64, &str)>) {
+        let job = TrackedJob::from_line(line);
+
+        let parsed = job.as_ref().map(|job| {
+            let started = job.started.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+            (job.job.pid(), started.as_secs(), job.job.output().as_str())
+        });
+        assert_eq!(parsed, parsed_should);
+        if let Some(job) = job {
+            assert_eq!(job.to_line(), line);
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn jobs_work() {
+        let session = crate::test_server::connect().await;
+        let jobs = session.jobs();
+
+        let quick = jobs
+            .spawn(session.command("echo").arg("done"), "quick.log")
+            .await
+            .unwrap();
+        let slow = jobs
+            .spawn(session.command("sleep").arg("60"), "slow.log")
+            .await
+            .unwrap();
+        while quick.job().status(&session).await.unwrap() == JobStatus::Running {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(jobs.list().await.unwrap(), [quick.clone(), slow.clone()]);
+        assert_eq!(jobs.tail(quick.job(), 1).await.unwrap(), "done\n");
+        assert_eq!(
+            jobs.reap().await.unwrap(),
+            [(quick.clone(), JobStatus::Exited(0))]
+        );
+        assert_eq!(jobs.list().await.unwrap(), [slow.clone()]);
+
+        slow.job().kill(&session, "KILL").await.unwrap();
+    }
+}
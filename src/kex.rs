@@ -0,0 +1,256 @@
+//! Key exchange messages, and probing the algorithms servers offer.
+
+use std::io;
+
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::ToSocketAddrs;
+
+use crate::Result;
+use crate::Session;
+use crate::banner::read_ident;
+use crate::transport::AsyncStream;
+
+/// Message number of `SSH_MSG_KEXINIT`.
+const MSG_KEXINIT: u8 = 20;
+/// Identification string sent when probing servers.
+const PROBE_IDENT: &str = concat!("SSH-2.0-ssh_util_", env!("CARGO_PKG_VERSION"), "\r\n");
+/// Largest first packet accepted from a server.
+const MAX_PACKET_LEN: u32 = 256 * 1024;
+
+/// Pseudo-algorithm advertised by servers supporting OpenSSH's strict key
+/// exchange extension, which mitigates the Terrapin attack (CVE-2023-48795).
+pub const STRICT_KEX_SERVER: &str = "kex-strict-s-v00@openssh.com";
+
+/// Algorithms offered by a peer in its `SSH_MSG_KEXINIT` message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct KexInit {
+    pub kex: Vec<String>,
+    pub host_key: Vec<String>,
+    pub cipher_client_to_server: Vec<String>,
+    pub cipher_server_to_client: Vec<String>,
+    pub mac_client_to_server: Vec<String>,
+    pub mac_server_to_client: Vec<String>,
+    pub compression_client_to_server: Vec<String>,
+    pub compression_server_to_client: Vec<String>,
+}
+
+impl KexInit {
+    /// Parses the payload of an unencrypted binary packet. Returns `None` if
+    /// the payload is not a well-formed `SSH_MSG_KEXINIT`.
+    #[must_use]
+    pub fn parse(payload: &[u8]) -> Option<KexInit> {
+        let (&msg, rest) = payload.split_first()?;
+        if msg != MSG_KEXINIT {
+            return None;
+        }
+
+        // Skip the random cookie.
+        let mut rest = rest.get(16..)?;
+        let mut next = || {
+            let (list, remaining) = read_name_list(rest)?;
+            rest = remaining;
+            Some(list)
+        };
+
+        Some(KexInit {
+            kex: next()?,
+            host_key: next()?,
+            cipher_client_to_server: next()?,
+            cipher_server_to_client: next()?,
+            mac_client_to_server: next()?,
+            mac_server_to_client: next()?,
+            compression_client_to_server: next()?,
+            compression_server_to_client: next()?,
+        })
+    }
+
+    /// Whether the peer offered strict key exchange as a server.
+    #[must_use]
+    pub fn supports_strict_kex(&self) -> bool {
+        self.kex.iter().any(|name| name == STRICT_KEX_SERVER)
+    }
+}
+
+/// What a server offers before authentication, as recorded by
+/// [`Session::probe_algorithms`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AlgorithmReport {
+    /// Identification string the server sent, such as `SSH-2.0-OpenSSH_9.6`.
+    pub ident: String,
+    /// Algorithms the server offered for the key exchange.
+    pub offer: KexInit,
+}
+
+impl Session {
+    /// Connects to the server at `addr` and records its identification
+    /// string and full key exchange offer, without completing the key
+    /// exchange or authenticating. The connection is closed afterwards.
+    ///
+    /// Nothing limits how long this takes, so wrap it in a timeout when
+    /// probing servers that may not answer.
+    ///
+    /// # Errors
+    ///
+    /// - If the TCP connection cannot be established.
+    /// - If the server does not speak SSH or sends a malformed offer.
+    pub async fn probe_algorithms(addr: impl ToSocketAddrs) -> Result<AlgorithmReport> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+
+        read_offer(stream).await
+    }
+}
+
+/// Sends an identification string over `stream`, then reads the server's
+/// identification string and `SSH_MSG_KEXINIT`, which is always the first
+/// packet and sent unencrypted.
+pub(crate) async fn read_offer(mut stream: impl AsyncStream) -> Result<AlgorithmReport> {
+    stream.write_all(PROBE_IDENT.as_bytes()).await?;
+    stream.flush().await?;
+    let mut reader = BufReader::new(stream);
+    let ident = read_ident(&mut reader).await?;
+
+    let packet_len = reader.read_u32().await?;
+    if packet_len > MAX_PACKET_LEN {
+        return Err(invalid_data("first packet is too large"));
+    }
+    let mut packet = vec![0; packet_len as usize];
+    reader.read_exact(&mut packet).await?;
+    let (&padding_len, rest) = packet
+        .split_first()
+        .ok_or_else(|| invalid_data("first packet is empty"))?;
+    let payload_len = rest.len().saturating_sub(usize::from(padding_len));
+    let offer =
+        KexInit::parse(&rest[..payload_len]).ok_or_else(|| invalid_data("malformed KEXINIT"))?;
+
+    Ok(AlgorithmReport { ident, offer })
+}
+
+fn invalid_data(message: &str) -> crate::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+/// Reads an SSH `name-list`, returning it along with the remaining input.
+fn read_name_list(input: &[u8]) -> Option<(Vec<String>, &[u8])> {
+    let len = input.get(..4)?.try_into().map(u32::from_be_bytes).ok()?;
+    let len = usize::try_from(len).ok()?;
+    let rest = &input[4..];
+    let list = std::str::from_utf8(rest.get(..len)?).ok()?;
+
+    let names = list
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+
+    Some((names, &rest[len..]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Encodes a `SSH_MSG_KEXINIT` payload with the given key exchange
+    /// algorithms and fixed values for everything else.
+    pub(crate) fn kex_init_payload(kex: &[&str]) -> Vec<u8> {
+        let mut payload = vec![MSG_KEXINIT];
+        payload.extend([0; 16]);
+        for list in [
+            kex,
+            &["ssh-ed25519"],
+            &["aes128-ctr"],
+            &["aes128-ctr"],
+            &["hmac-sha2-256"],
+            &["hmac-sha2-256"],
+            &["none"],
+            &["none"],
+            &[],
+            &[],
+        ] {
+            let list = list.join(",");
+            payload.extend(u32::try_from(list.len()).unwrap().to_be_bytes());
+            payload.extend(list.as_bytes());
+        }
+        // first_kex_packet_follows and reserved
+        payload.extend([0; 5]);
+        payload
+    }
+
+    #[rstest]
+    #[case(&["curve25519-sha256", STRICT_KEX_SERVER], true)]
+    #[case(&["curve25519-sha256"], false)]
+    fn parse_works(#[case] kex: &[&str], #[case] strict_should: bool) {
+        let kex_init = KexInit::parse(&kex_init_payload(kex)).unwrap();
+
+        assert_eq!(kex_init.kex, kex);
+        assert_eq!(kex_init.host_key, ["ssh-ed25519"]);
+        assert_eq!(kex_init.compression_server_to_client, ["none"]);
+        assert_eq!(kex_init.supports_strict_kex(), strict_should);
+    }
+
+    #[rstest]
+    #[case(&[])]
+    #[case(&[21])]
+    #[case(&[MSG_KEXINIT, 0, 0])]
+    #[case(&[MSG_KEXINIT, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255])]
+    fn parse_rejects_malformed(#[case] payload: &[u8]) {
+        assert_eq!(KexInit::parse(payload), None);
+    }
+
+    /// Sends `lines`, which should end with the identification string, then
+    /// a `SSH_MSG_KEXINIT` packet with `payload` over `server`, and checks
+    /// the client's identification string.
+    pub(crate) async fn serve_offer(mut server: impl AsyncStream, lines: &[u8], payload: &[u8]) {
+        let padding = [0u8; 4];
+        let packet_len = u32::try_from(1 + payload.len() + padding.len()).unwrap();
+        let mut server_bytes = lines.to_vec();
+        server_bytes.extend(packet_len.to_be_bytes());
+        server_bytes.push(u8::try_from(padding.len()).unwrap());
+        server_bytes.extend(payload);
+        server_bytes.extend(padding);
+
+        server.write_all(&server_bytes).await.unwrap();
+        let mut ident = [0; PROBE_IDENT.len()];
+        server.read_exact(&mut ident).await.unwrap();
+        assert_eq!(ident, PROBE_IDENT.as_bytes());
+    }
+
+    /// Server sending `lines` before its identification string, then a
+    /// `SSH_MSG_KEXINIT` packet with `payload`.
+    fn serve(lines: &'static [u8], payload: Vec<u8>) -> tokio::io::DuplexStream {
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(async move { serve_offer(server, lines, &payload).await });
+
+        client
+    }
+
+    #[tokio::test]
+    async fn read_offer_works() {
+        let stream = serve(
+            b"pre-banner line\r\nSSH-2.0-OpenSSH_9.6\r\n",
+            kex_init_payload(&["curve25519-sha256", STRICT_KEX_SERVER]),
+        );
+
+        let report = read_offer(stream).await.unwrap();
+
+        assert_eq!(report.ident, "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(report.offer.kex, ["curve25519-sha256", STRICT_KEX_SERVER]);
+        assert_eq!(report.offer.cipher_server_to_client, ["aes128-ctr"]);
+        assert!(report.offer.supports_strict_kex());
+    }
+
+    #[rstest]
+    #[case(b"HTTP/1.1 400 Bad Request\r\n", kex_init_payload(&[]))]
+    #[case(b"SSH-2.0-OpenSSH_9.6\r\n", vec![21])]
+    #[tokio::test]
+    async fn read_offer_rejects_malformed(#[case] lines: &'static [u8], #[case] payload: Vec<u8>) {
+        let result = read_offer(serve(lines, payload)).await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Passwords and key passphrases kept in the OS keyring: the macOS Keychain,
+//! the Secret Service on Linux or the Windows Credential Manager.
+//!
+//! With this feature, keys loaded with a [`PassphraseProvider`] are first
+//! tried with the passphrase stored for their path, so the provider is only
+//! asked if there is none or it is wrong.
+//!
+//! [`PassphraseProvider`]: crate::PassphraseProvider
+
+use camino::Utf8Path;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use secrecy::ExposeSecret;
+use secrecy::SecretSlice;
+use secrecy::SecretString;
+
+use crate::Error;
+use crate::Result;
+use crate::SecretSource;
+
+/// Service entries are stored under unless another is given.
+const DEFAULT_SERVICE: &str = "ssh-util";
+
+/// Entries of one service in the OS keyring. Every operation blocks while
+/// the keyring is consulted, which may prompt the user to unlock it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyring {
+    service: String,
+}
+
+impl Default for Keyring {
+    fn default() -> Self {
+        Self::new(DEFAULT_SERVICE)
+    }
+}
+
+impl Keyring {
+    /// Keyring entries of `service`, which should name the application.
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    /// Password stored for `user` on `host`, if any.
+    ///
+    /// # Errors
+    ///
+    /// - If the keyring cannot be reached or refuses access.
+    pub fn password(&self, user: &str, host: &str) -> Result<Option<SecretString>> {
+        self.get(&password_account(user, host))
+    }
+
+    /// Stores `password` for `user` on `host`, replacing any stored before.
+    ///
+    /// # Errors
+    ///
+    /// - If the keyring cannot be reached or refuses access.
+    pub fn set_password(&self, user: &str, host: &str, password: &SecretString) -> Result<()> {
+        self.set(&password_account(user, host), password)
+    }
+
+    /// Passphrase stored for the private key at `path`, if any.
+    ///
+    /// # Errors
+    ///
+    /// - If the keyring cannot be reached or refuses access.
+    pub fn passphrase(&self, path: &Utf8Path) -> Result<Option<SecretString>> {
+        self.get(&passphrase_account(path))
+    }
+
+    /// Stores `passphrase` for the private key at `path`, replacing any
+    /// stored before.
+    ///
+    /// # Errors
+    ///
+    /// - If the keyring cannot be reached or refuses access.
+    pub fn set_passphrase(&self, path: &Utf8Path, passphrase: &SecretString) -> Result<()> {
+        self.set(&passphrase_account(path), passphrase)
+    }
+
+    /// Removes the entry named `account`, as stored by the other methods or
+    /// fetched as a [`SecretSource`]. Removing a missing entry succeeds.
+    ///
+    /// # Errors
+    ///
+    /// - If the keyring cannot be reached or refuses access.
+    pub fn delete(&self, account: &str) -> Result<()> {
+        match self.entry(account)?.delete_credential() {
+            Ok(()) | Err(::keyring::Error::NoEntry) => Ok(()),
+            Err(error) => Err(keyring_error(error)),
+        }
+    }
+
+    fn get(&self, account: &str) -> Result<Option<SecretString>> {
+        match self.entry(account)?.get_password() {
+            Ok(secret) => Ok(Some(SecretString::from(secret))),
+            Err(::keyring::Error::NoEntry) => Ok(None),
+            Err(error) => Err(keyring_error(error)),
+        }
+    }
+
+    fn set(&self, account: &str, secret: &SecretString) -> Result<()> {
+        self.entry(account)?
+            .set_password(secret.expose_secret())
+            .map_err(keyring_error)
+    }
+
+    fn entry(&self, account: &str) -> Result<::keyring::Entry> {
+        ::keyring::Entry::new(&self.service, account).map_err(keyring_error)
+    }
+}
+
+/// Fetches the entry named by the secret's name.
+impl SecretSource for Keyring {
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<SecretSlice<u8>>> {
+        let keyring = self.clone();
+        let name = name.to_string();
+        async move {
+            tokio::task::spawn_blocking(move || match keyring.get(&name)? {
+                Some(secret) => Ok(SecretSlice::from(
+                    secret.expose_secret().as_bytes().to_vec(),
+                )),
+                None => Err(Error::SecretNotFound(name)),
+            })
+            .await
+            .map_err(|error| Error::SecretSource(error.into()))?
+        }
+        .boxed()
+    }
+}
+
+/// Passphrase stored for a private key, tried before any other passphrase
+/// of a [`PassphraseProvider`](crate::PassphraseProvider).
+pub(crate) fn stored_passphrase(path: &Utf8Path) -> Option<SecretString> {
+    Keyring::default()
+        .passphrase(path)
+        .inspect_err(|error| tracing::debug!(%path, %error, "could not read keyring"))
+        .ok()
+        .flatten()
+}
+
+fn password_account(user: &str, host: &str) -> String {
+    format!("password:{user}@{host}")
+}
+
+fn passphrase_account(path: &Utf8Path) -> String {
+    format!("passphrase:{path}")
+}
+
+fn keyring_error(error: ::keyring::Error) -> Error {
+    Error::SecretSource(Box::new(error))
+}
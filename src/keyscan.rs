@@ -0,0 +1,243 @@
+//! Collecting the host keys servers present without authenticating, like
+//! `ssh-keyscan`, to populate `known_hosts` files or audit an inventory.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bon::Builder;
+use futures::StreamExt;
+use ssh_key::PublicKey;
+use tokio::sync::oneshot;
+
+use crate::Error;
+use crate::Result;
+use crate::driver::russh::to_public_key;
+use crate::known_hosts::host_pattern;
+use crate::transport::AsyncStream;
+
+/// Time allowed per key, from connecting to receiving it, like
+/// `ssh-keyscan`'s default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_CONCURRENCY: usize = 64;
+
+/// Kind of host key to ask servers for. Servers present one key per key
+/// exchange, so each kind takes a connection of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    Ed25519,
+    Ecdsa,
+    Rsa,
+}
+
+impl KeyType {
+    pub const ALL: [KeyType; 3] = [KeyType::Ed25519, KeyType::Ecdsa, KeyType::Rsa];
+
+    /// Host key algorithms offered when asking for this kind of key.
+    fn algorithms(self) -> &'static [&'static str] {
+        match self {
+            KeyType::Ed25519 => &["ssh-ed25519"],
+            KeyType::Ecdsa => &[
+                "ecdsa-sha2-nistp256",
+                "ecdsa-sha2-nistp384",
+                "ecdsa-sha2-nistp521",
+            ],
+            KeyType::Rsa => &["rsa-sha2-512", "rsa-sha2-256", "ssh-rsa"],
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyType::Ed25519 => "ed25519",
+            KeyType::Ecdsa => "ecdsa",
+            KeyType::Rsa => "rsa",
+        })
+    }
+}
+
+/// Scan of the host keys of many hosts at once.
+#[derive(Debug, Builder)]
+pub struct KeyScan {
+    #[builder(field)]
+    hosts: Vec<(String, u16)>,
+    /// Kinds of key to ask every host for. Defaults to all of them.
+    #[builder(
+        default = KeyType::ALL.to_vec(),
+        with = |key_types: impl IntoIterator<Item = KeyType>| key_types.into_iter().collect()
+    )]
+    key_types: Vec<KeyType>,
+    /// Time allowed per key, from connecting to receiving it. Defaults to 5
+    /// seconds.
+    #[builder(default = DEFAULT_TIMEOUT)]
+    timeout: Duration,
+    /// Most hosts scanned at once. Defaults to 64.
+    #[builder(default = DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+}
+
+impl<S: key_scan_builder::State> KeyScanBuilder<S> {
+    /// Adds a host to scan on port 22.
+    pub fn host(self, host: impl Into<String>) -> Self {
+        self.host_with_port(host, 22)
+    }
+
+    /// Adds a host to scan on `port`.
+    pub fn host_with_port(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.hosts.push((host.into(), port));
+        self
+    }
+}
+
+/// Host keys found on a host.
+#[derive(Debug)]
+pub struct ScannedHost {
+    pub host: String,
+    pub port: u16,
+    /// Key of each kind asked for, or why it could not be collected, such as
+    /// the host having no key of that kind.
+    pub keys: Vec<(KeyType, Result<PublicKey>)>,
+}
+
+impl ScannedHost {
+    /// Lines recording the keys found in a `known_hosts` file.
+    pub fn known_hosts_lines(&self) -> impl Iterator<Item = String> + '_ {
+        let pattern = host_pattern(&self.host, self.port);
+        self.keys.iter().filter_map(move |(_, key)| {
+            let key = key.as_ref().ok()?;
+            let mut key = key.clone();
+            key.set_comment("");
+            Some(format!("{pattern} {}", key.to_openssh().ok()?))
+        })
+    }
+}
+
+impl KeyScan {
+    /// Connects to every host, at most `concurrency` at once, and collects
+    /// its keys. Nothing is authenticated: each connection is dropped as
+    /// soon as the server has presented its key. Results are in the order
+    /// hosts were added.
+    pub async fn scan(&self) -> Vec<ScannedHost> {
+        futures::stream::iter(&self.hosts)
+            .map(|(host, port)| self.scan_host(host, *port))
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    async fn scan_host(&self, host: &str, port: u16) -> ScannedHost {
+        let mut keys = Vec::with_capacity(self.key_types.len());
+        for &key_type in &self.key_types {
+            let result = tokio::time::timeout(self.timeout, async {
+                let stream = tokio::net::TcpStream::connect((host, port)).await?;
+                exchange_keys(stream, key_type).await
+            })
+            .await
+            .unwrap_or(Err(Error::ConnectTimeout));
+            if let Err(error) = &result {
+                tracing::debug!(host, port, %key_type, %error, "could not collect host key");
+            }
+            keys.push((key_type, result));
+        }
+
+        ScannedHost {
+            host: host.to_string(),
+            port,
+            keys,
+        }
+    }
+}
+
+/// Runs a key exchange over `stream` offering only the algorithms of
+/// `key_type`, and returns the host key the server presents.
+async fn exchange_keys(stream: impl AsyncStream, key_type: KeyType) -> Result<PublicKey> {
+    let mut config = russh::client::Config::default();
+    config.preferred.key = key_type
+        .algorithms()
+        .iter()
+        .filter_map(|name| russh::keys::Algorithm::new(name).ok())
+        .collect::<Vec<_>>()
+        .into();
+
+    let (sender, mut receiver) = oneshot::channel();
+    let handler = ScanHandler { key: Some(sender) };
+    let result = russh::client::connect_stream(Arc::new(config), stream, handler).await;
+
+    match (receiver.try_recv(), result) {
+        (Ok(key), _) => key,
+        (Err(_), Err(error)) => Err(error),
+        (Err(_), Ok(_)) => unreachable!("every host key is rejected"),
+    }
+}
+
+/// Handler passing on the host key, then rejecting it to end the connection
+/// before authentication.
+struct ScanHandler {
+    key: Option<oneshot::Sender<Result<PublicKey>>>,
+}
+
+impl russh::client::Handler for ScanHandler {
+    type Error = Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh::keys::PublicKey,
+    ) -> Result<bool> {
+        if let Some(sender) = self.key.take() {
+            let _ = sender.send(to_public_key(server_public_key));
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::test_server;
+
+    fn key(name: &str) -> PublicKey {
+        PublicKey::read_openssh_file(format!("test/creds/{name}.pub").as_ref()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn exchange_keys_works() {
+        let stream = test_server::spawn().into_stream().unwrap();
+
+        let host_key = exchange_keys(stream, KeyType::Ed25519).await.unwrap();
+
+        assert_eq!(host_key.key_data(), key("id_ed25519").key_data());
+    }
+
+    #[tokio::test]
+    async fn exchange_keys_fails_without_key_of_type() {
+        let stream = test_server::spawn().into_stream().unwrap();
+
+        let result = exchange_keys(stream, KeyType::Rsa).await;
+
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[case(22, "web1 ssh-ed25519 ")]
+    #[case(2222, "[web1]:2222 ssh-ed25519 ")]
+    fn known_hosts_lines_works(#[case] port: u16, #[case] prefix_should: &str) {
+        let scanned = ScannedHost {
+            host: "web1".to_string(),
+            port,
+            keys: vec![
+                (KeyType::Ed25519, Ok(key("id_ed25519"))),
+                (KeyType::Rsa, Err(Error::ConnectTimeout)),
+            ],
+        };
+
+        let lines: Vec<_> = scanned.known_hosts_lines().collect();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(prefix_should));
+        assert!(!lines[0].ends_with(' '));
+    }
+}
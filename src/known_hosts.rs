@@ -0,0 +1,630 @@
+//! Reading and updating OpenSSH `known_hosts` files.
+
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use hmac::Hmac;
+use hmac::Mac;
+use sha1::Sha1;
+use ssh_encoding::base64::Base64;
+use ssh_encoding::base64::Encoding;
+use ssh_key::HashAlg;
+use ssh_key::PublicKey;
+use ssh_key::rand_core::OsRng;
+use ssh_key::rand_core::RngCore;
+
+use crate::Decision;
+use crate::Error;
+use crate::HostKeyVerifier;
+use crate::Result;
+use crate::config::matches_pattern_list;
+
+/// Storage of known host keys, so that verification decisions can be backed
+/// by something other than a local file, such as a database or a central
+/// attestation service. Implemented by [`KnownHostsFile`].
+///
+/// Backends should report their own failures as [`Error::Store`].
+pub trait KnownHostsStore: Send + Sync {
+    /// Looks up `key` for `host` and `port`.
+    fn check<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<HostKeyStatus>>;
+
+    /// Records `key` for `host` and `port`, in addition to any keys already
+    /// recorded for them.
+    fn add<'a>(&'a self, host: &'a str, port: u16, key: &'a PublicKey)
+    -> BoxFuture<'a, Result<()>>;
+}
+
+/// OpenSSH `known_hosts` file.
+///
+/// Updates are safe from concurrent processes: they hold an advisory lock on
+/// a `.lock` file next to it while rewriting the file, and replace it
+/// atomically so readers never see a partial write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownHostsFile {
+    path: Utf8PathBuf,
+    /// Whether hosts are recorded hashed, like OpenSSH's `HashKnownHosts`.
+    hash_hosts: bool,
+}
+
+/// Whether a host key is recorded for a host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    /// The key is recorded for the host.
+    Known,
+    /// A different key of the same type is recorded for the host, so the key
+    /// may belong to an impostor.
+    Changed { known: Box<PublicKey> },
+    /// The key is marked `@revoked`.
+    Revoked,
+    /// No key of this type is recorded for the host.
+    Unknown,
+}
+
+/// Line of a `known_hosts` file holding a host key.
+struct Entry<'a> {
+    marker: Option<&'a str>,
+    patterns: &'a str,
+    key: PublicKey,
+}
+
+impl KnownHostsFile {
+    pub fn new(path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            hash_hosts: false,
+        }
+    }
+
+    /// Records hosts hashed, like OpenSSH's `HashKnownHosts=yes`, so that the
+    /// file does not list the hosts the user connects to.
+    #[must_use]
+    pub fn hash_hosts(mut self, hash_hosts: bool) -> Self {
+        self.hash_hosts = hash_hosts;
+        self
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Looks up `key` for `host` and `port`. A missing file records no keys.
+    /// Hostnames hashed by `HashKnownHosts` or `ssh-keygen -H` match like
+    /// plain ones. `@cert-authority` lines are not supported and never match.
+    ///
+    /// # Errors
+    ///
+    /// - If the file exists but cannot be read.
+    pub fn check(&self, host: &str, port: u16, key: &PublicKey) -> Result<HostKeyStatus> {
+        let text = self.read()?;
+
+        Ok(check_text(&text, host, port, key))
+    }
+
+    /// Records `key` for `host` and `port`, as OpenSSH does for
+    /// `StrictHostKeyChecking=accept-new`. Entries that duplicate another for
+    /// the same hosts and key are dropped while the file is rewritten, so
+    /// recording a key twice, even from several processes at once, leaves a
+    /// single entry. Hashed names differ on every write, so a key already
+    /// recorded for the host is not recorded again when hashing. The file and
+    /// its directory are created if missing.
+    ///
+    /// # Errors
+    ///
+    /// - If the lock cannot be taken.
+    /// - If the file cannot be read or replaced.
+    pub fn add(&self, host: &str, port: u16, key: &PublicKey) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}.lock", self.path))?;
+        lock.lock()?;
+
+        let existing = self.read()?;
+        if self.hash_hosts && check_text(&existing, host, port, key) == HostKeyStatus::Known {
+            lock.unlock()?;
+            return Ok(());
+        }
+
+        let mut key = key.clone();
+        key.set_comment("");
+        let name = host_pattern(host, port);
+        let name = if self.hash_hosts {
+            hash_name(&name)
+        } else {
+            name
+        };
+        let entry = format!("{name} {}", key.to_openssh()?);
+
+        let mut seen = HashSet::new();
+        let mut text = String::with_capacity(existing.len() + entry.len() + 1);
+        for line in existing.lines().chain([entry.as_str()]) {
+            if let Some(parsed) = Entry::parse(line) {
+                let identity = (
+                    parsed.marker,
+                    parsed.patterns,
+                    parsed.key.key_data().clone(),
+                );
+                if !seen.insert(identity) {
+                    continue;
+                }
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        self.replace(&text)?;
+
+        lock.unlock()?;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Ok(text),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Atomically replaces the file's contents with `text`, keeping its
+    /// permissions.
+    fn replace(&self, text: &str) -> Result<()> {
+        let temp = Utf8PathBuf::from(format!("{}.{}.tmp", self.path, std::process::id()));
+        let result = (|| {
+            let mut file = File::create(&temp)?;
+            file.write_all(text.as_bytes())?;
+            file.sync_all()?;
+            if let Ok(metadata) = fs::metadata(&self.path) {
+                fs::set_permissions(&temp, metadata.permissions())?;
+            }
+            fs::rename(&temp, &self.path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+
+        Ok(result?)
+    }
+}
+
+/// File operations block, so they run on the blocking thread pool.
+impl KnownHostsStore for KnownHostsFile {
+    fn check<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<HostKeyStatus>> {
+        let (file, host, key) = (self.clone(), host.to_string(), key.clone());
+        blocking(move || file.check(&host, port, &key)).boxed()
+    }
+
+    fn add<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<()>> {
+        let (file, host, key) = (self.clone(), host.to_string(), key.clone());
+        blocking(move || file.add(&host, port, &key)).boxed()
+    }
+}
+
+/// Accepts keys recorded in the file, and rejects others like OpenSSH's
+/// `StrictHostKeyChecking=yes`: a key that changed fails with
+/// [`Error::HostKeyMismatch`], and unknown or revoked keys are rejected.
+impl HostKeyVerifier for KnownHostsFile {
+    fn verify<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<Decision>> {
+        async move {
+            match KnownHostsStore::check(self, host, port, key).await? {
+                HostKeyStatus::Known => Ok(Decision::Accept),
+                HostKeyStatus::Changed { known } => Err(mismatch(&known, key)),
+                status => {
+                    tracing::warn!(host, port, ?status, path = %self.path, "refusing host key");
+                    Ok(Decision::Reject)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Trust on first use: accepts keys recorded in the store, and records and
+/// accepts the key of a host with none of its type, like OpenSSH's
+/// `StrictHostKeyChecking=accept-new`. A key that changed fails with
+/// [`Error::HostKeyMismatch`], and revoked keys are rejected.
+///
+/// Meant for bootstrapping fleets whose host keys are not distributed
+/// beforehand: the first connection to each host is open to impostors, but
+/// later ones are not.
+#[derive(Debug, Clone)]
+pub struct TrustOnFirstUse<S>(pub S);
+
+impl<S: KnownHostsStore> HostKeyVerifier for TrustOnFirstUse<S> {
+    fn verify<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<Decision>> {
+        async move {
+            match self.0.check(host, port, key).await? {
+                HostKeyStatus::Known => Ok(Decision::Accept),
+                HostKeyStatus::Unknown => {
+                    tracing::info!(
+                        host,
+                        port,
+                        fingerprint = %key.fingerprint(HashAlg::Sha256),
+                        "recording new host key"
+                    );
+                    self.0.add(host, port, key).await?;
+                    Ok(Decision::Accept)
+                }
+                HostKeyStatus::Changed { known } => Err(mismatch(&known, key)),
+                HostKeyStatus::Revoked => {
+                    tracing::warn!(host, port, "refusing revoked host key");
+                    Ok(Decision::Reject)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+fn mismatch(known: &PublicKey, key: &PublicKey) -> Error {
+    Error::HostKeyMismatch {
+        expected: Box::new(known.fingerprint(HashAlg::Sha256)),
+        got: Box::new(key.fingerprint(HashAlg::Sha256)),
+    }
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|error| Error::Io(io::Error::other(error)))?
+}
+
+impl<'a> Entry<'a> {
+    /// Parses a line, returning `None` for comments, blank lines and keys
+    /// that cannot be parsed.
+    fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let mut first = fields.next()?;
+        if first.starts_with('#') {
+            return None;
+        }
+
+        let marker = first.starts_with('@').then_some(first);
+        if marker.is_some() {
+            first = fields.next()?;
+        }
+
+        let algorithm = fields.next()?;
+        let data = fields.next()?;
+        let key = PublicKey::from_openssh(&format!("{algorithm} {data}")).ok()?;
+
+        Some(Self {
+            marker,
+            patterns: first,
+            key,
+        })
+    }
+}
+
+/// Looks up `key` for `host` and `port` in the contents of a `known_hosts`
+/// file.
+pub(crate) fn check_text(text: &str, host: &str, port: u16, key: &PublicKey) -> HostKeyStatus {
+    let name = host_pattern(host, port);
+
+    let mut status = HostKeyStatus::Unknown;
+    for entry in text.lines().filter_map(Entry::parse) {
+        let matches = if entry.patterns.starts_with('|') {
+            matches_hashed(entry.patterns, &name)
+        } else {
+            let patterns: Vec<String> = entry.patterns.split(',').map(str::to_string).collect();
+            matches_pattern_list(&patterns, &name)
+        };
+        if !matches {
+            continue;
+        }
+
+        let same_key = entry.key.key_data() == key.key_data();
+        match entry.marker {
+            Some("@revoked") if same_key => return HostKeyStatus::Revoked,
+            None if same_key => status = HostKeyStatus::Known,
+            None if entry.key.algorithm() == key.algorithm()
+                && status == HostKeyStatus::Unknown =>
+            {
+                status = HostKeyStatus::Changed {
+                    known: Box::new(entry.key),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    status
+}
+
+/// Whether the hashed hostname `|1|salt|hash` is `name`: the hash is the
+/// HMAC-SHA1 of the name keyed with the salt, both in base64.
+fn matches_hashed(hashed: &str, name: &str) -> bool {
+    let Some((salt, hash)) = hashed
+        .strip_prefix("|1|")
+        .and_then(|rest| rest.split_once('|'))
+    else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (Base64::decode_vec(salt), Base64::decode_vec(hash)) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(name.as_bytes());
+
+    mac.verify_slice(&hash).is_ok()
+}
+
+/// Hashes `name` as `|1|salt|hash`, with a random salt.
+fn hash_name(name: &str) -> String {
+    let mut salt = [0; 20];
+    OsRng.fill_bytes(&mut salt);
+    let mut mac = Hmac::<Sha1>::new_from_slice(&salt).expect("HMAC takes keys of any size");
+    mac.update(name.as_bytes());
+
+    format!(
+        "|1|{}|{}",
+        Base64::encode_string(&salt),
+        Base64::encode_string(&mac.finalize().into_bytes())
+    )
+}
+
+/// Name `host` is recorded under, with the port only if it is not 22.
+pub(crate) fn host_pattern(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn key(name: &str) -> PublicKey {
+        PublicKey::read_openssh_file(format!("test/creds/{name}.pub").as_ref()).unwrap()
+    }
+
+    fn known_hosts(text: &str) -> (tempfile::TempDir, KnownHostsFile) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8Path::from_path(dir.path()).unwrap().join("known_hosts");
+        fs::write(&path, text).unwrap();
+        (dir, KnownHostsFile::new(path))
+    }
+
+    #[rstest]
+    #[case("web1", 22, "id_ed25519", HostKeyStatus::Known)]
+    #[case("web1", 2222, "id_ed25519", HostKeyStatus::Unknown)]
+    #[case("db1", 2222, "id_ed25519", HostKeyStatus::Known)]
+    #[case("web2.example.com", 22, "id_ed25519", HostKeyStatus::Known)]
+    #[case("web1", 22, "id_ecdsa", HostKeyStatus::Unknown)]
+    #[case("bad.example.com", 22, "id_ecdsa", HostKeyStatus::Revoked)]
+    #[case("web1", 22, "id_rsa", HostKeyStatus::Known)]
+    #[case("db1", 2222, "id_rsa", HostKeyStatus::Known)]
+    #[case("db1", 22, "id_rsa", HostKeyStatus::Unknown)]
+    fn check_works(
+        #[case] host: &str,
+        #[case] port: u16,
+        #[case] key_name: &str,
+        #[case] status_should: HostKeyStatus,
+    ) {
+        let ed25519 = key("id_ed25519").to_openssh().unwrap();
+        let ecdsa = key("id_ecdsa").to_openssh().unwrap();
+        let rsa = key("id_rsa").to_openssh().unwrap();
+        let (_dir, known_hosts) = known_hosts(&format!(
+            "# comment\n\
+             web1,[db1]:2222 {ed25519}\n\
+             *.example.com,!bad.example.com {ed25519}\n\
+             @revoked bad.example.com {ecdsa}\n\
+             |1|c2FsdA==|aGFzaA== {ecdsa}\n\
+             |1|M43c2DlHmXdwt5zHDcjN9+FcSPo=|RQeb+1KxsLLkounYkOND/WpbBQA= {rsa}\n\
+             |1|AnWvSECfwKDsteb0i8LRpPDadXo=|vn4X8WcikVHu4REP5Yah1JonbLw= {rsa}\n"
+        ));
+
+        let status = known_hosts.check(host, port, &key(key_name)).unwrap();
+
+        assert_eq!(status, status_should);
+    }
+
+    #[test]
+    fn check_detects_changed_key() {
+        let (_dir, known_hosts) = known_hosts("");
+        known_hosts.add("web1", 22, &key("id_ed25519")).unwrap();
+
+        let status = known_hosts.check("web1", 22, &key("enc_ed25519")).unwrap();
+
+        assert!(matches!(
+            status,
+            HostKeyStatus::Changed { known } if known.key_data() == key("id_ed25519").key_data()
+        ));
+    }
+
+    #[rstest]
+    #[case("id_ed25519", Some(Decision::Accept))]
+    #[case("id_ecdsa", Some(Decision::Reject))]
+    #[case("enc_ed25519", None)]
+    #[tokio::test]
+    async fn verify_works(#[case] key_name: &str, #[case] decision_should: Option<Decision>) {
+        let (_dir, known_hosts) = known_hosts("");
+        known_hosts.add("web1", 22, &key("id_ed25519")).unwrap();
+
+        let result = known_hosts.verify("web1", 22, &key(key_name)).await;
+
+        match decision_should {
+            Some(decision) => assert_eq!(result.unwrap(), decision),
+            None => assert!(matches!(result, Err(Error::HostKeyMismatch { .. }))),
+        }
+    }
+
+    #[rstest]
+    #[case("id_ed25519", Some(Decision::Accept), true)]
+    #[case("id_ecdsa", Some(Decision::Accept), true)]
+    #[case("enc_ed25519", None, false)]
+    #[tokio::test]
+    async fn trust_on_first_use_works(
+        #[case] key_name: &str,
+        #[case] decision_should: Option<Decision>,
+        #[case] recorded_should: bool,
+    ) {
+        let (_dir, known_hosts) = known_hosts("");
+        known_hosts.add("web1", 22, &key("id_ed25519")).unwrap();
+        let verifier = TrustOnFirstUse(known_hosts.clone());
+
+        let result = verifier.verify("web1", 22, &key(key_name)).await;
+
+        match decision_should {
+            Some(decision) => assert_eq!(result.unwrap(), decision),
+            None => assert!(matches!(result, Err(Error::HostKeyMismatch { .. }))),
+        }
+        let status = known_hosts.check("web1", 22, &key(key_name)).unwrap();
+        assert_eq!(status == HostKeyStatus::Known, recorded_should);
+    }
+
+    #[test]
+    fn add_hashes_hosts() {
+        let (_dir, known_hosts) = known_hosts("");
+        let known_hosts = known_hosts.hash_hosts(true);
+
+        known_hosts.add("db1", 2222, &key("id_ecdsa")).unwrap();
+        known_hosts.add("db1", 2222, &key("id_ecdsa")).unwrap();
+
+        let text = fs::read_to_string(known_hosts.path()).unwrap();
+        assert!(text.starts_with("|1|"));
+        assert!(!text.contains("db1"));
+        assert_eq!(text.lines().count(), 1);
+        let status = known_hosts.check("db1", 2222, &key("id_ecdsa")).unwrap();
+        assert_eq!(status, HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn add_works_concurrently() {
+        let (_dir, known_hosts) = known_hosts("# kept\n");
+        let hosts: Vec<String> = (0..8).map(|i| format!("host{i}")).collect();
+
+        std::thread::scope(|scope| {
+            for host in hosts.iter().chain(&hosts) {
+                let known_hosts = &known_hosts;
+                scope.spawn(move || known_hosts.add(host, 22, &key("id_ed25519")).unwrap());
+            }
+        });
+
+        let text = fs::read_to_string(known_hosts.path()).unwrap();
+        assert!(text.starts_with("# kept\n"));
+        assert_eq!(text.lines().count(), hosts.len() + 1);
+        for host in &hosts {
+            let status = known_hosts.check(host, 22, &key("id_ed25519")).unwrap();
+            assert_eq!(status, HostKeyStatus::Known);
+        }
+    }
+
+    /// Store keeping keys in memory, standing in for a database.
+    #[derive(Default)]
+    struct MemoryStore(std::sync::Mutex<Vec<(String, u16, PublicKey)>>);
+
+    impl KnownHostsStore for MemoryStore {
+        fn check<'a>(
+            &'a self,
+            host: &'a str,
+            port: u16,
+            key: &'a PublicKey,
+        ) -> BoxFuture<'a, Result<HostKeyStatus>> {
+            let keys = self.0.lock().unwrap();
+            let known = keys
+                .iter()
+                .any(|(h, p, k)| h == host && *p == port && k == key);
+            let status = if known {
+                HostKeyStatus::Known
+            } else {
+                HostKeyStatus::Unknown
+            };
+            futures::future::ready(Ok(status)).boxed()
+        }
+
+        fn add<'a>(
+            &'a self,
+            host: &'a str,
+            port: u16,
+            key: &'a PublicKey,
+        ) -> BoxFuture<'a, Result<()>> {
+            let mut keys = self.0.lock().unwrap();
+            keys.push((host.to_string(), port, key.clone()));
+            futures::future::ready(Ok(())).boxed()
+        }
+    }
+
+    #[rstest]
+    #[case::file(true)]
+    #[case::memory(false)]
+    #[tokio::test]
+    async fn store_works(#[case] file: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Box<dyn KnownHostsStore> = if file {
+            let dir = Utf8Path::from_path(dir.path()).unwrap();
+            Box::new(KnownHostsFile::new(dir.join("known_hosts")))
+        } else {
+            Box::new(MemoryStore::default())
+        };
+        let key = key("id_ed25519");
+        assert_eq!(
+            store.check("web1", 22, &key).await.unwrap(),
+            HostKeyStatus::Unknown
+        );
+
+        store.add("web1", 22, &key).await.unwrap();
+
+        assert_eq!(
+            store.check("web1", 22, &key).await.unwrap(),
+            HostKeyStatus::Known
+        );
+    }
+
+    #[test]
+    fn add_creates_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8Path::from_path(dir.path())
+            .unwrap()
+            .join(".ssh/known_hosts");
+        let known_hosts = KnownHostsFile::new(&path);
+
+        known_hosts.add("db1", 2222, &key("id_ecdsa")).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("[db1]:2222 ecdsa-sha2-nistp256 "));
+        assert_eq!(text.split_whitespace().count(), 3);
+    }
+}
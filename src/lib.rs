@@ -1,72 +1,893 @@
 #![warn(clippy::pedantic)]
 
+use std::fmt;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::hash::RandomState;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
 use bon::Builder;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+
+use crate::driver::Connected;
+use crate::event::Events;
+use crate::process::CommandEnv;
+use crate::transport::AsyncStream;
+use crate::transport::Transport;
+use crate::transport::TransportFactory;
+use crate::transport::meter::Counters;
+use crate::transport::meter::Metered;
+use crate::transport::tokio_tcp::TokioTcp;
 
+#[cfg(unix)]
+mod agent;
 mod auth;
+pub mod banner;
+mod config;
+mod diagnose;
 mod driver;
 mod error;
+mod event;
+mod fleet;
+mod forward;
+pub mod fs;
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing;
+mod host_key;
+pub mod jobs;
+mod kex;
+#[cfg(feature = "keyring")]
+pub mod keyring;
+#[cfg(feature = "russh")]
+pub mod keyscan;
+mod known_hosts;
+mod otp;
+#[cfg(feature = "pem")]
+mod pem;
+mod platform;
+mod policy;
+#[cfg(feature = "ppk")]
+mod ppk;
+mod probe;
+pub mod process;
+mod processes;
+#[cfg(feature = "prompt")]
+pub mod prompt;
+mod reboot;
+mod remote_env;
+mod scope;
+mod scp;
+mod secret;
+#[cfg(feature = "server")]
+pub mod server;
+mod session;
+#[cfg(unix)]
+pub mod sftp;
+mod shell;
+mod speedtest;
+#[cfg(all(test, feature = "russh"))]
+mod test_server;
+mod tokens;
 mod transport;
+mod wait;
 
+#[cfg(unix)]
+pub use agent::AgentConstraints;
 pub use auth::Auth;
+pub use auth::AuthKind;
+pub use auth::AuthOutcome;
+pub use auth::PassphraseProvider;
+pub use config::HostConfig;
+pub use config::ResolvedConfig;
+pub use config::SshConfig;
+pub use diagnose::Check;
+pub use diagnose::Diagnosis;
+pub use diagnose::Step;
+pub use diagnose::diagnose;
 pub use driver::DriverKind;
 pub use error::Error;
+pub use error::ErrorCode;
+pub use event::Event;
+pub use fleet::Fleet;
+pub use fleet::HostOptions;
+pub use fleet::HostOutput;
+pub use fleet::OutputDir;
+pub use fleet::OutputSink;
+pub use fleet::Target;
+pub use forward::LocalForward;
+pub use forward::RemoteForward;
+pub use host_key::Decision;
+pub use host_key::HostKeyVerification;
+pub use host_key::HostKeyVerifier;
+pub use kex::AlgorithmReport;
+pub use kex::KexInit;
+pub use known_hosts::HostKeyStatus;
+pub use known_hosts::KnownHostsFile;
+pub use known_hosts::KnownHostsStore;
+pub use known_hosts::TrustOnFirstUse;
+pub use otp::OtpProvider;
+#[cfg(feature = "totp")]
+pub use otp::Totp;
+pub use platform::Platform;
+pub use policy::Algorithms;
+pub use policy::Policy;
+#[cfg(feature = "ppk")]
+pub use ppk::PpkKey;
+pub use probe::ProgramVersion;
+pub use processes::ProcessInfo;
+pub use reboot::HostKeyExpectation;
+pub use reboot::RebootOptions;
+pub use scope::SessionScope;
+pub use secret::EnvSource;
+pub use secret::FileSource;
+pub use secret::SecretSource;
+pub use session::ConnectedSession;
+pub use speedtest::Speedtest;
+pub use tokens::Tokens;
+pub use transport::chaos::Chaos;
+pub use transport::chaos::ChaosStream;
+pub use transport::jump::JumpHost;
+pub use transport::meter::ChannelKind;
+pub use transport::meter::ChannelTraffic;
+pub use transport::meter::Traffic;
+pub use transport::proxy_command::ProxyCommand;
+pub use wait::Predicate;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+const DEFAULT_BUSY_BACKOFF: Duration = Duration::from_secs(1);
+/// Keepalives the server may leave unanswered in a row before the session is
+/// disconnected, like OpenSSH's default `ServerAliveCountMax`.
+#[cfg(feature = "russh")]
+const DEFAULT_KEEPALIVE_MAX: u32 = 3;
+
 /// SSH session.
 #[derive(Debug, Builder)]
 pub struct Session {
     #[builder(field)]
     auth: Vec<Auth>,
-    /// Remote user to login as.
+    #[builder(field)]
+    stream: Option<Box<dyn AsyncStream>>,
+    #[builder(field)]
+    pre_connect: Option<PreConnect>,
+    #[builder(field)]
+    init_commands: Vec<String>,
+    #[builder(field)]
+    on_connect: Vec<OnConnect>,
+    #[builder(field)]
+    configs: Vec<SshConfig>,
+    #[builder(field)]
+    host_key_verification: HostKeyVerification,
+    /// Underlying SSH implementations to try, in order, until one connects.
+    #[builder(
+        setters(name = driver_chain),
+        with = |drivers: impl IntoIterator<Item = DriverKind>| drivers.into_iter().collect()
+    )]
+    drivers: Vec<DriverKind>,
+    /// Remote user to login as. Defaults to the local user, like OpenSSH.
+    /// [`Tokens`] other than `%r` are expanded, so `%u` stands for the local
+    /// user.
     #[builder(into)]
-    user: String,
-    /// Remote host to connect to.
+    user: Option<String>,
+    /// Remote host to connect to, or its alias in an applied configuration.
     #[builder(into)]
     host: String,
-    /// Port to connect to on the remote host.
-    #[builder(default = 22)]
-    port: u16,
-    /// Underlying SSH implementation.
-    driver: DriverKind,
+    /// Port to connect to on the remote host. Defaults to 22.
+    port: Option<u16>,
+    /// Maximum time to wait for the TCP connection to be established.
+    /// Defaults to 30 seconds.
+    connect_timeout: Option<Duration>,
+    /// Renegotiate session keys after this many bytes have been sent or
+    /// received, like the first argument of OpenSSH's `RekeyLimit`. Values
+    /// above 1 GiB are clamped to 1 GiB.
+    rekey_bytes: Option<usize>,
+    /// Renegotiate session keys after this much time has elapsed, like the
+    /// second argument of OpenSSH's `RekeyLimit`.
+    rekey_interval: Option<Duration>,
+    /// Bytes the server may send on a channel before waiting for the client
+    /// to make room, which bounds each command's throughput to this much per
+    /// round trip. Raise it for bulk transfers over links with high latency;
+    /// lower it to save memory across many sessions. Only used by the russh
+    /// driver, which defaults to 2 MiB.
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    window_size: Option<u32>,
+    /// Largest data packet the server may send on a channel. Only used by
+    /// the russh driver, which defaults to 32 KiB.
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    max_packet_size: Option<u32>,
+    /// Refuse to authenticate unless the server supports OpenSSH's strict key
+    /// exchange (`kex-strict-s-v00@openssh.com`), which mitigates the Terrapin
+    /// attack. Strict key exchange is always offered and negotiated when the
+    /// server supports it; this only makes it mandatory.
+    #[builder(default)]
+    require_strict_kex: bool,
+    /// Algorithms allowed during key exchange. Uses the driver's defaults if
+    /// not set.
+    policy: Option<Policy>,
+    /// Total bytes that may be sent and received over the transport, including
+    /// protocol overhead. The session is terminated once it is used up.
+    max_bytes: Option<u64>,
+    /// Commands that may be run with [`ConnectedSession::command`]. Running
+    /// more fails with [`Error::LimitReached`].
+    max_commands: Option<u64>,
+    /// Channels that may be opened, for commands, file system access, tunnels
+    /// and the connections of local forwards. Opening more fails with
+    /// [`Error::LimitReached`].
+    max_channels: Option<u64>,
+    /// Faults to inject into the transport, for testing how the application
+    /// copes with unreliable networks.
+    chaos: Option<Chaos>,
+    /// Jump hosts to reach `host` through, like OpenSSH's `ProxyJump`.
+    /// `host` is then resolved by the last of them, and the [`pre_connect`]
+    /// hook is not run. Drivers that dial the host themselves, such as
+    /// OpenSSH, are skipped.
+    ///
+    /// [`pre_connect`]: SessionBuilder::pre_connect
+    jump_host: Option<JumpHost>,
+    /// Command to reach `host` through, like OpenSSH's `ProxyCommand`. `host`
+    /// is then not looked up, and the [`pre_connect`] hook is not run.
+    /// Ignored if a [`jump_host`] is set.
+    ///
+    /// [`pre_connect`]: SessionBuilder::pre_connect
+    /// [`jump_host`]: SessionBuilder::jump_host
+    proxy_command: Option<ProxyCommand>,
+    /// Maximum time the [`host_key_verification`] may take to decide.
+    /// Connecting fails with [`Error::HostKeyVerificationTimeout`] once it is
+    /// exceeded. Not limited if not set.
+    ///
+    /// [`host_key_verification`]: SessionBuilder::host_key_verification
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    host_key_timeout: Option<Duration>,
+    /// Emit [`Event::SlowConsumer`] once a command's stdout or stderr has
+    /// gone unread for this long, since the command is then stalled. Not
+    /// reported if not set.
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    slow_consumer_after: Option<Duration>,
+    /// Send a keepalive once this long has passed since the last, like
+    /// OpenSSH's `ServerAliveInterval`, emitting [`Event::KeepaliveMissed`]
+    /// for each the server does not reply to within the interval. Only used
+    /// by the russh driver. No keepalives are sent if not set.
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    keepalive_interval: Option<Duration>,
+    /// Keepalives the server may leave unanswered in a row before the
+    /// session is disconnected, like OpenSSH's `ServerAliveCountMax`.
+    /// Defaults to 3.
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    keepalive_max: Option<u32>,
+    /// Have forwards recover from failures, retrying this long after each
+    /// failed attempt until they do or the session is dropped. Local and
+    /// SOCKS forwards listen again when accepting connections fails,
+    /// emitting [`Event::ForwardRestored`], and remote forwards are requested
+    /// again when connections to them stop being handed on, emitting
+    /// [`Event::RemoteForwardRestored`]. A forward stops on the first failure
+    /// if not set.
+    restore_forwards_after: Option<Duration>,
+    /// Check that the session could connect without logging in, for
+    /// preflight checks in deployment pipelines. The configuration is
+    /// resolved and `host` looked up, then [`connect`] fails with
+    /// [`Error::DryRun`] if nothing else went wrong. Nothing is
+    /// authenticated or run.
+    ///
+    /// [`connect`]: Session::connect
+    #[builder(default)]
+    dry_run: bool,
+    /// Have a [`dry_run`] also open the TCP connection and complete the key
+    /// exchange, so that the server's host key is checked by the
+    /// [`host_key_verification`], before disconnecting. Runs the
+    /// [`pre_connect`] hook.
+    ///
+    /// [`dry_run`]: SessionBuilder::dry_run
+    /// [`host_key_verification`]: SessionBuilder::host_key_verification
+    /// [`pre_connect`]: SessionBuilder::pre_connect
+    #[builder(default)]
+    dry_run_connect: bool,
+    /// Times to reconnect when the server closes the connection before key
+    /// exchange, failing with [`Error::ServerBusy`], as sshd does to
+    /// connections beyond its `MaxStartups`. Not retried by default, nor if
+    /// the session was built [`with_stream`].
+    ///
+    /// [`with_stream`]: SessionBuilder::with_stream
+    #[builder(default)]
+    busy_retries: u32,
+    /// Delay before reconnecting to a busy server, doubled for each retry,
+    /// with up to as much again added at random so that many clients do not
+    /// reconnect in lockstep. Defaults to 1 second.
+    busy_backoff: Option<Duration>,
+    /// Most authentication payloads offered per connection, so that
+    /// misconfigured credentials do not get the client banned by tools such
+    /// as fail2ban. The server is first asked which methods it allows;
+    /// payloads it would not accept are skipped, and public keys are offered
+    /// before passwords. Set to 1 to offer a single payload. Unlimited by
+    /// default, offering every payload in order.
+    #[cfg_attr(not(feature = "russh"), allow(dead_code))]
+    max_auth_attempts: Option<u32>,
+    /// Locale commands without a pty run in, set as `LANG` and `LC_ALL`, so
+    /// that their output parses the same whatever the host's locale. Defaults
+    /// to `C.UTF-8`. See [`Command::locale`](process::Command::locale).
+    #[builder(into)]
+    locale: Option<String>,
+    /// `TERM` of commands without a pty. Defaults to `dumb`, so that programs
+    /// do not print colors or other escape sequences.
+    #[builder(into)]
+    term: Option<String>,
+}
+
+impl Session {
+    /// Connects to the remote host and authenticates with the configured
+    /// payloads.
+    ///
+    /// # Errors
+    ///
+    /// - If `host` cannot be resolved.
+    /// - If the TCP connection is not established within `connect_timeout`.
+    ///   Neither applies if the session was built [`with_stream`].
+    /// - If the SSH handshake fails.
+    /// - If none of the authentication payloads are accepted.
+    /// - If no driver in the chain is available in this build.
+    /// - With [`Error::DryRun`] once every check passed, if the session is a
+    ///   [`dry_run`].
+    ///
+    /// The handshake and authentication errors are those of the last driver
+    /// tried.
+    ///
+    /// [`with_stream`]: SessionBuilder::with_stream
+    /// [`dry_run`]: SessionBuilder::dry_run
+    pub async fn connect(mut self) -> Result<ConnectedSession> {
+        let resolved = self.resolved()?;
+        let mut stream = self.stream.take();
+        let stream_given = stream.is_some();
+        let mut result = Err(Error::NoDriver);
+
+        for driver in self.drivers.clone() {
+            if !driver.is_available() {
+                result = Err(Error::DriverUnavailable(driver));
+                continue;
+            }
+            if self.dry_run && !self.dry_run_connect {
+                if !stream_given && self.jump_host.is_none() && self.proxy_command.is_none() {
+                    self.lookup(&resolved).await?;
+                }
+                return Err(Error::DryRun);
+            }
+
+            let transport = match stream.take() {
+                // `ssh` dials the host itself, so it cannot use the stream.
+                Some(given) if driver.dials_itself() => {
+                    stream = Some(given);
+                    result = Err(Error::DriverUnavailable(driver));
+                    continue;
+                }
+                // Nor can it go through the jump hosts.
+                None if driver.dials_itself() && self.jump_host.is_some() => {
+                    result = Err(Error::DriverUnavailable(driver));
+                    continue;
+                }
+                None if driver.dials_itself() => Transport::None,
+                Some(stream) => Transport::Stream(stream),
+                // A stream handed to us can only be used once.
+                None if stream_given => break,
+                None => self.open(&resolved).await?,
+            };
+
+            result = self.connect_with(driver, transport, &resolved).await;
+            let mut retries = 0;
+            while matches!(result, Err(Error::ServerBusy { .. }))
+                && retries < self.busy_retries
+                && !stream_given
+            {
+                retries += 1;
+                let delay = backoff(self.busy_backoff.unwrap_or(DEFAULT_BUSY_BACKOFF), retries);
+                tracing::info!(?driver, retries, ?delay, "server is busy, reconnecting");
+                tokio::time::sleep(delay).await;
+                let transport = self.open(&resolved).await?;
+                result = self.connect_with(driver, transport, &resolved).await;
+            }
+            match &result {
+                // Other drivers would only add to a busy server's load.
+                Ok(_) | Err(Error::DryRun | Error::ServerBusy { .. }) => break,
+                Err(error) => tracing::warn!(?driver, %error, "driver failed to connect"),
+            }
+        }
+
+        let session = result?;
+        self.initialize(&session).await?;
+
+        Ok(session)
+    }
+
+    /// Runs the initialization commands, then the hooks.
+    async fn initialize(&self, session: &ConnectedSession) -> Result<()> {
+        for command_line in &self.init_commands {
+            let status = session
+                .inner
+                .exec(command_line, command_line, None)
+                .await?
+                .wait()
+                .await?;
+            if !status.success() {
+                return Err(Error::CommandFailed {
+                    command: command_line.clone(),
+                    status,
+                });
+            }
+        }
+        for hook in &self.on_connect {
+            (hook.0)(session).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn lookup(&self, resolved: &ResolvedConfig) -> Result<SocketAddr> {
+        let addr = tokio::net::lookup_host((resolved.host.as_str(), resolved.port))
+            .await?
+            .next()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+
+        Ok(addr)
+    }
+
+    async fn open(&self, resolved: &ResolvedConfig) -> Result<Transport> {
+        if let Some(jump_host) = &self.jump_host {
+            return jump_host.tunnel(&resolved.host, resolved.port).await;
+        }
+        if let Some(proxy_command) = &self.proxy_command {
+            return proxy_command.spawn(&resolved.tokens());
+        }
+        let addr = self.lookup(resolved).await?;
+
+        self.dial(resolved, addr).await
+    }
+
+    /// Runs the pre-connect hook, then opens a TCP connection to `addr`.
+    async fn dial(&self, resolved: &ResolvedConfig, addr: SocketAddr) -> Result<Transport> {
+        if let Some(pre_connect) = &self.pre_connect {
+            (pre_connect.0)(addr)
+                .await
+                .map_err(Error::PreConnectFailed)?;
+        }
+
+        TokioTcp::builder()
+            .timeout(resolved.connect_timeout)
+            .build()
+            .connect(addr)
+            .await
+    }
+
+    async fn connect_with(
+        &self,
+        driver: DriverKind,
+        transport: Transport,
+        resolved: &ResolvedConfig,
+    ) -> Result<ConnectedSession> {
+        let transport = match &self.chaos {
+            Some(chaos) => chaos.apply(transport),
+            None => transport,
+        };
+
+        let counters = Arc::new(
+            Counters::with_max_bytes(self.max_bytes)
+                .max_commands(self.max_commands)
+                .max_channels(self.max_channels),
+        );
+        let transport = transport.into_stream().map_or(Transport::None, |stream| {
+            Transport::Stream(Box::new(Metered::new(stream, Arc::clone(&counters))))
+        });
+
+        let events = Events::default();
+        let (connected, auth_outcome) = self
+            .connect_driver(driver, transport, resolved, events.clone())
+            .await?;
+
+        let session = ConnectedSession::new(
+            connected,
+            resolved,
+            auth_outcome,
+            counters,
+            events.clone(),
+            self.command_env(),
+            self.restore_forwards_after,
+        );
+        #[cfg(feature = "russh")]
+        if let Some(interval) = self.keepalive_interval {
+            tokio::spawn(driver::russh::keep_alive(
+                Arc::downgrade(&session.inner),
+                events,
+                interval,
+                self.keepalive_max.unwrap_or(DEFAULT_KEEPALIVE_MAX),
+            ));
+        }
+
+        Ok(session)
+    }
+
+    async fn connect_driver(
+        &self,
+        driver: DriverKind,
+        transport: Transport,
+        resolved: &ResolvedConfig,
+        events: Events,
+    ) -> Result<(Connected, AuthOutcome)> {
+        // Only the russh driver runs over the transport and emits events.
+        #[cfg(not(feature = "russh"))]
+        let _ = (transport, events);
+
+        match driver {
+            #[cfg(feature = "openssh")]
+            DriverKind::OpenSsh => self.connect_openssh(resolved).await,
+            #[cfg(feature = "russh")]
+            DriverKind::Russh => self.connect_russh(transport, resolved, events).await,
+            // Reachable with the drivers that are declared but not
+            // implemented.
+            #[allow(unreachable_patterns)]
+            other => Err(Error::DriverUnavailable(other)),
+        }
+    }
+
+    #[cfg(feature = "openssh")]
+    async fn connect_openssh(&self, resolved: &ResolvedConfig) -> Result<(Connected, AuthOutcome)> {
+        use crate::driver::Driver as _;
+        use crate::driver::Session as _;
+
+        // `ssh` checks the host key only on its way to authenticating.
+        if self.dry_run {
+            return Err(Error::DryRun);
+        }
+
+        let mut accept_new = false;
+        let mut hash_known_hosts = false;
+        let known_hosts = match &self.host_key_verification {
+            HostKeyVerification::KnownHostsFile(path) => {
+                Some(resolved.tokens().expand_path(path.as_str())?)
+            }
+            HostKeyVerification::TrustOnFirstUse { path, hash } => {
+                accept_new = true;
+                hash_known_hosts = *hash;
+                Some(resolved.tokens().expand_path(path.as_str())?)
+            }
+            HostKeyVerification::AcceptAny => None,
+            verification => {
+                tracing::warn!(
+                    ?verification,
+                    "ssh can only check host keys against known_hosts"
+                );
+                return Err(Error::DriverUnavailable(DriverKind::OpenSsh));
+            }
+        };
+
+        let mut builder = driver::openssh::OpenSshDriver::builder()
+            .user(resolved.user.clone())
+            .host(resolved.host.clone())
+            .port(resolved.port)
+            .connect_timeout(resolved.connect_timeout)
+            .maybe_known_hosts(known_hosts)
+            .accept_new(accept_new)
+            .hash_known_hosts(hash_known_hosts)
+            .maybe_proxy_command(
+                self.proxy_command
+                    .as_ref()
+                    .map(|proxy_command| proxy_command.command().to_string()),
+            );
+        for payload in self.auth.iter().cloned().chain(identity_files(resolved)) {
+            builder = builder.auth(payload);
+        }
+        let mut session = builder.build().connect().await?;
+        let auth_outcome = session.authenticate().await?;
+
+        Ok((Connected::OpenSsh(session), auth_outcome))
+    }
+
+    #[cfg(feature = "russh")]
+    async fn connect_russh(
+        &self,
+        transport: Transport,
+        resolved: &ResolvedConfig,
+        events: Events,
+    ) -> Result<(Connected, AuthOutcome)> {
+        use crate::driver::Driver as _;
+        use crate::driver::Session as _;
+
+        let mut session = self
+            .russh_driver(transport, resolved, events)?
+            .connect()
+            .await?;
+        if self.dry_run {
+            // The host key has been checked during the handshake, which is as
+            // far as a dry run goes.
+            if let Err(error) = session.disconnect().await {
+                tracing::debug!(%error, "could not disconnect dry run");
+            }
+            return Err(Error::DryRun);
+        }
+        let auth_outcome = session.authenticate().await?;
+
+        Ok((Connected::Russh(session), auth_outcome))
+    }
+
+    /// Russh driver set up like this session, with every payload to
+    /// authenticate with.
+    ///
+    /// # Errors
+    ///
+    /// - If the path of the `known_hosts` file cannot be expanded.
+    #[cfg(feature = "russh")]
+    fn russh_driver(
+        &self,
+        transport: Transport,
+        resolved: &ResolvedConfig,
+        events: Events,
+    ) -> Result<driver::russh::RusshDriver> {
+        let verifier = self.host_key_verification.verifier(&resolved.tokens())?;
+        let mut builder = driver::russh::RusshDriver::builder()
+            .user(resolved.user.clone())
+            .transport(transport)
+            .maybe_rekey_bytes(self.rekey_bytes)
+            .maybe_rekey_interval(self.rekey_interval)
+            .maybe_window_size(self.window_size)
+            .maybe_max_packet_size(self.max_packet_size)
+            .require_strict_kex(self.require_strict_kex)
+            .maybe_policy(self.policy.clone())
+            .maybe_slow_consumer_after(self.slow_consumer_after)
+            .maybe_max_auth_attempts(self.max_auth_attempts)
+            .maybe_verification(verifier.map(|verifier| host_key::Verification {
+                verifier,
+                host: resolved.host.clone(),
+                port: resolved.port,
+                timeout: self.host_key_timeout,
+            }))
+            .events(events);
+        for payload in self.auth.iter().cloned().chain(identity_files(resolved)) {
+            builder = builder.auth(payload);
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Values for `%` tokens in paths configured for this session, such as
+    /// identity files given to [`Auth::from_key_file`].
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`Session::resolved`].
+    pub fn tokens(&self) -> Result<Tokens> {
+        Ok(self.resolved()?.tokens())
+    }
+
+    /// Settings this session will connect with. Each is taken from the first
+    /// of these that sets it:
+    ///
+    /// 1. The value set on the builder.
+    /// 2. The configurations given to [`apply`], in the order applied.
+    /// 3. The environment, which provides the local user.
+    /// 4. The default.
+    ///
+    /// # Errors
+    ///
+    /// - If an applied configuration cannot be resolved for `host`.
+    /// - If the user or an identity file has a token that cannot be expanded,
+    ///   or no user is set and the local user is unknown.
+    ///
+    /// [`apply`]: SessionBuilder::apply
+    pub fn resolved(&self) -> Result<ResolvedConfig> {
+        let layers = self
+            .configs
+            .iter()
+            .map(|config| config.resolve(&self.host, self.user.as_deref()))
+            .collect::<Result<Vec<_>>>()?;
+        let layered = |get: fn(&HostConfig) -> Option<&str>| layers.iter().find_map(get);
+
+        let host = layered(HostConfig::hostname).unwrap_or(&self.host);
+        let port = self
+            .port
+            .or_else(|| layers.iter().find_map(HostConfig::port))
+            .unwrap_or(22);
+        let tokens = Tokens::local().with_host(host, port);
+        let user = tokens.expand(
+            self.user
+                .as_deref()
+                .or_else(|| layered(HostConfig::user))
+                .unwrap_or("%u"),
+        )?;
+        let tokens = tokens.with_remote_user(&user);
+        let identity_files = layers
+            .iter()
+            .flat_map(HostConfig::identity_files)
+            .map(|path| tokens.expand_path(path))
+            .collect::<Result<_>>()?;
+
+        Ok(ResolvedConfig {
+            host: host.to_string(),
+            port,
+            user,
+            drivers: self.drivers.clone(),
+            auth: self.auth.iter().map(Auth::kind).collect(),
+            identity_files,
+            algorithms: self.policy.as_ref().map(Policy::algorithms),
+            require_strict_kex: self.require_strict_kex,
+            connect_timeout: self
+                .connect_timeout
+                .or_else(|| layers.iter().find_map(HostConfig::connect_timeout))
+                .unwrap_or(Duration::from_secs(30)),
+            rekey_bytes: self.rekey_bytes,
+            rekey_interval: self.rekey_interval,
+            max_bytes: self.max_bytes,
+        })
+    }
+
+    /// One-line description of the connection for logs, like
+    /// [`ResolvedConfig::summary`]. Secrets are never included.
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`Session::resolved`].
+    pub fn summary(&self) -> Result<String> {
+        Ok(self.resolved()?.summary())
+    }
+
+    fn command_env(&self) -> CommandEnv {
+        let defaults = CommandEnv::default();
+        CommandEnv {
+            locale: self.locale.clone().unwrap_or(defaults.locale),
+            term: self.term.clone().unwrap_or(defaults.term),
+        }
+    }
+}
+
+/// Delay before the `retry`th reconnection: `base` doubled for each retry
+/// after the first, plus a random amount up to as much again.
+fn backoff(base: Duration, retry: u32) -> Duration {
+    let delay = base.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+    let jitter = u32::try_from(RandomState::new().hash_one(retry) % 1024).unwrap_or_default();
+
+    delay.saturating_add(delay / 1024 * jitter)
+}
+
+/// Payloads for the identity files that exist and can be loaded without a
+/// passphrase.
+#[cfg(any(feature = "openssh", feature = "russh"))]
+fn identity_files(resolved: &ResolvedConfig) -> impl Iterator<Item = Auth> + '_ {
+    resolved.identity_files.iter().filter_map(|path| {
+        if !path.exists() {
+            return None;
+        }
+        Auth::from_key_file(path, None::<&[u8]>)
+            .inspect_err(|error| tracing::warn!(%path, %error, "skipping identity file"))
+            .ok()
+    })
 }
 
 impl<S: session_builder::State> SessionBuilder<S> {
+    /// Underlying SSH implementation. Shorthand for a [`driver_chain`] of one.
+    ///
+    /// [`driver_chain`]: SessionBuilder::driver_chain
+    pub fn driver(self, driver: DriverKind) -> SessionBuilder<session_builder::SetDrivers<S>>
+    where
+        S::Drivers: session_builder::IsUnset,
+    {
+        self.driver_chain([driver])
+    }
+
     /// Payload that will be used for authentication attempts. Will be called
     /// in order until authentication succeeds; any remaining payloads will not
-    /// be used.
+    /// be used. Certificates that are outside their validity period or not
+    /// issued for `user` are skipped.
     pub fn auth(mut self, value: Auth) -> Self {
         self.auth.push(value);
         self
     }
-}
 
-pub mod process {
-    pub struct Command {}
+    /// Hook run with the resolved address before every TCP connection is
+    /// opened, for rituals like port knocking or asking a firewall to let the
+    /// client in. Connecting fails if the hook does. Not run if the session
+    /// was built [`with_stream`].
+    ///
+    /// [`with_stream`]: SessionBuilder::with_stream
+    pub fn pre_connect<F, Fut, E>(mut self, hook: F) -> Self
+    where
+        F: Fn(SocketAddr) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        self.pre_connect = Some(PreConnect(Arc::new(move |addr| {
+            hook(addr).map(|result| result.map_err(Into::into)).boxed()
+        })));
+        self
+    }
 
-    pub struct Child {
-        pub stdin: Option<ChildStdin>,
-        pub stdout: Option<ChildStdout>,
-        pub stderr: Option<ChildStderr>,
+    /// Command line run right after authentication, through the remote user's
+    /// shell as given. May be given several times; commands run in order and
+    /// connecting fails with [`Error::CommandFailed`] if one exits
+    /// unsuccessfully. Each command runs in a shell of its own, so use them
+    /// for side effects like creating directories or starting a tmux
+    /// session; an `export` does not carry over to later commands.
+    pub fn init_command(mut self, command_line: impl Into<String>) -> Self {
+        self.init_commands.push(command_line.into());
+        self
     }
 
-    pub struct ChildStdin {}
+    /// Hook run with the session right after authentication and the
+    /// [`init_command`]s, for setup that needs more than a command line. May
+    /// be given several times; hooks run in order and connecting fails with
+    /// the error of the first that fails.
+    ///
+    /// [`init_command`]: SessionBuilder::init_command
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: for<'a> Fn(&'a ConnectedSession) -> BoxFuture<'a, Result<()>> + Send + Sync + 'static,
+    {
+        self.on_connect.push(OnConnect(Arc::new(hook)));
+        self
+    }
 
-    pub struct ChildStdout {}
+    /// How to decide whether to trust the server's host key, for the host
+    /// and port connected to after any `HostName` substitution. Connecting
+    /// fails with [`Error::HostKeyRejected`] if the key is rejected. Defaults
+    /// to the keys recorded in `~/.ssh/known_hosts`.
+    pub fn host_key_verification(mut self, verification: HostKeyVerification) -> Self {
+        self.host_key_verification = verification;
+        self
+    }
+
+    /// Leaves the decision whether to trust the server's host key to
+    /// `verifier`, as [`HostKeyVerification::Callback`].
+    pub fn host_key_verifier(self, verifier: impl HostKeyVerifier + 'static) -> Self {
+        self.host_key_verification(HostKeyVerification::Callback(Arc::new(verifier)))
+    }
+
+    /// Layers OpenSSH configuration under the values set on the builder.
+    /// May be given several times; configuration applied earlier takes
+    /// precedence, so apply the user's configuration before the system-wide
+    /// one, like OpenSSH reads them. `HostName`, `Port`, `User`,
+    /// `ConnectTimeout` and `IdentityFile` are used; see
+    /// [`Session::resolved`] for the full precedence.
+    pub fn apply(mut self, config: SshConfig) -> Self {
+        self.configs.push(config);
+        self
+    }
 
-    pub struct ChildStderr {}
+    /// Runs the session over an already-established stream instead of opening
+    /// a TCP connection to `host` and `port`, for example a socket accepted
+    /// elsewhere or a custom tunnel. The stream can only be used once, so
+    /// drivers later in the chain are not tried if the first available one
+    /// fails.
+    pub fn with_stream(
+        mut self,
+        stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    ) -> Self {
+        self.stream = Some(Box::new(stream));
+        self
+    }
 }
 
-pub mod fs {
-    pub struct DirBuilder {}
+/// Error returned by user-provided hooks.
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
-    pub struct DirEntry {}
+/// Hook given to [`SessionBuilder::pre_connect`].
+struct PreConnect(
+    Arc<dyn Fn(SocketAddr) -> BoxFuture<'static, std::result::Result<(), BoxError>> + Send + Sync>,
+);
 
-    pub struct File {}
+impl fmt::Debug for PreConnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PreConnect")
+    }
+}
+
+/// Hook given to [`SessionBuilder::on_connect`].
+struct OnConnect(Arc<OnConnectFn>);
 
-    pub struct OpenOptions {}
+type OnConnectFn = dyn for<'a> Fn(&'a ConnectedSession) -> BoxFuture<'a, Result<()>> + Send + Sync;
 
-    pub struct ReadDir {}
+impl fmt::Debug for OnConnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnConnect")
+    }
 }
 
 #[cfg(test)]
@@ -75,4 +896,392 @@ mod tests {
 
     #[test]
     fn test_session_builder() {}
+
+    #[tokio::test]
+    async fn connect_fails_without_driver() {
+        let result = Session::builder()
+            .user("test_user")
+            .host("localhost")
+            .driver_chain([])
+            .build()
+            .connect()
+            .await;
+
+        assert!(matches!(result, Err(Error::NoDriver)));
+    }
+
+    #[test]
+    fn user_defaults_to_local_user() {
+        let default = Session::builder()
+            .host("localhost")
+            .driver(DriverKind::Mock)
+            .build();
+        let expanded = Session::builder()
+            .user("%u-admin")
+            .host("localhost")
+            .driver(DriverKind::Mock)
+            .build();
+
+        let local = tokens::local_user().unwrap();
+        assert_eq!(default.resolved().unwrap().user, local);
+        assert_eq!(expanded.resolved().unwrap().user, format!("{local}-admin"));
+    }
+
+    #[test]
+    fn resolved_layers_config() {
+        let user_config = SshConfig::parse(
+            "Host web\n  HostName web.example.com\n  Port 2222\n  IdentityFile /keys/%r@%h\n",
+        )
+        .unwrap();
+        let system_config =
+            SshConfig::parse("Host *\n  Port 22\n  User admin\n  ConnectTimeout 5\n").unwrap();
+        let session = Session::builder()
+            .host("web")
+            .port(2200)
+            .driver(DriverKind::Mock)
+            .auth(Auth::Password("secret".into()))
+            .apply(user_config)
+            .apply(system_config)
+            .build();
+
+        let resolved = session.resolved().unwrap();
+
+        assert_eq!(
+            resolved,
+            ResolvedConfig {
+                host: "web.example.com".to_string(),
+                port: 2200,
+                user: "admin".to_string(),
+                drivers: vec![DriverKind::Mock],
+                auth: vec![AuthKind::Password],
+                identity_files: vec!["/keys/admin@web.example.com".into()],
+                algorithms: None,
+                require_strict_kex: false,
+                connect_timeout: Duration::from_secs(5),
+                rekey_bytes: None,
+                rekey_interval: None,
+                max_bytes: None,
+            }
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("web1", vec![Auth::Password("secret".into())], "deploy@web1:22 via mock (auth: password)")]
+    #[case(
+        "::1",
+        vec![Auth::Password("secret".into()), Auth::Password("other".into())],
+        "deploy@[::1]:22 via mock (auth: password)"
+    )]
+    #[case("web1", vec![], "deploy@web1:22 via mock (auth: none)")]
+    fn summary_works(#[case] host: &str, #[case] auth: Vec<Auth>, #[case] summary_should: &str) {
+        let mut builder = Session::builder()
+            .user("deploy")
+            .host(host)
+            .driver(DriverKind::Mock);
+        for payload in auth {
+            builder = builder.auth(payload);
+        }
+
+        let summary = builder.build().summary().unwrap();
+
+        assert_eq!(summary, summary_should);
+        assert!(!summary.contains("secret"));
+    }
+
+    #[test]
+    fn resolved_displays_like_ssh_g() {
+        let session = Session::builder()
+            .user("deploy")
+            .host("web1")
+            .driver(DriverKind::Mock)
+            .auth(Auth::Password("secret".into()))
+            .policy(Policy::Custom(
+                Algorithms::builder()
+                    .kex(["curve25519-sha256"])
+                    .host_key(["ssh-ed25519"])
+                    .cipher(["aes128-ctr", "aes256-ctr"])
+                    .mac(["hmac-sha2-256"])
+                    .build(),
+            ))
+            .rekey_interval(Duration::from_hours(1))
+            .build();
+
+        let dump = session.resolved().unwrap().to_string();
+
+        assert_eq!(
+            dump,
+            "hostname web1\nport 22\nuser deploy\ndriver Mock\nauth password\n\
+             kexalgorithms curve25519-sha256\nhostkeyalgorithms ssh-ed25519\n\
+             ciphers aes128-ctr,aes256-ctr\nmacs hmac-sha2-256\nrequirestrictkex no\n\
+             connecttimeout 30\nrekeylimit 0 3600\n"
+        );
+        assert!(!dump.contains("secret"));
+    }
+
+    #[test]
+    fn tokens_describe_session() {
+        let session = Session::builder()
+            .user("deploy")
+            .host("web1")
+            .port(2222)
+            .driver(DriverKind::Mock)
+            .build();
+
+        let tokens = session.tokens().unwrap();
+
+        assert_eq!(tokens.expand("%r@%h:%p").unwrap(), "deploy@web1:2222");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn connect_skips_unavailable_drivers() {
+        let stream = test_server::spawn().into_stream().unwrap();
+
+        let session = Session::builder()
+            .user(test_server::USER)
+            .host("localhost")
+            .driver_chain([DriverKind::Mock, DriverKind::Russh])
+            .auth(Auth::from_password_file("test/creds/password").unwrap())
+            .host_key_verification(HostKeyVerification::AcceptAny)
+            .with_stream(stream)
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        assert_eq!(session.driver(), DriverKind::Russh);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn on_connect_runs_after_auth() {
+        let session = Session::builder()
+            .user(test_server::USER)
+            .host("localhost")
+            .driver(DriverKind::Russh)
+            .auth(Auth::from_password_file("test/creds/password").unwrap())
+            .init_command("mkdir -p work")
+            .on_connect(|session| {
+                async move {
+                    session.fs().write("work/ready", "yes").await?;
+                    Ok(())
+                }
+                .boxed()
+            })
+            .host_key_verification(HostKeyVerification::AcceptAny)
+            .with_stream(test_server::spawn().into_stream().unwrap())
+            .build()
+            .connect()
+            .await
+            .unwrap();
+
+        assert_eq!(session.fs().read("work/ready").await.unwrap(), b"yes");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn failed_init_command_fails_connect() {
+        let result = Session::builder()
+            .user(test_server::USER)
+            .host("localhost")
+            .driver(DriverKind::Russh)
+            .auth(Auth::from_password_file("test/creds/password").unwrap())
+            .init_command("exit 4")
+            .host_key_verification(HostKeyVerification::AcceptAny)
+            .with_stream(test_server::spawn().into_stream().unwrap())
+            .build()
+            .connect()
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(Error::CommandFailed { command, status }) if command == "exit 4" && status.code() == Some(4)
+        ));
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest::rstest]
+    #[case(Decision::Accept, true)]
+    #[case(Decision::Reject, false)]
+    #[tokio::test]
+    async fn host_key_verifier_decides(#[case] decision: Decision, #[case] connects: bool) {
+        let (sender, mut verified) = tokio::sync::mpsc::unbounded_channel();
+
+        let result = Session::builder()
+            .user(test_server::USER)
+            .host("localhost")
+            .driver(DriverKind::Russh)
+            .auth(Auth::from_password_file("test/creds/password").unwrap())
+            .host_key_verifier(move |host, port, key: ssh_key::PublicKey| {
+                let _ = sender.send((host, port, key));
+                async move { Ok(decision) }
+            })
+            .with_stream(test_server::spawn().into_stream().unwrap())
+            .build()
+            .connect()
+            .await;
+
+        assert_eq!(result.is_ok(), connects);
+        let (host, port, key) = verified.recv().await.unwrap();
+        let host_key =
+            ssh_key::PublicKey::read_openssh_file("test/creds/id_ed25519.pub".as_ref()).unwrap();
+        assert_eq!((host.as_str(), port), ("localhost", 22));
+        assert_eq!(key.key_data(), host_key.key_data());
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn known_hosts_verification_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = camino::Utf8Path::from_path(dir.path())
+            .unwrap()
+            .join("known_hosts");
+        let connect = || {
+            Session::builder()
+                .user(test_server::USER)
+                .host("localhost")
+                .driver(DriverKind::Russh)
+                .auth(Auth::from_password_file("test/creds/password").unwrap())
+                .host_key_verification(HostKeyVerification::KnownHostsFile(path.clone()))
+                .with_stream(test_server::spawn().into_stream().unwrap())
+                .build()
+                .connect()
+        };
+
+        let unknown = connect().await;
+        KnownHostsFile::new(&path)
+            .add(
+                "localhost",
+                22,
+                &ssh_key::PublicKey::read_openssh_file("test/creds/id_ed25519.pub".as_ref())
+                    .unwrap(),
+            )
+            .unwrap();
+        let known = connect().await;
+
+        assert!(matches!(unknown, Err(Error::HostKeyRejected(_))));
+        assert!(known.is_ok());
+    }
+
+    #[rstest::rstest]
+    #[case(1, Duration::from_secs(1))]
+    #[case(3, Duration::from_secs(4))]
+    #[case(100, Duration::from_secs(u32::MAX.into()))]
+    fn backoff_works(#[case] retry: u32, #[case] min_should: Duration) {
+        let delay = backoff(Duration::from_secs(1), retry);
+
+        assert!(delay >= min_should);
+        assert!(delay <= min_should.saturating_mul(2));
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest::rstest]
+    #[case(b"", false)]
+    #[case(b"SSH-2.0-OpenSSH_9.6\r\n", true)]
+    #[tokio::test]
+    async fn connect_retries_busy_server(#[case] sent: &'static [u8], #[case] banner_should: bool) {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = tokio::spawn(async move {
+            let mut accepted = 0;
+            while let Ok(Ok((mut stream, _))) =
+                tokio::time::timeout(Duration::from_secs(1), listener.accept()).await
+            {
+                let _ = stream.write_all(sent).await;
+                accepted += 1;
+            }
+            accepted
+        });
+
+        let result = Session::builder()
+            .user("test_user")
+            .host("127.0.0.1")
+            .port(port)
+            .driver(DriverKind::Russh)
+            .busy_retries(2)
+            .busy_backoff(Duration::from_millis(1))
+            .build()
+            .connect()
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(Error::ServerBusy { banner_received }) if banner_received == banner_should
+        ));
+        assert_eq!(accepted.await.unwrap(), 3);
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest::rstest]
+    #[case("localhost", ErrorCode::DryRun)]
+    #[case("nonexistent.invalid", ErrorCode::Io)]
+    #[tokio::test]
+    async fn dry_run_resolves_host(#[case] host: &str, #[case] code_should: ErrorCode) {
+        let result = Session::builder()
+            .user("test_user")
+            .host(host)
+            .port(1)
+            .driver(DriverKind::Russh)
+            .pre_connect(|_| async { Err("dialed") })
+            .dry_run(true)
+            .build()
+            .connect()
+            .await;
+
+        assert_eq!(result.err().map(|error| error.code()), Some(code_should));
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest::rstest]
+    #[case(Decision::Accept, ErrorCode::DryRun)]
+    #[case(Decision::Reject, ErrorCode::HostKeyRejected)]
+    #[tokio::test]
+    async fn dry_run_connect_checks_host_key(
+        #[case] decision: Decision,
+        #[case] code_should: ErrorCode,
+    ) {
+        let result = Session::builder()
+            .user(test_server::USER)
+            .host("localhost")
+            .driver(DriverKind::Russh)
+            .auth(Auth::from_password_file("test/creds/password").unwrap())
+            .host_key_verifier(move |_, _, _: ssh_key::PublicKey| async move { Ok(decision) })
+            .dry_run(true)
+            .dry_run_connect(true)
+            .with_stream(test_server::spawn().into_stream().unwrap())
+            .build()
+            .connect()
+            .await;
+
+        assert_eq!(result.err().map(|error| error.code()), Some(code_should));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn pre_connect_runs_before_dialing() {
+        let (sender, mut knocked) = tokio::sync::mpsc::unbounded_channel();
+
+        let result = Session::builder()
+            .user("test_user")
+            .host("127.0.0.1")
+            .port(1)
+            .driver(DriverKind::Russh)
+            .pre_connect(move |addr| {
+                let _ = sender.send(addr);
+                async { Err("knock refused") }
+            })
+            .build()
+            .connect()
+            .await;
+
+        assert!(
+            matches!(result, Err(Error::PreConnectFailed(err)) if err.to_string() == "knock refused")
+        );
+        assert_eq!(
+            knocked.recv().await,
+            Some(SocketAddr::from(([127, 0, 0, 1], 1)))
+        );
+    }
 }
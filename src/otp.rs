@@ -0,0 +1,44 @@
+//! One-time codes for servers that ask for a second factor through
+//! keyboard-interactive authentication.
+
+use std::fmt;
+use std::future::Future;
+
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use secrecy::SecretString;
+
+use crate::Result;
+
+#[cfg(feature = "totp")]
+mod totp;
+
+#[cfg(feature = "totp")]
+pub use totp::Totp;
+
+/// Answers the prompts of keyboard-interactive authentication other than
+/// the password, such as `Verification code: `, for example from a TOTP
+/// secret or by waiting for a push approval. Implemented by [`Totp`] with the
+/// `totp` feature, and for closures taking the prompt by value and
+/// returning a future, such as
+/// `|prompt| async move { Ok(Some(approval.code().await?)) }`.
+pub trait OtpProvider: Send + Sync {
+    /// Answer to `prompt`, or `None` to give up on authenticating with it.
+    fn answer<'a>(&'a self, prompt: &'a str) -> BoxFuture<'a, Result<Option<SecretString>>>;
+}
+
+impl<F, Fut> OtpProvider for F
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Option<SecretString>>> + Send + 'static,
+{
+    fn answer<'a>(&'a self, prompt: &'a str) -> BoxFuture<'a, Result<Option<SecretString>>> {
+        self(prompt.to_string()).boxed()
+    }
+}
+
+impl fmt::Debug for dyn OtpProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OtpProvider")
+    }
+}
@@ -0,0 +1,100 @@
+use crate::ConnectedSession;
+use crate::Result;
+
+/// Operating system of a remote host, as told by
+/// [`ConnectedSession::platform`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Platform {
+    Linux,
+    MacOs,
+    /// Windows, which runs commands through `cmd.exe` or PowerShell unless a
+    /// POSIX environment such as Cygwin is the login shell.
+    Windows,
+    /// Any other Unix-like system, such as FreeBSD or Solaris.
+    OtherUnix,
+}
+
+impl Platform {
+    /// Whether the host runs a Unix-like system.
+    #[must_use]
+    pub fn is_unix(self) -> bool {
+        self != Platform::Windows
+    }
+
+    /// Platform named by the output of `uname -s`.
+    fn from_uname(name: &str) -> Self {
+        match name {
+            "Linux" => Platform::Linux,
+            "Darwin" => Platform::MacOs,
+            name if ["CYGWIN", "MINGW", "MSYS"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix)) =>
+            {
+                Platform::Windows
+            }
+            _ => Platform::OtherUnix,
+        }
+    }
+}
+
+impl ConnectedSession {
+    /// Operating system of the remote host, for helpers that work
+    /// differently on each. Told by `uname -s`, which Windows lacks unless
+    /// it has a POSIX environment. Checked once and cached for the lifetime
+    /// of the session.
+    ///
+    /// # Errors
+    ///
+    /// - If the check itself cannot be run.
+    pub async fn platform(&self) -> Result<Platform> {
+        self.platform
+            .get_or_try_init(|| async {
+                // Run as is, since `cmd.exe` would not run the `env` that
+                // commands start with.
+                let output = self
+                    .inner
+                    .exec("uname", "uname -s", None)
+                    .await?
+                    .wait_with_output()
+                    .await?;
+                if !output.status.success() {
+                    return Ok(Platform::Windows);
+                }
+
+                Ok(Platform::from_uname(
+                    String::from_utf8_lossy(&output.stdout).trim(),
+                ))
+            })
+            .await
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("Linux", Platform::Linux)]
+    #[case("Darwin", Platform::MacOs)]
+    #[case("FreeBSD", Platform::OtherUnix)]
+    #[case("MINGW64_NT-10.0-19045", Platform::Windows)]
+    #[case("CYGWIN_NT-10.0", Platform::Windows)]
+    fn from_uname_works(#[case] name: &str, #[case] platform_should: Platform) {
+        assert_eq!(Platform::from_uname(name), platform_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn platform_works() {
+        let session = crate::test_server::connect().await;
+
+        let platform = session.platform().await.unwrap();
+
+        // The test server runs commands with the local `sh`.
+        assert!(platform.is_unix());
+    }
+}
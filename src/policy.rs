@@ -0,0 +1,215 @@
+use bon::Builder;
+
+const MODERN_KEX: &[&str] = &[
+    "mlkem768x25519-sha256",
+    "curve25519-sha256",
+    "curve25519-sha256@libssh.org",
+    "ecdh-sha2-nistp256",
+    "ecdh-sha2-nistp384",
+    "ecdh-sha2-nistp521",
+    "diffie-hellman-group-exchange-sha256",
+    "diffie-hellman-group16-sha512",
+    "diffie-hellman-group14-sha256",
+];
+const MODERN_HOST_KEY: &[&str] = &[
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "rsa-sha2-512",
+    "rsa-sha2-256",
+];
+const MODERN_CIPHER: &[&str] = &[
+    "chacha20-poly1305@openssh.com",
+    "aes256-gcm@openssh.com",
+    "aes128-gcm@openssh.com",
+    "aes256-ctr",
+    "aes192-ctr",
+    "aes128-ctr",
+];
+const MODERN_MAC: &[&str] = &[
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512-etm@openssh.com",
+];
+
+const FIPS_KEX: &[&str] = &[
+    "ecdh-sha2-nistp256",
+    "ecdh-sha2-nistp384",
+    "ecdh-sha2-nistp521",
+    "diffie-hellman-group-exchange-sha256",
+    "diffie-hellman-group16-sha512",
+    "diffie-hellman-group14-sha256",
+];
+const FIPS_HOST_KEY: &[&str] = &[
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "rsa-sha2-512",
+    "rsa-sha2-256",
+];
+const FIPS_CIPHER: &[&str] = &[
+    "aes256-gcm@openssh.com",
+    "aes128-gcm@openssh.com",
+    "aes256-ctr",
+    "aes192-ctr",
+    "aes128-ctr",
+];
+const FIPS_MAC: &[&str] = &[
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512-etm@openssh.com",
+    "hmac-sha2-256",
+    "hmac-sha2-512",
+];
+
+const LEGACY_KEX: &[&str] = &[
+    "diffie-hellman-group14-sha1",
+    "diffie-hellman-group-exchange-sha1",
+    "diffie-hellman-group1-sha1",
+];
+const LEGACY_HOST_KEY: &[&str] = &["ssh-rsa", "ssh-dss"];
+const LEGACY_CIPHER: &[&str] = &["aes256-cbc", "aes192-cbc", "aes128-cbc", "3des-cbc"];
+const LEGACY_MAC: &[&str] = &[
+    "hmac-sha2-256",
+    "hmac-sha2-512",
+    "hmac-sha1-etm@openssh.com",
+    "hmac-sha1",
+];
+
+/// Predefined or custom set of algorithms a session is allowed to negotiate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    /// Only algorithms without known weaknesses, preferring post-quantum and
+    /// Curve25519 key exchange.
+    Modern,
+    /// Only FIPS 140 approved algorithms (NIST curves, RSA, AES, SHA-2).
+    Fips,
+    /// [`Policy::Modern`] followed by SHA-1, CBC and DSA based algorithms, for
+    /// old servers that support nothing better.
+    Legacy,
+    /// Algorithms defined by the caller.
+    Custom(Algorithms),
+}
+
+impl Policy {
+    /// Algorithm lists this policy allows, in order of preference.
+    #[must_use]
+    pub fn algorithms(&self) -> Algorithms {
+        match self {
+            Policy::Modern => Algorithms::from_lists(
+                &[MODERN_KEX],
+                &[MODERN_HOST_KEY],
+                &[MODERN_CIPHER],
+                &[MODERN_MAC],
+            ),
+            Policy::Fips => {
+                Algorithms::from_lists(&[FIPS_KEX], &[FIPS_HOST_KEY], &[FIPS_CIPHER], &[FIPS_MAC])
+            }
+            Policy::Legacy => Algorithms::from_lists(
+                &[MODERN_KEX, LEGACY_KEX],
+                &[MODERN_HOST_KEY, LEGACY_HOST_KEY],
+                &[MODERN_CIPHER, LEGACY_CIPHER],
+                &[MODERN_MAC, LEGACY_MAC],
+            ),
+            Policy::Custom(algorithms) => algorithms.clone(),
+        }
+    }
+}
+
+/// Algorithm names, as used by OpenSSH, allowed for each negotiated category,
+/// in order of preference. Names a driver does not implement are skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Builder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Algorithms {
+    /// Key exchange algorithms, like OpenSSH's `KexAlgorithms`.
+    #[builder(with = |names: impl IntoIterator<Item = impl Into<String>>| collect(names))]
+    pub kex: Vec<String>,
+    /// Server host key algorithms, like OpenSSH's `HostKeyAlgorithms`.
+    #[builder(with = |names: impl IntoIterator<Item = impl Into<String>>| collect(names))]
+    pub host_key: Vec<String>,
+    /// Symmetric ciphers, like OpenSSH's `Ciphers`.
+    #[builder(with = |names: impl IntoIterator<Item = impl Into<String>>| collect(names))]
+    pub cipher: Vec<String>,
+    /// Message authentication codes, like OpenSSH's `MACs`.
+    #[builder(with = |names: impl IntoIterator<Item = impl Into<String>>| collect(names))]
+    pub mac: Vec<String>,
+}
+
+impl Algorithms {
+    fn from_lists(
+        kex: &[&[&str]],
+        host_key: &[&[&str]],
+        cipher: &[&[&str]],
+        mac: &[&[&str]],
+    ) -> Self {
+        Self {
+            kex: collect(kex.concat()),
+            host_key: collect(host_key.concat()),
+            cipher: collect(cipher.concat()),
+            mac: collect(mac.concat()),
+        }
+    }
+}
+
+fn collect(names: impl IntoIterator<Item = impl Into<String>>) -> Vec<String> {
+    names.into_iter().map(Into::into).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Policy::Modern)]
+    #[case(Policy::Fips)]
+    #[case(Policy::Legacy)]
+    fn algorithms_not_empty(#[case] policy: Policy) {
+        let algorithms = policy.algorithms();
+
+        assert!(!algorithms.kex.is_empty());
+        assert!(!algorithms.host_key.is_empty());
+        assert!(!algorithms.cipher.is_empty());
+        assert!(!algorithms.mac.is_empty());
+    }
+
+    #[test]
+    fn fips_excludes_non_approved() {
+        let algorithms = Policy::Fips.algorithms();
+
+        assert!(
+            !algorithms
+                .kex
+                .iter()
+                .any(|name| name.contains("curve25519"))
+        );
+        assert!(!algorithms.host_key.contains(&"ssh-ed25519".to_string()));
+        assert!(
+            !algorithms
+                .cipher
+                .iter()
+                .any(|name| name.contains("chacha20"))
+        );
+    }
+
+    #[test]
+    fn legacy_prefers_modern() {
+        let modern = Policy::Modern.algorithms();
+        let legacy = Policy::Legacy.algorithms();
+
+        assert!(legacy.kex.starts_with(&modern.kex));
+        assert!(legacy.mac.ends_with(&["hmac-sha1".to_string()]));
+    }
+
+    #[test]
+    fn custom_works() {
+        let algorithms = Algorithms::builder()
+            .kex(["curve25519-sha256"])
+            .host_key(["ssh-ed25519"])
+            .cipher(["aes256-gcm@openssh.com"])
+            .mac(["hmac-sha2-256-etm@openssh.com"])
+            .build();
+
+        assert_eq!(Policy::Custom(algorithms.clone()).algorithms(), algorithms);
+    }
+}
@@ -0,0 +1,492 @@
+//! PuTTY private key files (`.ppk`), versions 2 and 3.
+
+use std::fmt;
+use std::fs;
+
+use camino::Utf8Path;
+use hmac::Hmac;
+use hmac::Mac;
+use secrecy::zeroize::Zeroizing;
+use sha1::Digest;
+use sha1::Sha1;
+use sha2::Sha256;
+use ssh_encoding::Decode;
+use ssh_encoding::Encode;
+use ssh_encoding::base64::Base64;
+use ssh_encoding::base64::Encoding;
+use ssh_key::Cipher;
+use ssh_key::Mpint;
+use ssh_key::PrivateKey;
+use ssh_key::PublicKey;
+use ssh_key::private::KeypairData;
+use ssh_key::private::RsaKeypair;
+use ssh_key::private::RsaPrivateKey;
+use ssh_key::public::EcdsaPublicKey;
+use ssh_key::public::KeyData;
+
+use crate::Error;
+use crate::Result;
+
+/// Key used for the MAC of version 2 files, before the passphrase.
+const V2_MAC_KEY_PREFIX: &[u8] = b"putty-private-key-file-mac-key";
+const AES256_KEY_LEN: usize = 32;
+const AES_BLOCK_LEN: usize = 16;
+const V3_MAC_KEY_LEN: usize = 32;
+
+/// PuTTY private key, as read from a `.ppk` file. Convert it with
+/// [`PpkKey::to_private_key`] or [`PpkKey::decrypt`] to use it.
+#[derive(Clone)]
+pub struct PpkKey {
+    version: u8,
+    algorithm: String,
+    encrypted: bool,
+    comment: String,
+    public_key: Vec<u8>,
+    private_key: Vec<u8>,
+    argon2: Option<Argon2Params>,
+    mac: Vec<u8>,
+}
+
+/// Key derivation settings of an encrypted version 3 file.
+#[derive(Debug, Clone)]
+struct Argon2Params {
+    algorithm: argon2::Algorithm,
+    memory: u32,
+    passes: u32,
+    parallelism: u32,
+    salt: Vec<u8>,
+}
+
+impl PpkKey {
+    /// Parses the contents of a `.ppk` file.
+    ///
+    /// # Errors
+    ///
+    /// - If `text` is not a version 2 or 3 PuTTY private key.
+    /// - If the key is encrypted with anything but `aes256-cbc`.
+    pub fn from_ppk(text: &str) -> Result<PpkKey> {
+        let mut fields = Fields {
+            lines: text.lines(),
+        };
+
+        let header = fields.line()?;
+        let (version, algorithm) = match header.split_once(": ") {
+            Some(("PuTTY-User-Key-File-2", algorithm)) => (2, algorithm.to_string()),
+            Some(("PuTTY-User-Key-File-3", algorithm)) => (3, algorithm.to_string()),
+            _ => return Err(Error::InvalidPpk("not a version 2 or 3 PuTTY key file")),
+        };
+        let encrypted = match fields.field("Encryption")? {
+            "none" => false,
+            "aes256-cbc" => true,
+            _ => return Err(Error::InvalidPpk("unsupported encryption")),
+        };
+        let comment = fields.field("Comment")?.to_string();
+        let public_key = fields.blob("Public-Lines")?;
+        let argon2 = if version == 3 && encrypted {
+            let algorithm = match fields.field("Key-Derivation")? {
+                "Argon2d" => argon2::Algorithm::Argon2d,
+                "Argon2i" => argon2::Algorithm::Argon2i,
+                "Argon2id" => argon2::Algorithm::Argon2id,
+                _ => return Err(Error::InvalidPpk("unsupported key derivation")),
+            };
+            Some(Argon2Params {
+                algorithm,
+                memory: parse_number(fields.field("Argon2-Memory")?)?,
+                passes: parse_number(fields.field("Argon2-Passes")?)?,
+                parallelism: parse_number(fields.field("Argon2-Parallelism")?)?,
+                salt: parse_hex(fields.field("Argon2-Salt")?)?,
+            })
+        } else {
+            None
+        };
+        let private_key = fields.blob("Private-Lines")?;
+        let mac = parse_hex(fields.field("Private-MAC")?)?;
+
+        Ok(PpkKey {
+            version,
+            algorithm,
+            encrypted,
+            comment,
+            public_key,
+            private_key,
+            argon2,
+            mac,
+        })
+    }
+
+    /// Reads a `.ppk` file.
+    ///
+    /// # Errors
+    ///
+    /// - If `path` cannot be read.
+    /// - For the same reasons as [`PpkKey::from_ppk`].
+    pub fn read_file(path: impl AsRef<Utf8Path>) -> Result<PpkKey> {
+        let text = fs::read_to_string(path.as_ref())?;
+
+        PpkKey::from_ppk(&text)
+    }
+
+    /// Key algorithm, such as `ssh-ed25519`.
+    #[must_use]
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// Comment stored alongside the key.
+    #[must_use]
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Whether a passphrase is needed to use the key.
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Public half of the key, which is never encrypted.
+    ///
+    /// # Errors
+    ///
+    /// - If the public key is malformed.
+    pub fn public_key(&self) -> Result<PublicKey> {
+        let key_data = KeyData::decode(&mut self.public_key.as_slice())?;
+
+        Ok(PublicKey::new(key_data, &self.comment))
+    }
+
+    /// Converts an unencrypted key.
+    ///
+    /// # Errors
+    ///
+    /// - If the key is encrypted.
+    /// - If the file was tampered with, or its key is malformed or of an
+    ///   unsupported algorithm.
+    pub fn to_private_key(&self) -> Result<PrivateKey> {
+        if self.encrypted {
+            return Err(Error::EncryptedPrivateKeyNoPasshrase);
+        }
+
+        self.private_key_with(b"")
+    }
+
+    /// Decrypts the key with `passphrase` and converts it. Unencrypted keys
+    /// are converted without needing the passphrase.
+    ///
+    /// # Errors
+    ///
+    /// - If `passphrase` is wrong or the file was tampered with.
+    /// - If the key is malformed or of an unsupported algorithm.
+    pub fn decrypt(&self, passphrase: impl AsRef<[u8]>) -> Result<PrivateKey> {
+        if !self.encrypted {
+            return self.to_private_key();
+        }
+
+        self.private_key_with(passphrase.as_ref())
+    }
+
+    fn private_key_with(&self, passphrase: &[u8]) -> Result<PrivateKey> {
+        let (cipher_key, iv, mac_key) = self.derive_keys(passphrase)?;
+
+        let mut private_key = Zeroizing::new(self.private_key.clone());
+        if self.encrypted {
+            if !private_key.len().is_multiple_of(AES_BLOCK_LEN) {
+                return Err(Error::InvalidPpk("private key is not padded"));
+            }
+            Cipher::Aes256Cbc
+                .decrypt(&cipher_key, &iv, &mut private_key, None)
+                .map_err(ssh_key::Error::from)?;
+        }
+        self.verify_mac(&mac_key, &private_key)?;
+
+        let key_data = keypair_data(&self.public_key, &private_key)?;
+        let private_key = PrivateKey::new(key_data, &self.comment)?;
+
+        Ok(private_key)
+    }
+
+    /// Cipher key, IV and MAC key for `passphrase`.
+    #[allow(clippy::type_complexity)]
+    fn derive_keys(
+        &self,
+        passphrase: &[u8],
+    ) -> Result<(Zeroizing<Vec<u8>>, Vec<u8>, Zeroizing<Vec<u8>>)> {
+        if self.version == 2 {
+            let mut cipher_key = Zeroizing::new(Vec::with_capacity(2 * 20));
+            for counter in [0u32, 1] {
+                let digest = Sha1::new()
+                    .chain_update(counter.to_be_bytes())
+                    .chain_update(passphrase)
+                    .finalize();
+                cipher_key.extend_from_slice(&digest);
+            }
+            cipher_key.truncate(AES256_KEY_LEN);
+            let mac_key = Sha1::new()
+                .chain_update(V2_MAC_KEY_PREFIX)
+                .chain_update(passphrase)
+                .finalize();
+
+            return Ok((
+                cipher_key,
+                vec![0; AES_BLOCK_LEN],
+                Zeroizing::new(mac_key.to_vec()),
+            ));
+        }
+
+        let Some(params) = &self.argon2 else {
+            return Ok((Zeroizing::default(), Vec::new(), Zeroizing::default()));
+        };
+        let argon2_params = argon2::Params::new(
+            params.memory,
+            params.passes,
+            params.parallelism,
+            Some(AES256_KEY_LEN + AES_BLOCK_LEN + V3_MAC_KEY_LEN),
+        )
+        .map_err(|_| Error::InvalidPpk("invalid key derivation parameters"))?;
+        let mut output = Zeroizing::new(vec![0; AES256_KEY_LEN + AES_BLOCK_LEN + V3_MAC_KEY_LEN]);
+        argon2::Argon2::new(params.algorithm, argon2::Version::V0x13, argon2_params)
+            .hash_password_into(passphrase, &params.salt, &mut output)
+            .map_err(|_| Error::InvalidPpk("invalid key derivation parameters"))?;
+
+        let (cipher_key, rest) = output.split_at(AES256_KEY_LEN);
+        let (iv, mac_key) = rest.split_at(AES_BLOCK_LEN);
+        Ok((
+            Zeroizing::new(cipher_key.to_vec()),
+            iv.to_vec(),
+            Zeroizing::new(mac_key.to_vec()),
+        ))
+    }
+
+    /// Checks the MAC over the file's contents, which is the only way to tell
+    /// a wrong passphrase apart from a right one.
+    fn verify_mac(&self, mac_key: &[u8], private_key: &[u8]) -> Result<()> {
+        let mut data = Zeroizing::new(Vec::new());
+        (|| {
+            self.algorithm.encode(&mut *data)?;
+            if self.encrypted { "aes256-cbc" } else { "none" }.encode(&mut *data)?;
+            self.comment.encode(&mut *data)?;
+            self.public_key.encode(&mut *data)?;
+            private_key.encode(&mut *data)
+        })()
+        .map_err(ssh_key::Error::from)?;
+
+        let verified = if self.version == 2 {
+            verify_hmac::<Hmac<Sha1>>(mac_key, &data, &self.mac)
+        } else {
+            verify_hmac::<Hmac<Sha256>>(mac_key, &data, &self.mac)
+        };
+        if !verified {
+            return Err(Error::InvalidPpk(
+                "MAC does not match; wrong passphrase or corrupted file",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PpkKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PpkKey")
+            .field("version", &self.version)
+            .field("algorithm", &self.algorithm)
+            .field("encrypted", &self.encrypted)
+            .field("comment", &self.comment)
+            .finish_non_exhaustive()
+    }
+}
+
+fn verify_hmac<M: Mac + hmac::digest::KeyInit>(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let Ok(mut mac) = <M as Mac>::new_from_slice(key) else {
+        return false;
+    };
+    mac.update(data);
+
+    mac.verify_slice(tag).is_ok()
+}
+
+/// Keypair from PuTTY's public and private blobs, which split some key
+/// types' fields differently than OpenSSH does.
+fn keypair_data(public_key: &[u8], private_key: &[u8]) -> ssh_key::Result<KeypairData> {
+    let mut private_reader = private_key;
+    match KeyData::decode(&mut &*public_key)? {
+        KeyData::Rsa(public) => {
+            let d = Mpint::decode(&mut private_reader)?;
+            let p = Mpint::decode(&mut private_reader)?;
+            let q = Mpint::decode(&mut private_reader)?;
+            let iqmp = Mpint::decode(&mut private_reader)?;
+            Ok(KeypairData::Rsa(RsaKeypair {
+                public,
+                private: RsaPrivateKey { d, iqmp, p, q },
+            }))
+        }
+        // OpenSSH's encoding of these is PuTTY's public blob followed by
+        // the private fields.
+        key_data => {
+            let mut openssh = Zeroizing::new(public_key.to_vec());
+            match key_data {
+                KeyData::Ed25519(public) => {
+                    let seed = Zeroizing::new(Vec::<u8>::decode(&mut private_reader)?);
+                    let mut keypair = Zeroizing::new(seed.to_vec());
+                    keypair.extend_from_slice(&public.0);
+                    keypair.encode(&mut *openssh)?;
+                }
+                KeyData::Ecdsa(public) => {
+                    // PuTTY drops leading zeros from the scalar, but OpenSSH
+                    // needs the curve's full size.
+                    let scalar = Mpint::decode(&mut private_reader)?;
+                    let scalar = scalar.as_positive_bytes().unwrap_or_default();
+                    let size = ecdsa_scalar_len(&public);
+                    let padding = size
+                        .checked_sub(scalar.len())
+                        .ok_or(ssh_key::Error::FormatEncoding)?;
+                    let mut bytes = Zeroizing::new(vec![0; padding]);
+                    bytes.extend_from_slice(scalar);
+                    if bytes[0] >= 0x80 {
+                        bytes.insert(0, 0);
+                    }
+                    bytes.encode(&mut *openssh)?;
+                }
+                _ => openssh.extend_from_slice(private_key),
+            }
+            KeypairData::decode(&mut openssh.as_slice())
+        }
+    }
+}
+
+fn ecdsa_scalar_len(public: &EcdsaPublicKey) -> usize {
+    match public {
+        EcdsaPublicKey::NistP256(_) => 32,
+        EcdsaPublicKey::NistP384(_) => 48,
+        EcdsaPublicKey::NistP521(_) => 66,
+    }
+}
+
+/// Lines of a `.ppk` file, read in the order they must appear.
+struct Fields<'a> {
+    lines: std::str::Lines<'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn line(&mut self) -> Result<&'a str> {
+        self.lines
+            .next()
+            .ok_or(Error::InvalidPpk("unexpected end of file"))
+    }
+
+    /// Value of the next line, which must be the field `name`.
+    fn field(&mut self, name: &'static str) -> Result<&'a str> {
+        match self.line()?.split_once(": ") {
+            Some((found, value)) if found == name => Ok(value),
+            _ => Err(Error::InvalidPpk(name)),
+        }
+    }
+
+    /// Base64 blob spanning the number of lines given by the field `name`.
+    fn blob(&mut self, name: &'static str) -> Result<Vec<u8>> {
+        let count: usize = parse_number(self.field(name)?)?;
+        let mut encoded = String::new();
+        for _ in 0..count {
+            encoded.push_str(self.line()?);
+        }
+
+        Base64::decode_vec(&encoded).map_err(|_| Error::InvalidPpk("invalid base64"))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidPpk("invalid number"))
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return Err(Error::InvalidPpk("invalid hex"));
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or(Error::InvalidPpk("invalid hex"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("test/creds/id_ed25519.ppk", "test/creds/id_ed25519", None)]
+    #[case("test/creds/id_rsa.ppk", "test/creds/id_rsa", None)]
+    #[case(
+        "test/creds/enc_id_ed25519.ppk",
+        "test/creds/id_ed25519",
+        Some("test_passphrase")
+    )]
+    #[case(
+        "test/creds/enc_id_ecdsa.ppk",
+        "test/creds/id_ecdsa",
+        Some("test_passphrase")
+    )]
+    fn decrypt_works(
+        #[case] ppk_file: &str,
+        #[case] openssh_file: &str,
+        #[case] passphrase: Option<&str>,
+    ) {
+        let ppk = PpkKey::read_file(ppk_file).unwrap();
+        let openssh = PrivateKey::read_openssh_file(openssh_file.as_ref()).unwrap();
+
+        let private_key = match passphrase {
+            Some(passphrase) => ppk.decrypt(passphrase).unwrap(),
+            None => ppk.to_private_key().unwrap(),
+        };
+
+        assert_eq!(ppk.is_encrypted(), passphrase.is_some());
+        assert_eq!(private_key.key_data(), openssh.key_data());
+        assert_eq!(
+            ppk.public_key().unwrap().key_data(),
+            openssh.public_key().key_data()
+        );
+    }
+
+    #[rstest]
+    #[case("test/creds/enc_id_ed25519.ppk")]
+    #[case("test/creds/enc_id_ecdsa.ppk")]
+    fn decrypt_rejects_wrong_passphrase(#[case] ppk_file: &str) {
+        let ppk = PpkKey::read_file(ppk_file).unwrap();
+
+        assert!(matches!(ppk.decrypt("wrong"), Err(Error::InvalidPpk(_))));
+        assert!(matches!(
+            ppk.to_private_key(),
+            Err(Error::EncryptedPrivateKeyNoPasshrase)
+        ));
+    }
+
+    #[test]
+    fn to_private_key_detects_tampering() {
+        let text = fs::read_to_string("test/creds/id_ed25519.ppk").unwrap();
+        let tampered = text.replace("Comment: test", "Comment: tampered");
+
+        let result = PpkKey::from_ppk(&tampered).unwrap().to_private_key();
+
+        assert!(matches!(result, Err(Error::InvalidPpk(_))));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("PuTTY-User-Key-File-1: ssh-rsa")]
+    #[case("PuTTY-User-Key-File-3: ssh-ed25519\nEncryption: aes256-ctr")]
+    #[case(
+        "PuTTY-User-Key-File-3: ssh-ed25519\nEncryption: none\nComment: test\nPublic-Lines: 2\nAAAA"
+    )]
+    fn from_ppk_rejects_malformed(#[case] text: &str) {
+        assert!(matches!(PpkKey::from_ppk(text), Err(Error::InvalidPpk(_))));
+    }
+}
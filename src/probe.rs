@@ -0,0 +1,140 @@
+use semver::Version;
+use semver::VersionReq;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+/// Remote program found by [`ConnectedSession::command_version`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramVersion {
+    /// Where the program was found on the remote `PATH`.
+    pub path: String,
+    /// Version the program reported.
+    pub version: Version,
+}
+
+impl ConnectedSession {
+    /// Location of `program` on the remote host, as resolved by the remote
+    /// shell's `command -v`, or `None` if it is not installed.
+    ///
+    /// # Errors
+    ///
+    /// - If the check itself cannot be run.
+    pub async fn which(&self, program: &str) -> Result<Option<String>> {
+        let output = self
+            .command("command")
+            .args(["-v", program])
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.lines().next().map(|line| line.trim().to_string()))
+    }
+
+    /// Checks that `program` is installed on the remote host and that the
+    /// version it reports for `--version` satisfies `required`.
+    ///
+    /// # Errors
+    ///
+    /// - If `program` is not installed.
+    /// - If no version number can be found in its `--version` output.
+    /// - If its version does not satisfy `required`.
+    pub async fn command_version(
+        &self,
+        program: &str,
+        required: &VersionReq,
+    ) -> Result<ProgramVersion> {
+        let Some(path) = self.which(program).await? else {
+            return Err(Error::ProgramNotFound(program.to_string()));
+        };
+
+        let output = self.command(program).arg("--version").output().await?;
+        // Some programs, like older Pythons, print their version to stderr.
+        let text = [output.stdout, output.stderr].concat();
+        let version = parse_version(&String::from_utf8_lossy(&text))
+            .ok_or_else(|| Error::ProgramVersionUnknown(program.to_string()))?;
+
+        if !required.matches(&version) {
+            return Err(Error::ProgramVersionMismatch {
+                program: program.to_string(),
+                found: version,
+                required: required.clone(),
+            });
+        }
+
+        Ok(ProgramVersion { path, version })
+    }
+}
+
+/// Finds the first dotted version number in `--version` output, like `3.2.7`
+/// in `rsync  version 3.2.7  protocol version 31`. Missing minor or patch
+/// components are taken as zero.
+fn parse_version(text: &str) -> Option<Version> {
+    text.split_whitespace().find_map(|word| {
+        let word = word.strip_prefix('v').unwrap_or(word);
+        let end = word
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(word.len());
+        let mut parts = word[..end]
+            .split('.')
+            .filter(|part| !part.is_empty())
+            .map(str::parse::<u64>);
+
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().and_then(std::result::Result::ok).unwrap_or(0);
+
+        Some(Version::new(major, minor, patch))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case(
+        "rsync  version 3.2.7  protocol version 31",
+        Some(Version::new(3, 2, 7))
+    )]
+    #[case("Python 3.11.4\n", Some(Version::new(3, 11, 4)))]
+    #[case("tar (GNU tar) 1.34", Some(Version::new(1, 34, 0)))]
+    #[case("v18.19.0", Some(Version::new(18, 19, 0)))]
+    #[case("git version 2.43.0.windows.1", Some(Version::new(2, 43, 0)))]
+    #[case("usage: foo [-h]", None)]
+    fn parse_version_works(#[case] text: &str, #[case] version_should: Option<Version>) {
+        assert_eq!(parse_version(text), version_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn which_works() {
+        let session = test_server::connect().await;
+
+        let found = session.which("sh").await.unwrap();
+        let missing = session.which("ssh-util-not-installed").await.unwrap();
+
+        assert!(found.is_some_and(|path| path.ends_with("/sh")));
+        assert_eq!(missing, None);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn command_version_reports_missing_program() {
+        let session = test_server::connect().await;
+
+        let result = session
+            .command_version("ssh-util-not-installed", &VersionReq::STAR)
+            .await;
+
+        assert!(matches!(result, Err(Error::ProgramNotFound(_))));
+    }
+}
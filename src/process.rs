@@ -0,0 +1,1107 @@
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+
+use bon::Builder;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::io::ReadBuf;
+use tokio::sync::oneshot;
+
+use crate::Error;
+use crate::Result;
+use crate::driver::Connected;
+use crate::shell;
+use crate::transport::meter::ChannelKind;
+use crate::transport::meter::Counters;
+use crate::transport::meter::Metered;
+
+mod become_user;
+mod coalesce;
+mod flow;
+mod job;
+mod limits;
+mod multiplexer;
+mod preamble;
+mod windows;
+
+pub use become_user::Become;
+pub use become_user::BecomeMethod;
+pub use coalesce::WriteStrategy;
+pub use flow::ChannelStats;
+use flow::Counted;
+use flow::Direction;
+pub(crate) use flow::Flow;
+pub use job::Job;
+pub use job::JobStatus;
+pub use limits::IoPriority;
+pub use limits::Limit;
+use limits::Resources;
+pub use multiplexer::Multiplexer;
+use preamble::StripPreamble;
+pub use windows::PowerShellOutput;
+
+pub use crate::shell::Shell;
+
+/// Locale of commands without a pty, unless set otherwise, which every host
+/// with a recent C library provides.
+pub(crate) const DEFAULT_LOCALE: &str = "C.UTF-8";
+/// Terminal type of commands without a pty, unless set otherwise, which keeps
+/// programs from printing colors and other escape sequences.
+pub(crate) const DEFAULT_TERM: &str = "dumb";
+
+/// Locale and terminal type the commands of a session run with, unless set
+/// on the command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommandEnv {
+    pub(crate) locale: String,
+    pub(crate) term: String,
+}
+
+impl Default for CommandEnv {
+    fn default() -> Self {
+        Self {
+            locale: DEFAULT_LOCALE.to_string(),
+            term: DEFAULT_TERM.to_string(),
+        }
+    }
+}
+
+/// Builder for a command to run on the remote host, similar to
+/// [`tokio::process::Command`]. Created by
+/// [`ConnectedSession::command`](crate::ConnectedSession::command).
+pub struct Command<'s> {
+    session: &'s Connected,
+    defaults: &'s CommandEnv,
+    traffic: &'s Arc<Counters>,
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    locale: Option<String>,
+    term: Option<String>,
+    current_dir: Option<Utf8PathBuf>,
+    resources: Resources,
+    pty: Option<Pty>,
+    write_strategy: Option<WriteStrategy>,
+    become_user: Option<Become>,
+    shell: Shell,
+    /// Line printed right before the command starts, if what comes before
+    /// it is dropped.
+    preamble_sentinel: Option<String>,
+}
+
+/// Pseudo-terminal to run a command on, for programs that only work
+/// interactively. Its output and error are merged into stdout.
+#[derive(Debug, Clone, PartialEq, Eq, Builder)]
+pub struct Pty {
+    /// Terminal type, which tells the program what escape sequences it may
+    /// use.
+    #[builder(into, default = "xterm-256color")]
+    pub(crate) term: String,
+    /// Width in characters.
+    #[builder(default = 80)]
+    pub(crate) cols: u32,
+    /// Height in lines.
+    #[builder(default = 24)]
+    pub(crate) rows: u32,
+}
+
+impl<'s> Command<'s> {
+    pub(crate) fn new(
+        session: &'s Connected,
+        defaults: &'s CommandEnv,
+        traffic: &'s Arc<Counters>,
+        program: impl Into<String>,
+    ) -> Self {
+        Self {
+            session,
+            defaults,
+            traffic,
+            program: program.into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            locale: None,
+            term: None,
+            current_dir: None,
+            resources: Resources::default(),
+            pty: None,
+            write_strategy: None,
+            become_user: None,
+            shell: Shell::Posix,
+            preamble_sentinel: None,
+        }
+    }
+
+    /// Adds an argument to pass to the program.
+    pub fn arg(&mut self, arg: impl Into<String>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Adds multiple arguments to pass to the program.
+    pub fn args(&mut self, args: impl IntoIterator<Item = impl Into<String>>) -> &mut Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets an environment variable for the program, through `env`, since
+    /// servers accept only the variables allowed by their `AcceptEnv`. `key`
+    /// must not contain `=`.
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets multiple environment variables for the program.
+    pub fn envs(
+        &mut self,
+        vars: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> &mut Self {
+        self.env.extend(
+            vars.into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+        self
+    }
+
+    /// Runs the program in `locale`, set as `LANG` and `LC_ALL`, instead of
+    /// the session's. Unlike the session's, it also applies on a pty.
+    pub fn locale(&mut self, locale: impl Into<String>) -> &mut Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    /// Sets `TERM` to `term` instead of the session's. Ignored on a pty,
+    /// whose terminal type is the [`Pty`]'s.
+    pub fn term(&mut self, term: impl Into<String>) -> &mut Self {
+        self.term = Some(term.into());
+        self
+    }
+
+    /// Runs the program in `dir` instead of the remote user's home. Relative
+    /// paths are relative to the home, and a leading `~` is left to the
+    /// shell.
+    pub fn current_dir(&mut self, dir: impl Into<Utf8PathBuf>) -> &mut Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Runs the command on a pseudo-terminal. Detached commands never get
+    /// one.
+    pub fn pty(&mut self, pty: Pty) -> &mut Self {
+        self.pty = Some(pty);
+        self
+    }
+
+    /// Runs the command as another user, switched to as `chain` says, such
+    /// as `"postgres"` for `sudo -u postgres`. Everything else set on the
+    /// command applies as that user. See [`Become`].
+    pub fn become_user(&mut self, chain: impl Into<Become>) -> &mut Self {
+        self.become_user = Some(chain.into());
+        self
+    }
+
+    /// Writes the command line for `shell`, the remote user's login shell,
+    /// instead of a POSIX shell, for hosts running Windows. Use
+    /// [`Shell::PowerShell`] to run PowerShell commands and scripts whatever
+    /// the login shell is.
+    ///
+    /// The locale, `TERM`, resource restrictions,
+    /// [`Command::become_user`] and [`Command::strip_preamble`] only apply to
+    /// POSIX shells, and [`Command::detach`] requires one.
+    pub fn shell(&mut self, shell: Shell) -> &mut Self {
+        self.shell = shell;
+        self
+    }
+
+    /// Drops whatever the remote shell prints before running the command,
+    /// such as a message of the day or the output of the remote user's
+    /// profile, so that output can be parsed. The command line is prefixed
+    /// with commands printing a unique sentinel line to stdout and stderr,
+    /// and everything up to that line is discarded. Output is kept whole if
+    /// the sentinel never comes, as when the shell fails to start. Only
+    /// applies to [`Command::spawn`] and the methods built on it.
+    pub fn strip_preamble(&mut self) -> &mut Self {
+        self.preamble_sentinel = Some(preamble::sentinel());
+        self
+    }
+
+    /// Groups writes to stdin into SSH packets according to `strategy`,
+    /// instead of sending them at once on a pty and coalescing them
+    /// otherwise.
+    pub fn write_strategy(&mut self, strategy: WriteStrategy) -> &mut Self {
+        self.write_strategy = Some(strategy);
+        self
+    }
+
+    /// Runs the command with its niceness adjusted by `adjustment`, from -20
+    /// for the most favorable scheduling to 19 for the least. Lowering it
+    /// below the current niceness requires root.
+    pub fn nice(&mut self, adjustment: i8) -> &mut Self {
+        self.resources.nice = Some(adjustment);
+        self
+    }
+
+    /// Runs the command with the given I/O scheduling class and priority.
+    /// Requires `ionice` on the remote host, which is part of util-linux.
+    pub fn ionice(&mut self, priority: IoPriority) -> &mut Self {
+        self.resources.ionice = Some(priority);
+        self
+    }
+
+    /// Throttles the command to `percent` of one CPU. Requires `cpulimit` on
+    /// the remote host.
+    pub fn cpu_limit(&mut self, percent: u32) -> &mut Self {
+        self.resources.cpu_limit = Some(percent);
+        self
+    }
+
+    /// Runs the command under a resource limit. May be given several times.
+    pub fn ulimit(&mut self, limit: Limit) -> &mut Self {
+        self.resources.limits.push(limit);
+        self
+    }
+
+    /// Command line sent to the server. SSH runs commands through the remote
+    /// user's shell, so the program and arguments are quoted for a POSIX
+    /// shell. Environment variables and resource restrictions are applied by
+    /// programs the command line starts with, after changing to the current
+    /// directory.
+    ///
+    /// Without a pty, the locale and `TERM` are set first, to the session's
+    /// unless set on the command, so that output parses the same whatever the
+    /// host's settings. Variables set with [`Command::env`] come after them
+    /// and take precedence.
+    ///
+    /// With [`Command::become_user`], all of this is run through the chain of
+    /// `sudo` and `su`.
+    ///
+    /// For other shells set with [`Command::shell`], the program and arguments
+    /// are quoted for that shell, and environment variables set by it after
+    /// changing to the current directory. PowerShell gets them as an encoded
+    /// script that exits with the program's exit code.
+    #[must_use]
+    pub fn command_line(&self) -> String {
+        let current_dir = self.current_dir.as_deref();
+        match self.shell {
+            Shell::Posix => self.posix_command_line(),
+            Shell::Cmd => windows::cmd_line(&self.program, &self.args, &self.env, current_dir),
+            Shell::PowerShell => shell::powershell(&windows::powershell_script(
+                &self.program,
+                &self.args,
+                &self.env,
+                current_dir,
+            )),
+        }
+    }
+
+    fn posix_command_line(&self) -> String {
+        let locale = match (&self.locale, &self.pty) {
+            (Some(locale), _) => Some(locale),
+            (None, None) => Some(&self.defaults.locale),
+            (None, Some(_)) => None,
+        };
+        let term = self
+            .pty
+            .is_none()
+            .then(|| self.term.as_ref().unwrap_or(&self.defaults.term));
+
+        let env: Vec<String> = locale
+            .into_iter()
+            .flat_map(|locale| [("LANG", locale), ("LC_ALL", locale)])
+            .chain(term.map(|term| ("TERM", term)))
+            .chain(self.env.iter().map(|(key, value)| (key.as_str(), value)))
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        let prefix = self.resources.prefix();
+
+        let command_line = shell::join(
+            (!env.is_empty())
+                .then_some("env")
+                .into_iter()
+                .chain(env.iter().map(String::as_str))
+                .chain(prefix.iter().map(String::as_str))
+                .chain(std::iter::once(self.program.as_str()))
+                .chain(self.args.iter().map(String::as_str)),
+        );
+        let command_line = match &self.current_dir {
+            Some(dir) => format!("cd {} && {command_line}", cd_target(dir)),
+            None => command_line,
+        };
+        match &self.become_user {
+            Some(chain) => chain.wrap(&command_line),
+            None => command_line,
+        }
+    }
+
+    /// Runs the command, returning a handle to it. Standard input, output and
+    /// error are all piped.
+    ///
+    /// With [`Command::become_user`] and passwords, the command runs on a
+    /// default [`Pty`] unless given one, and returns once the password
+    /// prompts are answered.
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses to open a channel.
+    /// - If the session ran as many commands or opened as many channels as it
+    ///   may, as [`Error::LimitReached`].
+    /// - If a password is refused or another user cannot be switched to, as
+    ///   [`Error::BecomeFailed`].
+    pub async fn spawn(&mut self) -> Result<Child> {
+        // Prompts are answered and the preamble dropped in POSIX shells only.
+        let posix = self.shell == Shell::Posix;
+        let prompted = self
+            .become_user
+            .as_ref()
+            .filter(|chain| posix && chain.needs_pty());
+        let preamble_sentinel = self.preamble_sentinel.as_ref().filter(|_| posix);
+        let pty = self
+            .pty
+            .clone()
+            .or_else(|| prompted.map(|_| Pty::builder().build()));
+        let command_line = match preamble_sentinel {
+            // On a pty, stderr is merged into stdout.
+            Some(sentinel) if pty.is_some() => {
+                format!("printf '%s\\n' {sentinel}; {}", self.command_line())
+            }
+            Some(sentinel) => format!(
+                "printf '%s\\n' {sentinel}; printf '%s\\n' {sentinel} >&2; {}",
+                self.command_line()
+            ),
+            None => self.command_line(),
+        };
+        let mut child = self.exec(&command_line, pty.as_ref()).await?;
+        if let Some(chain) = prompted
+            && let (Some(stdin), Some(mut stdout)) = (child.stdin.as_mut(), child.stdout.take())
+        {
+            // Answering the prompts drops the preamble too.
+            let rest = chain.answer_prompts(stdin, &mut stdout).await?;
+            child.stdout = Some(stdout.prepend(rest));
+        }
+        if let Some(sentinel) = preamble_sentinel {
+            if prompted.is_none() {
+                child.stdout = child.stdout.map(|stdout| stdout.strip_preamble(sentinel));
+            }
+            if pty.is_none() {
+                child.stderr = child.stderr.map(|stderr| stderr.strip_preamble(sentinel));
+            }
+        }
+        let strategy = self
+            .write_strategy
+            .unwrap_or_else(|| WriteStrategy::default_for(pty.is_some()));
+        if let Some(stdin) = child.stdin.as_mut() {
+            stdin.coalesce(strategy);
+        }
+
+        Ok(child)
+    }
+
+    /// Runs the command to completion with empty stdin, collecting its
+    /// output and exit status. See [`Child::wait_with_output`].
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses to open a channel.
+    /// - If the session ran as many commands or opened as many channels as it
+    ///   may, as [`Error::LimitReached`].
+    /// - For the same reasons as [`Child::wait_with_output`].
+    pub async fn output(&mut self) -> Result<Output> {
+        self.spawn().await?.wait_with_output().await
+    }
+
+    /// Runs the command to completion with empty stdin, returning its exit
+    /// status. Its output is read and discarded, so the command is never
+    /// stalled by it.
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses to open a channel.
+    /// - If the session ran as many commands or opened as many channels as it
+    ///   may, as [`Error::LimitReached`].
+    /// - For the same reasons as [`Child::wait_with_sinks`].
+    pub async fn status(&mut self) -> Result<ExitStatus> {
+        self.spawn()
+            .await?
+            .wait_with_sinks(tokio::io::sink(), tokio::io::sink())
+            .await
+    }
+
+    /// Starts the command in the background, detached from the session so
+    /// that it keeps running after the session ends. Its stdout and stderr
+    /// are written to `output` on the remote host, relative to the home
+    /// directory unless absolute, and its exit code to `output` with a
+    /// `.status` suffix. Stdin is empty.
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses to open a channel.
+    /// - If the session ran as many commands or opened as many channels as it
+    ///   may, as [`Error::LimitReached`].
+    /// - If the command cannot be started in the background.
+    pub async fn detach(&mut self, output: impl AsRef<Utf8Path>) -> Result<Job> {
+        let output = output.as_ref();
+        let launcher = Job::launcher(&self.command_line(), output);
+        let result = self.exec(&launcher, None).await?.wait_with_output().await?;
+        let stdout = String::from_utf8_lossy(&result.stdout);
+
+        if !result.status.success() {
+            return Err(Error::CommandFailed {
+                command: launcher,
+                status: result.status,
+            });
+        }
+        let pid = stdout.trim().parse().map_err(|_| Error::UnexpectedOutput {
+            command: launcher,
+            output: stdout.to_string(),
+        })?;
+
+        Ok(Job::new(pid, output))
+    }
+
+    /// Runs `command_line`, counting it and its data in the session's
+    /// traffic.
+    async fn exec(&self, command_line: &str, pty: Option<&Pty>) -> Result<Child> {
+        let slot = self.traffic.open_channel(ChannelKind::Exec)?;
+        let mut child = self.session.exec(&self.program, command_line, pty).await?;
+        slot.commit();
+        child.meter(self.traffic);
+
+        Ok(child)
+    }
+}
+
+/// Handle to a command running on the remote host.
+pub struct Child {
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    status: Option<oneshot::Receiver<Result<ExitStatus>>>,
+    exit_status: Option<ExitStatus>,
+    flow: Arc<Flow>,
+}
+
+impl Child {
+    pub(crate) fn new(
+        stdin: impl AsyncWrite + Send + 'static,
+        stdout: impl AsyncRead + Send + 'static,
+        stderr: impl AsyncRead + Send + 'static,
+        status: oneshot::Receiver<Result<ExitStatus>>,
+        flow: Arc<Flow>,
+    ) -> Self {
+        let stdin = Counted::new(Box::pin(stdin), flow.clone(), Direction::Sent);
+        let stdout = Counted::new(Box::pin(stdout), flow.clone(), Direction::Read);
+        let stderr = Counted::new(Box::pin(stderr), flow.clone(), Direction::Read);
+
+        Self {
+            stdin: Some(ChildStdin(Some(Box::pin(stdin)))),
+            stdout: Some(ChildStdout(Box::pin(stdout))),
+            stderr: Some(ChildStderr(Box::pin(stderr))),
+            status: Some(status),
+            exit_status: None,
+            flow,
+        }
+    }
+
+    /// Counts the data of the pipes in `counters`, as that of an exec
+    /// channel.
+    fn meter(&mut self, counters: &Arc<Counters>) {
+        if let Some(stdin) = self.stdin.as_mut() {
+            stdin.meter(counters);
+        }
+        self.stdout = self.stdout.take().map(|stdout| stdout.meter(counters));
+        self.stderr = self.stderr.take().map(|stderr| stderr.meter(counters));
+    }
+
+    /// Data that has flowed through the command's channel so far, to find
+    /// out whether a stalled command is waiting for its output to be read.
+    #[must_use]
+    pub fn stats(&self) -> ChannelStats {
+        self.flow.stats()
+    }
+
+    /// Waits for the command to exit. Shuts down stdin first, so commands
+    /// reading it see end of file instead of waiting forever.
+    ///
+    /// Output that is not read is buffered only up to a limit, after which
+    /// the command is stalled, so read stdout and stderr before waiting or
+    /// use [`Child::wait_with_output`].
+    ///
+    /// # Errors
+    ///
+    /// - If the server rejected the command.
+    /// - If the session ended before the command's exit status was received.
+    pub async fn wait(&mut self) -> Result<ExitStatus> {
+        self.close_stdin().await;
+
+        if let Some(status) = self.status.take() {
+            let status = status.await.unwrap_or(Err(Error::MissingExitStatus))?;
+            self.exit_status = Some(status);
+        }
+
+        self.exit_status.clone().ok_or(Error::MissingExitStatus)
+    }
+
+    /// Shuts down stdin, reads stdout and stderr to the end and waits for the
+    /// command to exit.
+    ///
+    /// # Errors
+    ///
+    /// - If reading stdout or stderr fails.
+    /// - For the same reasons as [`Child::wait`].
+    pub async fn wait_with_output(mut self) -> Result<Output> {
+        self.close_stdin().await;
+
+        let (stdout, stderr) = futures::try_join!(
+            read_to_end(self.stdout.take()),
+            read_to_end(self.stderr.take())
+        )?;
+        let status = self.wait().await?;
+
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Shuts down stdin, copies stdout and stderr to the given writers as
+    /// they arrive and waits for the command to exit, to keep large output
+    /// out of memory. The writers are flushed but not shut down.
+    ///
+    /// # Errors
+    ///
+    /// - If reading stdout or stderr, or writing them, fails.
+    /// - For the same reasons as [`Child::wait`].
+    pub async fn wait_with_sinks(
+        mut self,
+        stdout: impl AsyncWrite + Unpin,
+        stderr: impl AsyncWrite + Unpin,
+    ) -> Result<ExitStatus> {
+        self.close_stdin().await;
+
+        futures::try_join!(
+            copy_to(self.stdout.take(), stdout),
+            copy_to(self.stderr.take(), stderr)
+        )?;
+
+        self.wait().await
+    }
+
+    async fn close_stdin(&mut self) {
+        if let Some(mut stdin) = self.stdin.take() {
+            // Fails only if the channel is already closed, in which case the
+            // command cannot be waiting for input anymore.
+            let _ = stdin.shutdown().await;
+        }
+    }
+}
+
+/// `dir` quoted for `cd`, leaving a leading `~` or `~user` for the shell to
+/// expand.
+fn cd_target(dir: &Utf8Path) -> String {
+    let dir = dir.as_str();
+    let tilde_len = dir
+        .strip_prefix('~')
+        .map(|rest| 1 + rest.find('/').unwrap_or(rest.len()));
+    match tilde_len {
+        Some(len)
+            if dir[1..len]
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"._-".contains(&byte)) =>
+        {
+            let rest = &dir[len..];
+            if rest.is_empty() {
+                dir.to_string()
+            } else {
+                format!("{}{}", &dir[..len], shell::quote(rest))
+            }
+        }
+        _ => format!("-- {}", shell::quote(dir)),
+    }
+}
+
+async fn read_to_end(reader: Option<impl AsyncRead + Unpin>) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if let Some(mut reader) = reader {
+        reader.read_to_end(&mut buf).await?;
+    }
+
+    Ok(buf)
+}
+
+async fn copy_to(
+    reader: Option<impl AsyncRead + Unpin>,
+    mut writer: impl AsyncWrite + Unpin,
+) -> io::Result<()> {
+    if let Some(mut reader) = reader {
+        tokio::io::copy(&mut reader, &mut writer).await?;
+    }
+
+    writer.flush().await
+}
+
+/// Writes to the standard input of a [`Child`].
+///
+/// - A completed write has been handed to the session, but may still be waiting
+///   for the server to accept more data, or be held to be sent with later
+///   writes as set by [`Command::write_strategy`].
+/// - Flushing waits until everything written has been sent.
+/// - Shutting down sends end of file to the command once everything written has
+///   been sent. Writing afterward fails.
+/// - Dropping without shutting down sends end of file in the background.
+pub struct ChildStdin(Option<Pin<Box<dyn AsyncWrite + Send>>>);
+
+/// Reads from the standard output of a [`Child`].
+pub struct ChildStdout(Pin<Box<dyn AsyncRead + Send>>);
+
+/// Reads from the standard error of a [`Child`].
+pub struct ChildStderr(Pin<Box<dyn AsyncRead + Send>>);
+
+impl ChildStdin {
+    fn meter(&mut self, counters: &Arc<Counters>) {
+        self.0 = self
+            .0
+            .take()
+            .map(|inner| -> Pin<Box<dyn AsyncWrite + Send>> {
+                Box::pin(Metered::channel(
+                    inner,
+                    Arc::clone(counters),
+                    ChannelKind::Exec,
+                ))
+            });
+    }
+
+    fn coalesce(&mut self, strategy: WriteStrategy) {
+        self.0 = self
+            .0
+            .take()
+            .map(|inner| coalesce::coalesce(inner, strategy));
+    }
+
+    fn inner(&mut self) -> io::Result<Pin<&mut (dyn AsyncWrite + Send + 'static)>> {
+        self.0
+            .as_mut()
+            .map(Pin::as_mut)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stdin is shut down"))
+    }
+}
+
+impl AsyncWrite for ChildStdin {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner()?.poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.0.as_mut() {
+            Some(inner) => inner.as_mut().poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(inner) = self.0.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        ready!(inner.as_mut().poll_shutdown(cx))?;
+        self.0 = None;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for ChildStdin {
+    fn drop(&mut self) {
+        let Some(mut inner) = self.0.take() else {
+            return;
+        };
+
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                let _ = inner.shutdown().await;
+            });
+        }
+    }
+}
+
+impl ChildStdout {
+    fn meter(self, counters: &Arc<Counters>) -> Self {
+        ChildStdout(Box::pin(Metered::channel(
+            self.0,
+            Arc::clone(counters),
+            ChannelKind::Exec,
+        )))
+    }
+
+    fn strip_preamble(self, sentinel: &str) -> Self {
+        ChildStdout(Box::pin(StripPreamble::new(self.0, sentinel)))
+    }
+
+    /// Stdout reading `read` first, which was read ahead of the caller.
+    fn prepend(self, read: Vec<u8>) -> Self {
+        ChildStdout(Box::pin(io::Cursor::new(read).chain(self.0)))
+    }
+}
+
+impl ChildStderr {
+    fn meter(self, counters: &Arc<Counters>) -> Self {
+        ChildStderr(Box::pin(Metered::channel(
+            self.0,
+            Arc::clone(counters),
+            ChannelKind::Exec,
+        )))
+    }
+
+    fn strip_preamble(self, sentinel: &str) -> Self {
+        ChildStderr(Box::pin(StripPreamble::new(self.0, sentinel)))
+    }
+}
+
+impl AsyncRead for ChildStdout {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.0.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl AsyncRead for ChildStderr {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.0.as_mut().poll_read(cx, buf)
+    }
+}
+
+/// How a remote command exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatus {
+    code: Option<u32>,
+    signal: Option<String>,
+}
+
+impl ExitStatus {
+    pub(crate) fn from_code(code: u32) -> Self {
+        Self {
+            code: Some(code),
+            signal: None,
+        }
+    }
+
+    pub(crate) fn from_signal(signal: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            signal: Some(signal.into()),
+        }
+    }
+
+    /// Whether the command exited with code 0.
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Exit code, or `None` if the command was killed by a signal.
+    #[must_use]
+    pub fn code(&self) -> Option<u32> {
+        self.code
+    }
+
+    /// Name of the signal that killed the command, without the `SIG` prefix.
+    #[must_use]
+    pub fn signal(&self) -> Option<&str> {
+        self.signal.as_deref()
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.code, &self.signal) {
+            (Some(code), _) => write!(f, "exit status: {code}"),
+            (None, Some(signal)) => write!(f, "signal: {signal}"),
+            (None, None) => f.write_str("unknown exit status"),
+        }
+    }
+}
+
+/// Exit status and collected output of a finished command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn nice_works() {
+        let session = test_server::connect().await;
+
+        let output = session
+            .command("sh")
+            .args(["-c", "ulimit -n; nice"])
+            .nice(5)
+            .ulimit(Limit::OpenFiles(64))
+            .spawn()
+            .await
+            .unwrap()
+            .wait_with_output()
+            .await
+            .unwrap();
+
+        let niceness = std::process::Command::new("nice").output().unwrap().stdout;
+        let niceness: i32 = String::from_utf8(niceness).unwrap().trim().parse().unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("64\n{}\n", (niceness + 5).min(19))
+        );
+    }
+
+    #[rstest]
+    #[case("/srv/my app", "-- '/srv/my app'")]
+    #[case("-rf", "-- -rf")]
+    #[case("~", "~")]
+    #[case("~/my app", "~'/my app'")]
+    #[case("~deploy/app", "~deploy/app")]
+    #[case("~$(reboot)/app", "-- '~$(reboot)/app'")]
+    fn cd_target_works(#[case] dir: &str, #[case] target_should: &str) {
+        assert_eq!(cd_target(Utf8Path::new(dir)), target_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn env_and_current_dir_work() {
+        let session = test_server::connect().await;
+        session.fs().create_dir("~/my app").await.unwrap();
+
+        let mut command = session.command("sh");
+        command
+            .args(["-c", "echo \"$GREETING\"; pwd"])
+            .env("GREETING", "hi there")
+            .current_dir("my app");
+        let output = command
+            .spawn()
+            .await
+            .unwrap()
+            .wait_with_output()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            command.command_line(),
+            "cd -- 'my app' && env LANG=C.UTF-8 LC_ALL=C.UTF-8 TERM=dumb 'GREETING=hi there' \
+             sh -c 'echo \"$GREETING\"; pwd'"
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.starts_with("hi there\n"));
+        assert!(stdout.trim_end().ends_with("/my app"));
+    }
+
+    #[cfg(feature = "russh")]
+    #[rstest]
+    #[case(None, None, "C.UTF-8 C.UTF-8 dumb\n")]
+    #[case(Some("POSIX"), Some("vt100"), "POSIX POSIX vt100\n")]
+    #[tokio::test]
+    async fn locale_and_term_work(
+        #[case] locale: Option<&str>,
+        #[case] term: Option<&str>,
+        #[case] stdout_should: &str,
+    ) {
+        let session = test_server::connect().await;
+        let mut command = session.command("sh");
+        command.args(["-c", "echo \"$LANG $LC_ALL $TERM\""]);
+        if let Some(locale) = locale {
+            command.locale(locale);
+        }
+        if let Some(term) = term {
+            command.term(term);
+        }
+
+        let output = command.output().await.unwrap();
+
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), stdout_should);
+    }
+
+    #[test]
+    fn exit_status_display_works() {
+        assert_eq!(ExitStatus::from_code(3).to_string(), "exit status: 3");
+        assert_eq!(ExitStatus::from_signal("TERM").to_string(), "signal: TERM");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn spawn_works() {
+        let session = test_server::connect().await;
+
+        let output = session
+            .command("echo")
+            .arg("hello world")
+            .spawn()
+            .await
+            .unwrap()
+            .wait_with_output()
+            .await
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello world\n");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn output_and_status_work() {
+        let session = test_server::connect().await;
+        let mut command = session.command("sh");
+        command.args(["-c", "cat; echo out; echo err >&2; exit 4"]);
+
+        let output = command.output().await.unwrap();
+        let status = command.status().await.unwrap();
+
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.status.code(), Some(4));
+        assert_eq!(status.code(), Some(4));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn strip_preamble_hides_sentinel() {
+        let session = test_server::connect().await;
+
+        let output = session
+            .command("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .strip_preamble()
+            .output()
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn stdin_works() {
+        let session = test_server::connect().await;
+        let mut child = session.command("cat").spawn().await.unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"piped").await.unwrap();
+        stdin.shutdown().await.unwrap();
+        let output = child.wait_with_output().await.unwrap();
+
+        assert_eq!(output.stdout, b"piped");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn dropping_stdin_sends_eof() {
+        let session = test_server::connect().await;
+        let mut child = session.command("cat").spawn().await.unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"dropped").await.unwrap();
+        drop(stdin);
+        let mut stdout = Vec::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_end(&mut stdout)
+            .await
+            .unwrap();
+
+        assert_eq!(stdout, b"dropped");
+        assert!(child.wait().await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn stdin_rejects_writes_after_shutdown() {
+        let (writer, _reader) = tokio::io::duplex(64);
+        let mut stdin = ChildStdin(Some(Box::pin(writer)));
+
+        stdin.shutdown().await.unwrap();
+        let err = stdin.write_all(b"late").await.unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        stdin.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn unread_output_is_reported() {
+        use futures::StreamExt;
+
+        let session = crate::Session::builder()
+            .user(test_server::USER)
+            .host("localhost")
+            .driver(crate::DriverKind::Russh)
+            .auth(crate::Auth::from_password_file("test/creds/password").unwrap())
+            .slow_consumer_after(std::time::Duration::from_millis(100))
+            .host_key_verification(crate::HostKeyVerification::AcceptAny)
+            .with_stream(test_server::spawn().into_stream().unwrap())
+            .build()
+            .connect()
+            .await
+            .unwrap();
+        let mut events = session.events();
+
+        let child = session
+            .command("head")
+            .args(["-c", "1000000", "/dev/zero"])
+            .spawn()
+            .await
+            .unwrap();
+        let event = events.next().await.unwrap();
+        let stats = child.stats();
+
+        assert!(matches!(event, crate::Event::SlowConsumer { program, .. } if program == "head"));
+        assert!(stats.stalled_for.is_some());
+        assert!(stats.unread > 0);
+        assert_eq!(stats.sent, 0);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn wait_reports_exit_code() {
+        let session = test_server::connect().await;
+
+        let output = session
+            .command("sh")
+            .args(["-c", "echo oops >&2; exit 3"])
+            .spawn()
+            .await
+            .unwrap()
+            .wait_with_output()
+            .await
+            .unwrap();
+
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stderr, b"oops\n");
+    }
+}
@@ -0,0 +1,395 @@
+use secrecy::ExposeSecret;
+use secrecy::SecretString;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+
+use super::preamble;
+use crate::Error;
+use crate::Result;
+use crate::shell;
+
+/// Bytes read at a time while answering password prompts.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Prompt of `su`, which is run in the C locale so that it is not
+/// translated.
+const SU_PROMPT: &str = "Password: ";
+
+/// Program switching to another user in a [`Become`] chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BecomeMethod {
+    /// `sudo -u`, which asks for the password of the user switching, unless
+    /// the sudoers policy lets them switch without one.
+    Sudo,
+    /// `su -`, which asks for the password of the user switched to, unless
+    /// run by root. Commands start in that user's home, with their login
+    /// environment.
+    Su,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    method: BecomeMethod,
+    user: String,
+    password: Option<SecretString>,
+}
+
+/// Users a command runs as, each switched to from the previous one, for
+/// hosts where service accounts cannot log in directly. For example,
+/// `Become::sudo("root").then_su("postgres")` runs `sudo` to become root and
+/// then `su -` to become postgres. A user name converts to a single `sudo`
+/// step.
+///
+/// Commands with a step that has a password run on a pty, so that `su` can
+/// ask for it; the prompts are answered before the command's output is
+/// returned. Steps without a password must not need one: `sudo` then runs
+/// with `-n`, failing instead of asking.
+#[derive(Debug, Clone)]
+pub struct Become {
+    steps: Vec<Step>,
+    /// Line printed right before the command starts, once every prompt is
+    /// answered.
+    sentinel: String,
+}
+
+impl Become {
+    /// Switches to `user` with `sudo -u`.
+    #[must_use]
+    pub fn sudo(user: impl Into<String>) -> Self {
+        Self::new(BecomeMethod::Sudo, user)
+    }
+
+    /// Switches to `user` with `su -`.
+    #[must_use]
+    pub fn su(user: impl Into<String>) -> Self {
+        Self::new(BecomeMethod::Su, user)
+    }
+
+    fn new(method: BecomeMethod, user: impl Into<String>) -> Self {
+        Self {
+            steps: Vec::new(),
+            sentinel: preamble::sentinel(),
+        }
+        .then(method, user)
+    }
+
+    /// Switches on from the last user to `user` with `method`.
+    #[must_use]
+    pub fn then(mut self, method: BecomeMethod, user: impl Into<String>) -> Self {
+        self.steps.push(Step {
+            method,
+            user: user.into(),
+            password: None,
+        });
+        self
+    }
+
+    /// Switches on from the last user to `user` with `sudo -u`.
+    #[must_use]
+    pub fn then_sudo(self, user: impl Into<String>) -> Self {
+        self.then(BecomeMethod::Sudo, user)
+    }
+
+    /// Switches on from the last user to `user` with `su -`.
+    #[must_use]
+    pub fn then_su(self, user: impl Into<String>) -> Self {
+        self.then(BecomeMethod::Su, user)
+    }
+
+    /// Password to answer the last step's prompt with: the password of the
+    /// user switching for `sudo`, and of the user switched to for `su`.
+    #[must_use]
+    pub fn password(mut self, password: impl Into<SecretString>) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.password = Some(password.into());
+        }
+        self
+    }
+
+    /// Whether prompts are to be answered, on a pty.
+    pub(crate) fn needs_pty(&self) -> bool {
+        self.steps.iter().any(|step| step.password.is_some())
+    }
+
+    /// `command_line` run through every step, innermost last. Each step runs
+    /// the rest of the chain through the shell of the user it switches to.
+    pub(crate) fn wrap(&self, command_line: &str) -> String {
+        let mut line = if self.needs_pty() {
+            format!("printf '%s\\n' {}; {command_line}", self.sentinel)
+        } else {
+            command_line.to_string()
+        };
+
+        for (index, step) in self.steps.iter().enumerate().rev() {
+            line = match step.method {
+                BecomeMethod::Sudo => {
+                    let prompt = self.sudo_prompt(index);
+                    let mut words = vec!["sudo"];
+                    match &step.password {
+                        Some(_) => words.extend(["-p", &prompt]),
+                        None => words.push("-n"),
+                    }
+                    words.extend(["-H", "-u", &step.user, "--", "sh", "-c", &line]);
+                    shell::join(words)
+                }
+                BecomeMethod::Su => {
+                    shell::join(["env", "LC_ALL=C", "su", "-", &step.user, "-c", &line])
+                }
+            };
+        }
+
+        line
+    }
+
+    /// Prompt `sudo` is told to ask for the password of step `index` with,
+    /// which no other program prints.
+    fn sudo_prompt(&self, index: usize) -> String {
+        format!("[sudo {index}] {}: ", self.sentinel)
+    }
+
+    /// Answers the password prompts of the chain on `stdin` as they appear
+    /// on `stdout`, until the sentinel line shows that the command started.
+    /// Whatever comes before it, such as the prompts themselves and the
+    /// lecture `sudo` gives first-time users, is dropped. Returns what was
+    /// read after it.
+    ///
+    /// # Errors
+    ///
+    /// - If a password is refused, or output ends before the command starts, as
+    ///   [`Error::BecomeFailed`] with the last line of output.
+    /// - If reading stdout or writing stdin fails.
+    pub(crate) async fn answer_prompts(
+        &self,
+        stdin: &mut (impl AsyncWrite + Unpin),
+        stdout: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        // Steps before this one have been answered, or had nothing to answer.
+        let mut next = 0;
+
+        loop {
+            if let Some(rest) = self.after_sentinel(&buffer) {
+                return Ok(rest.to_vec());
+            }
+            if let Some(index) = self.prompted(&buffer, next)? {
+                let password = self.steps[index]
+                    .password
+                    .as_ref()
+                    .expect("only steps with a password are prompted for");
+                stdin.write_all(password.expose_secret().as_bytes()).await?;
+                stdin.write_all(b"\n").await?;
+                stdin.flush().await?;
+                buffer.clear();
+                next = index + 1;
+                continue;
+            }
+
+            let mut chunk = [0; READ_SIZE];
+            let read = stdout.read(&mut chunk).await?;
+            if read == 0 {
+                let reason = String::from_utf8_lossy(&buffer)
+                    .lines()
+                    .map(str::trim)
+                    .rfind(|line| !line.is_empty())
+                    .unwrap_or("output ended before the command started")
+                    .to_string();
+                return Err(self.failed(next.min(self.steps.len() - 1), reason));
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// What follows the sentinel's line in `buffer`, once it is complete.
+    fn after_sentinel<'b>(&self, buffer: &'b [u8]) -> Option<&'b [u8]> {
+        let mut start = 0;
+        for end in buffer
+            .iter()
+            .enumerate()
+            .filter_map(|(index, &byte)| (byte == b'\n').then_some(index))
+        {
+            let line = buffer[start..end]
+                .strip_suffix(b"\r")
+                .unwrap_or(&buffer[start..end]);
+            if line == self.sentinel.as_bytes() {
+                return Some(&buffer[end + 1..]);
+            }
+            start = end + 1;
+        }
+
+        None
+    }
+
+    /// Step whose password `buffer` ends asking for, if any. `sudo` asks
+    /// again after a wrong password, which is an error.
+    fn prompted(&self, buffer: &[u8], next: usize) -> Result<Option<usize>> {
+        for (index, step) in self.steps.iter().enumerate() {
+            if step.method == BecomeMethod::Sudo
+                && buffer.ends_with(self.sudo_prompt(index).as_bytes())
+            {
+                if index < next {
+                    return Err(self.failed(index, "incorrect password".to_string()));
+                }
+                return Ok(Some(index));
+            }
+        }
+
+        if buffer.ends_with(SU_PROMPT.as_bytes()) {
+            let mut su_steps = self
+                .steps
+                .iter()
+                .enumerate()
+                .skip(next)
+                .filter(|(_, step)| step.method == BecomeMethod::Su);
+            if let Some((index, _)) = su_steps.clone().find(|(_, step)| step.password.is_some()) {
+                return Ok(Some(index));
+            }
+            let index = su_steps
+                .next()
+                .map_or(self.steps.len() - 1, |(index, _)| index);
+            return Err(self.failed(index, "asked for a password that was not given".to_string()));
+        }
+
+        Ok(None)
+    }
+
+    fn failed(&self, index: usize, reason: String) -> Error {
+        Error::BecomeFailed {
+            user: self.steps[index].user.clone(),
+            reason,
+        }
+    }
+}
+
+impl From<&str> for Become {
+    fn from(user: &str) -> Self {
+        Self::sudo(user)
+    }
+}
+
+impl From<String> for Become {
+    fn from(user: String) -> Self {
+        Self::sudo(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::io::AsyncBufReadExt;
+
+    use super::*;
+
+    /// Chain with a fixed sentinel, so that command lines can be compared.
+    fn chain(chain: Become) -> Become {
+        Become {
+            sentinel: "sentinel".to_string(),
+            ..chain
+        }
+    }
+
+    #[rstest]
+    #[case(Become::sudo("postgres"), "sudo -n -H -u postgres -- sh -c 'id -un'")]
+    #[case(Become::su("postgres"), "env 'LC_ALL=C' su - postgres -c 'id -un'")]
+    #[case(
+        Become::sudo("root").then_su("postgres"),
+        "sudo -n -H -u root -- sh -c 'env '\\''LC_ALL=C'\\'' su - postgres -c '\\''id -un'\\'''"
+    )]
+    #[case(
+        Become::sudo("root").password("secret"),
+        "sudo -p '[sudo 0] sentinel: ' -H -u root -- sh -c 'printf '\\''%s\\n'\\'' sentinel; id -un'"
+    )]
+    fn wrap_works(#[case] become_user: Become, #[case] line_should: &str) {
+        assert_eq!(chain(become_user).wrap("id -un"), line_should);
+    }
+
+    /// Answers the prompts of `become_user` in `output`, which is written in
+    /// parts, waiting for an answer after each part ending like a prompt.
+    /// Returns the result and the answers.
+    async fn answer(become_user: Become, output: &[&[u8]]) -> (Result<Vec<u8>>, String) {
+        let become_user = chain(become_user);
+        let (mut writer, mut stdout) = tokio::io::duplex(64);
+        let (mut stdin, reader) = tokio::io::duplex(64);
+
+        let remote = async move {
+            let mut reader = tokio::io::BufReader::new(reader);
+            let mut answers = String::new();
+            for part in output {
+                writer.write_all(part).await.unwrap();
+                if part.ends_with(b": ") {
+                    reader.read_line(&mut answers).await.unwrap();
+                }
+            }
+            answers
+        };
+        let answering = async move { become_user.answer_prompts(&mut stdin, &mut stdout).await };
+        let (answers, result) = tokio::join!(remote, answering);
+
+        (result, answers)
+    }
+
+    #[rstest]
+    #[case(
+        Become::sudo("root").password("one").then_su("postgres").password("two"),
+        &[&b"[sudo 0] sentinel: "[..], b"\r\nPassword: ", b"\r\nsentinel\r\npostgres\r\n"],
+        "one\ntwo\n"
+    )]
+    #[case(
+        Become::sudo("root").password("one"),
+        &[&b"We trust you have received the usual lecture\r\n\r\n[sudo 0] sen"[..], b"tinel: ", b"\r\nsentinel\r\npostgres\r\n"],
+        "one\n"
+    )]
+    #[tokio::test]
+    async fn answer_prompts_works(
+        #[case] become_user: Become,
+        #[case] output: &[&[u8]],
+        #[case] answers_should: &str,
+    ) {
+        let (rest, answers) = answer(become_user, output).await;
+
+        assert_eq!(rest.unwrap(), b"postgres\r\n");
+        assert_eq!(answers, answers_should);
+    }
+
+    #[rstest]
+    #[case(
+        Become::sudo("root").password("wrong"),
+        &[&b"[sudo 0] sentinel: "[..], b"\r\nSorry, try again.\r\n[sudo 0] sentinel: "],
+        "root",
+        "incorrect password"
+    )]
+    #[case(
+        Become::sudo("root").then_su("postgres").password("wrong"),
+        &[&b"Password: "[..], b"\r\nsu: Authentication failure\r\n"],
+        "postgres",
+        "su: Authentication failure"
+    )]
+    #[case(
+        Become::sudo("root").then_su("postgres"),
+        &[&b"Password: "[..]],
+        "postgres",
+        "asked for a password that was not given"
+    )]
+    #[case(
+        Become::sudo("root").password("secret"),
+        &[],
+        "root",
+        "output ended before the command started"
+    )]
+    #[tokio::test]
+    async fn answer_prompts_fails(
+        #[case] become_user: Become,
+        #[case] output: &[&[u8]],
+        #[case] user_should: &str,
+        #[case] reason_should: &str,
+    ) {
+        let (result, _) = answer(become_user, output).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::BecomeFailed { user, reason })
+                if user == user_should && reason == reason_should
+        ));
+    }
+}
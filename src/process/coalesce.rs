@@ -0,0 +1,353 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::time::Duration;
+
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Bytes held at most by [`WriteStrategy::Coalesce`], a little more than the
+/// data one SSH packet usually carries.
+const COALESCE_MAX_BYTES: usize = 32 * 1024;
+/// Bytes and delay of the default strategy for commands without a pty, which
+/// merges writes made in quick succession at little cost to latency.
+const DEFAULT_THRESHOLD: WriteStrategy = WriteStrategy::Threshold {
+    bytes: COALESCE_MAX_BYTES,
+    delay: Duration::from_millis(2),
+};
+
+/// How writes to a command's stdin are grouped into SSH packets. Each packet
+/// carries a header and a MAC, so many small writes are cheaper sent
+/// together, while interactive programs need every keystroke sent at once.
+///
+/// Flushing or shutting down stdin sends what is held right away, whatever
+/// the strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStrategy {
+    /// Sends every write as soon as it is made. The default for commands on
+    /// a pty.
+    Immediate,
+    /// Holds writes for up to this long after the first, to send them
+    /// together.
+    Coalesce(Duration),
+    /// Holds writes until `bytes` are waiting or `delay` has passed since the
+    /// first, whichever comes first. The default for other commands, with
+    /// 32 KiB and 2 ms.
+    Threshold { bytes: usize, delay: Duration },
+}
+
+impl WriteStrategy {
+    /// Default strategy for a command, depending on whether it runs on a pty.
+    pub(crate) fn default_for(pty: bool) -> Self {
+        if pty {
+            WriteStrategy::Immediate
+        } else {
+            DEFAULT_THRESHOLD
+        }
+    }
+
+    /// Bytes and delay after which held writes are sent, or `None` if writes
+    /// are not held.
+    fn limits(self) -> Option<(usize, Duration)> {
+        match self {
+            WriteStrategy::Immediate => None,
+            WriteStrategy::Coalesce(delay) => Some((COALESCE_MAX_BYTES, delay)),
+            WriteStrategy::Threshold { bytes, delay } => Some((bytes.max(1), delay)),
+        }
+    }
+}
+
+type Writer = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// Wraps `inner` to group writes according to `strategy`. Held writes are
+/// sent by a task, so that they leave once the delay has passed even if
+/// nothing else is written.
+pub(crate) fn coalesce(inner: Writer, strategy: WriteStrategy) -> Writer {
+    let Some((bytes, delay)) = strategy.limits() else {
+        return inner;
+    };
+
+    let shared = Arc::new(Shared::default());
+    tokio::spawn(pump(inner, shared.clone(), bytes, delay));
+
+    Box::pin(Coalesced { shared, bytes })
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Wakes the pump when there is something for it to do.
+    pending: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    buffer: Vec<u8>,
+    /// When the oldest byte in `buffer` was written.
+    since: Option<Instant>,
+    /// Bytes accepted from the writer and bytes sent by the pump, which are
+    /// equal once everything written has been sent.
+    written: u64,
+    sent: u64,
+    flush: bool,
+    shutdown: bool,
+    /// Set by the pump once it has shut down the inner writer or failed.
+    closed: bool,
+    /// Kind and message of the error the pump failed with, since
+    /// [`io::Error`] cannot be cloned for every later call.
+    error: Option<(io::ErrorKind, String)>,
+    waker: Option<Waker>,
+}
+
+impl State {
+    fn error(&self) -> Option<io::Error> {
+        self.error
+            .as_ref()
+            .map(|(kind, message)| io::Error::new(*kind, message.clone()))
+    }
+
+    /// Whether held writes are due to be sent.
+    fn due(&self, bytes: usize, delay: Duration) -> bool {
+        !self.buffer.is_empty()
+            && (self.flush
+                || self.shutdown
+                || self.buffer.len() >= bytes
+                || self.since.is_some_and(|since| since.elapsed() >= delay))
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Writer half, which only fills the buffer for the pump to send.
+struct Coalesced {
+    shared: Arc<Shared>,
+    bytes: usize,
+}
+
+impl Coalesced {
+    /// Leaves the caller to be woken once the pump has made progress.
+    fn park(&self, state: &mut State, cx: &Context<'_>) -> Poll<io::Result<()>> {
+        state.waker = Some(cx.waker().clone());
+        self.shared.pending.notify_one();
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for Coalesced {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(error) = state.error() {
+            return Poll::Ready(Err(error));
+        }
+        if state.shutdown {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stdin is shut down",
+            )));
+        }
+        // Full until the pump takes the buffer, which it does once it
+        // reaches `bytes`.
+        let room = self.bytes.saturating_sub(state.buffer.len());
+        if room == 0 {
+            return self.park(&mut state, cx).map_ok(|()| 0);
+        }
+
+        let len = buf.len().min(room);
+        state.buffer.extend_from_slice(&buf[..len]);
+        state.since.get_or_insert_with(Instant::now);
+        state.written += len as u64;
+        self.shared.pending.notify_one();
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(error) = state.error() {
+            return Poll::Ready(Err(error));
+        }
+        if state.sent == state.written {
+            return Poll::Ready(Ok(()));
+        }
+
+        state.flush = true;
+        self.park(&mut state, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(error) = state.error() {
+            return Poll::Ready(Err(error));
+        }
+        if state.closed {
+            return Poll::Ready(Ok(()));
+        }
+
+        state.shutdown = true;
+        self.park(&mut state, cx)
+    }
+}
+
+impl Drop for Coalesced {
+    /// Lets the pump send what is held, then shut down the inner writer,
+    /// instead of waiting forever.
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.pending.notify_one();
+    }
+}
+
+/// What the pump does next.
+enum Step {
+    Send(Vec<u8>),
+    Shutdown,
+    /// Waits to be notified, or until held writes are due.
+    Wait(Option<Instant>),
+}
+
+/// Sends held writes to `inner` whenever they are due, until shut down.
+async fn pump(mut inner: Writer, shared: Arc<Shared>, bytes: usize, delay: Duration) {
+    let result = async {
+        loop {
+            let step = {
+                let mut state = shared.state.lock().unwrap();
+                if state.due(bytes, delay) {
+                    state.since = None;
+                    state.flush = false;
+                    Step::Send(std::mem::take(&mut state.buffer))
+                } else if state.buffer.is_empty() && state.shutdown {
+                    Step::Shutdown
+                } else {
+                    Step::Wait(state.since.map(|since| since + delay))
+                }
+            };
+
+            let data = match step {
+                Step::Send(data) => data,
+                Step::Shutdown => return inner.shutdown().await,
+                Step::Wait(Some(deadline)) => {
+                    let _ = tokio::time::timeout_at(deadline, shared.pending.notified()).await;
+                    continue;
+                }
+                Step::Wait(None) => {
+                    shared.pending.notified().await;
+                    continue;
+                }
+            };
+            inner.write_all(&data).await?;
+            inner.flush().await?;
+
+            let mut state = shared.state.lock().unwrap();
+            state.sent += data.len() as u64;
+            state.wake();
+        }
+    }
+    .await;
+
+    let mut state = shared.state.lock().unwrap();
+    if let Err(error) = result {
+        state.error = Some((error.kind(), error.to_string()));
+    }
+    state.closed = true;
+    state.wake();
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    fn coalesced(strategy: WriteStrategy) -> (Writer, DuplexStream) {
+        let (writer, reader) = tokio::io::duplex(1024);
+        (coalesce(Box::pin(writer), strategy), reader)
+    }
+
+    /// Reads what is available within a short time.
+    async fn read_now(reader: &mut DuplexStream) -> Vec<u8> {
+        let mut buf = vec![0; 1024];
+        match tokio::time::timeout(Duration::from_millis(20), reader.read(&mut buf)).await {
+            Ok(read) => buf.truncate(read.unwrap()),
+            Err(_) => buf.clear(),
+        }
+        buf
+    }
+
+    #[tokio::test]
+    async fn coalesce_sends_after_delay() {
+        let (mut writer, mut reader) =
+            coalesced(WriteStrategy::Coalesce(Duration::from_millis(100)));
+
+        writer.write_all(b"he").await.unwrap();
+        writer.write_all(b"llo").await.unwrap();
+
+        assert_eq!(read_now(&mut reader).await, b"");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(read_now(&mut reader).await, b"hello");
+    }
+
+    #[tokio::test]
+    async fn threshold_sends_once_reached() {
+        let (mut writer, mut reader) = coalesced(WriteStrategy::Threshold {
+            bytes: 4,
+            delay: Duration::from_mins(1),
+        });
+
+        writer.write_all(b"he").await.unwrap();
+        assert_eq!(read_now(&mut reader).await, b"");
+        writer.write_all(b"llo").await.unwrap();
+
+        assert_eq!(read_now(&mut reader).await, b"hell");
+    }
+
+    #[tokio::test]
+    async fn flush_sends_held_writes() {
+        let (mut writer, mut reader) = coalesced(WriteStrategy::Coalesce(Duration::from_mins(1)));
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.flush().await.unwrap();
+
+        assert_eq!(read_now(&mut reader).await, b"hello");
+    }
+
+    #[tokio::test]
+    async fn shutdown_sends_held_writes_then_eof() {
+        let (mut writer, mut reader) = coalesced(WriteStrategy::Coalesce(Duration::from_mins(1)));
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+
+        assert_eq!(read, b"hello");
+        let err = writer.write_all(b"late").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn pump_errors_are_reported() {
+        let (mut writer, reader) = coalesced(WriteStrategy::Coalesce(Duration::from_mins(1)));
+        drop(reader);
+
+        writer.write_all(b"hello").await.unwrap();
+        let err = writer.flush().await.unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert!(writer.write_all(b"again").await.is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use std::time::Instant;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+
+/// Snapshot of the data flowing through a command's channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChannelStats {
+    /// Bytes written to stdin.
+    pub sent: u64,
+    /// Bytes of stdout and stderr received from the server.
+    pub received: u64,
+    /// Bytes of stdout and stderr received but not read yet. Once a stream's
+    /// buffer is full, the server is not allowed to send more until it is
+    /// read.
+    pub unread: u64,
+    /// How long the channel has been waiting for stdout or stderr to be read,
+    /// or `None` if it is not.
+    pub stalled_for: Option<Duration>,
+}
+
+/// Counters shared between a [`Child`](super::Child) and the task copying
+/// its output.
+#[derive(Debug, Default)]
+pub(crate) struct Flow {
+    sent: AtomicU64,
+    received: AtomicU64,
+    read: AtomicU64,
+    stalled_since: Mutex<Option<Instant>>,
+}
+
+impl Flow {
+    pub(crate) fn stats(&self) -> ChannelStats {
+        let received = self.received.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Relaxed);
+
+        ChannelStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            received,
+            unread: received.saturating_sub(read),
+            stalled_for: self
+                .stalled_since
+                .lock()
+                .unwrap()
+                .map(|since| since.elapsed()),
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    pub(crate) fn add_received(&self, len: usize) {
+        self.received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Marks the channel as waiting for output to be read, until
+    /// [`Flow::resume`].
+    #[cfg(feature = "russh")]
+    pub(crate) fn stall(&self) {
+        *self.stalled_since.lock().unwrap() = Some(Instant::now());
+    }
+
+    #[cfg(feature = "russh")]
+    pub(crate) fn resume(&self) {
+        *self.stalled_since.lock().unwrap() = None;
+    }
+}
+
+/// Which counter of a [`Flow`] a [`Counted`] stream adds to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Sent,
+    Read,
+}
+
+/// Stream that counts the bytes passing through it.
+pub(crate) struct Counted<T> {
+    inner: T,
+    flow: Arc<Flow>,
+    direction: Direction,
+}
+
+impl<T> Counted<T> {
+    pub(crate) fn new(inner: T, flow: Arc<Flow>, direction: Direction) -> Self {
+        Self {
+            inner,
+            flow,
+            direction,
+        }
+    }
+
+    fn count(&self, len: usize) {
+        let counter = match self.direction {
+            Direction::Sent => &self.flow.sent,
+            Direction::Read => &self.flow.read,
+        };
+        counter.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Counted<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.count(buf.filled().len() - before);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Counted<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.count(len);
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stats_works() {
+        let flow = Arc::new(Flow::default());
+        let (writer, reader) = tokio::io::duplex(64);
+        let mut writer = Counted::new(writer, flow.clone(), Direction::Sent);
+        let mut reader = Counted::new(reader, flow.clone(), Direction::Read);
+
+        writer.write_all(b"hello").await.unwrap();
+        flow.add_received(5);
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).await.unwrap();
+        flow.stall();
+
+        let stats = flow.stats();
+        assert_eq!(stats.sent, 5);
+        assert_eq!(stats.received, 5);
+        assert_eq!(stats.unread, 3);
+        assert!(stats.stalled_for.is_some());
+
+        flow.resume();
+        assert_eq!(flow.stats().stalled_for, None);
+    }
+}
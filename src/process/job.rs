@@ -0,0 +1,207 @@
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::shell;
+
+/// Command started with [`Command::detach`](super::Command::detach), which
+/// keeps running after the session ends. Only its PID and output path are
+/// needed to find it again, so it can be checked on from a later session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pid: u32,
+    output: Utf8PathBuf,
+}
+
+/// State of a detached [`Job`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    /// The command exited with this code.
+    Exited(u32),
+    /// The command is not running but left no exit code, usually because it
+    /// was killed along with its wrapper.
+    Vanished,
+}
+
+impl Job {
+    /// Refers to a job started earlier, possibly from another session.
+    pub fn new(pid: u32, output: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            pid,
+            output: output.into(),
+        }
+    }
+
+    /// Script that starts `command_line` detached, writing its output to
+    /// `output` and its exit code next to it, and prints its PID.
+    ///
+    /// `setsid` puts the job in its own session and process group, so that
+    /// [`Job::kill`] reaches everything it started; `nohup` is the fallback
+    /// where it is missing.
+    pub(crate) fn launcher(command_line: &str, output: &Utf8Path) -> String {
+        let status = status_file(output);
+        let inner = format!(
+            "{command_line}; echo $? > {}",
+            shell::quote(status.as_str())
+        );
+
+        format!(
+            "if command -v setsid >/dev/null 2>&1; then detach=setsid; else detach=nohup; fi; \
+             rm -f -- {status}; \
+             $detach sh -c {inner} > {output} 2>&1 < /dev/null & echo $!",
+            status = shell::quote(status.as_str()),
+            inner = shell::quote(&inner),
+            output = shell::quote(output.as_str()),
+        )
+    }
+
+    /// Remote file the job's exit code is written to once it exits.
+    pub(crate) fn status_file(&self) -> Utf8PathBuf {
+        status_file(&self.output)
+    }
+
+    /// Process ID of the job on the remote host.
+    #[must_use]
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Remote file the job's stdout and stderr are written to.
+    #[must_use]
+    pub fn output(&self) -> &Utf8Path {
+        &self.output
+    }
+
+    /// Whether the job is still running, and if not, how it exited. A job
+    /// that vanished long ago may be reported as running if its PID has been
+    /// reused since.
+    ///
+    /// # Errors
+    ///
+    /// - If the status cannot be queried.
+    pub async fn status(&self, session: &ConnectedSession) -> Result<JobStatus> {
+        // Zombies count as gone, since nothing may be left to reap them.
+        let script = format!(
+            "if [ -f {status} ]; then cat {status}; \
+             else case $(ps -o stat= -p {pid} 2>/dev/null) in \
+             ''|Z*) echo vanished ;; *) echo running ;; esac; fi",
+            pid = self.pid,
+            status = shell::quote(self.status_file().as_str()),
+        );
+        let mut command = session.command("sh");
+        command.args(["-c", &script]);
+        let output = command.output().await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        match stdout.trim() {
+            // The exit code file exists but is not written yet.
+            "running" | "" => Ok(JobStatus::Running),
+            "vanished" => Ok(JobStatus::Vanished),
+            code => code
+                .parse()
+                .map(JobStatus::Exited)
+                .map_err(|_| Error::UnexpectedOutput {
+                    command: command.command_line(),
+                    output: stdout.to_string(),
+                }),
+        }
+    }
+
+    /// Sends `signal`, like `TERM` or `KILL`, to the job and every process
+    /// it started.
+    ///
+    /// # Errors
+    ///
+    /// - If the job is not running.
+    /// - If the signal cannot be sent.
+    pub async fn kill(&self, session: &ConnectedSession, signal: &str) -> Result<()> {
+        let script = format!(
+            "kill -s {signal} -- -{pid} 2>/dev/null || kill -s {signal} {pid}",
+            signal = shell::quote(signal),
+            pid = self.pid,
+        );
+        let mut command = session.command("sh");
+        command.args(["-c", &script]);
+        let status = command.status().await?;
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// File the exit code of a job writing to `output` is written to.
+fn status_file(output: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{output}.status"))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "russh")]
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn launcher_quotes_paths() {
+        let script = Job::launcher("echo hi", Utf8Path::new("my job.log"));
+
+        assert!(script.contains("> 'my job.log' 2>&1"));
+        assert!(script.contains(r"sh -c 'echo hi; echo $? > '\''my job.log.status'\'''"));
+    }
+
+    #[cfg(feature = "russh")]
+    async fn wait_until_stopped(job: &Job, session: &ConnectedSession) -> JobStatus {
+        loop {
+            let status = job.status(session).await.unwrap();
+            if status != JobStatus::Running {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn detach_works() {
+        let session = crate::test_server::connect().await;
+
+        let job = session
+            .command("sh")
+            .args(["-c", "echo hello; exit 3"])
+            .detach("job.log")
+            .await
+            .unwrap();
+
+        // Found again from what a caller would persist.
+        let job = Job::new(job.pid(), job.output());
+        let status = wait_until_stopped(&job, &session).await;
+        assert_eq!(status, JobStatus::Exited(3));
+        assert_eq!(session.fs().read(job.output()).await.unwrap(), b"hello\n");
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn kill_works() {
+        let session = crate::test_server::connect().await;
+        let job = session
+            .command("sleep")
+            .arg("60")
+            .detach("sleep.log")
+            .await
+            .unwrap();
+        assert_eq!(job.status(&session).await.unwrap(), JobStatus::Running);
+
+        job.kill(&session, "TERM").await.unwrap();
+
+        let status = wait_until_stopped(&job, &session).await;
+        assert_ne!(status, JobStatus::Exited(0));
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+/// I/O scheduling class and priority, as set by `ionice`. Priorities range
+/// from 0, the highest, to 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Served before anything else. Requires root.
+    Realtime(u8),
+    BestEffort(u8),
+    /// Served only when no other process needs the disk.
+    Idle,
+}
+
+/// Resource limit applied with the shell's `ulimit` builtin. Both the soft
+/// and the hard limit are set, so the command cannot raise it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Maximum number of open file descriptors.
+    OpenFiles(u64),
+    /// Maximum CPU time, rounded down to whole seconds.
+    CpuTime(Duration),
+    /// Maximum size of the address space in bytes, rounded down to KiB.
+    VirtualMemory(u64),
+    /// Disables core dumps.
+    NoCoreDumps,
+}
+
+impl Limit {
+    /// `ulimit` invocation. Options that `bash` and `dash` agree on only, one
+    /// per invocation as `dash` ignores the rest.
+    fn command(self) -> String {
+        match self {
+            Limit::OpenFiles(files) => format!("ulimit -n {files}"),
+            Limit::CpuTime(time) => format!("ulimit -t {}", time.as_secs()),
+            Limit::VirtualMemory(bytes) => format!("ulimit -v {}", bytes / 1024),
+            Limit::NoCoreDumps => "ulimit -c 0".to_string(),
+        }
+    }
+}
+
+/// Restrictions on the resources a remote command may use, applied by
+/// wrapping it in other programs.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Resources {
+    pub(crate) nice: Option<i8>,
+    pub(crate) ionice: Option<IoPriority>,
+    pub(crate) cpu_limit: Option<u32>,
+    pub(crate) limits: Vec<Limit>,
+}
+
+impl Resources {
+    /// Words to run the command after, each its own argument so that they
+    /// can be quoted like the command's.
+    pub(crate) fn prefix(&self) -> Vec<String> {
+        let mut words = Vec::new();
+
+        if !self.limits.is_empty() {
+            let limits: Vec<String> = self.limits.iter().map(|limit| limit.command()).collect();
+            words.extend([
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("{} && exec \"$@\"", limits.join(" && ")),
+                "sh".to_string(),
+            ]);
+        }
+        if let Some(nice) = self.nice {
+            words.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+        if let Some(ionice) = self.ionice {
+            let (class, priority) = match ionice {
+                IoPriority::Realtime(priority) => (1, Some(priority)),
+                IoPriority::BestEffort(priority) => (2, Some(priority)),
+                IoPriority::Idle => (3, None),
+            };
+            words.extend(["ionice".to_string(), "-c".to_string(), class.to_string()]);
+            if let Some(priority) = priority {
+                words.extend(["-n".to_string(), priority.min(7).to_string()]);
+            }
+        }
+        if let Some(percent) = self.cpu_limit {
+            words.extend([
+                "cpulimit".to_string(),
+                "-l".to_string(),
+                percent.to_string(),
+                "--".to_string(),
+            ]);
+        }
+
+        words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Resources::default(), "")]
+    #[case(Resources { nice: Some(10), ..Default::default() }, "nice -n 10")]
+    #[case(Resources { nice: Some(-5), ionice: Some(IoPriority::Idle), ..Default::default() }, "nice -n -5 ionice -c 3")]
+    #[case(Resources { ionice: Some(IoPriority::BestEffort(9)), ..Default::default() }, "ionice -c 2 -n 7")]
+    #[case(Resources { cpu_limit: Some(50), ..Default::default() }, "cpulimit -l 50 --")]
+    #[case(
+        Resources {
+            limits: vec![Limit::OpenFiles(64), Limit::VirtualMemory(1 << 30), Limit::NoCoreDumps],
+            ..Default::default()
+        },
+        "sh -c 'ulimit -n 64 && ulimit -v 1048576 && ulimit -c 0 && exec \"$@\"' sh"
+    )]
+    fn prefix_works(#[case] resources: Resources, #[case] prefix_should: &str) {
+        let prefix = resources.prefix();
+
+        assert_eq!(
+            crate::shell::join(prefix.iter().map(String::as_str)),
+            prefix_should
+        );
+    }
+}
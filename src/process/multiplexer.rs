@@ -0,0 +1,143 @@
+use super::Child;
+use super::Pty;
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::shell;
+
+/// Terminal multiplexer that keeps interactive sessions running on the remote
+/// host while no client is attached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+impl Multiplexer {
+    /// Script that creates a detached session called `name` unless it exists.
+    fn ensure_script(self, name: &str) -> String {
+        let name = shell::quote(name);
+        match self {
+            // Checked again if creating fails, in case another client created
+            // it in the meantime.
+            Multiplexer::Tmux => format!(
+                "tmux has-session -t ={name} 2>/dev/null || tmux new-session -d -s {name} || \
+                 tmux has-session -t ={name}"
+            ),
+            Multiplexer::Screen => {
+                format!("screen -S {name} -X select . >/dev/null 2>&1 || screen -dmS {name}")
+            }
+        }
+    }
+
+    /// Arguments that attach to the session called `name` without detaching
+    /// other clients.
+    fn attach_args(self, name: &str) -> Vec<String> {
+        match self {
+            Multiplexer::Tmux => vec![
+                "tmux".to_string(),
+                "attach-session".to_string(),
+                "-t".to_string(),
+                format!("={name}"),
+            ],
+            Multiplexer::Screen => vec!["screen".to_string(), "-x".to_string(), name.to_string()],
+        }
+    }
+}
+
+impl ConnectedSession {
+    /// Creates a detached session called `name` in `multiplexer` unless one
+    /// exists, for example to start work that must survive disconnects.
+    ///
+    /// # Errors
+    ///
+    /// - If the multiplexer is not installed or cannot create the session.
+    pub async fn ensure_multiplexer_session(
+        &self,
+        multiplexer: Multiplexer,
+        name: &str,
+    ) -> Result<()> {
+        let mut command = self.command("sh");
+        command.args(["-c", &multiplexer.ensure_script(name)]);
+        let status = command.status().await?;
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Attaches an interactive terminal on `pty` to the session called `name`
+    /// in `multiplexer`, creating it first unless it exists. Whatever runs in
+    /// the session keeps running when the returned child is dropped or the
+    /// connection is lost, and the next call attaches to it again.
+    ///
+    /// Write keystrokes to the child's stdin and read the screen from its
+    /// stdout, as a terminal emulator would.
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as
+    ///   [`ConnectedSession::ensure_multiplexer_session`].
+    /// - If the server refuses to open a channel.
+    pub async fn attach_multiplexer_session(
+        &self,
+        multiplexer: Multiplexer,
+        name: &str,
+        pty: Pty,
+    ) -> Result<Child> {
+        self.ensure_multiplexer_session(multiplexer, name).await?;
+
+        let args = multiplexer.attach_args(name);
+        self.command(&args[0])
+            .args(&args[1..])
+            .pty(pty)
+            .spawn()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Multiplexer::Tmux, "tmux attach-session -t '=my work'")]
+    #[case(Multiplexer::Screen, "screen -x 'my work'")]
+    fn attach_args_works(#[case] multiplexer: Multiplexer, #[case] command_line_should: &str) {
+        let args = multiplexer.attach_args("my work");
+
+        assert_eq!(
+            shell::join(args.iter().map(String::as_str)),
+            command_line_should
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn ensure_multiplexer_session_works() {
+        let session = crate::test_server::connect().await;
+        if session.which("tmux").await.unwrap().is_none() {
+            return;
+        }
+        let name = format!("ssh-util-test-{}", std::process::id());
+
+        session
+            .ensure_multiplexer_session(Multiplexer::Tmux, &name)
+            .await
+            .unwrap();
+        session
+            .ensure_multiplexer_session(Multiplexer::Tmux, &name)
+            .await
+            .unwrap();
+
+        let mut kill = session.command("tmux");
+        kill.args(["kill-session", "-t", &format!("={name}")]);
+        assert!(kill.spawn().await.unwrap().wait().await.unwrap().success());
+    }
+}
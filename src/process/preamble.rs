@@ -0,0 +1,143 @@
+use std::hash::BuildHasher;
+use std::hash::RandomState;
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+
+use tokio::io::AsyncRead;
+use tokio::io::ReadBuf;
+
+/// Bytes read at a time while looking for the sentinel.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Line unlikely to be printed by anything but the wrapper of one command.
+pub(crate) fn sentinel() -> String {
+    format!(
+        "ssh-util-preamble-{:016x}",
+        RandomState::new().hash_one("preamble")
+    )
+}
+
+/// Reader dropping everything up to and including the line holding
+/// `sentinel`, such as a message of the day or output of the remote user's
+/// shell profile. If the stream ends before the sentinel, which happens when
+/// the shell fails before running the command, everything read is kept, since
+/// it is likely to explain why.
+pub(crate) struct StripPreamble<R> {
+    inner: R,
+    sentinel: Vec<u8>,
+    /// Read while looking for the sentinel, or left over after it, waiting
+    /// to be returned.
+    buffer: Vec<u8>,
+    /// Whether the sentinel has been found, or the stream has ended.
+    done: bool,
+}
+
+impl<R> StripPreamble<R> {
+    pub(crate) fn new(inner: R, sentinel: &str) -> Self {
+        Self {
+            inner,
+            sentinel: sentinel.as_bytes().to_vec(),
+            buffer: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Drops the preamble if the sentinel's line is complete in `buffer`.
+    fn strip(&mut self) -> bool {
+        let Some(start) = self
+            .buffer
+            .windows(self.sentinel.len())
+            .position(|window| window == self.sentinel)
+        else {
+            return false;
+        };
+        let Some(end) = self.buffer[start..].iter().position(|&byte| byte == b'\n') else {
+            return false;
+        };
+
+        self.buffer.drain(..=start + end);
+        true
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for StripPreamble<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while !this.done {
+            let mut chunk = [0; READ_SIZE];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                this.done = true;
+            } else {
+                this.buffer.extend_from_slice(chunk.filled());
+                this.done = this.strip();
+            }
+        }
+
+        if this.buffer.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let len = this.buffer.len().min(buf.remaining());
+        buf.put_slice(&this.buffer[..len]);
+        this.buffer.drain(..len);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[rstest]
+    #[case(b"Welcome!\nsentinel\nhello\n", b"hello\n")]
+    #[case(b"sentinel\r\nhello\r\n", b"hello\r\n")]
+    #[case(b"sentinel\n", b"")]
+    #[case(b"bash: cd: missing\n", b"bash: cd: missing\n")]
+    #[tokio::test]
+    async fn strip_preamble_works(#[case] output: &[u8], #[case] stripped_should: &[u8]) {
+        let mut reader = StripPreamble::new(output, "sentinel");
+
+        let mut stripped = Vec::new();
+        reader.read_to_end(&mut stripped).await.unwrap();
+
+        assert_eq!(stripped, stripped_should);
+    }
+
+    #[tokio::test]
+    async fn strip_preamble_waits_for_sentinel_line() {
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let mut reader = StripPreamble::new(reader, "sentinel");
+
+        let feed = async {
+            for part in [&b"motd\nsenti"[..], b"nel", b"\nhel", b"lo"] {
+                tokio::io::AsyncWriteExt::write_all(&mut writer, part)
+                    .await
+                    .unwrap();
+                tokio::task::yield_now().await;
+            }
+            drop(writer);
+        };
+        let mut stripped = Vec::new();
+        let ((), read) = tokio::join!(feed, reader.read_to_end(&mut stripped));
+        read.unwrap();
+
+        assert_eq!(stripped, b"hello");
+    }
+
+    #[test]
+    fn sentinel_is_unique() {
+        assert_ne!(sentinel(), sentinel());
+    }
+}
@@ -0,0 +1,266 @@
+use camino::Utf8Path;
+
+use super::ExitStatus;
+use super::Output;
+use super::preamble;
+use crate::ConnectedSession;
+use crate::Result;
+use crate::shell;
+
+/// Output of a script run by [`ConnectedSession::run_powershell`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerShellOutput {
+    /// Exit status of PowerShell: the code the script exited with, if it
+    /// ended with `exit`, and otherwise 1 if its last command failed and 0 if
+    /// not.
+    pub status: ExitStatus,
+    /// `$LASTEXITCODE` once the script ended, which is the exit code of the
+    /// last program it ran. `None` if it ran none, or ended with `exit`.
+    pub last_exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl ConnectedSession {
+    /// Runs `script` with Windows PowerShell, passed as an encoded command so
+    /// that it needs no quoting whatever the login shell is, and collects its
+    /// output. Stdin is empty. Progress records, which PowerShell would write
+    /// to stderr as CLIXML, are not shown.
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses to open a channel.
+    /// - For the same reasons as
+    ///   [`Child::wait_with_output`](super::Child::wait_with_output).
+    pub async fn run_powershell(&self, script: &str) -> Result<PowerShellOutput> {
+        let sentinel = preamble::sentinel();
+        let command_line = shell::powershell(&with_trailer(script, &sentinel));
+        let output = self
+            .inner
+            .exec("powershell", &command_line, None)
+            .await?
+            .wait_with_output()
+            .await?;
+
+        Ok(without_trailer(output, &sentinel))
+    }
+}
+
+/// `script` followed by commands printing a line with `sentinel` and
+/// `$LASTEXITCODE`, and exiting with 1 if the script's last command failed.
+fn with_trailer(script: &str, sentinel: &str) -> String {
+    // The script may end with a comment or without a newline.
+    format!(
+        "$ProgressPreference = 'SilentlyContinue'\n\
+         {script}\n\
+         $succeeded = $?\n\
+         Write-Output \"{sentinel} $LASTEXITCODE\"\n\
+         if (-not $succeeded) {{ exit 1 }}"
+    )
+}
+
+/// `output` with the line printed by the trailer of [`with_trailer`] taken
+/// out of stdout and parsed.
+fn without_trailer(output: Output, sentinel: &str) -> PowerShellOutput {
+    let Output {
+        status,
+        mut stdout,
+        stderr,
+    } = output;
+    let trailer = stdout
+        .windows(sentinel.len())
+        .rposition(|window| window == sentinel.as_bytes())
+        .filter(|&start| start == 0 || stdout[start - 1] == b'\n');
+
+    let mut last_exit_code = None;
+    if let Some(start) = trailer {
+        last_exit_code = String::from_utf8_lossy(&stdout[start + sentinel.len()..])
+            .trim()
+            .parse()
+            .ok();
+        stdout.truncate(start);
+    }
+
+    PowerShellOutput {
+        status,
+        last_exit_code,
+        stdout,
+        stderr,
+    }
+}
+
+/// Command line running `program` with `args` through `cmd.exe`, after
+/// changing to `current_dir` if set and setting `env`.
+pub(super) fn cmd_line(
+    program: &str,
+    args: &[String],
+    env: &[(String, String)],
+    current_dir: Option<&Utf8Path>,
+) -> String {
+    let mut commands = Vec::new();
+    if let Some(dir) = current_dir {
+        commands.push(format!("cd /d {}", shell::quote_cmd(dir.as_str())));
+    }
+    for (key, value) in env {
+        // `set "KEY=value"` ends the value at the closing quote, so that the
+        // space before `&&` is not part of it.
+        let assignment = format!("\"{key}={value}\"");
+        commands.push(format!("set {}", shell::escape_cmd(&assignment)));
+    }
+    // `cmd.exe` finds the program name before removing escapes, so only real
+    // quotes keep one with spaces whole.
+    let program = if program.contains([' ', '\t']) {
+        format!("\"{program}\"")
+    } else {
+        shell::escape_cmd(program)
+    };
+    commands.push(
+        std::iter::once(program)
+            .chain(args.iter().map(|arg| shell::quote_cmd(arg)))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+
+    let line = commands.join(" && ");
+    // `cmd /c` drops the first and last quote of a line starting with one.
+    if line.starts_with('"') {
+        format!("\"{line}\"")
+    } else {
+        line
+    }
+}
+
+/// PowerShell script running `program` with `args`, after changing to
+/// `current_dir` if set and setting `env`. The script exits with the
+/// program's exit code, or 1 if the program is a cmdlet or function that
+/// fails.
+pub(super) fn powershell_script(
+    program: &str,
+    args: &[String],
+    env: &[(String, String)],
+    current_dir: Option<&Utf8Path>,
+) -> String {
+    // Progress would otherwise be written to stderr as CLIXML.
+    let mut lines = vec!["$ProgressPreference = 'SilentlyContinue'".to_string()];
+    if let Some(dir) = current_dir {
+        lines.push(format!(
+            "Set-Location -LiteralPath {} -ErrorAction Stop",
+            shell::quote_powershell(dir.as_str())
+        ));
+    }
+    for (key, value) in env {
+        lines.push(format!(
+            "[Environment]::SetEnvironmentVariable({}, {})",
+            shell::quote_powershell(key),
+            shell::quote_powershell(value)
+        ));
+    }
+    lines.push(
+        std::iter::once("&".to_string())
+            .chain(
+                std::iter::once(program)
+                    .chain(args.iter().map(String::as_str))
+                    .map(shell::quote_powershell),
+            )
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    lines.push("$succeeded = $?".to_string());
+    lines.push("if ($LASTEXITCODE) { exit $LASTEXITCODE }".to_string());
+    lines.push("if (-not $succeeded) { exit 1 }".to_string());
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(ToString::to_string).collect()
+    }
+
+    #[rstest]
+    #[case("whoami", &[], &[], None, "whoami")]
+    #[case(
+        "findstr",
+        &["a b", "%PATH%"],
+        &[("GREETING", "hi & bye")],
+        Some("C:\\Users\\deploy\\my app"),
+        r#"cd /d ^"C:\Users\deploy\my app^" && set ^"GREETING=hi ^& bye^" && findstr ^"a b^" ^%PATH^%"#
+    )]
+    #[case(
+        "C:\\Program Files\\Tool\\tool.exe",
+        &["-v"],
+        &[],
+        None,
+        r#"""C:\Program Files\Tool\tool.exe" -v""#
+    )]
+    fn cmd_line_works(
+        #[case] program: &str,
+        #[case] args: &[&str],
+        #[case] env: &[(&str, &str)],
+        #[case] current_dir: Option<&str>,
+        #[case] line_should: &str,
+    ) {
+        let env: Vec<(String, String)> = env
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let line = cmd_line(
+            program,
+            &strings(args),
+            &env,
+            current_dir.map(Utf8Path::new),
+        );
+
+        assert_eq!(line, line_should);
+    }
+
+    #[rstest]
+    #[case(b"done\r\nsentinel 3\r\n", b"done\r\n", Some(3))]
+    #[case(b"sentinel \r\n", b"", None)]
+    #[case(b"sentinel -1073741819\r\n", b"", Some(-1_073_741_819))]
+    #[case(b"exited early\r\n", b"exited early\r\n", None)]
+    #[case(b"echo sentinel 3\r\n", b"echo sentinel 3\r\n", None)]
+    fn without_trailer_works(
+        #[case] stdout: &[u8],
+        #[case] stdout_should: &[u8],
+        #[case] last_exit_code_should: Option<i32>,
+    ) {
+        let output = Output {
+            status: ExitStatus::from_code(0),
+            stdout: stdout.to_vec(),
+            stderr: Vec::new(),
+        };
+
+        let output = without_trailer(output, "sentinel");
+
+        assert_eq!(output.stdout, stdout_should);
+        assert_eq!(output.last_exit_code, last_exit_code_should);
+    }
+
+    #[test]
+    fn powershell_script_works() {
+        let script = powershell_script(
+            "Get-ChildItem",
+            &strings(&["-Name", "it's"]),
+            &[("GREETING".to_string(), "$hi".to_string())],
+            Some(Utf8Path::new("C:/Users/deploy")),
+        );
+
+        assert_eq!(
+            script,
+            "$ProgressPreference = 'SilentlyContinue'\n\
+             Set-Location -LiteralPath 'C:/Users/deploy' -ErrorAction Stop\n\
+             [Environment]::SetEnvironmentVariable('GREETING', '$hi')\n\
+             & 'Get-ChildItem' '-Name' 'it''s'\n\
+             $succeeded = $?\n\
+             if ($LASTEXITCODE) { exit $LASTEXITCODE }\n\
+             if (-not $succeeded) { exit 1 }"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::platform::Platform;
+
+/// Script listing processes on Windows, one per line with tab-separated
+/// fields.
+const GET_PROCESS: &str = "Get-Process | ForEach-Object { \"{0}`t{1}`t{2}`t{3}\" -f $_.Id, $_.WorkingSet64, $_.ProcessName, $_.Path }";
+
+/// Process running on a remote host, as listed by
+/// [`ConnectedSession::processes`]. Fields a platform does not report are
+/// `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Process that started this one. Not reported on Windows.
+    pub parent_pid: Option<u32>,
+    /// User the process runs as, or their ID if `ps` does not fit the name.
+    /// Not reported on Windows, where it takes administrator rights.
+    pub user: Option<String>,
+    /// Resident memory, in bytes.
+    pub memory: Option<u64>,
+    /// Program name, such as `sshd`.
+    pub name: String,
+    /// Command line on Unix-like systems, with kernel threads in brackets,
+    /// and the program's path on Windows. Empty if unknown.
+    pub command: String,
+}
+
+impl ConnectedSession {
+    /// Processes running on the remote host, from `ps` on Unix-like systems
+    /// and `Get-Process` on Windows. See [`ConnectedSession::platform`].
+    ///
+    /// # Errors
+    ///
+    /// - If the platform cannot be told.
+    /// - If `ps` or `Get-Process` fails, as [`Error::CommandFailed`].
+    pub async fn processes(&self) -> Result<Vec<ProcessInfo>> {
+        if self.platform().await? == Platform::Windows {
+            let output = self.run_powershell(GET_PROCESS).await?;
+            if !output.status.success() {
+                return Err(Error::CommandFailed {
+                    command: GET_PROCESS.to_string(),
+                    status: output.status,
+                });
+            }
+            return Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(parse_get_process)
+                .collect());
+        }
+
+        let mut command = self.command("ps");
+        // With `=`, no header is printed.
+        command.args(["-A", "-o", "pid=", "-o", "ppid=", "-o", "user="]);
+        command.args(["-o", "rss=", "-o", "args="]);
+        let output = command.output().await?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status: output.status,
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(parse_ps)
+            .collect())
+    }
+
+    /// Sends `signal`, such as `TERM` or `KILL`, to process `pid` with
+    /// `kill`. On Windows, which has no signals, the process is stopped with
+    /// `Stop-Process -Force` whatever the signal.
+    ///
+    /// # Errors
+    ///
+    /// - If the platform cannot be told.
+    /// - If the process does not exist or may not be signalled, as
+    ///   [`Error::CommandFailed`].
+    pub async fn kill(&self, pid: u32, signal: &str) -> Result<()> {
+        if self.platform().await? == Platform::Windows {
+            let script = format!("Stop-Process -Id {pid} -Force -ErrorAction Stop");
+            let output = self.run_powershell(&script).await?;
+            if !output.status.success() {
+                return Err(Error::CommandFailed {
+                    command: script,
+                    status: output.status,
+                });
+            }
+            return Ok(());
+        }
+
+        let mut command = self.command("kill");
+        command.args(["-s", signal, "--", &pid.to_string()]);
+        let status = command.status().await?;
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: command.command_line(),
+                status,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Process on a line of `ps -o pid=,ppid=,user=,rss=,args=`. `None` if the
+/// line is malformed.
+fn parse_ps(line: &str) -> Option<ProcessInfo> {
+    let (pid, rest) = next_field(line)?;
+    let (parent_pid, rest) = next_field(rest)?;
+    let (user, rest) = next_field(rest)?;
+    let (rss, command) = next_field(rest)?;
+    // Programs that retitle themselves, like `sshd: deploy [priv]`, follow
+    // their name with a colon.
+    let name = command
+        .split_whitespace()
+        .next()
+        .map(|program| program.rsplit('/').next().unwrap_or(program))
+        .map(|program| program.strip_suffix(':').unwrap_or(program))
+        .unwrap_or_default();
+
+    Some(ProcessInfo {
+        pid: pid.parse().ok()?,
+        parent_pid: parent_pid.parse().ok(),
+        user: Some(user.to_string()),
+        // In KiB.
+        memory: rss.parse::<u64>().ok().map(|rss| rss * 1024),
+        name: name.to_string(),
+        command: command.to_string(),
+    })
+}
+
+/// Process on a line printed by [`GET_PROCESS`]. `None` if the line is
+/// malformed.
+fn parse_get_process(line: &str) -> Option<ProcessInfo> {
+    let mut fields = line.trim_end_matches('\r').splitn(4, '\t');
+    let pid = fields.next()?.parse().ok()?;
+    let memory = fields.next()?.parse().ok();
+    let name = fields.next()?.to_string();
+    let command = fields.next().unwrap_or_default().to_string();
+
+    Some(ProcessInfo {
+        pid,
+        parent_pid: None,
+        user: None,
+        memory,
+        name,
+        command,
+    })
+}
+
+/// First whitespace-separated field of `line`, and the rest after the
+/// whitespace following it.
+fn next_field(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if line.is_empty() {
+        return None;
+    }
+    let (field, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+    Some((field, rest.trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(
+        "  812     1 root      6144 sshd: /usr/sbin/sshd -D [listener] 0 of 10-100 startups",
+        Some(ProcessInfo {
+            pid: 812,
+            parent_pid: Some(1),
+            user: Some("root".to_string()),
+            memory: Some(6_291_456),
+            name: "sshd".to_string(),
+            command: "sshd: /usr/sbin/sshd -D [listener] 0 of 10-100 startups".to_string(),
+        })
+    )]
+    #[case(
+        "    2     0 root         0 [kthreadd]",
+        Some(ProcessInfo {
+            pid: 2,
+            parent_pid: Some(0),
+            user: Some("root".to_string()),
+            memory: Some(0),
+            name: "[kthreadd]".to_string(),
+            command: "[kthreadd]".to_string(),
+        })
+    )]
+    #[case(
+        " 4242   812 1000     20480 /usr/bin/python3 -m http.server",
+        Some(ProcessInfo {
+            pid: 4242,
+            parent_pid: Some(812),
+            user: Some("1000".to_string()),
+            memory: Some(20_971_520),
+            name: "python3".to_string(),
+            command: "/usr/bin/python3 -m http.server".to_string(),
+        })
+    )]
+    #[case("", None)]
+    #[case("PID PPID USER RSS COMMAND", None)]
+    fn parse_ps_works(#[case] line: &str, #[case] process_should: Option<ProcessInfo>) {
+        assert_eq!(parse_ps(line), process_should);
+    }
+
+    #[rstest]
+    #[case(
+        "4\t155648\tSystem\t\r",
+        Some(ProcessInfo {
+            pid: 4,
+            parent_pid: None,
+            user: None,
+            memory: Some(155_648),
+            name: "System".to_string(),
+            command: String::new(),
+        })
+    )]
+    #[case(
+        "5120\t10485760\tsshd\tC:\\Windows\\System32\\OpenSSH\\sshd.exe\r",
+        Some(ProcessInfo {
+            pid: 5120,
+            parent_pid: None,
+            user: None,
+            memory: Some(10_485_760),
+            name: "sshd".to_string(),
+            command: "C:\\Windows\\System32\\OpenSSH\\sshd.exe".to_string(),
+        })
+    )]
+    #[case("", None)]
+    fn parse_get_process_works(#[case] line: &str, #[case] process_should: Option<ProcessInfo>) {
+        assert_eq!(parse_get_process(line), process_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn processes_and_kill_work() {
+        let session = crate::test_server::connect().await;
+        let mut sleep = std::process::Command::new("sleep")
+            .arg("60")
+            .spawn()
+            .unwrap();
+
+        let processes = session.processes().await.unwrap();
+        session.kill(sleep.id(), "KILL").await.unwrap();
+        let status = sleep.wait().unwrap();
+
+        let listed = processes
+            .iter()
+            .find(|process| process.pid == sleep.id())
+            .unwrap();
+        assert_eq!(listed.name, "sleep");
+        assert_eq!(listed.command, "sleep 60");
+        assert_eq!(listed.parent_pid, Some(std::process::id()));
+        assert!(!status.success());
+        assert!(session.kill(sleep.id(), "KILL").await.is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Prompts on the controlling terminal, for command-line tools: host key
+//! confirmation like OpenSSH's `StrictHostKeyChecking=ask`, passphrases read
+//! without echo, and keyboard-interactive questions.
+//!
+//! Everything here blocks on the terminal; [`AskVerifier`] does so on the
+//! blocking thread pool.
+
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::Arc;
+
+use bon::Builder;
+use camino::Utf8Path;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use secrecy::SecretString;
+use ssh_key::HashAlg;
+use ssh_key::PublicKey;
+
+use crate::Decision;
+use crate::HostKeyStatus;
+use crate::HostKeyVerifier;
+use crate::KnownHostsStore;
+use crate::Result;
+
+/// Host key verifier asking the user to confirm keys it does not know, like
+/// OpenSSH's `StrictHostKeyChecking=ask`.
+///
+/// With a known hosts store, keys recorded in it are accepted without
+/// asking, keys that changed or are revoked are rejected, and confirmed keys
+/// are recorded. Without one, every key is asked about.
+#[derive(Builder)]
+pub struct AskVerifier {
+    /// Where known keys are looked up and confirmed ones recorded.
+    #[builder(with = |store: impl KnownHostsStore + 'static| Arc::new(store) as Arc<dyn KnownHostsStore>)]
+    known_hosts: Option<Arc<dyn KnownHostsStore>>,
+}
+
+/// Question asked during keyboard-interactive authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub prompt: String,
+    /// Whether the answer may be shown as it is typed.
+    pub echo: bool,
+}
+
+impl HostKeyVerifier for AskVerifier {
+    fn verify<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        key: &'a PublicKey,
+    ) -> BoxFuture<'a, Result<Decision>> {
+        async move {
+            if let Some(store) = &self.known_hosts {
+                match store.check(host, port, key).await? {
+                    HostKeyStatus::Known => return Ok(Decision::Accept),
+                    HostKeyStatus::Unknown => {}
+                    status => {
+                        tracing::warn!(host, port, ?status, "refusing host key");
+                        return Ok(Decision::Reject);
+                    }
+                }
+            }
+
+            let (name, shown) = (host.to_string(), key.clone());
+            let confirmed = tokio::task::spawn_blocking(move || {
+                let (mut reader, mut writer) = tty()?;
+                confirm(&mut reader, &mut writer, &name, port, &shown)
+            })
+            .await
+            .map_err(io::Error::other)??;
+            if !confirmed {
+                return Ok(Decision::Reject);
+            }
+
+            if let Some(store) = &self.known_hosts {
+                store.add(host, port, key).await?;
+            }
+            Ok(Decision::Accept)
+        }
+        .boxed()
+    }
+}
+
+/// Reads a passphrase from the terminal after showing `prompt`, without
+/// echoing it.
+///
+/// # Errors
+///
+/// - If there is no terminal to read from.
+pub fn passphrase(prompt: &str) -> Result<SecretString> {
+    let passphrase = rpassword::prompt_password(prompt)?;
+
+    Ok(SecretString::from(passphrase))
+}
+
+/// [`PassphraseProvider`](crate::PassphraseProvider) asking on the terminal
+/// like OpenSSH does, for use with
+/// [`Auth::from_key_file_with`](crate::Auth::from_key_file_with). Gives up if
+/// the terminal cannot be read.
+#[must_use]
+pub fn ask_passphrase(path: &Utf8Path, attempt: u32) -> Option<SecretString> {
+    let prompt = if attempt > 1 {
+        format!("Bad passphrase, try again for {path}: ")
+    } else {
+        format!("Enter passphrase for key '{path}': ")
+    };
+
+    passphrase(&prompt).ok()
+}
+
+/// Asks `questions` on the terminal, after showing the `name` and
+/// `instructions` the server sent unless they are empty. Answers to
+/// questions that must not be echoed are read like [`passphrase`].
+///
+/// # Errors
+///
+/// - If there is no terminal to read from.
+pub fn keyboard_interactive(
+    name: &str,
+    instructions: &str,
+    questions: &[Question],
+) -> Result<Vec<SecretString>> {
+    let (mut reader, mut writer) = tty()?;
+    let answers = answer(
+        &mut reader,
+        &mut writer,
+        name,
+        instructions,
+        questions,
+        |_, _, prompt| rpassword::prompt_password(prompt),
+    )?;
+
+    Ok(answers)
+}
+
+/// Reader and writer of the controlling terminal, falling back to stdin and
+/// stderr where there is no `/dev/tty`.
+fn tty() -> io::Result<(impl BufRead, impl Write)> {
+    #[cfg(unix)]
+    {
+        let tty = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        Ok((io::BufReader::new(tty.try_clone()?), tty))
+    }
+    #[cfg(not(unix))]
+    {
+        Ok((io::BufReader::new(io::stdin()), io::stderr()))
+    }
+}
+
+/// Asks whether to trust `key` for `host` and `port`, with OpenSSH's wording.
+/// Typing the key's fingerprint also confirms it.
+fn confirm(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    host: &str,
+    port: u16,
+    key: &PublicKey,
+) -> io::Result<bool> {
+    let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+    let name = if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{host}]:{port}")
+    };
+    write!(
+        writer,
+        "The authenticity of host '{name}' can't be established.\n\
+         {} key fingerprint is {fingerprint}.\n\
+         Are you sure you want to continue connecting (yes/no/[fingerprint])? ",
+        key.algorithm(),
+    )?;
+    writer.flush()?;
+
+    loop {
+        let mut answer = String::new();
+        if reader.read_line(&mut answer)? == 0 {
+            return Ok(false);
+        }
+
+        match answer.trim() {
+            "yes" => return Ok(true),
+            "no" => return Ok(false),
+            typed if typed == fingerprint => return Ok(true),
+            typed if typed.starts_with("SHA256:") => {
+                writeln!(
+                    writer,
+                    "Warning: the fingerprint you entered does not match."
+                )?;
+                return Ok(false);
+            }
+            _ => {
+                write!(writer, "Please type 'yes', 'no' or the fingerprint: ")?;
+                writer.flush()?;
+            }
+        }
+    }
+}
+
+/// Asks `questions` on `reader` and `writer`, reading answers that must not
+/// be echoed with `read_hidden`.
+fn answer<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    name: &str,
+    instructions: &str,
+    questions: &[Question],
+    mut read_hidden: impl FnMut(&mut R, &mut W, &str) -> io::Result<String>,
+) -> io::Result<Vec<SecretString>> {
+    for text in [name, instructions] {
+        if !text.is_empty() {
+            writeln!(writer, "{text}")?;
+        }
+    }
+
+    let mut answers = Vec::with_capacity(questions.len());
+    for question in questions {
+        let answer = if question.echo {
+            write!(writer, "{}", question.prompt)?;
+            writer.flush()?;
+            let mut answer = String::new();
+            reader.read_line(&mut answer)?;
+            answer.trim_end_matches(['\r', '\n']).to_string()
+        } else {
+            read_hidden(reader, writer, &question.prompt)?
+        };
+        answers.push(SecretString::from(answer));
+    }
+
+    Ok(answers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rstest::rstest;
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    fn key() -> PublicKey {
+        PublicKey::read_openssh_file("test/creds/id_ed25519.pub".as_ref()).unwrap()
+    }
+
+    #[rstest]
+    #[case("yes\n", true)]
+    #[case("no\n", false)]
+    #[case("maybe\nyes\n", true)]
+    #[case("SHA256:wrong\n", false)]
+    #[case("", false)]
+    fn confirm_works(#[case] input: &str, #[case] confirmed_should: bool) {
+        let mut output = Vec::new();
+
+        let confirmed =
+            confirm(&mut Cursor::new(input), &mut output, "web1", 2222, &key()).unwrap();
+
+        assert_eq!(confirmed, confirmed_should);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("The authenticity of host '[web1]:2222' can't be established."));
+    }
+
+    #[test]
+    fn confirm_accepts_fingerprint() {
+        let fingerprint = key().fingerprint(HashAlg::Sha256);
+
+        let confirmed = confirm(
+            &mut Cursor::new(format!("{fingerprint}\n")),
+            &mut Vec::new(),
+            "web1",
+            22,
+            &key(),
+        )
+        .unwrap();
+
+        assert!(confirmed);
+    }
+
+    #[test]
+    fn answer_works() {
+        let questions = [
+            Question {
+                prompt: "Username: ".to_string(),
+                echo: true,
+            },
+            Question {
+                prompt: "Code: ".to_string(),
+                echo: false,
+            },
+        ];
+        let mut output = Vec::new();
+
+        let answers = answer(
+            &mut Cursor::new("alice\n"),
+            &mut output,
+            "Login",
+            "",
+            &questions,
+            |_, writer, prompt| {
+                write!(writer, "{prompt}")?;
+                Ok("123456".to_string())
+            },
+        )
+        .unwrap();
+
+        let answers: Vec<_> = answers.iter().map(ExposeSecret::expose_secret).collect();
+        assert_eq!(answers, ["alice", "123456"]);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Login\nUsername: Code: "
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Rebooting a host and waiting for it to come back, which needs the old
+//! connection to be seen dying before a new one can be trusted to reach the
+//! rebooted host.
+
+use std::time::Duration;
+
+use bon::Builder;
+use ssh_key::HashAlg;
+use ssh_key::PublicKey;
+use tokio::time::Instant;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::Session;
+
+/// Host key the rebooted host must present for
+/// [`ConnectedSession::reboot_and_wait`] to succeed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HostKeyExpectation {
+    /// Whatever key the reconnecting session's verifier accepts.
+    #[default]
+    Any,
+    /// The same key as before the reboot.
+    Unchanged,
+    /// This key, for hosts reinstalled by the reboot.
+    Expected(PublicKey),
+}
+
+impl HostKeyExpectation {
+    /// Checks the key presented after the reboot against the expectation,
+    /// given the key presented before. Missing keys are not checked.
+    fn check(&self, before: Option<&PublicKey>, after: Option<&PublicKey>) -> Result<()> {
+        let expected = match self {
+            HostKeyExpectation::Any => None,
+            HostKeyExpectation::Unchanged => before,
+            HostKeyExpectation::Expected(key) => Some(key),
+        };
+
+        match (expected, after) {
+            (Some(expected), Some(after)) if expected.key_data() != after.key_data() => {
+                Err(Error::HostKeyChanged {
+                    expected: Box::new(expected.fingerprint(HashAlg::Sha256)),
+                    got: Box::new(after.fingerprint(HashAlg::Sha256)),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How [`ConnectedSession::reboot_and_wait`] reboots the host and waits for
+/// it.
+#[derive(Debug, Clone, Builder)]
+pub struct RebootOptions {
+    /// Command line that reboots the host, run by the remote user's shell.
+    #[builder(into, default = "sudo -n reboot")]
+    command: String,
+    /// Longest the whole reboot may take, from running the command to
+    /// authenticating again. Defaults to 10 minutes.
+    #[builder(default = Duration::from_mins(10))]
+    timeout: Duration,
+    /// Time between checks that the old connection is gone and between
+    /// attempts to reconnect. Defaults to 5 seconds.
+    #[builder(default = Duration::from_secs(5))]
+    interval: Duration,
+    /// Host key the host must present once back.
+    #[builder(default)]
+    host_key: HostKeyExpectation,
+}
+
+impl Default for RebootOptions {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl ConnectedSession {
+    /// Reboots the host and waits until it is back: runs the reboot command,
+    /// waits for this session's connection to drop, then connects sessions
+    /// built by `reconnect` until one authenticates, and checks the host key
+    /// it was presented. Returns the new session.
+    ///
+    /// Sessions are consumed by connecting, so a new one is built for every
+    /// attempt, usually with the same settings as this one.
+    ///
+    /// # Errors
+    ///
+    /// - If the reboot command fails before the connection drops.
+    /// - If the host is not back within the timeout, as [`Error::WaitTimeout`].
+    /// - If the host presents another host key than expected, as
+    ///   [`Error::HostKeyChanged`].
+    pub async fn reboot_and_wait(
+        self,
+        reconnect: impl Fn() -> Session,
+        options: &RebootOptions,
+    ) -> Result<ConnectedSession> {
+        let deadline = Instant::now() + options.timeout;
+        let timed_out = || Error::WaitTimeout {
+            condition: format!("{} to come back after reboot", self.host()),
+        };
+        let host_key = self.host_key();
+
+        // The connection may drop before the command's status is sent, which
+        // is what rebooting looks like.
+        match self
+            .command("sh")
+            .args(["-c", &options.command])
+            .spawn()
+            .await
+        {
+            Ok(child) => match child.wait_with_output().await {
+                Ok(output) if !output.status.success() => {
+                    return Err(Error::CommandFailed {
+                        command: options.command.clone(),
+                        status: output.status,
+                    });
+                }
+                Ok(_) | Err(_) => {}
+            },
+            Err(error) => tracing::debug!(%error, "connection dropped starting reboot"),
+        }
+
+        while self.is_alive(options.interval).await {
+            if Instant::now() + options.interval >= deadline {
+                return Err(timed_out());
+            }
+            tokio::time::sleep(options.interval).await;
+        }
+        tracing::info!(host = self.host(), "connection dropped, reconnecting");
+
+        loop {
+            match tokio::time::timeout_at(deadline, reconnect().connect()).await {
+                Ok(Ok(session)) => {
+                    options
+                        .host_key
+                        .check(host_key.as_ref(), session.host_key().as_ref())?;
+                    return Ok(session);
+                }
+                Ok(Err(error)) => tracing::debug!(%error, "host not back yet"),
+                Err(_) => return Err(timed_out()),
+            }
+            if Instant::now() + options.interval >= deadline {
+                return Err(timed_out());
+            }
+            tokio::time::sleep(options.interval).await;
+        }
+    }
+
+    /// Whether the session can still run a command within `timeout`. A
+    /// host that went down without closing the connection does not answer.
+    async fn is_alive(&self, timeout: Duration) -> bool {
+        let alive = async {
+            let mut child = self.command("true").spawn().await?;
+            child.wait().await
+        };
+
+        matches!(tokio::time::timeout(timeout, alive).await, Ok(Ok(_)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn key(name: &str) -> PublicKey {
+        PublicKey::read_openssh_file(format!("test/creds/{name}.pub").as_ref()).unwrap()
+    }
+
+    #[rstest]
+    #[case(HostKeyExpectation::Any, "id_ed25519", "id_rsa", true)]
+    #[case(HostKeyExpectation::Unchanged, "id_ed25519", "id_ed25519", true)]
+    #[case(HostKeyExpectation::Unchanged, "id_ed25519", "id_rsa", false)]
+    #[case(
+        HostKeyExpectation::Expected(key("id_rsa")),
+        "id_ed25519",
+        "id_rsa",
+        true
+    )]
+    #[case(
+        HostKeyExpectation::Expected(key("id_rsa")),
+        "id_ed25519",
+        "id_ed25519",
+        false
+    )]
+    fn host_key_expectation_works(
+        #[case] expectation: HostKeyExpectation,
+        #[case] before: &str,
+        #[case] after: &str,
+        #[case] accepted: bool,
+    ) {
+        let result = expectation.check(Some(&key(before)), Some(&key(after)));
+
+        assert_eq!(result.is_ok(), accepted);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn reboot_and_wait_reports_failed_command() {
+        let session = crate::test_server::connect().await;
+        let options = RebootOptions::builder().command("exit 1").build();
+
+        let result = session.reboot_and_wait(|| unreachable!(), &options).await;
+
+        assert!(matches!(result, Err(Error::CommandFailed { .. })));
+    }
+}
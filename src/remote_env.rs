@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+impl ConnectedSession {
+    /// Environment that commands run by this session see, captured with
+    /// `env -0`, or plain `env` where `-0` is not supported. Captured once and
+    /// cached for the lifetime of the session.
+    ///
+    /// # Errors
+    ///
+    /// - If `env` cannot be run.
+    pub async fn remote_env(&self) -> Result<&HashMap<String, String>> {
+        self.remote_env
+            .get_or_try_init(|| async {
+                let output = self.command("env").arg("-0").output().await?;
+                if output.status.success() {
+                    return Ok(parse_env(&output.stdout, b'\0'));
+                }
+
+                let mut command = self.command("env");
+                let output = command.output().await?;
+                if !output.status.success() {
+                    return Err(Error::CommandFailed {
+                        command: command.command_line(),
+                        status: output.status,
+                    });
+                }
+
+                Ok(parse_env(&output.stdout, b'\n'))
+            })
+            .await
+    }
+
+    /// Expands a leading `~` and any `$VAR` or `${VAR}` in `path` using the
+    /// remote environment, like the remote shell would. Useful before file
+    /// operations, which do not go through a shell.
+    ///
+    /// # Errors
+    ///
+    /// - If the remote environment cannot be captured.
+    /// - If `path` refers to a variable that is not set remotely.
+    pub async fn expand_remote(&self, path: &str) -> Result<String> {
+        expand(path, self.remote_env().await?)
+    }
+}
+
+/// Parses `KEY=VALUE` entries separated by `separator`.
+fn parse_env(output: &[u8], separator: u8) -> HashMap<String, String> {
+    output
+        .split(|&byte| byte == separator)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn expand(path: &str, env: &HashMap<String, String>) -> Result<String> {
+    let var = |name: &str| {
+        env.get(name)
+            .map(String::as_str)
+            .ok_or_else(|| Error::UnknownRemoteVariable(name.to_string()))
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(var("HOME")?);
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let braced = after
+            .strip_prefix('{')
+            .and_then(|braced| Some(braced.split_at(braced.find('}')?)));
+        let (name, remaining) = if let Some((name, remaining)) = braced {
+            (name, &remaining[1..])
+        } else if after.starts_with(|c: char| c.is_ascii_digit()) {
+            ("", after)
+        } else {
+            let end = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            after.split_at(end)
+        };
+
+        // A `$` not followed by a variable name is taken literally.
+        if name.is_empty() {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        expanded.push_str(var(name)?);
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    fn env() -> HashMap<String, String> {
+        parse_env(b"HOME=/home/test\0USER=test\0EMPTY=\0", b'\0')
+    }
+
+    #[test]
+    fn parse_env_works() {
+        let env = parse_env(b"A=1\nB=x=y\nnot an entry\n", b'\n');
+
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["A"], "1");
+        assert_eq!(env["B"], "x=y");
+    }
+
+    #[rstest]
+    #[case("~", "/home/test")]
+    #[case("~/bin", "/home/test/bin")]
+    #[case("$HOME/.ssh", "/home/test/.ssh")]
+    #[case("/srv/${USER}_data", "/srv/test_data")]
+    #[case("/srv/$EMPTY", "/srv/")]
+    #[case("/price/$5", "/price/$5")]
+    #[case("/tmp/~/x", "/tmp/~/x")]
+    #[case("~other/x", "~other/x")]
+    fn expand_works(#[case] path: &str, #[case] expanded_should: &str) {
+        assert_eq!(expand(path, &env()).unwrap(), expanded_should);
+    }
+
+    #[test]
+    fn expand_rejects_unknown_variables() {
+        let result = expand("$NOPE/x", &env());
+
+        assert!(matches!(result, Err(Error::UnknownRemoteVariable(name)) if name == "NOPE"));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn expand_remote_works() {
+        let session = test_server::connect().await;
+        let home = session.remote_env().await.unwrap()["HOME"].clone();
+
+        let expanded = session.expand_remote("~/.ssh").await.unwrap();
+
+        assert_eq!(expanded, format!("{home}/.ssh"));
+    }
+}
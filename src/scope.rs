@@ -0,0 +1,205 @@
+use std::any::Any;
+use std::future::Future;
+use std::mem;
+use std::sync::Arc;
+
+use tokio::task::JoinSet;
+
+use crate::ConnectedSession;
+use crate::Result;
+
+/// Group of sessions, commands, tunnels and tasks that end together, so that
+/// none outlives the part of the program that needed it.
+///
+/// When the scope is closed, joined or dropped, its tasks are aborted, the
+/// resources it holds are dropped, which closes their channels, and its
+/// sessions are disconnected, even if clones of them are still held
+/// elsewhere. A task failing ends the whole scope when it is joined.
+///
+/// Dropping the scope disconnects sessions in the background, which needs a
+/// Tokio runtime; outside one, sessions end only once their last clone is
+/// dropped. Use [`SessionScope::close`] to wait until everything has ended.
+#[derive(Default)]
+pub struct SessionScope {
+    sessions: Vec<Arc<ConnectedSession>>,
+    resources: Vec<Box<dyn Any + Send>>,
+    tasks: JoinSet<Result<()>>,
+}
+
+impl SessionScope {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes ownership of `session`, returning a handle to share with tasks.
+    pub fn add_session(&mut self, session: ConnectedSession) -> Arc<ConnectedSession> {
+        let session = Arc::new(session);
+        self.sessions.push(session.clone());
+        session
+    }
+
+    /// Keeps `resource`, such as a [`Child`](crate::process::Child) or a
+    /// tunnel from [`ConnectedSession::open_tunnel`], until the scope ends.
+    pub fn hold(&mut self, resource: impl Send + 'static) {
+        self.resources.push(Box::new(resource));
+    }
+
+    /// Runs `task` on the Tokio runtime until it completes or the scope ends.
+    ///
+    /// # Panics
+    ///
+    /// - If called outside a Tokio runtime.
+    pub fn spawn(&mut self, task: impl Future<Output = Result<()>> + Send + 'static) {
+        self.tasks.spawn(task);
+    }
+
+    /// Waits for every task to complete, then closes the scope. As soon as a
+    /// task fails, the others are aborted and the scope is closed.
+    ///
+    /// # Errors
+    ///
+    /// - The error of the first task to fail.
+    ///
+    /// # Panics
+    ///
+    /// - If a task panicked, with the task's panic.
+    pub async fn join(mut self) -> Result<()> {
+        while let Some(joined) = self.tasks.join_next().await {
+            let result = match joined {
+                Ok(result) => result,
+                Err(error) if error.is_panic() => {
+                    self.close_now().await;
+                    std::panic::resume_unwind(error.into_panic());
+                }
+                // Tasks are only cancelled by the scope itself.
+                Err(_) => Ok(()),
+            };
+            if let Err(error) = result {
+                self.close_now().await;
+                return Err(error);
+            }
+        }
+
+        self.close_now().await;
+        Ok(())
+    }
+
+    /// Aborts every task, drops every resource held and disconnects every
+    /// session, waiting until all have ended.
+    pub async fn close(mut self) {
+        self.close_now().await;
+    }
+
+    async fn close_now(&mut self) {
+        self.tasks.shutdown().await;
+        self.resources.clear();
+
+        let sessions = mem::take(&mut self.sessions);
+        futures::future::join_all(sessions.iter().map(|session| disconnect(session))).await;
+    }
+}
+
+impl Drop for SessionScope {
+    fn drop(&mut self) {
+        self.tasks.abort_all();
+        self.resources.clear();
+
+        let sessions = mem::take(&mut self.sessions);
+        if sessions.is_empty() {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                for session in &sessions {
+                    disconnect(session).await;
+                }
+            });
+        }
+    }
+}
+
+/// Disconnects `session`, which may already have ended.
+async fn disconnect(session: &ConnectedSession) {
+    if let Err(error) = session.disconnect().await {
+        tracing::debug!(%error, "could not disconnect session");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Error;
+
+    /// Sets a flag when dropped.
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn join_works() {
+        let mut scope = SessionScope::new();
+        let dropped = Arc::new(AtomicBool::new(false));
+        scope.hold(DropFlag(dropped.clone()));
+        scope.spawn(async { Ok(()) });
+        scope.spawn(async { Ok(()) });
+
+        scope.join().await.unwrap();
+
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn join_aborts_on_failure() {
+        let mut scope = SessionScope::new();
+        let aborted = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(aborted.clone());
+        scope.spawn(async move {
+            let _flag = flag;
+            tokio::time::sleep(Duration::from_hours(1)).await;
+            Ok(())
+        });
+        scope.spawn(async { Err(Error::ConnectTimeout) });
+
+        let result = scope.join().await;
+
+        assert!(matches!(result, Err(Error::ConnectTimeout)));
+        assert!(aborted.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn drop_aborts_tasks() {
+        let mut scope = SessionScope::new();
+        let aborted = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(aborted.clone());
+        scope.spawn(async move {
+            let _flag = flag;
+            tokio::time::sleep(Duration::from_hours(1)).await;
+            Ok(())
+        });
+
+        drop(scope);
+        tokio::task::yield_now().await;
+
+        assert!(aborted.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn close_disconnects_shared_sessions() {
+        let mut scope = SessionScope::new();
+        let session = scope.add_session(crate::test_server::connect().await);
+
+        scope.close().await;
+
+        assert!(session.command("true").spawn().await.is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! File copies over the legacy SCP protocol, for servers that run commands
+//! but have no SFTP subsystem, such as many embedded and network devices.
+//! The remote end is `scp` itself, run in sink (`-t`) or source (`-f`) mode.
+
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
+use camino::Utf8Path;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::fs::Permissions;
+use crate::process::Child;
+use crate::process::ChildStdin;
+use crate::process::ChildStdout;
+use crate::process::Command;
+
+/// Exit status of a shell that did not find the command.
+const NOT_FOUND_STATUS: u32 = 127;
+
+impl ConnectedSession {
+    /// Copies the local file at `local` to `remote` with `scp -t`, replacing
+    /// `remote` if it exists. If `remote` is a directory, the file is copied
+    /// into it under its local name. The copy gets the local file's
+    /// permissions, less the remote umask. Returns the bytes copied.
+    ///
+    /// `remote` is passed to the remote `scp` as is, so relative paths are
+    /// relative to the remote user's home; expand `~` with
+    /// [`ConnectedSession::expand_remote`] first.
+    ///
+    /// # Errors
+    ///
+    /// - If `local` cannot be read or is not a file.
+    /// - If the name of `local` has a newline, which the remote `scp` would
+    ///   take for the end of the header.
+    /// - If `scp` is not installed remotely, as [`Error::ProgramNotFound`].
+    /// - If the remote `scp` refuses the copy, for example because `remote`
+    ///   cannot be written, as [`Error::Scp`].
+    pub async fn scp_send(
+        &self,
+        local: impl AsRef<Utf8Path>,
+        remote: impl AsRef<Utf8Path>,
+    ) -> Result<u64> {
+        let local = local.as_ref();
+        let mut file = tokio::fs::File::open(local).await?;
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{local} is not a file"),
+            )
+            .into());
+        }
+        let name = local.file_name().unwrap_or_default();
+        if name.contains('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{local:?} has a newline in its name"),
+            )
+            .into());
+        }
+        let permissions = Permissions::from_mode(metadata.permissions().mode());
+
+        let mut command = self.command("scp");
+        command.args(["-t", "--", remote.as_ref().as_str()]);
+        let (mut child, mut stdin, mut stdout) = start(&mut command).await?;
+
+        let copied = async {
+            read_ack(&mut stdout).await?;
+            let header = format!("C{:04o} {} {name}\n", permissions.mode(), metadata.len());
+            send(&mut stdin, header.as_bytes()).await?;
+            read_ack(&mut stdout).await?;
+
+            let copied = tokio::io::copy(&mut (&mut file).take(metadata.len()), &mut stdin).await?;
+            if copied != metadata.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{local} shrank while it was copied"),
+                )
+                .into());
+            }
+            send(&mut stdin, b"\0").await?;
+            read_ack(&mut stdout).await?;
+
+            Ok(copied)
+        }
+        .await;
+
+        child.stdin = Some(stdin);
+        child.stdout = Some(stdout.into_inner());
+        finish(&command, child, copied).await
+    }
+
+    /// Copies the remote file at `remote` to `local` with `scp -f`, replacing
+    /// `local` if it exists. If `local` is a directory, the file is copied
+    /// into it under its remote name. The copy gets the remote file's
+    /// permissions when it is created, less the local umask. Returns the bytes
+    /// copied.
+    ///
+    /// `remote` is passed to the remote `scp` as is; see
+    /// [`ConnectedSession::scp_send`].
+    ///
+    /// # Errors
+    ///
+    /// - If `scp` is not installed remotely, as [`Error::ProgramNotFound`].
+    /// - If the remote `scp` cannot send `remote`, for example because it does
+    ///   not exist or is a directory, as [`Error::Scp`].
+    /// - If the server sends something other than a single file, or a name that
+    ///   would leave `local`, as [`Error::Scp`].
+    /// - If `local` cannot be written.
+    pub async fn scp_recv(
+        &self,
+        remote: impl AsRef<Utf8Path>,
+        local: impl AsRef<Utf8Path>,
+    ) -> Result<u64> {
+        let local = local.as_ref();
+
+        let mut command = self.command("scp");
+        command.args(["-f", "--", remote.as_ref().as_str()]);
+        let (mut child, mut stdin, mut stdout) = start(&mut command).await?;
+
+        let copied = async {
+            send(&mut stdin, b"\0").await?;
+            let header = read_header(&mut stdout).await?;
+            let (permissions, len, name) = parse_header(&header)?;
+            let path = if tokio::fs::metadata(local)
+                .await
+                .is_ok_and(|metadata| metadata.is_dir())
+            {
+                local.join(name)
+            } else {
+                local.to_path_buf()
+            };
+            let mut file = create(&path, permissions).await?;
+            send(&mut stdin, b"\0").await?;
+
+            let copied = tokio::io::copy(&mut (&mut stdout).take(len), &mut file).await?;
+            if copied != len {
+                return Err(Error::Scp(format!(
+                    "connection ended after {copied} of {len} bytes"
+                )));
+            }
+            file.shutdown().await?;
+            read_ack(&mut stdout).await?;
+            send(&mut stdin, b"\0").await?;
+
+            Ok(copied)
+        }
+        .await;
+
+        child.stdin = Some(stdin);
+        child.stdout = Some(stdout.into_inner());
+        finish(&command, child, copied).await
+    }
+}
+
+/// Spawns the remote `scp`, returning its stdin and buffered stdout, which
+/// are to be put back before [`finish`].
+async fn start(command: &mut Command<'_>) -> Result<(Child, ChildStdin, BufReader<ChildStdout>)> {
+    let mut child = command.spawn().await?;
+    let stdin = child.stdin.take().expect("stdin is set by spawn");
+    let stdout = child.stdout.take().expect("stdout is set by spawn");
+
+    Ok((child, stdin, BufReader::new(stdout)))
+}
+
+/// Waits for the remote `scp` to exit, and explains a failed copy by its
+/// exit status and error output if the protocol could not, such as when
+/// `scp` is missing. Output left unread after a failure is discarded, so
+/// that the remote `scp` is not stalled writing it.
+async fn finish(command: &Command<'_>, child: Child, copied: Result<u64>) -> Result<u64> {
+    let mut stderr = Vec::new();
+    let status = child
+        .wait_with_sinks(tokio::io::sink(), &mut stderr)
+        .await?;
+    match copied {
+        Err(Error::Scp(message)) if message.is_empty() => {
+            if status.code() == Some(NOT_FOUND_STATUS) {
+                return Err(Error::ProgramNotFound("scp".to_string()));
+            }
+            let stderr = String::from_utf8_lossy(&stderr).trim_end().to_string();
+            if !stderr.is_empty() {
+                return Err(Error::Scp(stderr));
+            }
+            Err(Error::CommandFailed {
+                command: command.command_line(),
+                status,
+            })
+        }
+        Err(error) => Err(error),
+        Ok(_) if !status.success() => Err(Error::CommandFailed {
+            command: command.command_line(),
+            status,
+        }),
+        Ok(copied) => Ok(copied),
+    }
+}
+
+async fn send(stdin: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> Result<()> {
+    stdin.write_all(data).await?;
+    stdin.flush().await?;
+
+    Ok(())
+}
+
+/// Reads the byte the other end answers each step with: 0 if it succeeded,
+/// or 1 or 2 followed by a message line if it failed. Fails with an empty
+/// message if the output ends, leaving the exit status to explain why.
+async fn read_ack(stdout: &mut (impl AsyncBufReadExt + Unpin)) -> Result<()> {
+    let mut ack = [0];
+    if stdout.read(&mut ack).await? == 0 {
+        return Err(Error::Scp(String::new()));
+    }
+    match ack[0] {
+        0 => Ok(()),
+        1 | 2 => Err(Error::Scp(read_message(stdout).await?)),
+        byte => Err(Error::Scp(format!("unexpected reply {byte:#04x}"))),
+    }
+}
+
+/// Reads the line announcing the file, skipping the failure byte of an error
+/// message so that it can be reported like an acknowledgement's.
+async fn read_header(stdout: &mut (impl AsyncBufReadExt + Unpin)) -> Result<String> {
+    let mut first = [0];
+    if stdout.read(&mut first).await? == 0 {
+        return Err(Error::Scp(String::new()));
+    }
+    if matches!(first[0], 1 | 2) {
+        return Err(Error::Scp(read_message(stdout).await?));
+    }
+
+    let mut header = vec![first[0]];
+    stdout.read_until(b'\n', &mut header).await?;
+    Ok(String::from_utf8_lossy(&header).into_owned())
+}
+
+async fn read_message(stdout: &mut (impl AsyncBufReadExt + Unpin)) -> Result<String> {
+    let mut message = Vec::new();
+    stdout.read_until(b'\n', &mut message).await?;
+
+    Ok(String::from_utf8_lossy(&message).trim_end().to_string())
+}
+
+/// Permissions, size and name from a `C0644 1234 name` header. Names that
+/// are not a plain file name are refused, so that a malicious server cannot
+/// write outside of the destination, and only the permission bits of the
+/// mode are kept, so that it cannot create setuid or setgid files.
+fn parse_header(header: &str) -> Result<(Permissions, u64, &str)> {
+    let invalid = || Error::Scp(format!("unexpected header {:?}", header.trim_end()));
+    let Some(rest) = header.strip_prefix('C') else {
+        return Err(match header.chars().next() {
+            Some('D') => Error::Scp("remote path is a directory".to_string()),
+            _ => invalid(),
+        });
+    };
+
+    let mut fields = rest.trim_end_matches('\n').splitn(3, ' ');
+    let (Some(mode), Some(len), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(invalid());
+    };
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+    let len = len.parse().map_err(|_| invalid())?;
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(Error::Scp(format!("refusing file name {name:?}")));
+    }
+
+    Ok((Permissions::from_mode(mode & 0o777), len, name))
+}
+
+async fn create(path: &Utf8Path, permissions: Permissions) -> Result<tokio::fs::File> {
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(permissions.mode())
+        .open(path)
+        .await?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case("C0644 5 hello.txt\n", Some((0o644, 5, "hello.txt")))]
+    #[case("C0755 0 my file\n", Some((0o755, 0, "my file")))]
+    #[case("C6755 5 suid\n", Some((0o755, 5, "suid")))]
+    #[case("C0644 5 ../evil\n", None)]
+    #[case("C0644 5 ..\n", None)]
+    #[case("C0644 five hello.txt\n", None)]
+    #[case("D0755 0 dir\n", None)]
+    #[case("T1700000000 0 1700000000 0\n", None)]
+    fn parse_header_works(#[case] header: &str, #[case] parsed_should: Option<(u32, u64, &str)>) {
+        let parsed = parse_header(header)
+            .ok()
+            .map(|(permissions, len, name)| (permissions.mode(), len, name));
+
+        assert_eq!(parsed, parsed_should);
+    }
+
+    #[rstest]
+    #[case(b"\0", None)]
+    #[case(
+        b"\x01scp: /etc/shadow: Permission denied\n",
+        Some("scp: /etc/shadow: Permission denied")
+    )]
+    #[case(b"", Some(""))]
+    #[tokio::test]
+    async fn read_ack_works(#[case] reply: &[u8], #[case] message_should: Option<&str>) {
+        let result = read_ack(&mut BufReader::new(reply)).await;
+
+        match message_should {
+            None => result.unwrap(),
+            Some(message_should) => {
+                assert!(matches!(result, Err(Error::Scp(message)) if message == message_should));
+            }
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn scp_round_trips() {
+        let session = test_server::connect().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        std::fs::write(dir.join("sent.txt"), "hello over scp").unwrap();
+        std::fs::set_permissions(dir.join("sent.txt"), std::fs::Permissions::from_mode(0o640))
+            .unwrap();
+        std::fs::create_dir(dir.join("back")).unwrap();
+
+        let sent = session
+            .scp_send(dir.join("sent.txt"), "remote.txt")
+            .await
+            .unwrap();
+        let received = session
+            .scp_recv("remote.txt", dir.join("back"))
+            .await
+            .unwrap();
+        let missing = session.scp_recv("missing.txt", dir.join("back")).await;
+
+        assert_eq!((sent, received), (14, 14));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("back/remote.txt")).unwrap(),
+            "hello over scp"
+        );
+        let mode = std::fs::metadata(dir.join("back/remote.txt"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert!(matches!(missing, Err(Error::Scp(message)) if message.contains("missing.txt")));
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn scp_send_rejects_newline_names() {
+        let session = test_server::connect().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        std::fs::write(dir.join("evil\nC0755 5 x"), "hello").unwrap();
+
+        let result = session
+            .scp_send(dir.join("evil\nC0755 5 x"), "remote.txt")
+            .await;
+
+        assert!(
+            matches!(result, Err(Error::Io(error)) if error.kind() == io::ErrorKind::InvalidInput)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Lookup of passwords, private keys and passphrases by name, so that
+//! applications can keep them in password managers and vaults that this
+//! crate knows nothing about.
+
+use std::env;
+use std::future::Future;
+use std::io;
+
+use camino::Utf8Component;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use secrecy::ExposeSecret;
+use secrecy::SecretSlice;
+use secrecy::SecretString;
+
+use crate::Error;
+use crate::Result;
+
+/// Store of secrets looked up by name, such as Vault, 1Password or the OS
+/// keyring. Implemented by [`EnvSource`] and [`FileSource`], and for closures
+/// taking the name by value and returning a future, such as
+/// `|name| async move { vault.read(&name).await }`.
+///
+/// Backends should report secrets they do not have as
+/// [`Error::SecretNotFound`] and their own failures as
+/// [`Error::SecretSource`].
+pub trait SecretSource: Send + Sync {
+    /// Secret named `name`.
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<SecretSlice<u8>>>;
+
+    /// Secret named `name`, which must be UTF-8, such as a password.
+    fn fetch_string<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<SecretString>> {
+        async move {
+            let secret = self.fetch(name).await?;
+            let text = std::str::from_utf8(secret.expose_secret())
+                .map_err(|_| Error::SecretSource(format!("secret {name} is not UTF-8").into()))?;
+
+            Ok(SecretString::from(text))
+        }
+        .boxed()
+    }
+}
+
+impl<F, Fut> SecretSource for F
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<SecretSlice<u8>>> + Send + 'static,
+{
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<SecretSlice<u8>>> {
+        self(name.to_string()).boxed()
+    }
+}
+
+/// Secrets in environment variables, named by the secret's name after a
+/// prefix, as CI systems provide them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvSource {
+    prefix: String,
+}
+
+impl EnvSource {
+    /// Source looking up the secret `name` in the variable `{prefix}{name}`.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl SecretSource for EnvSource {
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<SecretSlice<u8>>> {
+        let variable = format!("{}{name}", self.prefix);
+        let secret = match env::var_os(&variable) {
+            Some(value) => value
+                .into_string()
+                .map(|value| SecretSlice::from(value.into_bytes()))
+                .map_err(|_| Error::SecretSource(format!("{variable} is not UTF-8").into())),
+            None => Err(Error::SecretNotFound(variable)),
+        };
+
+        futures::future::ready(secret).boxed()
+    }
+}
+
+/// Secrets in files of a directory, named by the secret's name, as container
+/// orchestrators mount them. Names may contain `/` to reach into
+/// subdirectories, but not leave the directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSource {
+    dir: Utf8PathBuf,
+}
+
+impl FileSource {
+    pub fn new(dir: impl Into<Utf8PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl SecretSource for FileSource {
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<SecretSlice<u8>>> {
+        async move {
+            let relative = Utf8Path::new(name);
+            let is_inside = relative
+                .components()
+                .all(|component| matches!(component, Utf8Component::Normal(_)));
+            if name.is_empty() || !is_inside {
+                return Err(Error::SecretSource(
+                    format!("secret name {name} leaves the directory").into(),
+                ));
+            }
+
+            match tokio::fs::read(self.dir.join(relative)).await {
+                Ok(secret) => Ok(SecretSlice::from(secret)),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    Err(Error::SecretNotFound(name.to_string()))
+                }
+                Err(error) => Err(error.into()),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("password", Some("test_password"))]
+    #[case("missing", None)]
+    #[case("../creds/password", None)]
+    #[case("/etc/passwd", None)]
+    #[tokio::test]
+    async fn file_source_works(#[case] name: &str, #[case] secret_should: Option<&str>) {
+        let source = FileSource::new("test/creds");
+
+        let secret = source.fetch_string(name).await;
+
+        assert_eq!(
+            secret.ok().as_ref().map(ExposeSecret::expose_secret),
+            secret_should
+        );
+    }
+
+    #[tokio::test]
+    async fn env_source_works() {
+        let source = EnvSource::new("SSH_UTIL_TEST_");
+
+        let secret = source.fetch("NO_SUCH_SECRET").await;
+
+        assert!(
+            matches!(secret, Err(Error::SecretNotFound(variable)) if variable == "SSH_UTIL_TEST_NO_SUCH_SECRET")
+        );
+    }
+
+    #[tokio::test]
+    async fn closure_source_works() {
+        let source = |name: String| async move { Ok(SecretSlice::from(name.into_bytes())) };
+
+        let secret = source.fetch_string("db/root").await.unwrap();
+
+        assert_eq!(secret.expose_secret(), "db/root");
+    }
+}
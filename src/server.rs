@@ -0,0 +1,538 @@
+//! Building blocks for SSH servers, such as bastions and honeypots, on top of
+//! russh. A [`Handler`] decides who may log in and what their requests do; a
+//! [`Server`] speaks the protocol for it.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bon::Builder;
+use futures::future::BoxFuture;
+use russh::Channel;
+use russh::ChannelId;
+use russh::server::Auth;
+use russh::server::Msg;
+use russh::server::Session;
+use ssh_key::Certificate;
+use ssh_key::PrivateKey;
+use ssh_key::PublicKey;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+
+use crate::Policy;
+use crate::Result;
+use crate::driver::russh::from_russh_certificate;
+use crate::driver::russh::preferred;
+use crate::driver::russh::to_public_key;
+use crate::driver::russh::to_russh_private_key;
+
+mod honeypot;
+
+pub use honeypot::AuditEvent;
+pub use honeypot::Honeypot;
+
+/// Extended data type code of stderr.
+const EXTENDED_DATA_STDERR: u32 = 1;
+
+/// Decides what clients of a [`Server`] may do. Everything is refused by
+/// default, so implementations only opt in to what they need.
+///
+/// Requests run in the background, so the futures they return must own what
+/// they use; keep shared state behind an [`Arc`].
+pub trait Handler: Send + Sync + 'static {
+    /// Whether `user` may log in with `password`.
+    fn auth_password(&self, user: &str, password: &str) -> impl Future<Output = bool> + Send {
+        let _ = (user, password);
+        async { false }
+    }
+
+    /// Whether `user` may log in with `key`. The client has already proven
+    /// that it holds the private key.
+    fn auth_publickey(&self, user: &str, key: &PublicKey) -> impl Future<Output = bool> + Send {
+        let _ = (user, key);
+        async { false }
+    }
+
+    /// Whether `user` may log in with `certificate`. The client has already
+    /// proven that it holds the certified key, but the handler must check
+    /// that a trusted CA signed it, that it is valid now and that it lists
+    /// `user` as a principal.
+    fn auth_certificate(
+        &self,
+        user: &str,
+        certificate: &Certificate,
+    ) -> impl Future<Output = bool> + Send {
+        let _ = (user, certificate);
+        async { false }
+    }
+
+    /// Runs `command` for `user`, returning a future that resolves to its
+    /// exit code, or `None` to refuse.
+    fn exec(&self, user: &str, command: &str, io: ChannelIo) -> Option<BoxFuture<'static, u32>> {
+        let _ = (user, command, io);
+        None
+    }
+
+    /// Runs an interactive shell for `user`, returning a future that resolves
+    /// to its exit code, or `None` to refuse.
+    fn shell(&self, user: &str, io: ChannelIo) -> Option<BoxFuture<'static, u32>> {
+        let _ = (user, io);
+        None
+    }
+
+    /// SFTP server for `user`, or `None` to refuse the `sftp` subsystem.
+    #[cfg(unix)]
+    fn sftp(&self, user: &str) -> Option<crate::sftp::Server> {
+        let _ = user;
+        None
+    }
+}
+
+/// Standard streams of an exec or shell request.
+pub struct ChannelIo {
+    /// Terminal type, like `xterm-256color`, if the client requested a
+    /// pseudo-terminal. The handler is then responsible for echoing input and
+    /// for line endings, as there is no real terminal to do it.
+    pub terminal: Option<String>,
+    /// Data sent by the client.
+    pub stdin: Box<dyn AsyncRead + Send + Unpin>,
+    /// Data sent to the client. Shutting it down sends end of file.
+    pub stdout: Box<dyn AsyncWrite + Send + Unpin>,
+    /// Data sent to the client as stderr.
+    pub stderr: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl ChannelIo {
+    fn new(channel: Channel<Msg>, terminal: Option<String>) -> Self {
+        let stderr = channel.make_writer_ext(Some(EXTENDED_DATA_STDERR));
+        let (stdin, stdout) = tokio::io::split(channel.into_stream());
+
+        Self {
+            terminal,
+            stdin: Box::new(stdin),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+        }
+    }
+}
+
+/// SSH server that delegates authentication and requests to a [`Handler`].
+#[derive(Builder)]
+pub struct Server<H: Handler> {
+    #[builder(field)]
+    host_keys: Vec<PrivateKey>,
+
+    #[builder(with = |handler: H| Arc::new(handler))]
+    handler: Arc<H>,
+    /// Algorithms allowed during key exchange. Uses russh's defaults if not
+    /// set.
+    policy: Option<Policy>,
+    /// Delay before replying to a failed authentication attempt, to slow down
+    /// password guessing. Uses russh's default if not set.
+    auth_rejection_time: Option<Duration>,
+    /// Disconnect clients that send nothing for this long.
+    inactivity_timeout: Option<Duration>,
+}
+
+impl<H: Handler, S: server_builder::State> ServerBuilder<H, S> {
+    /// Host key to identify the server with. At least one is required; add
+    /// one per algorithm to offer several.
+    pub fn host_key(mut self, key: PrivateKey) -> Self {
+        self.host_keys.push(key);
+        self
+    }
+}
+
+impl<H: Handler> Server<H> {
+    /// Serves a single client over `stream` until it disconnects.
+    ///
+    /// # Errors
+    ///
+    /// - If a host key cannot be used, or the policy allows no algorithm that
+    ///   russh implements.
+    /// - If the connection fails.
+    pub async fn run_stream(
+        &self,
+        stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    ) -> Result<()> {
+        let config = Arc::new(self.config()?);
+        russh::server::run_stream(config, stream, self.connection())
+            .await?
+            .await?;
+
+        Ok(())
+    }
+
+    /// Accepts clients on `addr` until accepting fails, serving each in the
+    /// background. Failures of individual connections are only logged.
+    ///
+    /// # Errors
+    ///
+    /// - If a host key cannot be used, or the policy allows no algorithm that
+    ///   russh implements.
+    /// - If `addr` cannot be bound or accepting a connection fails.
+    pub async fn listen(&self, addr: SocketAddr) -> Result<()> {
+        let config = Arc::new(self.config()?);
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let config = config.clone();
+            let connection = self.connection();
+            tokio::spawn(async move {
+                let result = match russh::server::run_stream(config, stream, connection).await {
+                    Ok(session) => session.await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    tracing::warn!(%peer, %err, "connection failed");
+                }
+            });
+        }
+    }
+
+    fn config(&self) -> Result<russh::server::Config> {
+        let mut config = russh::server::Config {
+            keys: self
+                .host_keys
+                .iter()
+                .map(to_russh_private_key)
+                .collect::<Result<_>>()?,
+            inactivity_timeout: self.inactivity_timeout,
+            ..Default::default()
+        };
+        if let Some(policy) = &self.policy {
+            config.preferred = preferred(&policy.algorithms())?;
+        }
+        if let Some(auth_rejection_time) = self.auth_rejection_time {
+            config.auth_rejection_time = auth_rejection_time;
+            config.auth_rejection_time_initial = Some(auth_rejection_time);
+        }
+
+        Ok(config)
+    }
+
+    fn connection(&self) -> Connection<H> {
+        Connection {
+            handler: self.handler.clone(),
+            user: String::new(),
+            channels: HashMap::new(),
+            terminals: HashMap::new(),
+        }
+    }
+}
+
+/// State of one client of a [`Server`].
+struct Connection<H> {
+    handler: Arc<H>,
+    /// User the client authenticated as.
+    user: String,
+    /// Channels waiting for a request.
+    channels: HashMap<ChannelId, Channel<Msg>>,
+    /// Terminal types of channels that requested a pseudo-terminal.
+    terminals: HashMap<ChannelId, String>,
+}
+
+impl<H> Connection<H> {
+    /// Streams of `channel`, which is no longer waiting for a request.
+    fn take_io(&mut self, channel: ChannelId) -> Option<ChannelIo> {
+        let stream = self.channels.remove(&channel)?;
+        Some(ChannelIo::new(stream, self.terminals.remove(&channel)))
+    }
+
+    fn accept(&mut self, user: &str) -> Auth {
+        self.user = user.to_string();
+        Auth::Accept
+    }
+
+    fn reject() -> Auth {
+        Auth::Reject {
+            proceed_with_methods: None,
+            partial_success: false,
+        }
+    }
+
+    /// Replies to a request on `channel`, running `process` in the background
+    /// if it was accepted and reporting its exit code when it finishes.
+    fn run(
+        channel: ChannelId,
+        process: Option<BoxFuture<'static, u32>>,
+        session: &mut Session,
+    ) -> Result<(), russh::Error> {
+        let Some(process) = process else {
+            return session.channel_failure(channel);
+        };
+
+        session.channel_success(channel)?;
+        let handle = session.handle();
+        tokio::spawn(async move {
+            let code = process.await;
+            // Fails only if the client is already gone.
+            let _ = handle.exit_status_request(channel, code).await;
+            let _ = handle.eof(channel).await;
+            let _ = handle.close(channel).await;
+        });
+
+        Ok(())
+    }
+}
+
+impl<H: Handler> russh::server::Handler for Connection<H> {
+    type Error = russh::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        if self.handler.auth_password(user, password).await {
+            Ok(self.accept(user))
+        } else {
+            Ok(Self::reject())
+        }
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        public_key: &russh::keys::PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        let Ok(key) = to_public_key(public_key) else {
+            return Ok(Self::reject());
+        };
+
+        if self.handler.auth_publickey(user, &key).await {
+            Ok(self.accept(user))
+        } else {
+            Ok(Self::reject())
+        }
+    }
+
+    async fn auth_openssh_certificate(
+        &mut self,
+        user: &str,
+        certificate: &russh::keys::Certificate,
+    ) -> Result<Auth, Self::Error> {
+        let Ok(certificate) = from_russh_certificate(certificate) else {
+            return Ok(Self::reject());
+        };
+
+        if self.handler.auth_certificate(user, &certificate).await {
+            Ok(self.accept(user))
+        } else {
+            Ok(Self::reject())
+        }
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        Ok(true)
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.channels.remove(&channel);
+        self.terminals.remove(&channel);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(russh::Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.terminals.insert(channel, term.to_string());
+        session.channel_success(channel)
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let Some(io) = self.take_io(channel) else {
+            return session.channel_failure(channel);
+        };
+
+        let command = String::from_utf8_lossy(data);
+        let process = self.handler.exec(&self.user, &command, io);
+        Self::run(channel, process, session)
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let Some(io) = self.take_io(channel) else {
+            return session.channel_failure(channel);
+        };
+
+        let process = self.handler.shell(&self.user, io);
+        Self::run(channel, process, session)
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        #[cfg(unix)]
+        if name == "sftp"
+            && let Some(sftp) = self.handler.sftp(&self.user)
+            && let Some(stream) = self.channels.remove(&channel)
+        {
+            session.channel_success(channel)?;
+            sftp.serve(stream.into_stream()).await;
+            return Ok(());
+        }
+
+        let _ = name;
+        session.channel_failure(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::Auth as ClientAuth;
+    use crate::ConnectedSession;
+    use crate::DriverKind;
+    use crate::Error;
+
+    /// Password in `test/creds/password`.
+    const PASSWORD: &str = "test_password";
+
+    /// Lets `alice` in with the password, uppercases stdin for `upper` and
+    /// serves a temporary directory over SFTP.
+    struct Upper {
+        root: tempfile::TempDir,
+    }
+
+    impl Handler for Upper {
+        async fn auth_password(&self, user: &str, password: &str) -> bool {
+            user == "alice" && password == PASSWORD
+        }
+
+        fn exec(
+            &self,
+            _user: &str,
+            command: &str,
+            mut io: ChannelIo,
+        ) -> Option<BoxFuture<'static, u32>> {
+            if command != "upper" {
+                return None;
+            }
+
+            Some(
+                async move {
+                    let mut input = String::new();
+                    let _ = io.stdin.read_to_string(&mut input).await;
+                    let _ = io.stdout.write_all(input.to_uppercase().as_bytes()).await;
+                    let _ = io.stderr.write_all(b"done").await;
+                    3
+                }
+                .boxed(),
+            )
+        }
+
+        fn sftp(&self, _user: &str) -> Option<crate::sftp::Server> {
+            Some(
+                crate::sftp::Server::builder()
+                    .root(self.root.path())
+                    .build(),
+            )
+        }
+    }
+
+    async fn connect(password_file: &str) -> Result<ConnectedSession> {
+        let (client, stream) = tokio::io::duplex(64 * 1024);
+        let server = Server::builder()
+            .host_key(PrivateKey::read_openssh_file("test/creds/id_ed25519".as_ref()).unwrap())
+            .handler(Upper {
+                root: tempfile::tempdir().unwrap(),
+            })
+            .auth_rejection_time(Duration::ZERO)
+            .build();
+        tokio::spawn(async move { server.run_stream(stream).await });
+
+        crate::Session::builder()
+            .user("alice")
+            .host("localhost")
+            .driver(DriverKind::Russh)
+            .auth(ClientAuth::from_password_file(password_file)?)
+            .host_key_verification(crate::HostKeyVerification::AcceptAny)
+            .with_stream(client)
+            .build()
+            .connect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn exec_works() {
+        let session = connect("test/creds/password").await.unwrap();
+        let mut child = session.command("upper").spawn().await.unwrap();
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"shout").await.unwrap();
+        stdin.shutdown().await.unwrap();
+        let output = child.wait_with_output().await.unwrap();
+
+        assert_eq!(output.stdout, b"SHOUT");
+        assert_eq!(output.stderr, b"done");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn exec_refused() {
+        let session = connect("test/creds/password").await.unwrap();
+
+        let result = session
+            .command("whoami")
+            .spawn()
+            .await
+            .unwrap()
+            .wait()
+            .await;
+
+        assert!(matches!(result, Err(Error::ExecRejected)));
+    }
+
+    #[tokio::test]
+    async fn sftp_works() {
+        let session = connect("test/creds/password").await.unwrap();
+        let fs = session.fs().expand_tilde(false);
+
+        fs.write("/served.txt", "served").await.unwrap();
+
+        assert_eq!(fs.read("served.txt").await.unwrap(), b"served");
+    }
+
+    #[tokio::test]
+    async fn auth_rejects_wrong_password() {
+        let wrong = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(wrong.path(), "wrong").unwrap();
+
+        let result = connect(wrong.path().to_str().unwrap()).await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use std::sync::Arc;
+
+use bon::Builder;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use ssh_key::HashAlg;
+use ssh_key::PublicKey;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+use super::ChannelIo;
+use super::Handler;
+
+/// Exit code of a command the fake shell does not know, as in `bash`.
+const COMMAND_NOT_FOUND: u32 = 127;
+/// Longest line the fake shell takes, and longest command recorded, in bytes,
+/// like the 4096 byte line discipline limit of a Linux terminal. What a
+/// client types beyond it is ignored, so that it cannot grow without bound.
+const MAX_LINE: usize = 4096;
+
+/// Something a client of a [`Honeypot`] attempted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEvent {
+    Password {
+        user: String,
+        password: String,
+        accepted: bool,
+    },
+    /// Public keys are always rejected, so that clients fall back to
+    /// passwords.
+    PublicKey {
+        user: String,
+        /// SHA-256 fingerprint of the key.
+        fingerprint: String,
+    },
+    /// Command run with exec, or a line entered into the fake shell. Cut to
+    /// its first 4096 bytes.
+    Command { user: String, command: String },
+}
+
+/// Sink events are recorded to.
+type Sink = Arc<dyn Fn(AuditEvent) + Send + Sync>;
+
+/// [`Handler`] for a honeypot. Logs in clients with the configured
+/// credentials, records every authentication attempt and command, and answers
+/// with a fake shell that knows a handful of commands but never runs any.
+#[derive(Builder)]
+pub struct Honeypot {
+    #[builder(field)]
+    credentials: Vec<(String, String)>,
+
+    /// Accept every password, not only the configured credentials.
+    #[builder(default)]
+    accept_any_password: bool,
+    /// Host name the fake shell reports.
+    #[builder(into, default = "localhost")]
+    hostname: String,
+    /// Called with every event. Events are logged with `tracing` if not set.
+    #[builder(with = |sink: impl Fn(AuditEvent) + Send + Sync + 'static| Arc::new(sink) as Sink)]
+    sink: Option<Sink>,
+}
+
+impl<S: honeypot_builder::State> HoneypotBuilder<S> {
+    /// User name and password to accept. May be given several times.
+    pub fn credential(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials.push((user.into(), password.into()));
+        self
+    }
+}
+
+impl Honeypot {
+    fn fake_shell(&self, user: &str) -> FakeShell {
+        FakeShell {
+            user: user.to_string(),
+            hostname: self.hostname.clone(),
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+impl Handler for Honeypot {
+    async fn auth_password(&self, user: &str, password: &str) -> bool {
+        let accepted = self.accept_any_password
+            || self
+                .credentials
+                .iter()
+                .any(|(u, p)| u == user && p == password);
+        record(
+            self.sink.as_ref(),
+            AuditEvent::Password {
+                user: user.to_string(),
+                password: password.to_string(),
+                accepted,
+            },
+        );
+
+        accepted
+    }
+
+    async fn auth_publickey(&self, user: &str, key: &PublicKey) -> bool {
+        record(
+            self.sink.as_ref(),
+            AuditEvent::PublicKey {
+                user: user.to_string(),
+                fingerprint: key.fingerprint(HashAlg::Sha256).to_string(),
+            },
+        );
+
+        false
+    }
+
+    fn exec(
+        &self,
+        user: &str,
+        command: &str,
+        mut io: ChannelIo,
+    ) -> Option<BoxFuture<'static, u32>> {
+        let shell = self.fake_shell(user);
+        let command = command.to_string();
+
+        Some(
+            async move {
+                let (output, code) = shell.run(&command).unwrap_or_default();
+                let _ = io.stdout.write_all(output.as_bytes()).await;
+                code
+            }
+            .boxed(),
+        )
+    }
+
+    fn shell(&self, user: &str, io: ChannelIo) -> Option<BoxFuture<'static, u32>> {
+        let shell = self.fake_shell(user);
+
+        Some(async move { shell.interact(io).await }.boxed())
+    }
+}
+
+fn record(sink: Option<&Sink>, event: AuditEvent) {
+    if let Some(sink) = sink {
+        sink(event);
+    } else {
+        tracing::info!(?event, "honeypot");
+    }
+}
+
+/// Shell that records commands and pretends to run them.
+struct FakeShell {
+    user: String,
+    hostname: String,
+    sink: Option<Sink>,
+}
+
+impl FakeShell {
+    fn prompt(&self) -> String {
+        let sigil = if self.user == "root" { '#' } else { '$' };
+        format!("{}@{}:~{sigil} ", self.user, self.hostname)
+    }
+
+    /// Records `line` and returns its output and exit code, or `None` if it
+    /// exits the shell. Commands separated by `;` are answered in turn.
+    fn run(&self, line: &str) -> Option<(String, u32)> {
+        if !line.trim().is_empty() {
+            record(
+                self.sink.as_ref(),
+                AuditEvent::Command {
+                    user: self.user.clone(),
+                    command: line[..line.floor_char_boundary(MAX_LINE)].to_string(),
+                },
+            );
+        }
+
+        let mut output = String::new();
+        let mut code = 0;
+        for command in line.split(';') {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            let (text, status) = match words.as_slice() {
+                [] => continue,
+                ["exit" | "logout", ..] => return None,
+                ["whoami"] => (format!("{}\n", self.user), 0),
+                ["id"] => {
+                    let id = if self.user == "root" { 0 } else { 1000 };
+                    let user = &self.user;
+                    let groups = format!("uid={id}({user}) gid={id}({user}) groups={id}({user})\n");
+                    (groups, 0)
+                }
+                ["hostname"] => (format!("{}\n", self.hostname), 0),
+                ["pwd"] if self.user == "root" => ("/root\n".to_string(), 0),
+                ["pwd"] => (format!("/home/{}\n", self.user), 0),
+                ["uname"] => ("Linux\n".to_string(), 0),
+                ["uname", "-a"] => {
+                    let hostname = &self.hostname;
+                    let uname = format!(
+                        "Linux {hostname} 5.15.0-105-generic #115-Ubuntu SMP x86_64 GNU/Linux\n"
+                    );
+                    (uname, 0)
+                }
+                ["echo", words @ ..] => (format!("{}\n", words.join(" ")), 0),
+                ["ls" | "cd" | "true", ..] => (String::new(), 0),
+                [program, ..] => (
+                    format!("-bash: {program}: command not found\n"),
+                    COMMAND_NOT_FOUND,
+                ),
+            };
+            output.push_str(&text);
+            code = status;
+        }
+
+        Some((output, code))
+    }
+
+    /// Reads lines from the client until it exits or disconnects, returning
+    /// the exit code of the last command. Lines are cut to [`MAX_LINE`].
+    async fn interact(&self, mut io: ChannelIo) -> u32 {
+        // Without a terminal the client echoes input itself and expects bare
+        // newlines.
+        let terminal = io.terminal.is_some();
+        let newline = if terminal { "\r\n" } else { "\n" };
+
+        let mut line = String::new();
+        let mut code = 0;
+        let mut buffer = [0; 1024];
+        let mut previous = 0;
+        let mut reply = self.prompt();
+        loop {
+            if io.stdout.write_all(reply.as_bytes()).await.is_err() {
+                return code;
+            }
+            reply.clear();
+
+            let Ok(len @ 1..) = io.stdin.read(&mut buffer).await else {
+                return code;
+            };
+            for &byte in &buffer[..len] {
+                match byte {
+                    // Terminals send `\r`, others `\n`, some both.
+                    b'\n' if previous == b'\r' => {}
+                    b'\r' | b'\n' => {
+                        if terminal {
+                            reply.push_str(newline);
+                        }
+                        let Some((output, status)) = self.run(&line) else {
+                            let _ = io.stdout.write_all(reply.as_bytes()).await;
+                            return code;
+                        };
+                        line.clear();
+                        code = status;
+                        reply.push_str(&output.replace('\n', newline));
+                        reply.push_str(&self.prompt());
+                    }
+                    // Interrupt discards the line.
+                    0x03 => {
+                        line.clear();
+                        if terminal {
+                            reply.push_str("^C");
+                        }
+                        reply.push_str(newline);
+                        reply.push_str(&self.prompt());
+                    }
+                    // End of file on an empty line exits.
+                    0x04 if line.is_empty() => {
+                        let _ = io.stdout.write_all(reply.as_bytes()).await;
+                        return code;
+                    }
+                    // Backspace and delete.
+                    0x08 | 0x7f if !line.is_empty() => {
+                        line.pop();
+                        if terminal {
+                            reply.push_str("\x08 \x08");
+                        }
+                    }
+                    byte if line.len() < MAX_LINE && (byte.is_ascii_graphic() || byte == b' ') => {
+                        line.push(char::from(byte));
+                        if terminal {
+                            reply.push(char::from(byte));
+                        }
+                    }
+                    _ => {}
+                }
+                previous = byte;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use rstest::rstest;
+    use ssh_key::PrivateKey;
+
+    use super::*;
+    use crate::Auth;
+    use crate::DriverKind;
+    use crate::server::Server;
+
+    fn shell(user: &str) -> FakeShell {
+        FakeShell {
+            user: user.to_string(),
+            hostname: "web-01".to_string(),
+            sink: None,
+        }
+    }
+
+    #[rstest]
+    #[case("alice", "whoami", Some(("alice\n", 0)))]
+    #[case("root", "id", Some(("uid=0(root) gid=0(root) groups=0(root)\n", 0)))]
+    #[case("alice", "pwd", Some(("/home/alice\n", 0)))]
+    #[case("alice", "cd /tmp; hostname", Some(("web-01\n", 0)))]
+    #[case("alice", "echo  hello   world", Some(("hello world\n", 0)))]
+    #[case("alice", "wget http://x", Some(("-bash: wget: command not found\n", 127)))]
+    #[case("alice", "ls; exit", None)]
+    #[case("alice", "", Some(("", 0)))]
+    fn run_works(
+        #[case] user: &str,
+        #[case] line: &str,
+        #[case] reply_should: Option<(&str, u32)>,
+    ) {
+        let reply = shell(user).run(line);
+
+        assert_eq!(
+            reply
+                .as_ref()
+                .map(|(output, code)| (output.as_str(), *code)),
+            reply_should
+        );
+    }
+
+    #[tokio::test]
+    async fn interact_bounds_lines() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let shell = FakeShell {
+            sink: Some({
+                let events = events.clone();
+                Arc::new(move |event: AuditEvent| events.lock().unwrap().push(event))
+            }),
+            ..shell("alice")
+        };
+        let mut input = vec![b'a'; 3 * MAX_LINE];
+        input.extend_from_slice(b"\nexit\n");
+        let io = ChannelIo {
+            terminal: None,
+            stdin: Box::new(std::io::Cursor::new(input)),
+            stdout: Box::new(tokio::io::sink()),
+            stderr: Box::new(tokio::io::sink()),
+        };
+
+        let code = shell.interact(io).await;
+
+        assert_eq!(code, COMMAND_NOT_FOUND);
+        assert!(matches!(
+            events.lock().unwrap().as_slice(),
+            [AuditEvent::Command { command, .. }, _] if command.len() == MAX_LINE
+        ));
+    }
+
+    #[test]
+    fn run_bounds_recorded_commands() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let shell = FakeShell {
+            sink: Some({
+                let events = events.clone();
+                Arc::new(move |event: AuditEvent| events.lock().unwrap().push(event))
+            }),
+            ..shell("alice")
+        };
+
+        shell.run(&"echo ".repeat(MAX_LINE));
+
+        assert!(matches!(
+            events.lock().unwrap().as_slice(),
+            [AuditEvent::Command { command, .. }] if command.len() == MAX_LINE
+        ));
+    }
+
+    #[tokio::test]
+    async fn honeypot_records_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let honeypot = Honeypot::builder()
+            .credential("alice", "test_password")
+            .sink({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event)
+            })
+            .build();
+        let server = Server::builder()
+            .host_key(PrivateKey::read_openssh_file("test/creds/id_ed25519".as_ref()).unwrap())
+            .handler(honeypot)
+            .auth_rejection_time(Duration::ZERO)
+            .build();
+        let (client, stream) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move { server.run_stream(stream).await });
+
+        let session = crate::Session::builder()
+            .user("alice")
+            .host("localhost")
+            .driver(DriverKind::Russh)
+            .auth(Auth::from_password_file("test/creds/password").unwrap())
+            .host_key_verification(crate::HostKeyVerification::AcceptAny)
+            .with_stream(client)
+            .build()
+            .connect()
+            .await
+            .unwrap();
+        let output = session
+            .command("whoami")
+            .spawn()
+            .await
+            .unwrap()
+            .wait_with_output()
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, b"alice\n");
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                AuditEvent::Password {
+                    user: "alice".to_string(),
+                    password: "test_password".to_string(),
+                    accepted: true,
+                },
+                AuditEvent::Command {
+                    user: "alice".to_string(),
+                    command: "whoami".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use futures::stream::BoxStream;
+use russh_sftp::client::SftpSession;
+use ssh_key::Certificate;
+use ssh_key::PublicKey;
+use tokio::sync::OnceCell;
+
+use crate::AuthOutcome;
+use crate::DriverKind;
+use crate::Error;
+use crate::Event;
+use crate::ResolvedConfig;
+use crate::Result;
+use crate::Traffic;
+use crate::config::summary;
+use crate::driver::Connected;
+#[cfg(any(feature = "openssh", feature = "russh"))]
+use crate::driver::Session as _;
+use crate::event::Events;
+use crate::fs::Fs;
+use crate::jobs::Jobs;
+use crate::platform::Platform;
+use crate::process::Command;
+use crate::process::CommandEnv;
+use crate::transport::AsyncStream;
+use crate::transport::meter::ChannelKind;
+use crate::transport::meter::Counters;
+
+/// Authenticated SSH session, created by [`crate::Session::connect`].
+pub struct ConnectedSession {
+    pub(crate) inner: Arc<Connected>,
+    user: String,
+    host: String,
+    port: u16,
+    auth_outcome: AuthOutcome,
+    pub(crate) traffic: Arc<Counters>,
+    pub(crate) events: Events,
+    command_env: CommandEnv,
+    pub(crate) restore_forwards_after: Option<Duration>,
+    pub(crate) remote_env: OnceCell<HashMap<String, String>>,
+    pub(crate) selinux: OnceCell<bool>,
+    pub(crate) platform: OnceCell<Platform>,
+    sftp: OnceCell<SftpSession>,
+    home_dir: OnceCell<Utf8PathBuf>,
+}
+
+impl ConnectedSession {
+    pub(crate) fn new(
+        inner: Connected,
+        resolved: &ResolvedConfig,
+        auth_outcome: AuthOutcome,
+        traffic: Arc<Counters>,
+        events: Events,
+        command_env: CommandEnv,
+        restore_forwards_after: Option<Duration>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            user: resolved.user.clone(),
+            host: resolved.host.clone(),
+            port: resolved.port,
+            auth_outcome,
+            traffic,
+            events,
+            command_env,
+            restore_forwards_after,
+            remote_env: OnceCell::new(),
+            selinux: OnceCell::new(),
+            platform: OnceCell::new(),
+            sftp: OnceCell::new(),
+            home_dir: OnceCell::new(),
+        }
+    }
+
+    /// File system of the remote host. The SFTP subsystem is started on first
+    /// use and shared by all file operations of the session.
+    #[must_use]
+    pub fn fs(&self) -> Fs<'_> {
+        Fs::new(self)
+    }
+
+    /// Detached jobs recorded on the remote host, to start new ones or check
+    /// on those started by earlier sessions.
+    #[must_use]
+    pub fn jobs(&self) -> Jobs<'_> {
+        Jobs::new(self)
+    }
+
+    pub(crate) async fn sftp(&self) -> Result<&SftpSession> {
+        self.sftp
+            .get_or_try_init(|| async {
+                let slot = self.traffic.open_channel(ChannelKind::Sftp)?;
+                let stream = self.inner.open_sftp().await?;
+                slot.commit();
+                let stream = self.traffic.meter(stream, ChannelKind::Sftp);
+                Ok(SftpSession::new(stream).await?)
+            })
+            .await
+    }
+
+    /// Directory SFTP sessions start in, which is the remote user's home.
+    pub(crate) async fn home_dir(&self) -> Result<&Utf8PathBuf> {
+        self.home_dir
+            .get_or_try_init(|| async {
+                let home = self.sftp().await?.canonicalize(".").await?;
+                Ok(home.into())
+            })
+            .await
+    }
+
+    /// Stream of lifecycle events from now on, such as channels opened,
+    /// rekeys, missed keepalives and disconnection. Any number of streams may
+    /// be open at once; each sees every event, unless it falls so far
+    /// behind that older ones are dropped. The stream ends when the session
+    /// is dropped.
+    #[must_use]
+    pub fn events(&self) -> BoxStream<'static, Event> {
+        self.events.subscribe()
+    }
+
+    /// Builder for a command to run on the remote host, like
+    /// [`tokio::process::Command::new`]. Commands without a pty run with the
+    /// session's locale and `TERM`.
+    pub fn command(&self, program: impl Into<String>) -> Command<'_> {
+        Command::new(&self.inner, &self.command_env, &self.traffic, program)
+    }
+
+    /// Opens a TCP connection from the remote host to `host` and `port`, like
+    /// OpenSSH's `-W`. The stream can carry another session, passed to
+    /// [`crate::Session::with_stream`], to reach hosts only the remote host
+    /// can; file system access and commands on that session then work as if
+    /// it were direct.
+    ///
+    /// # Errors
+    ///
+    /// - If the server refuses or fails to connect to `host`, reported as
+    ///   [`Error::TunnelFailed`] so that a failure in a chain of sessions can
+    ///   be told apart from a failure of the final one.
+    /// - If the session opened as many channels as
+    ///   [`crate::SessionBuilder::max_channels`] allows, as
+    ///   [`Error::LimitReached`].
+    pub async fn open_tunnel(&self, host: &str, port: u16) -> Result<Box<dyn AsyncStream>> {
+        let slot = self.traffic.open_channel(ChannelKind::Forward)?;
+        let stream =
+            self.inner
+                .open_tunnel(host, port)
+                .await
+                .map_err(|source| Error::TunnelFailed {
+                    host: host.to_string(),
+                    port,
+                    source: Box::new(source),
+                })?;
+        slot.commit();
+
+        Ok(self.traffic.meter(stream, ChannelKind::Forward))
+    }
+
+    /// Ends the session, closing every command, tunnel and file operation
+    /// still using it. Later operations on the session fail.
+    ///
+    /// # Errors
+    ///
+    /// - If the session has already ended.
+    pub async fn disconnect(&self) -> Result<()> {
+        self.inner.disconnect().await
+    }
+
+    /// Bytes transferred by the session so far, over its transport and by
+    /// kind of channel.
+    #[must_use]
+    pub fn traffic(&self) -> Traffic {
+        self.traffic.snapshot()
+    }
+
+    /// Whether the session was terminated for using up its byte quota.
+    #[must_use]
+    pub fn is_quota_exhausted(&self) -> bool {
+        self.traffic.is_exhausted()
+    }
+
+    /// Driver that established the session, which is the first in the chain
+    /// to connect successfully.
+    #[must_use]
+    pub fn driver(&self) -> DriverKind {
+        self.inner.kind()
+    }
+
+    /// Remote user the session is logged in as.
+    #[must_use]
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// Host connected to, after any `HostName` substitution.
+    #[must_use]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// One-line description of the session for logs, such as
+    /// `deploy@web1:22 via russh (auth: agent)`. Secrets are never included.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        summary(
+            &self.user,
+            &self.host,
+            self.port,
+            &[self.driver()],
+            &[self.auth_outcome.kind()],
+        )
+    }
+
+    /// Which authentication payload the server accepted.
+    #[must_use]
+    pub fn auth_outcome(&self) -> &AuthOutcome {
+        &self.auth_outcome
+    }
+
+    /// Certificate the server accepted for user authentication, if any.
+    #[must_use]
+    pub fn accepted_certificate(&self) -> Option<&Certificate> {
+        match &self.auth_outcome {
+            AuthOutcome::Cert { certificate } => Some(certificate),
+            _ => None,
+        }
+    }
+
+    /// Underlying russh handle, for features this crate does not wrap yet.
+    /// Returns `None` if another driver established the session.
+    ///
+    /// Only available with the `unstable-raw` feature, which is exempt from
+    /// semver: the handle's type changes with the russh version. Closing the
+    /// session or channels through it leaves this crate's state stale.
+    #[cfg(all(feature = "russh", feature = "unstable-raw"))]
+    #[must_use]
+    pub fn as_russh(
+        &self,
+    ) -> Option<&::russh::client::Handle<crate::driver::russh::ClientHandler>> {
+        match *self.inner {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(_) => None,
+            Connected::Russh(ref session) => Some(session.as_raw()),
+        }
+    }
+
+    /// Control socket of the `ssh` master process, to run `ssh -S` over the
+    /// same connection. Returns `None` if another driver established the
+    /// session.
+    ///
+    /// Only available with the `unstable-raw` feature, which is exempt from
+    /// semver. Closing the master through it leaves this crate's state stale.
+    #[cfg(all(feature = "openssh", feature = "unstable-raw"))]
+    #[must_use]
+    pub fn as_openssh_control_path(&self) -> Option<&camino::Utf8Path> {
+        match *self.inner {
+            Connected::OpenSsh(ref session) => Some(session.control_path()),
+            #[cfg(feature = "russh")]
+            Connected::Russh(_) => None,
+        }
+    }
+
+    /// Process ID of the `ssh` master process, to signal or watch it. Returns
+    /// `None` if another driver established the session, or once the master
+    /// has exited. The process itself stays with the session, which kills it
+    /// when it ends, so it cannot be waited for through this.
+    ///
+    /// Only available with the `unstable-raw` feature, which is exempt from
+    /// semver. Stopping the master through it leaves this crate's state
+    /// stale.
+    #[cfg(all(feature = "openssh", feature = "unstable-raw"))]
+    #[must_use]
+    pub fn as_openssh_master_pid(&self) -> Option<u32> {
+        match *self.inner {
+            Connected::OpenSsh(ref session) => session.master_pid(),
+            #[cfg(feature = "russh")]
+            Connected::Russh(_) => None,
+        }
+    }
+
+    /// Number of times session keys have been renegotiated since the initial
+    /// key exchange. The server host key is checked again on every rekey, and
+    /// the session fails if it changed and the host key verifier does not
+    /// accept the new one, or if every key was accepted at first.
+    #[must_use]
+    pub fn rekey_count(&self) -> usize {
+        match *self.inner {
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(ref session) => session.rekey_count(),
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.rekey_count(),
+        }
+    }
+
+    /// Host key the server presented during the initial key exchange.
+    #[must_use]
+    pub fn host_key(&self) -> Option<PublicKey> {
+        match *self.inner {
+            // `ssh` does not hand out the key it checked.
+            #[cfg(feature = "openssh")]
+            Connected::OpenSsh(_) => None,
+            #[cfg(feature = "russh")]
+            Connected::Russh(ref session) => session.host_key(),
+        }
+    }
+}
+
+impl fmt::Display for ConnectedSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.summary())
+    }
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn fs_works_through_tunnel() {
+        let jump = test_server::connect().await;
+        let tunnel = jump.open_tunnel("target", 22).await.unwrap();
+        let target = test_server::connect_stream(tunnel).await;
+        let fs = target.fs();
+
+        fs.write("~/hop.txt", "two hops").await.unwrap();
+
+        assert_eq!(fs.read("~/hop.txt").await.unwrap(), b"two hops");
+        assert!(!jump.fs().try_exists("~/hop.txt").await.unwrap());
+    }
+
+    #[cfg(feature = "unstable-raw")]
+    #[tokio::test]
+    async fn as_russh_works() {
+        let session = test_server::connect().await;
+
+        let handle = session.as_russh().unwrap();
+
+        assert!(!handle.is_closed());
+    }
+
+    #[tokio::test]
+    async fn display_works() {
+        let session = test_server::connect().await;
+
+        assert_eq!(
+            session.to_string(),
+            format!(
+                "{}@localhost:22 via russh (auth: password)",
+                test_server::USER
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect_works() {
+        let session = test_server::connect().await;
+
+        session.disconnect().await.unwrap();
+
+        assert!(session.command("true").spawn().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn traffic_counts_channels_by_kind() {
+        let session = test_server::connect().await;
+
+        let output = session.command("echo").arg("hello").output().await.unwrap();
+        session.fs().write("~/hello.txt", "hello").await.unwrap();
+
+        let traffic = session.traffic();
+        assert!(traffic.exec.received >= output.stdout.len() as u64);
+        assert!(traffic.sftp.sent > b"hello".len() as u64);
+        assert!(traffic.sftp.received > 0);
+        assert_eq!(traffic.forward, crate::ChannelTraffic::default());
+        assert!(traffic.sent > traffic.exec.sent + traffic.sftp.sent);
+    }
+
+    #[tokio::test]
+    async fn limits_are_enforced() {
+        let mut session = test_server::session("localhost");
+        session.max_commands = Some(1);
+        session.max_channels = Some(2);
+        session.stream = Some(test_server::spawn().into_stream().unwrap());
+        let session = session.connect().await.unwrap();
+
+        session.command("true").status().await.unwrap();
+        let command = session.command("true").status().await;
+        session.fs().try_exists("~").await.unwrap();
+        let tunnel = session.open_tunnel("target", 22).await;
+
+        assert!(matches!(
+            command,
+            Err(Error::LimitReached {
+                limit: "commands",
+                max: 1
+            })
+        ));
+        assert!(matches!(
+            tunnel,
+            Err(Error::LimitReached {
+                limit: "channels",
+                max: 2
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn limits_ignore_failed_opens() {
+        let mut session = test_server::session("localhost");
+        session.max_channels = Some(1);
+        session.stream = Some(test_server::spawn().into_stream().unwrap());
+        let session = session.connect().await.unwrap();
+
+        let refused = session.open_tunnel(test_server::UNREACHABLE_HOST, 22).await;
+        let opened = session.open_tunnel("target", 22).await;
+
+        assert!(matches!(refused, Err(Error::TunnelFailed { .. })));
+        opened.unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_tunnel_reports_target() {
+        let jump = test_server::connect().await;
+
+        let result = jump.open_tunnel(test_server::UNREACHABLE_HOST, 22).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::TunnelFailed { host, port: 22, .. }) if host == test_server::UNREACHABLE_HOST
+        ));
+    }
+}
@@ -0,0 +1,625 @@
+//! SFTP server backed by the local file system.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::SeekFrom;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bon::Builder;
+use russh_sftp::extensions;
+use russh_sftp::protocol::Attrs;
+use russh_sftp::protocol::Data;
+use russh_sftp::protocol::File;
+use russh_sftp::protocol::FileAttributes;
+use russh_sftp::protocol::Handle;
+use russh_sftp::protocol::Name;
+use russh_sftp::protocol::OpenFlags;
+use russh_sftp::protocol::Packet;
+use russh_sftp::protocol::Status;
+use russh_sftp::protocol::StatusCode;
+use russh_sftp::protocol::Version;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+
+/// Most bytes sent for one read, however many the client asks for, so that a
+/// client cannot make the server allocate up to 4 GiB at once. Clients send
+/// more reads for the rest, as they would at the end of a file.
+const MAX_READ: u32 = 256 * 1024;
+
+/// SFTP server for a local directory, which can serve any stream, such as a
+/// channel from a remote host that wants to pull files from this one.
+///
+/// By default clients are confined to `root`, which they see as `/`. Paths
+/// that lead outside of it, including through symbolic links, are refused,
+/// as are links that would lead outside of it. Files are not opened through
+/// links that lead nowhere, since where they would be created cannot be
+/// checked.
+///
+/// Only the permission bits of the modes clients set are kept, so that they
+/// cannot create setuid or setgid files.
+#[derive(Debug, Clone, Builder)]
+pub struct Server {
+    /// Directory that clients start in.
+    #[builder(into)]
+    root: PathBuf,
+    /// Whether clients are confined to `root`. If disabled, absolute paths
+    /// refer to the whole local file system, like OpenSSH's `sftp-server`.
+    #[builder(default = true)]
+    confine: bool,
+    /// Whether to refuse every request that would modify the file system.
+    #[builder(default)]
+    read_only: bool,
+}
+
+impl Server {
+    /// Serves SFTP over `stream` in the background, until the client closes
+    /// it.
+    pub async fn serve(&self, stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static) {
+        russh_sftp::server::run(stream, LocalFs::new(self.clone())).await;
+    }
+}
+
+enum OpenHandle {
+    File(tokio::fs::File),
+    /// Directory entries, taken by the first read.
+    Dir(Option<Vec<File>>),
+}
+
+/// State of one client of a [`Server`].
+struct LocalFs {
+    server: Server,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+}
+
+impl LocalFs {
+    fn new(server: Server) -> Self {
+        Self {
+            server,
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Local path for a path sent by the client.
+    async fn resolve(&self, path: &str) -> Result<PathBuf, StatusCode> {
+        if !self.server.confine {
+            return Ok(self.server.root.join(path));
+        }
+
+        // `..` is resolved lexically, so it cannot climb above the root.
+        let mut resolved = self.server.root.clone();
+        let mut depth = 0;
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => {
+                    resolved.push(name);
+                    depth += 1;
+                }
+                Component::ParentDir if depth > 0 => {
+                    resolved.pop();
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+
+        // Symbolic links can still lead out, so check where the deepest
+        // existing ancestor really is.
+        let root = tokio::fs::canonicalize(&self.server.root).await.status()?;
+        let mut existing = resolved.as_path();
+        let real = loop {
+            match tokio::fs::canonicalize(existing).await {
+                Ok(real) => break real,
+                Err(_) => existing = existing.parent().ok_or(StatusCode::NoSuchFile)?,
+            }
+        };
+        if !real.starts_with(&root) {
+            return Err(StatusCode::PermissionDenied);
+        }
+
+        Ok(resolved)
+    }
+
+    /// Path to show the client for a local path.
+    async fn display(&self, path: &Path) -> Result<String, StatusCode> {
+        if !self.server.confine {
+            return Ok(path.to_string_lossy().into_owned());
+        }
+
+        let root = tokio::fs::canonicalize(&self.server.root).await.status()?;
+        let relative = path
+            .strip_prefix(&root)
+            .map_err(|_| StatusCode::PermissionDenied)?;
+        Ok(format!("/{}", relative.to_string_lossy()))
+    }
+
+    /// Whether a symbolic link at `link`, a resolved path, to `target` stays
+    /// within the root, judged from `target` alone, as links it passes
+    /// through are checked when it is followed.
+    fn link_stays_inside(&self, link: &Path, target: &str) -> bool {
+        let Ok(relative) = link.strip_prefix(&self.server.root) else {
+            return false;
+        };
+        // Relative targets start from the directory the link is in.
+        let mut depth = relative.components().count().saturating_sub(1);
+        for component in Path::new(target).components() {
+            match component {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir if depth > 0 => depth -= 1,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+            }
+        }
+
+        true
+    }
+
+    fn check_writable(&self) -> Result<(), StatusCode> {
+        if self.server.read_only {
+            return Err(StatusCode::PermissionDenied);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, handle: OpenHandle) -> String {
+        self.next_handle += 1;
+        let name = self.next_handle.to_string();
+        self.handles.insert(name.clone(), handle);
+        name
+    }
+
+    fn file(&mut self, handle: &str) -> Result<&mut tokio::fs::File, StatusCode> {
+        match self.handles.get_mut(handle) {
+            Some(OpenHandle::File(file)) => Ok(file),
+            _ => Err(StatusCode::Failure),
+        }
+    }
+}
+
+/// Permissions for a mode sent by a client, without the setuid, setgid and
+/// sticky bits.
+fn permissions(mode: u32) -> std::fs::Permissions {
+    PermissionsExt::from_mode(mode & 0o777)
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn status_code(error: &io::Error) -> StatusCode {
+    match error.kind() {
+        io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    }
+}
+
+trait IoResultExt<T> {
+    fn status(self) -> Result<T, StatusCode>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn status(self) -> Result<T, StatusCode> {
+        self.map_err(|error| status_code(&error))
+    }
+}
+
+impl russh_sftp::server::Handler for LocalFs {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn init(
+        &mut self,
+        _version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version, Self::Error> {
+        let mut version = Version::new();
+        version
+            .extensions
+            .insert(extensions::FSYNC.to_string(), "1".to_string());
+        Ok(version)
+    }
+
+    async fn extended(
+        &mut self,
+        id: u32,
+        request: String,
+        data: Vec<u8>,
+    ) -> Result<Packet, Self::Error> {
+        if request != extensions::FSYNC {
+            return Err(StatusCode::OpUnsupported);
+        }
+
+        // The request data is the handle as an SSH string.
+        let handle = data
+            .get(4..)
+            .and_then(|handle| std::str::from_utf8(handle).ok())
+            .ok_or(StatusCode::BadMessage)?;
+        self.file(handle)?.sync_all().await.status()?;
+        Ok(Packet::Status(ok(id)))
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        if !(pflags - OpenFlags::READ).is_empty() {
+            self.check_writable()?;
+        }
+        let options = std::fs::OpenOptions::from(pflags);
+        let path = self.resolve(&filename).await?;
+        // A link that leads nowhere resolves to its own directory, so where
+        // it leads is unchecked.
+        if self.server.confine
+            && tokio::fs::symlink_metadata(&path)
+                .await
+                .is_ok_and(|metadata| metadata.is_symlink())
+            && tokio::fs::metadata(&path).await.is_err()
+        {
+            return Err(StatusCode::PermissionDenied);
+        }
+        let file = tokio::fs::OpenOptions::from(options)
+            .open(&path)
+            .await
+            .status()?;
+        if let Some(mode) = attrs.permissions {
+            file.set_permissions(permissions(mode)).await.status()?;
+        }
+
+        Ok(Handle {
+            id,
+            handle: self.insert(OpenHandle::File(file)),
+        })
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        self.handles.remove(&handle).ok_or(StatusCode::Failure)?;
+        Ok(ok(id))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset)).await.status()?;
+
+        let mut data = vec![0; len.min(MAX_READ) as usize];
+        let read = file.read(&mut data).await.status()?;
+        if read == 0 {
+            return Err(StatusCode::Eof);
+        }
+        data.truncate(read);
+
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset)).await.status()?;
+        file.write_all(&data).await.status()?;
+        // Writes are finished in the background otherwise, after the client
+        // was told they are done.
+        file.flush().await.status()?;
+
+        Ok(ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let metadata = tokio::fs::symlink_metadata(self.resolve(&path).await?)
+            .await
+            .status()?;
+        Ok(Attrs {
+            id,
+            attrs: (&metadata).into(),
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let metadata = tokio::fs::metadata(self.resolve(&path).await?)
+            .await
+            .status()?;
+        Ok(Attrs {
+            id,
+            attrs: (&metadata).into(),
+        })
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let metadata = self.file(&handle)?.metadata().await.status()?;
+        Ok(Attrs {
+            id,
+            attrs: (&metadata).into(),
+        })
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        if let Some(mode) = attrs.permissions {
+            tokio::fs::set_permissions(self.resolve(&path).await?, permissions(mode))
+                .await
+                .status()?;
+        }
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        if let Some(mode) = attrs.permissions {
+            self.file(&handle)?
+                .set_permissions(permissions(mode))
+                .await
+                .status()?;
+        }
+        Ok(ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(self.resolve(&path).await?)
+            .await
+            .status()?;
+        while let Some(entry) = read_dir.next_entry().await.status()? {
+            let metadata = tokio::fs::symlink_metadata(entry.path()).await.status()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push(File::new(name, (&metadata).into()));
+        }
+
+        Ok(Handle {
+            id,
+            handle: self.insert(OpenHandle::Dir(Some(entries))),
+        })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Dir(entries)) => {
+                let files = entries.take().ok_or(StatusCode::Eof)?;
+                Ok(Name { id, files })
+            }
+            _ => Err(StatusCode::Failure),
+        }
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        tokio::fs::remove_file(self.resolve(&filename).await?)
+            .await
+            .status()?;
+        Ok(ok(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        let path = self.resolve(&path).await?;
+        tokio::fs::create_dir(&path).await.status()?;
+        if let Some(mode) = attrs.permissions {
+            tokio::fs::set_permissions(&path, permissions(mode))
+                .await
+                .status()?;
+        }
+        Ok(ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        tokio::fs::remove_dir(self.resolve(&path).await?)
+            .await
+            .status()?;
+        Ok(ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let path = tokio::fs::canonicalize(self.resolve(&path).await?)
+            .await
+            .status()?;
+        Ok(Name {
+            id,
+            files: vec![File::dummy(self.display(&path).await?)],
+        })
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        tokio::fs::rename(self.resolve(&oldpath).await?, self.resolve(&newpath).await?)
+            .await
+            .status()?;
+        Ok(ok(id))
+    }
+
+    async fn readlink(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let target = tokio::fs::read_link(self.resolve(&path).await?)
+            .await
+            .status()?;
+        Ok(Name {
+            id,
+            files: vec![File::dummy(target.to_string_lossy())],
+        })
+    }
+
+    async fn symlink(
+        &mut self,
+        id: u32,
+        linkpath: String,
+        targetpath: String,
+    ) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        let link = self.resolve(&linkpath).await?;
+        if self.server.confine && !self.link_stays_inside(&link, &targetpath) {
+            return Err(StatusCode::PermissionDenied);
+        }
+        tokio::fs::symlink(targetpath, link).await.status()?;
+        Ok(ok(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use russh_sftp::client::SftpSession;
+    use russh_sftp::server::Handler;
+
+    use super::*;
+
+    async fn connect(server: &Server) -> SftpSession {
+        let (client, stream) = tokio::io::duplex(64 * 1024);
+        server.serve(stream).await;
+        SftpSession::new(client).await.unwrap()
+    }
+
+    async fn write(sftp: &SftpSession, path: &str, contents: &[u8]) -> io::Result<()> {
+        let mut file = sftp.create(path).await.map_err(io::Error::other)?;
+        file.write_all(contents).await?;
+        file.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn serve_works() {
+        let root = tempfile::tempdir().unwrap();
+        let sftp = connect(&Server::builder().root(root.path()).build()).await;
+
+        write(&sftp, "/hello.txt", b"hello").await.unwrap();
+
+        let local = std::fs::read(root.path().join("hello.txt")).unwrap();
+        assert_eq!(local, b"hello");
+        assert_eq!(sftp.canonicalize(".").await.unwrap(), "/");
+    }
+
+    #[tokio::test]
+    async fn serve_caps_reads() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("big.bin"), vec![7; 1024 * 1024]).unwrap();
+        let mut fs = LocalFs::new(Server::builder().root(root.path()).build());
+
+        let handle = fs
+            .open(
+                1,
+                "big.bin".to_string(),
+                OpenFlags::READ,
+                FileAttributes::empty(),
+            )
+            .await
+            .unwrap();
+        let data = fs.read(2, handle.handle, 0, u32::MAX).await.unwrap();
+
+        assert_eq!(data.data.len(), MAX_READ as usize);
+    }
+
+    #[tokio::test]
+    async fn serve_confines_to_root() {
+        let parent = tempfile::tempdir().unwrap();
+        let root = parent.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(parent.path().join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(parent.path(), root.join("escape")).unwrap();
+        let sftp = connect(&Server::builder().root(&root).build()).await;
+
+        assert!(sftp.read("../secret").await.is_err());
+        assert!(sftp.read("/escape/secret").await.is_err());
+        assert_eq!(sftp.canonicalize("/../..").await.unwrap(), "/");
+    }
+
+    #[tokio::test]
+    async fn serve_confines_links() {
+        let parent = tempfile::tempdir().unwrap();
+        let root = parent.path().join("root");
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        let outside = parent.path().join("new_file");
+        std::os::unix::fs::symlink(&outside, root.join("dangling")).unwrap();
+        let sftp = connect(&Server::builder().root(&root).build()).await;
+
+        let absolute = sftp.symlink("absolute", outside.to_str().unwrap()).await;
+        let climbing = sftp.symlink("dir/climbing", "../../new_file").await;
+        let inside = sftp.symlink("dir/inside", "../kept.txt").await;
+        let written = write(&sftp, "dangling", b"escaped").await;
+
+        assert!(absolute.is_err());
+        assert!(climbing.is_err());
+        inside.unwrap();
+        assert!(written.is_err());
+        assert!(!outside.exists());
+    }
+
+    #[tokio::test]
+    async fn serve_drops_special_mode_bits() {
+        let root = tempfile::tempdir().unwrap();
+        let mut fs = LocalFs::new(Server::builder().root(root.path()).build());
+
+        fs.open(
+            1,
+            "suid".to_string(),
+            OpenFlags::CREATE | OpenFlags::WRITE,
+            FileAttributes {
+                permissions: Some(0o4755),
+                ..FileAttributes::empty()
+            },
+        )
+        .await
+        .unwrap();
+
+        let mode = std::fs::metadata(root.path().join("suid"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+
+    #[tokio::test]
+    async fn serve_read_only_rejects_writes() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("kept.txt"), "kept").unwrap();
+        let server = Server::builder().root(root.path()).read_only(true).build();
+        let sftp = connect(&server).await;
+
+        assert_eq!(sftp.read("kept.txt").await.unwrap(), b"kept");
+        assert!(write(&sftp, "new.txt", b"new").await.is_err());
+        assert!(sftp.remove_file("kept.txt").await.is_err());
+        assert!(root.path().join("kept.txt").exists());
+    }
+}
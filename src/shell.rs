@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+
+use ssh_encoding::base64::Base64;
+use ssh_encoding::base64::Encoding;
+
+/// Bytes that never need quoting in a POSIX shell word.
+const SAFE: &[u8] = b"-_./:,+@%";
+
+/// Characters `cmd.exe` gives a meaning to, outside double quotes.
+const CMD_SPECIAL: &[char] = &['(', ')', '%', '!', '^', '"', '<', '>', '&', '|'];
+
+/// Characters PowerShell ends a single-quoted string on, unless doubled.
+const POWERSHELL_QUOTES: &[char] = &['\'', '\u{2018}', '\u{2019}', '\u{201a}', '\u{201b}'];
+
+/// Shell the server runs a command line with: the remote user's login shell,
+/// which SSH servers start every command through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Shell {
+    /// A POSIX shell such as `sh` or `bash`, as on Unix-like hosts.
+    #[default]
+    Posix,
+    /// `cmd.exe`, the default shell of OpenSSH on Windows.
+    Cmd,
+    /// Windows PowerShell, started as `powershell` from the login shell with
+    /// the command as an encoded script, which no shell in between alters.
+    /// Works whatever the login shell is.
+    PowerShell,
+}
+
+/// Quotes `word` for a POSIX shell, so that it reaches the remote program as a
+/// single argument exactly as given. Words that need no quoting are returned
+/// unchanged.
+pub fn quote(word: &str) -> Cow<'_, str> {
+    let is_safe = !word.is_empty()
+        && word
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || SAFE.contains(&byte));
+    if is_safe {
+        return Cow::Borrowed(word);
+    }
+
+    Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+}
+
+/// Joins `words` into a command line, quoting each one.
+pub fn join<'a>(words: impl IntoIterator<Item = &'a str>) -> String {
+    words.into_iter().map(quote).collect::<Vec<_>>().join(" ")
+}
+
+/// Quotes `word` the way Windows programs split their command line, as
+/// `CommandLineToArgvW` does, and escapes the result for `cmd.exe`, so that
+/// it reaches the program as a single argument exactly as given.
+pub fn quote_cmd(word: &str) -> String {
+    escape_cmd(&quote_argv(word))
+}
+
+/// `text` with every character `cmd.exe` would interpret escaped with `^`,
+/// so that it is passed on literally.
+pub fn escape_cmd(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if CMD_SPECIAL.contains(&c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quotes `word` as a single argument for `CommandLineToArgvW`: in double
+/// quotes if it has whitespace or quotes, with the backslashes before a quote
+/// doubled.
+fn quote_argv(word: &str) -> Cow<'_, str> {
+    if !word.is_empty() && !word.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return Cow::Borrowed(word);
+    }
+
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in word.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let escapes = if c == '"' {
+            backslashes * 2 + 1
+        } else {
+            backslashes
+        };
+        quoted.extend(std::iter::repeat_n('\\', escapes));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    // Backslashes before the closing quote would escape it.
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+
+    Cow::Owned(quoted)
+}
+
+/// Quotes `word` as a single-quoted PowerShell string, in which nothing is
+/// expanded.
+pub fn quote_powershell(word: &str) -> String {
+    let mut quoted = String::from('\'');
+    for c in word.chars() {
+        if POWERSHELL_QUOTES.contains(&c) {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Command line running `script` with Windows PowerShell, passed as Base64 of
+/// its UTF-16 encoding so that it needs no quoting in any shell.
+pub fn powershell(script: &str) -> String {
+    let utf16: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    format!(
+        "powershell -NoProfile -NonInteractive -EncodedCommand {}",
+        Base64::encode_string(&utf16)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Arguments of any content a command line can carry, which excludes
+    /// NUL.
+    fn words() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec("[^\\x00]{0,12}", 1..6)
+    }
+
+    /// Output of `printf '%s\0'` given `words`.
+    fn nul_terminated(words: &[String]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|word| [word.as_bytes(), b"\0"].concat())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("uname", "uname")]
+    #[case("/usr/bin/env", "/usr/bin/env")]
+    #[case("", "''")]
+    #[case("hello world", "'hello world'")]
+    #[case("$HOME", "'$HOME'")]
+    #[case("~", "'~'")]
+    #[case("A=b", "'A=b'")]
+    #[case("it's", r"'it'\''s'")]
+    fn quote_works(#[case] word: &str, #[case] quoted_should: &str) {
+        assert_eq!(quote(word), quoted_should);
+    }
+
+    #[rstest]
+    #[case("dir", "dir")]
+    #[case("", r#"^"^""#)]
+    #[case(r"C:\Program Files\", r#"^"C:\Program Files\\^""#)]
+    #[case(r#"say "hi""#, r#"^"say \^"hi\^"^""#)]
+    #[case(r#"a\"b"#, r#"^"a\\\^"b^""#)]
+    #[case("%PATH%&echo", "^%PATH^%^&echo")]
+    fn quote_cmd_works(#[case] word: &str, #[case] quoted_should: &str) {
+        assert_eq!(quote_cmd(word), quoted_should);
+    }
+
+    #[rstest]
+    #[case("Get-Date", "'Get-Date'")]
+    #[case("", "''")]
+    #[case("$env:PATH", "'$env:PATH'")]
+    #[case("it's", "'it''s'")]
+    #[case("it\u{2019}s", "'it\u{2019}\u{2019}s'")]
+    fn quote_powershell_works(#[case] word: &str, #[case] quoted_should: &str) {
+        assert_eq!(quote_powershell(word), quoted_should);
+    }
+
+    #[test]
+    fn powershell_works() {
+        assert_eq!(
+            powershell("exit 3"),
+            "powershell -NoProfile -NonInteractive -EncodedCommand ZQB4AGkAdAAgADMA"
+        );
+    }
+
+    #[tokio::test]
+    async fn quote_round_trips_through_sh() {
+        let words = [
+            "plain",
+            "two words",
+            "it's",
+            "$(reboot)",
+            "`id`",
+            "a\nb",
+            "*",
+        ];
+
+        let output = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\0' {}", join(words)))
+            .output()
+            .await
+            .unwrap();
+
+        let expected: Vec<u8> = words
+            .iter()
+            .flat_map(|w| [w.as_bytes(), b"\0"].concat())
+            .collect();
+        assert_eq!(output.stdout, expected);
+    }
+
+    proptest! {
+        #[test]
+        fn join_round_trips_through_sh(words in words()) {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf '%s\\0' {}", join(words.iter().map(String::as_str))))
+                .output()
+                .unwrap();
+
+            prop_assert_eq!(output.stdout, nul_terminated(&words));
+        }
+    }
+
+    #[cfg(feature = "russh")]
+    #[test]
+    fn command_line_round_trips_through_test_server() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let session = runtime.block_on(crate::test_server::connect());
+        let mut runner = proptest::test_runner::TestRunner::new(ProptestConfig::with_cases(64));
+
+        runner
+            .run(&words(), |words| {
+                let output = runtime.block_on(async {
+                    session
+                        .command("printf")
+                        .arg("%s\\0")
+                        .args(&words)
+                        .spawn()
+                        .await
+                        .unwrap()
+                        .wait_with_output()
+                        .await
+                        .unwrap()
+                });
+
+                prop_assert_eq!(output.stdout, nul_terminated(&words));
+                Ok(())
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+//! Measuring the round-trip time and throughput of a session, to tell a slow
+//! network from a slow remote host.
+
+use std::fmt;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+/// Round trips timed to measure latency.
+const RTT_SAMPLES: usize = 10;
+/// Size of the chunks sent and received while measuring throughput.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Streams zeros until its stdin is closed, which, unlike killing it, works
+/// with any driver.
+const DOWNLOAD_COMMAND: &str = "cat /dev/zero & cat > /dev/null; kill $!";
+
+/// Results of [`ConnectedSession::speedtest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Speedtest {
+    /// Median time for a byte to be echoed back through a command's channel.
+    /// Includes the remote process reading and writing it, which a loaded
+    /// host slows down.
+    pub rtt: Duration,
+    /// Bytes per second received from the remote host.
+    pub download: u64,
+    /// Bytes per second sent to the remote host.
+    pub upload: u64,
+}
+
+impl fmt::Display for Speedtest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rtt {:.1} ms, download {}, upload {}",
+            self.rtt.as_secs_f64() * 1000.0,
+            Rate(self.download),
+            Rate(self.upload),
+        )
+    }
+}
+
+/// Bytes per second, in the largest decimal unit that keeps the number at
+/// least 1.
+struct Rate(u64);
+
+impl fmt::Display for Rate {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rate = self.0 as f64;
+        for unit in ["B/s", "kB/s", "MB/s"] {
+            if rate < 1000.0 {
+                return write!(f, "{rate:.1} {unit}");
+            }
+            rate /= 1000.0;
+        }
+        write!(f, "{rate:.1} GB/s")
+    }
+}
+
+impl ConnectedSession {
+    /// Measures the round-trip time and the throughput in each direction
+    /// through exec channels, taking about `duration`, split between
+    /// downloading and uploading.
+    ///
+    /// A round-trip time much lower than the network's suggests a healthy
+    /// connection to a slow host, while throughput far below the link's
+    /// suggests the network, or SSH's window sizes, are the bottleneck.
+    ///
+    /// # Errors
+    ///
+    /// - If the commands cannot be run.
+    /// - If the session ends during the measurement.
+    pub async fn speedtest(&self, duration: Duration) -> Result<Speedtest> {
+        let rtt = self.measure_rtt().await?;
+        let download = self.measure_download(duration / 2).await?;
+        let upload = self.measure_upload(duration / 2).await?;
+
+        Ok(Speedtest {
+            rtt,
+            download,
+            upload,
+        })
+    }
+
+    async fn measure_rtt(&self) -> Result<Duration> {
+        let mut child = self.command("cat").spawn().await?;
+        let mut stdin = child.stdin.take().expect("stdin is set by spawn");
+        let mut stdout = child.stdout.take().expect("stdout is set by spawn");
+
+        let mut samples = Vec::with_capacity(RTT_SAMPLES);
+        let mut byte = [0];
+        for _ in 0..RTT_SAMPLES {
+            let start = Instant::now();
+            stdin.write_all(b"x").await?;
+            stdin.flush().await?;
+            stdout.read_exact(&mut byte).await?;
+            samples.push(start.elapsed());
+        }
+        stdin.shutdown().await?;
+        child.wait().await?;
+
+        samples.sort();
+        Ok(samples[samples.len() / 2])
+    }
+
+    async fn measure_download(&self, duration: Duration) -> Result<u64> {
+        let mut child = self
+            .command("sh")
+            .args(["-c", DOWNLOAD_COMMAND])
+            .spawn()
+            .await?;
+        let mut stdin = child.stdin.take().expect("stdin is set by spawn");
+        let mut stdout = child.stdout.take().expect("stdout is set by spawn");
+
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut received = 0;
+        let start = Instant::now();
+        let deadline = start + duration;
+        while Instant::now() < deadline {
+            match tokio::time::timeout_at(deadline, stdout.read(&mut buffer)).await {
+                // The deadline passed, or the command ended early and its status
+                // tells why.
+                Ok(Ok(0)) | Err(_) => break,
+                Ok(Ok(len)) => received += len as u64,
+                Ok(Err(error)) => return Err(error.into()),
+            }
+        }
+        let elapsed = start.elapsed();
+
+        // What is still in flight is drained uncounted.
+        stdin.shutdown().await?;
+        tokio::io::copy(&mut stdout, &mut tokio::io::sink()).await?;
+        let status = child.wait().await?;
+        if received == 0 && !status.success() {
+            return Err(Error::CommandFailed {
+                command: DOWNLOAD_COMMAND.to_string(),
+                status,
+            });
+        }
+
+        Ok(per_second(received, elapsed))
+    }
+
+    async fn measure_upload(&self, duration: Duration) -> Result<u64> {
+        let mut child = self
+            .command("sh")
+            .args(["-c", "cat > /dev/null"])
+            .spawn()
+            .await?;
+        let mut stdin = child.stdin.take().expect("stdin is set by spawn");
+
+        let buffer = vec![0; CHUNK_SIZE];
+        let mut sent = 0;
+        let start = Instant::now();
+        let deadline = start + duration;
+        while Instant::now() < deadline {
+            stdin.write_all(&buffer).await?;
+            sent += CHUNK_SIZE as u64;
+        }
+        // Written data may still be buffered locally; it has all arrived once
+        // the command exits.
+        stdin.shutdown().await?;
+        child.wait().await?;
+
+        Ok(per_second(sent, start.elapsed()))
+    }
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn per_second(bytes: u64, elapsed: Duration) -> u64 {
+    if elapsed.is_zero() {
+        return 0;
+    }
+
+    (bytes as f64 / elapsed.as_secs_f64()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(1_000_000, Duration::from_secs(2), 500_000)]
+    #[case(1_000, Duration::from_millis(100), 10_000)]
+    #[case(1_000, Duration::ZERO, 0)]
+    fn per_second_works(
+        #[case] bytes: u64,
+        #[case] elapsed: Duration,
+        #[case] per_second_should: u64,
+    ) {
+        assert_eq!(per_second(bytes, elapsed), per_second_should);
+    }
+
+    #[test]
+    fn display_works() {
+        let speedtest = Speedtest {
+            rtt: Duration::from_micros(12_340),
+            download: 45_600_000,
+            upload: 780,
+        };
+
+        assert_eq!(
+            speedtest.to_string(),
+            "rtt 12.3 ms, download 45.6 MB/s, upload 780.0 B/s"
+        );
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn speedtest_works() {
+        let session = crate::test_server::connect().await;
+
+        let speedtest = session.speedtest(Duration::from_millis(200)).await.unwrap();
+
+        assert!(speedtest.download > 0);
+        assert!(speedtest.upload > 0);
+    }
+}
@@ -0,0 +1,377 @@
+//! Embedded SSH server for tests, reachable over [`Transport::Memory`].
+//!
+//! Accepts `test_user` with the password from `test/creds/password`, any public
+//! key from `test/creds`, and certificates listing `test_user` as a principal.
+//! Exec requests are run by the local `sh`, so tests can use real programs, and
+//! the `sftp` subsystem serves the local file system. Both start in a temporary
+//! home directory unique to each connection. Tunnels to any host other than
+//! [`UNREACHABLE_HOST`] reach a fresh server, to test sessions through jump
+//! hosts.
+
+use std::collections::HashMap;
+use std::io;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use russh::Channel;
+use russh::ChannelId;
+use russh::CryptoVec;
+use russh::keys::Certificate;
+use russh::keys::PublicKey;
+use russh::server::Auth;
+use russh::server::Handle;
+use russh::server::Msg;
+use russh::server::Session;
+use tempfile::TempDir;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::process::ChildStdin;
+use tokio::task::AbortHandle;
+
+use crate::ConnectedSession;
+use crate::DriverKind;
+use crate::transport::AsyncStream;
+use crate::transport::Transport;
+
+pub const USER: &str = "test_user";
+pub const PASSWORD: &str = "test_password";
+/// Host that tunnels are refused to.
+pub const UNREACHABLE_HOST: &str = "unreachable";
+
+const HOST_KEY: &str = "test/creds/id_ed25519";
+const AUTHORIZED_KEYS: &[&str] = &[
+    "test/creds/id_rsa.pub",
+    "test/creds/id_ecdsa.pub",
+    "test/creds/id_ed25519.pub",
+    "test/creds/enc_ed25519.pub",
+];
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Starts a server on one end of an in-memory stream and returns the other.
+pub fn spawn() -> Transport {
+    let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+    serve(server);
+
+    Transport::Memory(client)
+}
+
+/// Runs a server over `stream` in the background.
+fn serve(stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static) {
+    let config = Arc::new(russh::server::Config {
+        keys: vec![russh::keys::load_secret_key(HOST_KEY, None).unwrap()],
+        auth_rejection_time: Duration::ZERO,
+        auth_rejection_time_initial: Some(Duration::ZERO),
+        ..Default::default()
+    });
+
+    tokio::spawn(async move {
+        let session = russh::server::run_stream(config, stream, TestServer::new()).await?;
+        session.await
+    });
+}
+
+/// Starts a server and returns a session authenticated to it with the
+/// password.
+pub async fn connect() -> ConnectedSession {
+    connect_stream(spawn().into_stream().unwrap()).await
+}
+
+/// Returns a session authenticated with the password to the server at the
+/// other end of `stream`.
+pub async fn connect_stream(stream: Box<dyn AsyncStream>) -> ConnectedSession {
+    let mut session = session("localhost");
+    session.stream = Some(stream);
+
+    session.connect().await.unwrap()
+}
+
+/// Session to `host` that authenticates with the password, yet to be given a
+/// stream or jump hosts to connect over.
+pub fn session(host: &str) -> crate::Session {
+    crate::Session::builder()
+        .user(USER)
+        .host(host)
+        .driver(DriverKind::Russh)
+        .auth(crate::Auth::from_password_file("test/creds/password").unwrap())
+        .host_key_verification(crate::HostKeyVerification::AcceptAny)
+        .build()
+}
+
+struct TestServer {
+    home: TempDir,
+    /// Open channels, kept until the client closes them.
+    channels: HashMap<ChannelId, Channel<Msg>>,
+    /// Stdin of commands started by exec requests.
+    stdin: HashMap<ChannelId, ChildStdin>,
+    /// Tasks accepting connections on remotely forwarded ports, by port.
+    forwards: HashMap<u32, AbortHandle>,
+}
+
+impl TestServer {
+    fn new() -> Self {
+        Self {
+            home: TempDir::new().unwrap(),
+            channels: HashMap::new(),
+            stdin: HashMap::new(),
+            forwards: HashMap::new(),
+        }
+    }
+
+    fn reject() -> Auth {
+        Auth::Reject {
+            proceed_with_methods: None,
+            partial_success: false,
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        for accept in self.forwards.values() {
+            accept.abort();
+        }
+    }
+}
+
+impl russh::server::Handler for TestServer {
+    type Error = russh::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        if user == USER && password == PASSWORD {
+            Ok(Auth::Accept)
+        } else {
+            Ok(Self::reject())
+        }
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        let authorized = AUTHORIZED_KEYS.iter().any(|path| {
+            russh::keys::load_public_key(path)
+                .is_ok_and(|key| key.key_data() == public_key.key_data())
+        });
+
+        if user == USER && authorized {
+            Ok(Auth::Accept)
+        } else {
+            Ok(Self::reject())
+        }
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        Ok(true)
+    }
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        host_to_connect: &str,
+        _port_to_connect: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        if host_to_connect == UNREACHABLE_HOST {
+            return Ok(false);
+        }
+
+        serve(channel.into_stream());
+        Ok(true)
+    }
+
+    /// Listens on the loopback address, whatever address is asked for.
+    async fn tcpip_forward(
+        &mut self,
+        address: &str,
+        port: &mut u32,
+        session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let Ok(port_to_bind) = u16::try_from(*port) else {
+            return Ok(false);
+        };
+        let listener = TcpListener::bind(("127.0.0.1", port_to_bind)).await?;
+        *port = u32::from(listener.local_addr()?.port());
+
+        let handle = session.handle();
+        let address = address.to_string();
+        let connected_port = *port;
+        let accept = tokio::spawn(async move {
+            while let Ok((mut stream, peer)) = listener.accept().await {
+                let channel = handle
+                    .channel_open_forwarded_tcpip(
+                        address.clone(),
+                        connected_port,
+                        peer.ip().to_string(),
+                        u32::from(peer.port()),
+                    )
+                    .await;
+                let Ok(channel) = channel else {
+                    continue;
+                };
+                tokio::spawn(async move {
+                    let mut channel = channel.into_stream();
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut channel).await;
+                });
+            }
+        });
+        self.forwards.insert(*port, accept.abort_handle());
+
+        Ok(true)
+    }
+
+    async fn cancel_tcpip_forward(
+        &mut self,
+        _address: &str,
+        port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        let Some(accept) = self.forwards.remove(&port) else {
+            return Ok(false);
+        };
+        accept.abort();
+
+        Ok(true)
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let mut child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(String::from_utf8_lossy(data).as_ref())
+            .current_dir(self.home.path())
+            .env("HOME", self.home.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        self.stdin.insert(channel, child.stdin.take().unwrap());
+        session.channel_success(channel)?;
+
+        let handle = session.handle();
+        tokio::spawn(async move {
+            let _ = tokio::join!(
+                forward(&handle, channel, None, stdout),
+                forward(&handle, channel, Some(1), stderr),
+            );
+            let code = child.wait().await?.code().unwrap_or(255);
+            let _ = handle
+                .exit_status_request(channel, u32::try_from(code).unwrap_or(255))
+                .await;
+            let _ = handle.eof(channel).await;
+            let _ = handle.close(channel).await;
+            io::Result::Ok(())
+        });
+
+        Ok(())
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        match (name, self.channels.remove(&channel)) {
+            ("sftp", Some(stream)) => {
+                session.channel_success(channel)?;
+                crate::sftp::Server::builder()
+                    .root(self.home.path())
+                    .confine(false)
+                    .build()
+                    .serve(stream.into_stream())
+                    .await;
+            }
+            _ => session.channel_failure(channel)?,
+        }
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // The command may already have exited without reading its input.
+        if let Some(stdin) = self.stdin.get_mut(&channel) {
+            let _ = stdin.write_all(data).await;
+        }
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.stdin.remove(&channel);
+        Ok(())
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.stdin.remove(&channel);
+        self.channels.remove(&channel);
+        Ok(())
+    }
+
+    async fn auth_openssh_certificate(
+        &mut self,
+        user: &str,
+        certificate: &Certificate,
+    ) -> Result<Auth, Self::Error> {
+        if user == USER && certificate.valid_principals().iter().any(|p| p == USER) {
+            Ok(Auth::Accept)
+        } else {
+            Ok(Self::reject())
+        }
+    }
+}
+
+/// Sends everything read from `output` to the client as channel data, or as
+/// extended data of type `ext` if given.
+async fn forward(
+    handle: &Handle,
+    channel: ChannelId,
+    ext: Option<u32>,
+    mut output: impl AsyncRead + Unpin,
+) -> io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let len = output.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        let data = CryptoVec::from_slice(&buf[..len]);
+        let sent = match ext {
+            Some(ext) => handle.extended_data(channel, ext, data).await,
+            None => handle.data(channel, data).await,
+        };
+        if sent.is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::OnceLock;
+
+use bon::Builder;
+use camino::Utf8PathBuf;
+
+use crate::Error;
+use crate::Result;
+
+/// Values substituted for OpenSSH-style `%` tokens in configured values, so
+/// that settings written for OpenSSH can be used as they are.
+///
+/// | Token | Value                                  |
+/// |-------|----------------------------------------|
+/// | `%%`  | A literal `%`                          |
+/// | `%d`  | Local home directory                   |
+/// | `%h`  | Remote host                            |
+/// | `%L`  | Local hostname, without the domain     |
+/// | `%l`  | Local hostname                         |
+/// | `%p`  | Remote port                            |
+/// | `%r`  | Remote user                            |
+/// | `%u`  | Local user                             |
+///
+/// The tokens for a session are given by
+/// [`Session::tokens`](crate::Session::tokens).
+#[derive(Debug, Clone, Default, Builder)]
+pub struct Tokens {
+    /// Name of the local user, for `%u`.
+    #[builder(into)]
+    local_user: Option<String>,
+    /// Home directory of the local user, for `%d` and a leading `~` in
+    /// paths.
+    #[builder(into)]
+    local_home: Option<String>,
+    /// Hostname of the local machine, for `%l` and `%L`.
+    #[builder(into)]
+    local_host: Option<String>,
+    /// Remote host, for `%h`.
+    #[builder(into)]
+    host: Option<String>,
+    /// Remote port, for `%p`.
+    port: Option<u16>,
+    /// Remote user, for `%r`.
+    #[builder(into)]
+    remote_user: Option<String>,
+}
+
+impl Tokens {
+    /// Tokens describing the local machine, as far as they can be
+    /// determined. Looked up once per process.
+    #[must_use]
+    pub fn local() -> Self {
+        static LOCAL: OnceLock<Tokens> = OnceLock::new();
+
+        LOCAL
+            .get_or_init(|| Tokens {
+                local_user: local_user(),
+                local_home: local_home(),
+                local_host: local_host(),
+                ..Tokens::default()
+            })
+            .clone()
+    }
+
+    /// Same tokens, with the remote host and port set.
+    pub(crate) fn with_host(self, host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: Some(host.into()),
+            port: Some(port),
+            ..self
+        }
+    }
+
+    /// Same tokens, with the remote user set.
+    pub(crate) fn with_remote_user(self, user: impl Into<String>) -> Self {
+        Self {
+            remote_user: Some(user.into()),
+            ..self
+        }
+    }
+
+    /// Replaces the tokens in `value`.
+    ///
+    /// # Errors
+    ///
+    /// - If `value` contains an unknown token, a token whose value is not set,
+    ///   or a lone `%` at its end.
+    pub fn expand(&self, value: &str) -> Result<String> {
+        let unexpandable = |token| Error::UnexpandableToken {
+            value: value.to_string(),
+            token,
+        };
+
+        let mut expanded = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+
+            let token = chars.next().ok_or_else(|| unexpandable('%'))?;
+            let port;
+            let replacement = match token {
+                '%' => Some("%"),
+                'd' => self.local_home.as_deref(),
+                'h' => self.host.as_deref(),
+                'L' => self
+                    .local_host
+                    .as_deref()
+                    .and_then(|host| host.split('.').next()),
+                'l' => self.local_host.as_deref(),
+                'p' => {
+                    port = self.port.map(|port| port.to_string());
+                    port.as_deref()
+                }
+                'r' => self.remote_user.as_deref(),
+                'u' => self.local_user.as_deref(),
+                _ => None,
+            };
+            expanded.push_str(replacement.ok_or_else(|| unexpandable(token))?);
+        }
+
+        Ok(expanded)
+    }
+
+    /// Replaces the tokens in `path`, and a leading `~` with the local home
+    /// directory, like OpenSSH does for paths such as `IdentityFile`.
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`Tokens::expand`], with `~` reported as `%d`.
+    pub fn expand_path(&self, path: &str) -> Result<Utf8PathBuf> {
+        let expanded = match path.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                self.expand(&format!("%d{rest}"))?
+            }
+            _ => self.expand(path)?,
+        };
+
+        Ok(Utf8PathBuf::from(expanded))
+    }
+}
+
+/// Name of the user running this process, from the environment or, failing
+/// that, `whoami`.
+pub(crate) fn local_user() -> Option<String> {
+    let user = env_var(&["LOGNAME", "USER", "USERNAME"]).or_else(|| command_output("whoami"))?;
+    // Windows prints the domain too, as `DOMAIN\user`.
+    user.rsplit('\\').next().map(str::to_string)
+}
+
+fn local_home() -> Option<String> {
+    env_var(&["HOME", "USERPROFILE"])
+}
+
+fn local_host() -> Option<String> {
+    env_var(&["HOSTNAME", "COMPUTERNAME"]).or_else(|| command_output("hostname"))
+}
+
+/// First of the environment variables `names` set to a non-empty value.
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Trimmed stdout of a local program run without arguments, if it succeeds.
+fn command_output(program: &str) -> Option<String> {
+    let output = std::process::Command::new(program).output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let stdout = stdout.trim();
+    (output.status.success() && !stdout.is_empty()).then(|| stdout.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn tokens() -> Tokens {
+        Tokens::builder()
+            .local_user("alice")
+            .local_home("/home/alice")
+            .local_host("laptop.example.com")
+            .host("web1")
+            .port(2222)
+            .remote_user("deploy")
+            .build()
+    }
+
+    #[rstest]
+    #[case("deploy", Some("deploy"))]
+    #[case("%u-admin", Some("alice-admin"))]
+    #[case("100%%", Some("100%"))]
+    #[case("%d/.ssh/known_hosts", Some("/home/alice/.ssh/known_hosts"))]
+    #[case("~/.ssh/cm-%r@%h:%p", Some("~/.ssh/cm-deploy@web1:2222"))]
+    #[case("%l %L", Some("laptop.example.com laptop"))]
+    #[case("%C", None)]
+    #[case("50%", None)]
+    fn expand_works(#[case] value: &str, #[case] expanded_should: Option<&str>) {
+        let expanded = tokens().expand(value);
+
+        assert_eq!(expanded.ok().as_deref(), expanded_should);
+    }
+
+    #[test]
+    fn expand_fails_without_value() {
+        let result = Tokens::default().expand("%u");
+
+        assert!(matches!(
+            result,
+            Err(Error::UnexpandableToken { token: 'u', .. })
+        ));
+    }
+
+    #[rstest]
+    #[case("~/.ssh/id_%h", "/home/alice/.ssh/id_web1")]
+    #[case("~", "/home/alice")]
+    #[case("~bob/.ssh/id", "~bob/.ssh/id")]
+    #[case("/etc/ssh/%r/key", "/etc/ssh/deploy/key")]
+    fn expand_path_works(#[case] path: &str, #[case] expanded_should: &str) {
+        let expanded = tokens().expand_path(path).unwrap();
+
+        assert_eq!(expanded, expanded_should);
+    }
+
+    #[test]
+    fn local_works() {
+        let tokens = Tokens::local();
+
+        assert!(tokens.local_user.is_some_and(|user| !user.is_empty()));
+        assert!(tokens.host.is_none());
+    }
+}
@@ -1,15 +1,65 @@
+use std::fmt;
 use std::net::SocketAddr;
 
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+
 use crate::Result;
 
+pub mod chaos;
+#[cfg(feature = "russh")]
+pub mod inspect;
+pub mod jump;
+pub mod meter;
+pub mod proxy_command;
 pub mod tokio_tcp;
 
 pub trait TransportFactory {
     async fn connect(&self, addr: SocketAddr) -> Result<Transport>;
 }
 
+/// Bidirectional byte stream that an SSH session can run over.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncStream for T {}
+
+impl fmt::Debug for dyn AsyncStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AsyncStream")
+    }
+}
+
 #[derive(Debug)]
 pub enum Transport {
     None,
     TokioTcp(tokio::net::TcpStream),
+    /// Stream established outside of this crate.
+    Stream(Box<dyn AsyncStream>),
+    /// In-memory stream backed by [`tokio::io::duplex`], connected to an
+    /// embedded server so tests run without sockets.
+    #[cfg(test)]
+    Memory(tokio::io::DuplexStream),
+}
+
+impl Transport {
+    /// Type-erased stream of this transport, or `None` for
+    /// [`Transport::None`].
+    pub fn into_stream(self) -> Option<Box<dyn AsyncStream>> {
+        match self {
+            Transport::None => None,
+            Transport::TokioTcp(stream) => Some(Box::new(stream)),
+            Transport::Stream(stream) => Some(stream),
+            #[cfg(test)]
+            Transport::Memory(stream) => Some(Box::new(stream)),
+        }
+    }
+}
+
+/// Piped stdin and stdout of `child`, as one stream.
+pub(crate) fn stdio_stream(mut child: tokio::process::Child) -> Box<dyn AsyncStream> {
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    // The process ends once its stdin is closed, and is reaped by tokio.
+    Box::new(tokio::io::join(stdout, stdin))
 }
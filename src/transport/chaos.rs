@@ -0,0 +1,324 @@
+use std::hash::BuildHasher;
+use std::hash::RandomState;
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+
+use bon::Builder;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+use super::AsyncStream;
+use super::Transport;
+
+/// Size of the buffer used to hold received bytes while they are delayed.
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Faults to inject into a transport, for testing how applications handle
+/// slow, unreliable or hostile networks. No faults are injected by default.
+#[derive(Debug, Clone, PartialEq, Builder)]
+pub struct Chaos {
+    /// Delay added to every read and write.
+    latency: Option<Duration>,
+    /// Maximum throughput in bytes per second, applied to each direction
+    /// separately.
+    bandwidth: Option<u64>,
+    /// Probability, per read or write, that the connection is reset. Once
+    /// reset, every further operation fails.
+    #[builder(default)]
+    reset_probability: f64,
+    /// Probability, per byte sent or received, that a bit in it is flipped.
+    #[builder(default)]
+    corrupt_probability: f64,
+    /// Seed for a reproducible sequence of faults. Random if not set.
+    seed: Option<u64>,
+}
+
+impl Chaos {
+    /// Wraps `inner` so that all traffic through it is subject to this
+    /// configuration.
+    pub fn wrap<S: AsyncStream>(&self, inner: S) -> ChaosStream<S> {
+        let seed = self
+            .seed
+            .unwrap_or_else(|| RandomState::new().hash_one("chaos"));
+
+        ChaosStream {
+            inner,
+            chaos: self.clone(),
+            rng: Rng::new(seed),
+            reset: false,
+            read_buffer: vec![0; READ_BUFFER_SIZE],
+            pending: Vec::new(),
+            pending_pos: 0,
+            read_delay: None,
+            read_next_allowed: None,
+            write_delay: None,
+            write_next_allowed: None,
+        }
+    }
+
+    pub(crate) fn apply(&self, transport: Transport) -> Transport {
+        transport.into_stream().map_or(Transport::None, |stream| {
+            Transport::Stream(Box::new(self.wrap(stream)))
+        })
+    }
+
+    /// Earliest time an operation may complete, given when the previous one in
+    /// the same direction allows the next to start.
+    fn deadline(&self, next_allowed: Option<Instant>) -> Option<Instant> {
+        let latency = self.latency.map(|latency| Instant::now() + latency);
+
+        match (latency, next_allowed) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Time after which the next operation may start, having just transferred
+    /// `len` bytes.
+    fn next_allowed(&self, previous: Option<Instant>, len: usize) -> Option<Instant> {
+        let bandwidth = self.bandwidth.filter(|&bandwidth| bandwidth > 0)?;
+        let nanos = (len as u128) * 1_000_000_000 / u128::from(bandwidth);
+        let cost = Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+
+        Some(previous.unwrap_or_else(Instant::now).max(Instant::now()) + cost)
+    }
+}
+
+/// Stream wrapper created by [`Chaos::wrap`].
+pub struct ChaosStream<S> {
+    inner: S,
+    chaos: Chaos,
+    rng: Rng,
+    reset: bool,
+    /// Scratch space that bytes are read into from `inner`.
+    read_buffer: Vec<u8>,
+    /// Received bytes waiting for their delay to elapse.
+    pending: Vec<u8>,
+    pending_pos: usize,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    read_next_allowed: Option<Instant>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+    write_next_allowed: Option<Instant>,
+}
+
+impl<S> ChaosStream<S> {
+    fn check_reset(&mut self) -> io::Result<()> {
+        if !self.reset && self.rng.chance(self.chaos.reset_probability) {
+            self.reset = true;
+        }
+
+        if self.reset {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "connection reset by chaos transport",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn corrupt(&mut self, bytes: &mut [u8]) {
+        if self.chaos.corrupt_probability <= 0.0 {
+            return;
+        }
+
+        for byte in bytes {
+            if self.rng.chance(self.chaos.corrupt_probability) {
+                *byte ^= 1 << (self.rng.next_u64() % 8);
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.pending_pos == this.pending.len() {
+            let mut read_buf = ReadBuf::new(&mut this.read_buffer);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+            let len = read_buf.filled().len();
+            if len == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            this.check_reset()?;
+
+            let mut received = this.read_buffer[..len].to_vec();
+            this.corrupt(&mut received);
+            this.pending = received;
+            this.pending_pos = 0;
+
+            this.read_delay = this
+                .chaos
+                .deadline(this.read_next_allowed)
+                .map(|deadline| Box::pin(tokio::time::sleep_until(deadline)));
+            this.read_next_allowed = this.chaos.next_allowed(this.read_next_allowed, len);
+        }
+
+        if let Some(delay) = &mut this.read_delay {
+            ready!(delay.as_mut().poll(cx));
+            this.read_delay = None;
+        }
+
+        let remaining = &this.pending[this.pending_pos..];
+        let len = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..len]);
+        this.pending_pos += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.write_delay.is_none() {
+            this.check_reset()?;
+            this.write_delay = this
+                .chaos
+                .deadline(this.write_next_allowed)
+                .map(|deadline| Box::pin(tokio::time::sleep_until(deadline)));
+        }
+        if let Some(delay) = &mut this.write_delay {
+            ready!(delay.as_mut().poll(cx));
+        }
+
+        let mut sent = buf.to_vec();
+        this.corrupt(&mut sent);
+        let len = ready!(Pin::new(&mut this.inner).poll_write(cx, &sent))?;
+
+        this.write_delay = None;
+        this.write_next_allowed = this.chaos.next_allowed(this.write_next_allowed, len);
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Small xorshift generator; fault injection needs speed and reproducibility,
+/// not cryptographic quality.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero.
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+
+        // Top 53 bits fit an f64 mantissa exactly.
+        let sample = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"SSH-2.0-OpenSSH_9.6\r\n";
+
+    async fn round_trip(chaos: &Chaos) -> io::Result<Vec<u8>> {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = chaos.wrap(client);
+
+        server.write_all(MESSAGE).await?;
+        let mut received = vec![0; MESSAGE.len()];
+        client.read_exact(&mut received).await?;
+
+        Ok(received)
+    }
+
+    #[tokio::test]
+    async fn chaos_passes_through_by_default() {
+        let received = round_trip(&Chaos::builder().build()).await.unwrap();
+
+        assert_eq!(received, MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn chaos_resets() {
+        let chaos = Chaos::builder().reset_probability(1.0).build();
+
+        let err = round_trip(&chaos).await.unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test]
+    async fn chaos_corrupts() {
+        let chaos = Chaos::builder().corrupt_probability(1.0).seed(42).build();
+
+        let received = round_trip(&chaos).await.unwrap();
+
+        assert!(received.iter().zip(MESSAGE).all(|(got, sent)| got != sent));
+    }
+
+    #[tokio::test]
+    async fn chaos_delays() {
+        let latency = Duration::from_millis(50);
+        let chaos = Chaos::builder().latency(latency).build();
+
+        let start = Instant::now();
+        round_trip(&chaos).await.unwrap();
+
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[tokio::test]
+    async fn chaos_limits_bandwidth() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = Chaos::builder().bandwidth(1000).build().wrap(client);
+
+        let start = Instant::now();
+        client.write_all(&[0; 50]).await.unwrap();
+        client.write_all(&[0; 50]).await.unwrap();
+        let mut received = [0; 100];
+        server.read_exact(&mut received).await.unwrap();
+
+        // The second write may only start once the first 50 bytes have been
+        // paid for at 1000 bytes per second.
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
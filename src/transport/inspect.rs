@@ -0,0 +1,212 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::task::Context;
+use std::task::Poll;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+
+use crate::kex::KexInit;
+
+/// Most bytes buffered while looking for the server's `SSH_MSG_KEXINIT`
+/// before giving up on inspection.
+const MAX_BUFFERED: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the identification string, possibly preceded by other
+    /// lines.
+    Ident,
+    /// Waiting for the first binary packet.
+    Packet,
+    /// Inspection finished; bytes are passed through untouched.
+    Done,
+}
+
+/// What the server sent of its side of the handshake.
+#[derive(Debug, Default)]
+pub struct Handshake {
+    /// First `SSH_MSG_KEXINIT` received from the server.
+    pub kex_init: OnceLock<KexInit>,
+    /// Set if the server closed the connection before its
+    /// `SSH_MSG_KEXINIT`, to whether its identification string had arrived.
+    pub closed_early: OnceLock<bool>,
+}
+
+/// Stream wrapper that passes all bytes through unmodified while recording the
+/// server's side of the handshake.
+pub struct Inspect<S> {
+    inner: S,
+    phase: Phase,
+    buffer: Vec<u8>,
+    handshake: Arc<Handshake>,
+}
+
+impl<S> Inspect<S> {
+    pub fn new(inner: S, handshake: Arc<Handshake>) -> Self {
+        Self {
+            inner,
+            phase: Phase::Ident,
+            buffer: Vec::new(),
+            handshake,
+        }
+    }
+
+    fn observe(&mut self, bytes: &[u8]) {
+        if self.phase == Phase::Done {
+            return;
+        }
+
+        self.buffer.extend_from_slice(bytes);
+
+        if self.phase == Phase::Ident {
+            while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let is_ident = self.buffer.starts_with(b"SSH-");
+                self.buffer.drain(..=end);
+                if is_ident {
+                    self.phase = Phase::Packet;
+                    break;
+                }
+            }
+        }
+
+        if self.phase == Phase::Packet
+            && let Some(packet_len) = self.buffer.get(..4)
+        {
+            let packet_len = u32::from_be_bytes(packet_len.try_into().unwrap()) as usize;
+            if let Some(packet) = self.buffer.get(4..4 + packet_len) {
+                if let Some((&padding_len, rest)) = packet.split_first() {
+                    let payload_len = rest.len().saturating_sub(usize::from(padding_len));
+                    if let Some(kex_init) = KexInit::parse(&rest[..payload_len]) {
+                        let _ = self.handshake.kex_init.set(kex_init);
+                    }
+                }
+                self.finish();
+            }
+        }
+
+        if self.buffer.len() > MAX_BUFFERED {
+            self.finish();
+        }
+    }
+
+    /// Records that the connection ended, by the server closing or
+    /// resetting it.
+    fn closed(&mut self) {
+        if self.phase == Phase::Done {
+            return;
+        }
+
+        let _ = self.handshake.closed_early.set(self.phase == Phase::Packet);
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        self.phase = Phase::Done;
+        self.buffer = Vec::new();
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Inspect<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let remaining = buf.remaining();
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        match &poll {
+            Poll::Ready(Ok(())) if remaining > 0 && buf.filled().len() == filled => this.closed(),
+            Poll::Ready(Ok(())) => this.observe(&buf.filled()[filled..]),
+            Poll::Ready(Err(error))
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                this.closed();
+            }
+            _ => {}
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Inspect<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::kex::STRICT_KEX_SERVER;
+    use crate::kex::tests::kex_init_payload;
+
+    #[tokio::test]
+    async fn inspect_records_server_kex_init() {
+        let payload = kex_init_payload(&["curve25519-sha256", STRICT_KEX_SERVER]);
+        let padding = [0u8; 4];
+        let packet_len = u32::try_from(1 + payload.len() + padding.len()).unwrap();
+
+        let mut server_bytes = b"pre-banner line\r\nSSH-2.0-OpenSSH_9.6\r\n".to_vec();
+        server_bytes.extend(packet_len.to_be_bytes());
+        server_bytes.push(u8::try_from(padding.len()).unwrap());
+        server_bytes.extend(&payload);
+        server_bytes.extend(padding);
+
+        let (client, mut server) = tokio::io::duplex(16);
+        let handshake = Arc::new(Handshake::default());
+        let mut inspect = Inspect::new(client, Arc::clone(&handshake));
+
+        let writer = tokio::spawn(async move {
+            server.write_all(&server_bytes).await.unwrap();
+            server_bytes
+        });
+        let mut received = Vec::new();
+        inspect.read_to_end(&mut received).await.unwrap();
+
+        assert_eq!(received, writer.await.unwrap());
+        assert!(handshake.kex_init.get().unwrap().supports_strict_kex());
+        assert_eq!(handshake.closed_early.get(), None);
+    }
+
+    #[rstest]
+    #[case(b"", false)]
+    #[case(b"SSH-2.0-OpenSSH_9.6\r\n", true)]
+    #[tokio::test]
+    async fn inspect_records_early_close(#[case] server_bytes: &[u8], #[case] banner_should: bool) {
+        let mut inspect = Inspect::new(server_bytes, Arc::new(Handshake::default()));
+
+        let mut received = Vec::new();
+        inspect.read_to_end(&mut received).await.unwrap();
+
+        assert_eq!(inspect.handshake.closed_early.get(), Some(&banner_should));
+    }
+}
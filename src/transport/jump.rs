@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::sync::Mutex;
+
+use super::Transport;
+use super::TransportFactory;
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+use crate::Session;
+
+/// Jump hosts to reach a host through, like OpenSSH's `ProxyJump` or
+/// `-J a,b,c`, for hosts that only bastions can reach. Given to
+/// [`SessionBuilder::jump_host`](crate::SessionBuilder::jump_host).
+///
+/// The first hop is connected to directly, and every next one through a
+/// tunnel from the one before; the target is then reached through a tunnel
+/// from the last. Hops are connected on first use and kept for later
+/// connections, which share them, until the handle is dropped. Each hop's
+/// host name is resolved by the hop before it, so names only known inside
+/// the private network work.
+pub struct JumpHost {
+    chain: Mutex<Chain>,
+}
+
+struct Chain {
+    /// Hops still to connect, in order.
+    pending: VecDeque<Session>,
+    /// Hops connected so far, each through the one before it, which must be
+    /// kept for the tunnels to stay open.
+    connected: Vec<ConnectedSession>,
+    /// Host of the hop that failed to connect, after which the chain is
+    /// unusable since hops cannot be connected twice.
+    failed: Option<String>,
+}
+
+impl JumpHost {
+    /// Jumps through `hops`, in order. Hops other than the first are
+    /// connected through a tunnel, so neither the TCP options nor the
+    /// [`pre_connect`] hook of theirs apply, and they need a driver that can
+    /// run over a stream, such as russh.
+    ///
+    /// [`pre_connect`]: crate::SessionBuilder::pre_connect
+    pub fn new(hops: impl IntoIterator<Item = Session>) -> Self {
+        Self {
+            chain: Mutex::new(Chain {
+                pending: hops.into_iter().collect(),
+                connected: Vec::new(),
+                failed: None,
+            }),
+        }
+    }
+
+    /// Opens a tunnel to `host` and `port` through the chain, connecting the
+    /// hops not connected yet.
+    ///
+    /// # Errors
+    ///
+    /// - If a hop cannot be reached or fails to connect, as
+    ///   [`Error::JumpHostFailed`] naming it, then and every time after.
+    /// - If the last hop cannot open a tunnel to `host`, as
+    ///   [`Error::TunnelFailed`].
+    /// - If there are no hops.
+    pub async fn tunnel(&self, host: &str, port: u16) -> Result<Transport> {
+        let mut chain = self.chain.lock().await;
+        if let Some(failed) = &chain.failed {
+            return Err(Error::JumpHostFailed {
+                host: failed.clone(),
+                source: Box::new(io::Error::other("jump host failed to connect earlier").into()),
+            });
+        }
+        while let Some(hop) = chain.pending.pop_front() {
+            let hop_host = hop.host.clone();
+            match connect_hop(hop, chain.connected.last()).await {
+                Ok(connected) => chain.connected.push(connected),
+                Err(source) => {
+                    chain.pending.clear();
+                    chain.failed = Some(hop_host.clone());
+                    return Err(Error::JumpHostFailed {
+                        host: hop_host,
+                        source: Box::new(source),
+                    });
+                }
+            }
+        }
+
+        let Some(last) = chain.connected.last() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no jump hosts given").into());
+        };
+        let stream = last.open_tunnel(host, port).await?;
+
+        Ok(Transport::Stream(stream))
+    }
+}
+
+/// Connects `hop`, through a tunnel from `previous` unless it is the first.
+async fn connect_hop(
+    mut hop: Session,
+    previous: Option<&ConnectedSession>,
+) -> Result<ConnectedSession> {
+    if let Some(previous) = previous {
+        let resolved = hop.resolved()?;
+        hop.stream = Some(previous.open_tunnel(&resolved.host, resolved.port).await?);
+    }
+
+    // Connecting a hop may go through jump hosts of its own.
+    Box::pin(hop.connect()).await
+}
+
+/// Tunnels to the address given, as the last hop sees it.
+impl TransportFactory for JumpHost {
+    async fn connect(&self, addr: SocketAddr) -> Result<Transport> {
+        self.tunnel(&addr.ip().to_string(), addr.port()).await
+    }
+}
+
+impl fmt::Debug for JumpHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JumpHost").finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "russh"))]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::*;
+    use crate::test_server;
+
+    /// Chain of `hosts`, the first served by a fresh server and the others by
+    /// the servers tunnels are opened to.
+    fn chain(hosts: &[&str]) -> JumpHost {
+        let mut hops: Vec<Session> = hosts
+            .iter()
+            .map(|host| test_server::session(host))
+            .collect();
+        hops[0].stream = test_server::spawn().into_stream();
+
+        JumpHost::new(hops)
+    }
+
+    #[tokio::test]
+    async fn jump_host_works() {
+        let mut target = test_server::session("target");
+        target.jump_host = Some(chain(&["bastion", "inner"]));
+
+        let session = target.connect().await.unwrap();
+        let output = session.command("echo").arg("hi").output().await.unwrap();
+
+        assert_eq!(output.stdout, b"hi\n");
+    }
+
+    #[tokio::test]
+    async fn jump_host_works_for_files() {
+        let mut target = test_server::session("target");
+        target.jump_host = Some(chain(&["bastion", "inner"]));
+
+        let session = target.connect().await.unwrap();
+        session
+            .fs()
+            .write("~/two-hops.txt", "through two hops")
+            .await
+            .unwrap();
+        let read = session.fs().read("~/two-hops.txt").await.unwrap();
+
+        assert_eq!(read, b"through two hops");
+    }
+
+    #[tokio::test]
+    async fn jump_host_works_for_scp() {
+        let mut target = test_server::session("target");
+        target.jump_host = Some(chain(&["bastion", "inner"]));
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        std::fs::write(dir.join("sent.txt"), "scp through two hops").unwrap();
+        std::fs::create_dir(dir.join("back")).unwrap();
+
+        let session = target.connect().await.unwrap();
+        let sent = session
+            .scp_send(dir.join("sent.txt"), "remote.txt")
+            .await
+            .unwrap();
+        let received = session
+            .scp_recv("remote.txt", dir.join("back"))
+            .await
+            .unwrap();
+
+        assert_eq!((sent, received), (20, 20));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("back/remote.txt")).unwrap(),
+            "scp through two hops"
+        );
+    }
+
+    #[tokio::test]
+    async fn jump_host_names_unreachable_hop() {
+        let jump_host = chain(&["bastion", test_server::UNREACHABLE_HOST, "inner"]);
+
+        let first = jump_host.tunnel("target", 22).await;
+        let again = jump_host.tunnel("target", 22).await;
+
+        assert!(matches!(
+            first,
+            Err(Error::JumpHostFailed { host, source })
+                if host == test_server::UNREACHABLE_HOST
+                    && matches!(*source, Error::TunnelFailed { .. })
+        ));
+        assert!(matches!(
+            again,
+            Err(Error::JumpHostFailed { host, .. }) if host == test_server::UNREACHABLE_HOST
+        ));
+    }
+
+    #[tokio::test]
+    async fn jump_host_reports_unreachable_target() {
+        let jump_host = chain(&["bastion"]);
+
+        let result = jump_host.tunnel(test_server::UNREACHABLE_HOST, 22).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::TunnelFailed { host, .. }) if host == test_server::UNREACHABLE_HOST
+        ));
+    }
+}
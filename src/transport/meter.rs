@@ -0,0 +1,401 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+
+use super::AsyncStream;
+use crate::Error;
+use crate::Result;
+
+/// Bytes transferred by a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    /// Bytes sent over the transport, including SSH framing, encryption and
+    /// key exchange overhead.
+    pub sent: u64,
+    /// Bytes received over the transport, with the same overhead.
+    pub received: u64,
+    /// Data of the commands run, with stdin sent and stdout and stderr
+    /// received.
+    pub exec: ChannelTraffic,
+    /// Data of the SFTP subsystem, which file system access goes through.
+    pub sftp: ChannelTraffic,
+    /// Data of tunnels and of the connections carried by port forwards.
+    pub forward: ChannelTraffic,
+}
+
+/// Data carried by the channels of one kind, without SSH overhead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelTraffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// What a channel is for. [`Traffic`] counts the data of each kind apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// A command.
+    Exec,
+    /// The SFTP subsystem.
+    Sftp,
+    /// A tunnel, or a connection to a port forward.
+    Forward,
+}
+
+/// Running byte counts shared between [`Metered`] streams and their session.
+#[derive(Debug, Default)]
+pub struct Counters {
+    transport: Counter,
+    exec: Counter,
+    sftp: Counter,
+    forward: Counter,
+    /// Commands run so far.
+    commands: AtomicU64,
+    /// Channels opened so far, including those for commands.
+    channels: AtomicU64,
+    /// Total bytes after which the transport refuses to transfer more.
+    max_bytes: Option<u64>,
+    max_commands: Option<u64>,
+    max_channels: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl Counter {
+    fn snapshot(&self) -> ChannelTraffic {
+        ChannelTraffic {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Counters {
+    pub fn with_max_bytes(max_bytes: Option<u64>) -> Self {
+        Self {
+            max_bytes,
+            ..Self::default()
+        }
+    }
+
+    /// Commands that may be run, after which [`Counters::open_channel`]
+    /// refuses exec channels.
+    pub fn max_commands(self, max_commands: Option<u64>) -> Self {
+        Self {
+            max_commands,
+            ..self
+        }
+    }
+
+    /// Channels that may be opened, after which [`Counters::open_channel`]
+    /// refuses any.
+    pub fn max_channels(self, max_channels: Option<u64>) -> Self {
+        Self {
+            max_channels,
+            ..self
+        }
+    }
+
+    pub fn snapshot(&self) -> Traffic {
+        let transport = self.transport.snapshot();
+        Traffic {
+            sent: transport.sent,
+            received: transport.received,
+            exec: self.exec.snapshot(),
+            sftp: self.sftp.snapshot(),
+            forward: self.forward.snapshot(),
+        }
+    }
+
+    /// Whether the byte quota, if any, has been used up.
+    pub fn is_exhausted(&self) -> bool {
+        let traffic = self.snapshot();
+        self.max_bytes
+            .is_some_and(|max_bytes| traffic.sent + traffic.received >= max_bytes)
+    }
+
+    /// Counts a channel of `kind` about to be opened, and a command if it is
+    /// for one. They are counted once the returned slot is committed, after
+    /// the channel is open, and given back if it is dropped before, so that
+    /// channels the server refuses do not count.
+    ///
+    /// # Errors
+    ///
+    /// - If as many channels, or for exec channels commands, were opened as
+    ///   allowed, as [`Error::LimitReached`].
+    pub fn open_channel(&self, kind: ChannelKind) -> Result<ChannelSlot<'_>> {
+        take(&self.channels, self.max_channels, "channels")?;
+        let mut slot = ChannelSlot {
+            counters: self,
+            command: false,
+        };
+        if kind == ChannelKind::Exec {
+            take(&self.commands, self.max_commands, "commands")?;
+            slot.command = true;
+        }
+
+        Ok(slot)
+    }
+
+    /// Counts the data of `stream` as that of a channel of `kind`.
+    pub fn meter(
+        self: &Arc<Self>,
+        stream: Box<dyn AsyncStream>,
+        kind: ChannelKind,
+    ) -> Box<dyn AsyncStream> {
+        Box::new(Metered::channel(stream, Arc::clone(self), kind))
+    }
+
+    /// Counter of the channels of `kind`, or of the transport if `None`.
+    fn counter(&self, kind: Option<ChannelKind>) -> &Counter {
+        match kind {
+            None => &self.transport,
+            Some(ChannelKind::Exec) => &self.exec,
+            Some(ChannelKind::Sftp) => &self.sftp,
+            Some(ChannelKind::Forward) => &self.forward,
+        }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.is_exhausted() {
+            return Err(io::Error::other("session byte quota exceeded"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Channel counted by [`Counters::open_channel`] while it is being opened.
+#[must_use = "the channel is given back unless the slot is committed"]
+pub struct ChannelSlot<'c> {
+    counters: &'c Counters,
+    /// Whether a command was counted too.
+    command: bool,
+}
+
+impl ChannelSlot<'_> {
+    /// Keeps the channel counted, once it is open.
+    pub fn commit(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for ChannelSlot<'_> {
+    fn drop(&mut self) {
+        self.counters.channels.fetch_sub(1, Ordering::Relaxed);
+        if self.command {
+            self.counters.commands.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Adds one to `count`, unless it already reached `max`.
+fn take(count: &AtomicU64, max: Option<u64>, limit: &'static str) -> Result<()> {
+    let Some(max) = max else {
+        count.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    };
+
+    count
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+            (count < max).then_some(count + 1)
+        })
+        .map(|_| ())
+        .map_err(|_| Error::LimitReached { limit, max })
+}
+
+/// Stream wrapper counting the bytes read from and written to `inner`.
+pub struct Metered<S> {
+    inner: S,
+    counters: Arc<Counters>,
+    kind: Option<ChannelKind>,
+}
+
+impl<S> Metered<S> {
+    /// Counts the bytes of `inner` as the session's transport.
+    pub fn new(inner: S, counters: Arc<Counters>) -> Self {
+        Self {
+            inner,
+            counters,
+            kind: None,
+        }
+    }
+
+    /// Counts the bytes of `inner` as the data of a channel of `kind`.
+    pub fn channel(inner: S, counters: Arc<Counters>, kind: ChannelKind) -> Self {
+        Self {
+            inner,
+            counters,
+            kind: Some(kind),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.counters.check()?;
+        let filled = buf.filled().len();
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let len = (buf.filled().len() - filled) as u64;
+            let counter = this.counters.counter(this.kind);
+            counter.received.fetch_add(len, Ordering::Relaxed);
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.counters.check()?;
+
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = poll {
+            let counter = this.counters.counter(this.kind);
+            counter.sent.fetch_add(len as u64, Ordering::Relaxed);
+        }
+
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn metered_counts_both_directions() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let counters = Arc::new(Counters::default());
+        let mut client = Metered::new(client, Arc::clone(&counters));
+
+        client.write_all(b"hello").await.unwrap();
+        server.write_all(b"hello world").await.unwrap();
+        let mut received = [0; 11];
+        client.read_exact(&mut received).await.unwrap();
+
+        assert_eq!(
+            counters.snapshot(),
+            Traffic {
+                sent: 5,
+                received: 11,
+                ..Traffic::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn metered_counts_channels_by_kind() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let counters = Arc::new(Counters::default());
+        let mut client = Metered::channel(client, Arc::clone(&counters), ChannelKind::Sftp);
+
+        client.write_all(b"hello").await.unwrap();
+        server.write_all(b"hi").await.unwrap();
+        let mut received = [0; 2];
+        client.read_exact(&mut received).await.unwrap();
+
+        let traffic = counters.snapshot();
+        assert_eq!(
+            traffic.sftp,
+            ChannelTraffic {
+                sent: 5,
+                received: 2
+            }
+        );
+        assert_eq!((traffic.sent, traffic.received), (0, 0));
+        assert_eq!(traffic.exec, ChannelTraffic::default());
+    }
+
+    #[tokio::test]
+    async fn metered_enforces_max_bytes() {
+        let (client, _server) = tokio::io::duplex(1024);
+        let counters = Arc::new(Counters::with_max_bytes(Some(8)));
+        let mut client = Metered::new(client, Arc::clone(&counters));
+
+        client.write_all(b"hello").await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let result = client.write_all(b"hello").await;
+
+        assert!(counters.is_exhausted());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn open_channel_enforces_limits() {
+        let counters = Counters::default()
+            .max_commands(Some(1))
+            .max_channels(Some(3));
+
+        counters.open_channel(ChannelKind::Exec).unwrap().commit();
+        let commands = counters.open_channel(ChannelKind::Exec).map(|_| ());
+        counters.open_channel(ChannelKind::Sftp).unwrap().commit();
+        counters
+            .open_channel(ChannelKind::Forward)
+            .unwrap()
+            .commit();
+        let channels = counters.open_channel(ChannelKind::Forward).map(|_| ());
+
+        assert!(matches!(
+            commands,
+            Err(Error::LimitReached {
+                limit: "commands",
+                max: 1
+            })
+        ));
+        assert!(matches!(
+            channels,
+            Err(Error::LimitReached {
+                limit: "channels",
+                max: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn open_channel_gives_back_uncommitted() {
+        let counters = Counters::default()
+            .max_commands(Some(1))
+            .max_channels(Some(1));
+
+        drop(counters.open_channel(ChannelKind::Exec).unwrap());
+        let reopened = counters.open_channel(ChannelKind::Exec);
+
+        reopened.unwrap().commit();
+        assert!(counters.open_channel(ChannelKind::Sftp).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::net::SocketAddr;
+use std::process::Stdio;
+
+use super::Transport;
+use super::TransportFactory;
+use super::stdio_stream;
+use crate::Result;
+use crate::Tokens;
+
+/// Command whose stdin and stdout carry the connection, like OpenSSH's
+/// `ProxyCommand`, for hosts that can only be reached through a helper such
+/// as `cloudflared access ssh --hostname %h` or `nc -X connect -x proxy:3128
+/// %h %p`. Given to [`SessionBuilder::proxy_command`].
+///
+/// The command is run with `sh -c` once its `%` tokens are expanded, as
+/// listed for [`Tokens`]. Its stderr is left to the terminal, as `ssh` does,
+/// so that the helper's errors and login prompts are seen. It ends once the
+/// connection is closed.
+///
+/// [`SessionBuilder::proxy_command`]: crate::SessionBuilder::proxy_command
+#[derive(Debug, Clone)]
+pub struct ProxyCommand {
+    command: String,
+}
+
+impl ProxyCommand {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+        }
+    }
+
+    /// Command as given, with its tokens unexpanded.
+    #[must_use]
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Runs the command with `tokens` expanded.
+    ///
+    /// # Errors
+    ///
+    /// - If the command has a token that `tokens` cannot expand.
+    /// - If `sh` cannot be run.
+    pub fn spawn(&self, tokens: &Tokens) -> Result<Transport> {
+        let command = tokens.expand(&self.command)?;
+        tracing::debug!(%command, "running proxy command");
+        let child = tokio::process::Command::new("sh")
+            .args(["-c", &command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        Ok(Transport::Stream(stdio_stream(child)))
+    }
+}
+
+/// Runs the command for the address given, which `%h` expands to.
+impl TransportFactory for ProxyCommand {
+    async fn connect(&self, addr: SocketAddr) -> Result<Transport> {
+        self.spawn(&Tokens::local().with_host(addr.ip().to_string(), addr.port()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::Error;
+
+    #[tokio::test]
+    async fn proxy_command_works() {
+        let proxy_command = ProxyCommand::new("echo %h:%p; cat");
+        let tokens = Tokens::builder().host("web1").port(2222).build();
+
+        let mut stream = proxy_command.spawn(&tokens).unwrap().into_stream().unwrap();
+        stream.write_all(b"SSH-2.0-test\r\n").await.unwrap();
+        let mut received = [0; 24];
+        stream.read_exact(&mut received).await.unwrap();
+
+        assert_eq!(&received, b"web1:2222\nSSH-2.0-test\r\n");
+    }
+
+    #[tokio::test]
+    async fn proxy_command_connects_to_address() {
+        let proxy_command = ProxyCommand::new("echo %h %p");
+
+        let transport = proxy_command
+            .connect("10.0.0.7:22".parse().unwrap())
+            .await
+            .unwrap();
+        let mut received = String::new();
+        transport
+            .into_stream()
+            .unwrap()
+            .read_to_string(&mut received)
+            .await
+            .unwrap();
+
+        assert_eq!(received, "10.0.0.7 22\n");
+    }
+
+    #[test]
+    fn proxy_command_rejects_unknown_tokens() {
+        let proxy_command = ProxyCommand::new("connect %z");
+
+        let result = proxy_command.spawn(&Tokens::default());
+
+        assert!(matches!(result, Err(Error::UnexpandableToken { .. })));
+    }
+}
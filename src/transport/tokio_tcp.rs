@@ -6,6 +6,7 @@ use tokio::net::TcpSocket;
 
 use super::Transport;
 use super::TransportFactory;
+use crate::Error;
 use crate::Result;
 
 pub type SocketModifier = dyn Fn(&TcpSocket) -> std::io::Result<()>;
@@ -21,17 +22,15 @@ impl TransportFactory for TokioTcp {
         let socket = match addr {
             SocketAddr::V4(_) => TcpSocket::new_v4(),
             SocketAddr::V6(_) => TcpSocket::new_v6(),
-        }
-        .unwrap();
+        }?;
 
         if let Some(modifier) = &self.modifier {
-            modifier(&socket).unwrap();
+            modifier(&socket)?;
         }
 
         let stream = tokio::time::timeout(self.timeout, socket.connect(addr))
             .await
-            .unwrap()
-            .unwrap();
+            .map_err(|_| Error::ConnectTimeout)??;
 
         Ok(Transport::TokioTcp(stream))
     }
@@ -0,0 +1,226 @@
+//! Polling of the remote host until it is ready, for gates after a deploy
+//! such as waiting for a service to listen on its port.
+
+use std::fmt;
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+
+use crate::ConnectedSession;
+use crate::Error;
+use crate::Result;
+
+/// Time between checks of the `wait_for_*` helpers.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Condition on the remote host that [`ConnectedSession::wait_for`] waits
+/// for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    /// Something accepts TCP connections on `host` and `port`, as reached
+    /// from the remote host through a tunnel like
+    /// [`ConnectedSession::open_tunnel`].
+    PortOpen { host: String, port: u16 },
+    /// The path exists, as checked over SFTP.
+    FileExists(Utf8PathBuf),
+    /// The command line, run by `sh -c`, exits with status 0.
+    CommandSucceeds(String),
+}
+
+impl Predicate {
+    pub fn port_open(host: impl Into<String>, port: u16) -> Self {
+        Predicate::PortOpen {
+            host: host.into(),
+            port,
+        }
+    }
+
+    pub fn file_exists(path: impl Into<Utf8PathBuf>) -> Self {
+        Predicate::FileExists(path.into())
+    }
+
+    pub fn command_succeeds(command_line: impl Into<String>) -> Self {
+        Predicate::CommandSucceeds(command_line.into())
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::PortOpen { host, port } => write!(f, "port {host}:{port} to be open"),
+            Predicate::FileExists(path) => write!(f, "{path} to exist"),
+            Predicate::CommandSucceeds(command_line) => {
+                write!(f, "`{command_line}` to succeed")
+            }
+        }
+    }
+}
+
+impl ConnectedSession {
+    /// Checks `predicate` every `interval` until it holds, giving up after
+    /// `timeout`, including the time the checks themselves take.
+    ///
+    /// # Errors
+    ///
+    /// - If `predicate` does not hold within `timeout`, as
+    ///   [`Error::WaitTimeout`].
+    /// - If a check fails for a reason other than the condition not holding
+    ///   yet, such as the session ending or the server failing to stat a path.
+    pub async fn wait_for(
+        &self,
+        predicate: &Predicate,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let timed_out = || Error::WaitTimeout {
+            condition: predicate.to_string(),
+        };
+
+        loop {
+            let holds = tokio::time::timeout_at(deadline, self.holds(predicate))
+                .await
+                .map_err(|_| timed_out())??;
+            if holds {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() + interval >= deadline {
+                return Err(timed_out());
+            }
+            tracing::debug!(%predicate, "waiting");
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Waits for something to accept TCP connections on `host` and `port`, as
+    /// reached from the remote host, checking every second. See
+    /// [`ConnectedSession::wait_for`].
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`ConnectedSession::wait_for`].
+    pub async fn wait_for_port(&self, host: &str, port: u16, timeout: Duration) -> Result<()> {
+        self.wait_for(&Predicate::port_open(host, port), DEFAULT_INTERVAL, timeout)
+            .await
+    }
+
+    /// Waits for `path` to exist, checking every second. See
+    /// [`ConnectedSession::wait_for`].
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`ConnectedSession::wait_for`].
+    pub async fn wait_for_file(
+        &self,
+        path: impl Into<Utf8PathBuf>,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.wait_for(&Predicate::file_exists(path), DEFAULT_INTERVAL, timeout)
+            .await
+    }
+
+    /// Waits for `command_line`, run by `sh -c`, to exit with status 0,
+    /// checking every second. See [`ConnectedSession::wait_for`].
+    ///
+    /// # Errors
+    ///
+    /// - For the same reasons as [`ConnectedSession::wait_for`].
+    pub async fn wait_for_command(
+        &self,
+        command_line: impl Into<String>,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.wait_for(
+            &Predicate::command_succeeds(command_line),
+            DEFAULT_INTERVAL,
+            timeout,
+        )
+        .await
+    }
+
+    /// Whether `predicate` holds now.
+    async fn holds(&self, predicate: &Predicate) -> Result<bool> {
+        match predicate {
+            // Refused connections and unknown hosts are both reported as the
+            // server failing to open the tunnel.
+            Predicate::PortOpen { host, port } => Ok(self.open_tunnel(host, *port).await.is_ok()),
+            Predicate::FileExists(path) => self.fs().try_exists(path).await,
+            Predicate::CommandSucceeds(command_line) => {
+                let status = self
+                    .command("sh")
+                    .args(["-c", command_line])
+                    .status()
+                    .await?;
+                Ok(status.success())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "russh")]
+    use crate::test_server;
+
+    #[rstest]
+    #[case(
+        Predicate::port_open("localhost", 8080),
+        "port localhost:8080 to be open"
+    )]
+    #[case(Predicate::file_exists("/run/app.pid"), "/run/app.pid to exist")]
+    #[case(
+        Predicate::command_succeeds("systemctl is-active app"),
+        "`systemctl is-active app` to succeed"
+    )]
+    fn predicate_display_works(#[case] predicate: Predicate, #[case] display_should: &str) {
+        assert_eq!(predicate.to_string(), display_should);
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn wait_for_file_works() {
+        let session = test_server::connect().await;
+        session.fs().write("~/ready", "").await.unwrap();
+
+        session
+            .wait_for_file("~/ready", Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn wait_for_command_works() {
+        let session = test_server::connect().await;
+
+        session
+            .wait_for(
+                &Predicate::command_succeeds(
+                    "test -e command_ready || { touch command_ready; false; }",
+                ),
+                Duration::from_millis(10),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "russh")]
+    #[tokio::test]
+    async fn wait_for_times_out() {
+        let session = test_server::connect().await;
+
+        let result = session
+            .wait_for(
+                &Predicate::port_open(test_server::UNREACHABLE_HOST, 22),
+                Duration::from_millis(10),
+                Duration::from_millis(100),
+            )
+            .await;
+
+        assert!(matches!(result, Err(Error::WaitTimeout { .. })));
+    }
+}